@@ -1,10 +1,170 @@
-use libnexus::NexusCli;
+use libnexus::{Config, NexusCli};
+use std::path::Path;
+use std::process::ExitCode;
+
+/// Whether a leading argument is a server address rather than a service name.
+fn is_endpoint(arg: &str) -> bool {
+    arg.contains('/') || arg.contains(':') || arg.contains('\\')
+}
+
+/// The name this binary was invoked as, for generated completion scripts.
+fn program() -> String {
+    std::env::args_os()
+        .next()
+        .as_deref()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "cli-shell".to_string())
+}
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let addr = std::env::args()
-        .nth(1)
+async fn main() -> anyhow::Result<ExitCode> {
+    // Options and the address come first; everything from the first other
+    // word on is the command (so command flags like `--size` are not eaten).
+    let mut json = false;
+    let mut format = None;
+    let mut tenant = None;
+    let mut token = None;
+    let mut addr = None;
+    let mut config = std::env::var("NEXUS_CONFIG").ok();
+    let mut file = None;
+    let mut keep_going = false;
+    let mut dry_run = false;
+    let mut command = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--json" {
+            json = true;
+        } else if let Some(value) = arg.strip_prefix("--format=") {
+            format = Some(value.parse()?);
+        } else if let Some(value) = arg.strip_prefix("--tenant=") {
+            tenant = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("--token=") {
+            token = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("--config=") {
+            config = Some(value.to_string());
+        } else if let Some(value) = arg.strip_prefix("--file=") {
+            file = Some(value.to_string());
+        } else if arg == "--keep-going" {
+            keep_going = true;
+        } else if arg == "--dry-run" {
+            dry_run = true;
+        } else if let Some(value) = arg.strip_prefix("--addr=") {
+            addr = Some(value.to_string());
+        } else if addr.is_none() && is_endpoint(&arg) {
+            addr = Some(arg);
+        } else {
+            command.push(arg);
+            command.extend(args);
+            break;
+        }
+    }
+    // Settings from the config file and NEXUS_* variables; flags win.
+    let config = match &config {
+        Some(path) => Config::load(path)?,
+        None => Config::from_env()?,
+    };
+    let addr = addr
+        .or(config.cli.endpoint.clone())
         .unwrap_or_else(|| libnexus::DEFAULT_ENDPOINT.to_string());
 
-    NexusCli::new(&addr).run().await
+    let mut cli = NexusCli::new(&addr)
+        .with_config(&config.cli)?
+        .with_keep_going(keep_going)
+        .with_dry_run(dry_run);
+    if let Some(format) = format {
+        cli = cli.with_format(format);
+    }
+    if json {
+        cli = cli.with_json(true);
+    }
+    if let Some(tenant) = &tenant {
+        cli = cli.with_tenant(tenant);
+    }
+    if let Some(token) = &token {
+        cli = cli.with_token(token);
+    }
+
+    if let Some(file) = &file {
+        let success = cli.run_script(file).await?;
+        return Ok(if success { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
+
+    match command.split_first() {
+        // `__complete <words...>` is the hidden entry point used by shell completion.
+        Some((first, words)) if first == "__complete" => {
+            cli.complete(words).await;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some((first, words)) if first == "completions" => {
+            let [shell] = words else {
+                eprintln!("Usage: cli-shell completions <bash|zsh|fish>");
+                return Ok(ExitCode::FAILURE);
+            };
+            print!("{}", cli.completion_script(shell, &program()).await?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Some((first, words)) if first == "docs" => {
+            let (mut format, mut out) = ("md", None);
+            for option in words.chunks(2) {
+                match option {
+                    [flag, value] if flag == "--format" => format = value.as_str(),
+                    [flag, value] if flag == "--out" => out = Some(value),
+                    _ => {
+                        eprintln!("Usage: cli-shell docs [--format md|man] [--out <dir>]");
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+            }
+            let pages = cli.docs(format, &program()).await?;
+            match out {
+                Some(dir) => {
+                    std::fs::create_dir_all(dir)?;
+                    for (file, contents) in &pages {
+                        std::fs::write(Path::new(dir).join(file), contents)?;
+                    }
+                    println!("Wrote {} pages to {}", pages.len(), dir);
+                }
+                None => {
+                    let contents: Vec<&str> = pages.iter().map(|(_, contents)| contents.as_str()).collect();
+                    print!("{}", contents.join("\n"));
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some((first, words)) if first == "bindings" => {
+            let (mut lang, mut out) = ("python", None);
+            for option in words.chunks(2) {
+                match option {
+                    [flag, value] if flag == "--lang" => lang = value.as_str(),
+                    [flag, value] if flag == "--out" => out = Some(value),
+                    _ => {
+                        eprintln!("Usage: cli-shell bindings [--lang python] [--out <file>]");
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+            }
+            if lang != "python" {
+                eprintln!("Error: unsupported language '{}' (expected python)", lang);
+                return Ok(ExitCode::FAILURE);
+            }
+            let module = cli.python_bindings(&program()).await?;
+            match out {
+                Some(file) => {
+                    std::fs::write(file, &module)?;
+                    println!("Wrote {}", file);
+                }
+                None => print!("{}", module),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(_) => {
+            Ok(ExitCode::from(cli.run_once(&command).await?))
+        }
+        None => {
+            cli.run().await?;
+            Ok(ExitCode::SUCCESS)
+        }
+    }
 }
@@ -1,4 +1,18 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("proto/nexus.proto")?;
+    // The descriptors of every file, for the reflection service.
+    let descriptors = std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("nexus_descriptor.bin");
+    tonic_build::configure().file_descriptor_set_path(descriptors).compile_protos(
+        &[
+            "proto/nexus.proto",
+            "proto/health.proto",
+            "proto/reflection/v1.proto",
+            "proto/reflection/v1alpha.proto",
+        ],
+        &["proto"],
+    )?;
+    // Plugins must be built by the same compiler as the daemon loading them.
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let version = std::process::Command::new(rustc).arg("--version").output()?.stdout;
+    println!("cargo:rustc-env=NEXUS_RUSTC_VERSION={}", String::from_utf8(version)?.trim());
     Ok(())
 }
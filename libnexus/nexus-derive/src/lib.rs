@@ -1,9 +1,9 @@
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote, quote_spanned};
 use syn::{parse_macro_input, Attribute, FnArg, ImplItem, ItemImpl, Pat};
 
-/// Extract doc comment strings from attributes.
-fn extract_doc_comment(attrs: &[Attribute]) -> String {
+/// Doc comment lines from attributes, one per `///` line.
+fn doc_lines(attrs: &[Attribute]) -> Vec<String> {
     attrs
         .iter()
         .filter_map(|attr| {
@@ -11,17 +11,36 @@ fn extract_doc_comment(attrs: &[Attribute]) -> String {
                 if let syn::Meta::NameValue(nv) = &attr.meta {
                     if let syn::Expr::Lit(expr_lit) = &nv.value {
                         if let syn::Lit::Str(s) = &expr_lit.lit {
-                            return Some(s.value().trim().to_string());
+                            return Some(s.value());
                         }
                     }
                 }
             }
             None
         })
+        .collect()
+}
+
+/// Extract the first paragraph of the doc comment, joined into one line.
+/// Later paragraphs only appear in the long help.
+fn extract_doc_comment(attrs: &[Attribute]) -> String {
+    doc_lines(attrs)
+        .iter()
+        .map(|line| line.trim())
+        .take_while(|line| !line.is_empty())
         .collect::<Vec<_>>()
         .join(" ")
 }
 
+/// Extract the doc comment with its line breaks kept (for long help).
+fn extract_doc_text(attrs: &[Attribute]) -> String {
+    doc_lines(attrs)
+        .iter()
+        .map(|line| line.strip_prefix(' ').unwrap_or(line).trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Check if an attribute list contains `#[command]`.
 fn has_command_attr(attrs: &[Attribute]) -> bool {
     attrs.iter().any(|attr| attr.path().is_ident("command"))
@@ -35,24 +54,281 @@ fn strip_command_attr(attrs: &[Attribute]) -> Vec<&Attribute> {
         .collect()
 }
 
+/// Parsed arguments of `#[nexus_service(name = "...", aliases("...", ...),
+/// parent = "...", max_concurrency = N, max_queue = N, client)]`, or of
+/// `#[nexus_service(extend)]` on a further impl block of the same service.
+/// `client` (also allowed with `extend`) generates the typed client.
+#[derive(Default)]
+struct ServiceMeta {
+    name: Option<String>,
+    aliases: Vec<String>,
+    parent: Option<String>,
+    max_concurrency: Option<usize>,
+    max_queue: Option<usize>,
+    extend: bool,
+    client: bool,
+}
+
+impl ServiceMeta {
+    fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("name") {
+            let lit: syn::LitStr = meta.value()?.parse()?;
+            self.name = Some(lit.value());
+        } else if meta.path.is_ident("aliases") {
+            let content;
+            syn::parenthesized!(content in meta.input);
+            let aliases = content.parse_terminated(
+                |input| input.parse::<syn::LitStr>(),
+                syn::Token![,],
+            )?;
+            self.aliases.extend(aliases.iter().map(|lit| lit.value()));
+        } else if meta.path.is_ident("parent") {
+            let lit: syn::LitStr = meta.value()?.parse()?;
+            self.parent = Some(lit.value());
+        } else if meta.path.is_ident("max_concurrency") {
+            let lit: syn::LitInt = meta.value()?.parse()?;
+            let permits = lit.base10_parse::<usize>()?;
+            if permits == 0 {
+                return Err(syn::Error::new_spanned(lit, "`max_concurrency` must be at least 1"));
+            }
+            self.max_concurrency = Some(permits);
+        } else if meta.path.is_ident("max_queue") {
+            let lit: syn::LitInt = meta.value()?.parse()?;
+            self.max_queue = Some(lit.base10_parse()?);
+        } else if meta.path.is_ident("extend") {
+            self.extend = true;
+        } else if meta.path.is_ident("client") {
+            self.client = true;
+        } else {
+            return Err(meta.error(
+                "expected `name`, `aliases`, `parent`, `max_concurrency`, `max_queue`, `extend` or `client`",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether anything besides `extend` and `client` was given: settings
+    /// that belong on the main block.
+    fn has_settings(&self) -> bool {
+        self.name.is_some()
+            || !self.aliases.is_empty()
+            || self.parent.is_some()
+            || self.max_concurrency.is_some()
+            || self.max_queue.is_some()
+    }
+}
+
+/// `Some(n)` or `None` as an expression.
+fn option_tokens(value: Option<usize>) -> proc_macro2::TokenStream {
+    match value {
+        Some(n) => quote! { Some(#n) },
+        None => quote! { None },
+    }
+}
+
+/// The `Service` hook a method is marked as with `#[on_start]`, `#[on_stop]`,
+/// `#[shutdown]`, `#[health]` or `#[rollback]`.
+fn hook_attr(attrs: &[Attribute]) -> Option<&'static str> {
+    ["on_start", "on_stop", "shutdown", "health", "rollback"]
+        .into_iter()
+        .find(|hook| attrs.iter().any(|attr| attr.path().is_ident(hook)))
+}
+
+/// Parsed metadata from `#[command(...)]` on a method.
+struct CommandMeta {
+    dry_run: bool,
+    background: bool,
+    blocking: bool,
+    priority: proc_macro2::TokenStream,
+    permission: String,
+    /// `Option<Duration>` expression.
+    timeout: proc_macro2::TokenStream,
+    since: String,
+    /// `Option<String>` expression.
+    deprecated: proc_macro2::TokenStream,
+    readonly: bool,
+    /// How long results are cached, in milliseconds.
+    cache: Option<u64>,
+    /// Key of the resource the command locks, e.g. "pool:{pool}".
+    lock: Option<syn::LitStr>,
+    /// Whether the command fails instead of waiting for a taken lock.
+    try_lock: bool,
+}
+
+impl Default for CommandMeta {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            background: false,
+            blocking: false,
+            priority: quote! { libnexus::Priority::Normal },
+            permission: String::new(),
+            timeout: quote! { None },
+            since: String::new(),
+            deprecated: quote! { None },
+            readonly: false,
+            cache: None,
+            lock: None,
+            try_lock: false,
+        }
+    }
+}
+
+/// Parse `#[command(dry_run, background, blocking, priority = "...", permission = "...",
+/// timeout = "...", since = "...", deprecated [= "..."], readonly, cache = "...",
+/// lock = "..." | try_lock = "...")]` from method attributes. A bare `#[command]` yields defaults.
+fn parse_command_attr(attrs: &[Attribute]) -> syn::Result<CommandMeta> {
+    let mut meta = CommandMeta::default();
+    let mut cache = None;
+
+    for attr in attrs {
+        if attr.path().is_ident("command") && matches!(attr.meta, syn::Meta::List(_)) {
+            attr.parse_nested_meta(|nested| {
+                if nested.path.is_ident("dry_run") {
+                    meta.dry_run = true;
+                } else if nested.path.is_ident("background") {
+                    meta.background = true;
+                } else if nested.path.is_ident("blocking") {
+                    meta.blocking = true;
+                } else if nested.path.is_ident("priority") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    meta.priority = match lit.value().as_str() {
+                        "low" => quote! { libnexus::Priority::Low },
+                        "normal" => quote! { libnexus::Priority::Normal },
+                        "high" => quote! { libnexus::Priority::High },
+                        _ => return Err(syn::Error::new_spanned(&lit, "expected \"low\", \"normal\" or \"high\"")),
+                    };
+                } else if nested.path.is_ident("permission") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    meta.permission = lit.value();
+                } else if nested.path.is_ident("timeout") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    let ms = duration_ms(&lit)?;
+                    meta.timeout = quote! { Some(std::time::Duration::from_millis(#ms)) };
+                } else if nested.path.is_ident("since") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    meta.since = lit.value();
+                } else if nested.path.is_ident("deprecated") {
+                    // A bare `deprecated` names no replacement.
+                    let note = if nested.input.peek(syn::Token![=]) {
+                        nested.value()?.parse::<syn::LitStr>()?.value()
+                    } else {
+                        String::new()
+                    };
+                    meta.deprecated = quote! { Some(#note.to_string()) };
+                } else if nested.path.is_ident("readonly") {
+                    meta.readonly = true;
+                } else if nested.path.is_ident("cache") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    meta.cache = Some(duration_ms(&lit)?);
+                    cache = Some(lit);
+                } else if nested.path.is_ident("lock") || nested.path.is_ident("try_lock") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    if meta.lock.is_some() {
+                        return Err(syn::Error::new_spanned(lit, "a command takes one `lock` or `try_lock`"));
+                    }
+                    lock_args(&lit)?;
+                    meta.try_lock = nested.path.is_ident("try_lock");
+                    meta.lock = Some(lit);
+                } else {
+                    return Err(nested.error(
+                        "expected `dry_run`, `background`, `blocking`, `priority`, `permission`, `timeout`, \
+                         `since`, `deprecated`, `readonly`, `cache`, `lock` or `try_lock`",
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    // A cached result is stale once the command has changed something.
+    if let Some(lit) = cache.filter(|_| !meta.readonly) {
+        return Err(syn::Error::new_spanned(lit, "only `readonly` commands can be cached"));
+    }
+    Ok(meta)
+}
+
+/// The arguments a lock key such as "pool:{pool}" names, in order.
+fn lock_args(lit: &syn::LitStr) -> syn::Result<Vec<String>> {
+    let error = || syn::Error::new_spanned(lit, "expected a key such as \"pool:{pool}\"");
+    let key = lit.value();
+    if key.is_empty() {
+        return Err(error());
+    }
+    let mut args = Vec::new();
+    let mut rest = key.as_str();
+    while let Some(start) = rest.find(['{', '}']) {
+        let placeholder = rest[start..].strip_prefix('{').and_then(|tail| tail.split_once('}'));
+        let Some((name, tail)) = placeholder.filter(|(name, _)| is_ident(name)) else {
+            return Err(error());
+        };
+        args.push(name.to_string());
+        rest = tail;
+    }
+    Ok(args)
+}
+
+/// Whether `word` can name an argument.
+fn is_ident(word: &str) -> bool {
+    word.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && word.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Milliseconds in a duration literal such as "30s".
+fn duration_ms(lit: &syn::LitStr) -> syn::Result<u64> {
+    parse_duration_ms(&lit.value()).ok_or_else(|| {
+        syn::Error::new_spanned(lit, "expected a duration such as \"500ms\", \"30s\", \"5m\" or \"1h\"")
+    })
+}
+
+/// Milliseconds in a duration written as a whole number and a unit
+/// (`ms`, `s`, `m` or `h`), e.g. "30s". Zero is rejected.
+fn parse_duration_ms(text: &str) -> Option<u64> {
+    let split = text.find(|c: char| !c.is_ascii_digit())?;
+    let (number, unit) = text.split_at(split);
+    let scale = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(scale).filter(|&ms| ms > 0)
+}
+
 /// Parsed metadata from `#[arg(...)]` on a parameter.
+#[derive(Default)]
 struct ArgMeta {
     hint: String,
+    /// `complete = "service.command($arg, ...)"`: the command, and the other
+    /// arguments whose values it is passed. The literal is kept for errors.
     completer: String,
+    completer_args: Vec<String>,
+    completer_lit: Option<syn::LitStr>,
     description: String,
+    glob: bool,
+    default: Option<String>,
+    secret: bool,
+    /// Checks on the value: `regex = "..."`, `range = "..."`, `choices(...)`.
+    regex: Option<syn::LitStr>,
+    range: Option<syn::LitStr>,
+    choices: Vec<String>,
 }
 
-/// Parse `#[arg(hint = "...", complete = "...", doc = "...")]` from parameter attributes.
-fn parse_arg_attr(attrs: &[Attribute]) -> ArgMeta {
-    let mut meta = ArgMeta {
-        hint: String::new(),
-        completer: String::new(),
-        description: String::new(),
-    };
+/// Parse `#[arg(hint = "...", complete = "...", doc = "...", glob, default = "...", secret,
+/// regex = "...", range = "...", choices("...", ...))]` from parameter attributes.
+fn parse_arg_attr(attrs: &[Attribute]) -> syn::Result<ArgMeta> {
+    let mut meta = ArgMeta::default();
 
     for attr in attrs {
         if attr.path().is_ident("arg") {
-            let _ = attr.parse_nested_meta(|nested| {
+            attr.parse_nested_meta(|nested| {
                 if nested.path.is_ident("hint") {
                     let value = nested.value()?;
                     let lit: syn::LitStr = value.parse()?;
@@ -60,18 +336,347 @@ fn parse_arg_attr(attrs: &[Attribute]) -> ArgMeta {
                 } else if nested.path.is_ident("complete") {
                     let value = nested.value()?;
                     let lit: syn::LitStr = value.parse()?;
-                    meta.completer = lit.value();
+                    let (completer, args) = parse_completer(&lit.value())
+                        .ok_or_else(|| syn::Error::new_spanned(
+                            &lit,
+                            "expected a command such as \"block.list\" or \"volume.snapshot.list($volume)\"",
+                        ))?;
+                    meta.completer = completer;
+                    meta.completer_args = args;
+                    meta.completer_lit = Some(lit);
                 } else if nested.path.is_ident("doc") {
                     let value = nested.value()?;
                     let lit: syn::LitStr = value.parse()?;
                     meta.description = lit.value();
+                } else if nested.path.is_ident("glob") {
+                    meta.glob = true;
+                } else if nested.path.is_ident("secret") {
+                    meta.secret = true;
+                } else if nested.path.is_ident("default") {
+                    let value = nested.value()?;
+                    let lit: syn::LitStr = value.parse()?;
+                    meta.default = Some(lit.value());
+                } else if nested.path.is_ident("regex") {
+                    meta.regex = Some(nested.value()?.parse()?);
+                } else if nested.path.is_ident("range") {
+                    meta.range = Some(nested.value()?.parse()?);
+                } else if nested.path.is_ident("choices") {
+                    let content;
+                    syn::parenthesized!(content in nested.input);
+                    let choices = content.parse_terminated(
+                        |input| input.parse::<syn::LitStr>(),
+                        syn::Token![,],
+                    )?;
+                    meta.choices.extend(choices.iter().map(|lit| lit.value()));
+                } else {
+                    return Err(nested.error(
+                        "expected `hint`, `complete`, `doc`, `glob`, `default`, `secret`, `regex`, `range` or `choices`",
+                    ));
                 }
                 Ok(())
-            });
+            })?;
+        }
+    }
+
+    Ok(meta)
+}
+
+/// Split `"service.command($arg, ...)"` into the command and the argument
+/// names; the parenthesized list may be left out.
+fn parse_completer(text: &str) -> Option<(String, Vec<String>)> {
+    let (command, args) = match text.split_once('(') {
+        Some((command, rest)) => (command.trim(), rest.strip_suffix(')')?),
+        None => (text.trim(), ""),
+    };
+    let (service, action) = command.rsplit_once('.')?;
+    if !service.split('.').all(is_ident) || !is_ident(action) {
+        return None;
+    }
+    let args = args
+        .split(',')
+        .map(str::trim)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| arg.strip_prefix('$').filter(|name| is_ident(name)).map(str::to_string))
+        .collect::<Option<_>>()?;
+    Some((command.to_string(), args))
+}
+
+/// Generate the `#[arg(regex, choices)]` checks on the raw string `raw` and the
+/// `#[arg(range)]` check on the parsed `value`. A pattern or range that does not
+/// compile is reported at the attribute.
+fn arg_checks(
+    name: &syn::Ident,
+    meta: &ArgMeta,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let mut raw_checks = Vec::new();
+    if let Some(lit) = &meta.regex {
+        match regex::Regex::new(&lit.value()) {
+            Ok(_) => raw_checks.push(quote! {
+                libnexus::validate::pattern(stringify!(#name), raw, #lit)?;
+            }),
+            Err(e) => raw_checks.push(
+                syn::Error::new(lit.span(), format!("invalid regex: {}", e)).to_compile_error(),
+            ),
+        }
+    }
+    if !meta.choices.is_empty() {
+        let choices = &meta.choices;
+        raw_checks.push(quote! {
+            libnexus::validate::choice(stringify!(#name), raw, &[#(#choices),*])?;
+        });
+    }
+    let value_check = match &meta.range {
+        Some(lit) => match lit.parse::<syn::ExprRange>() {
+            Ok(range) => quote! {
+                libnexus::validate::range(stringify!(#name), &value, #range, #lit)?;
+            },
+            Err(_) => syn::Error::new(lit.span(), "expected a range such as \"1..=1024\"")
+                .to_compile_error(),
+        },
+        None => quote! {},
+    };
+    (quote! { #(#raw_checks)* }, value_check)
+}
+
+/// Check whether a parameter type is `&Context` (any path ending in `Context`).
+fn is_context_type(ty: &syn::Type) -> bool {
+    if let syn::Type::Reference(reference) = ty {
+        if let syn::Type::Path(type_path) = &*reference.elem {
+            return type_path
+                .path
+                .segments
+                .last()
+                .is_some_and(|seg| seg.ident == "Context");
         }
     }
+    false
+}
+
+/// Check whether a parameter type is a `Context` taken by value, which the
+/// macro cannot pass.
+fn is_by_value_context(ty: &syn::Type) -> bool {
+    let syn::Type::Path(type_path) = ty else {
+        return false;
+    };
+    type_path.path.segments.last().is_some_and(|seg| seg.ident == "Context")
+}
+
+/// Check the parts of a command or hook signature the macro cannot work
+/// with: it must take `&self` first and must not be generic.
+fn check_signature(sig: &syn::Signature) -> syn::Result<()> {
+    match sig.inputs.first() {
+        Some(FnArg::Receiver(receiver)) if receiver.reference.is_some() && receiver.mutability.is_none() => {}
+        Some(FnArg::Receiver(receiver)) => {
+            return Err(syn::Error::new_spanned(
+                receiver,
+                "a service is shared between requests, so its methods take `&self`",
+            ));
+        }
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &sig.ident,
+                "expected `&self` as the first parameter",
+            ));
+        }
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "service methods cannot be generic; arguments are parsed into the declared types",
+        ));
+    }
+    Ok(())
+}
+
+/// Check whether a parameter type is `CancellationToken` (any path ending in
+/// `CancellationToken`).
+fn is_cancellation_type(ty: &syn::Type) -> bool {
+    if let syn::Type::Path(type_path) = ty {
+        return type_path
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "CancellationToken");
+    }
+    false
+}
+
+/// Check whether a parameter type is `Progress` (any path ending in `Progress`).
+fn is_progress_type(ty: &syn::Type) -> bool {
+    if let syn::Type::Path(type_path) = ty {
+        return type_path
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "Progress");
+    }
+    false
+}
+
+/// Check whether a parameter type is `Upload` or `Download` (any path
+/// ending in `name`).
+fn is_transfer_type(ty: &syn::Type, name: &str) -> bool {
+    if let syn::Type::Path(type_path) = ty {
+        return type_path.path.segments.last().is_some_and(|seg| seg.ident == name);
+    }
+    false
+}
+
+/// Check whether a parameter type is `State<T>` (any path ending in `State`
+/// with a type argument), the server's shared value of type `T`.
+fn is_state_type(ty: &syn::Type) -> bool {
+    type_param(ty, "State").is_some()
+}
+
+/// If `ty` is `Option<T>`, return `T`.
+fn option_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    type_param(ty, "Option")
+}
+
+/// If `ty` is `Vec<T>` (a variadic parameter), return `T`.
+fn vec_inner(ty: &syn::Type) -> Option<&syn::Type> {
+    type_param(ty, "Vec")
+}
+
+/// The type parameter of `ty` if it is the generic type `wrapper<T>`.
+fn type_param<'a>(ty: &'a syn::Type, wrapper: &str) -> Option<&'a syn::Type> {
+    let syn::Type::Path(type_path) = ty else {
+        return None;
+    };
+    let seg = type_path.path.segments.last()?;
+    if seg.ident != wrapper {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(generics) = &seg.arguments else {
+        return None;
+    };
+    match generics.args.first()? {
+        syn::GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
+/// Check whether a method returns `impl Stream<...>` (a streaming command).
+fn returns_stream(output: &syn::ReturnType) -> bool {
+    returns_impl(output, "Stream")
+}
+
+/// Check whether a method returns `impl Trait` with a bound named `trait_name`.
+fn returns_impl(output: &syn::ReturnType, trait_name: &str) -> bool {
+    let syn::ReturnType::Type(_, ty) = output else {
+        return false;
+    };
+    let syn::Type::ImplTrait(impl_trait) = &**ty else {
+        return false;
+    };
+    impl_trait.bounds.iter().any(|bound| match bound {
+        syn::TypeParamBound::Trait(trait_bound) => trait_bound
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == trait_name),
+        _ => false,
+    })
+}
+
+/// Turn `value`, what a non-streaming command method returned, into an
+/// `anyhow::Result<CommandOutput>`: a `Result` is mapped, anything else
+/// wrapped in `Ok`. `()` is empty output and `impl Display` its text.
+fn command_result(output: &syn::ReturnType, value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let ty = match output {
+        syn::ReturnType::Default => return quote! { { #value; Ok(libnexus::CommandOutput::from(())) } },
+        syn::ReturnType::Type(_, ty) => ty,
+    };
+    if matches!(&**ty, syn::Type::Tuple(tuple) if tuple.elems.is_empty()) {
+        return quote! { { #value; Ok(libnexus::CommandOutput::from(())) } };
+    }
+    if type_param(ty, "Result").is_some() {
+        return quote! { #value.map(libnexus::CommandOutput::from) };
+    }
+    if returns_impl(output, "Display") {
+        return quote! { Ok(libnexus::CommandOutput::from(#value.to_string())) };
+    }
+    quote! { Ok(libnexus::CommandOutput::from(#value)) }
+}
+
+/// Whether `ty` is `String`, which typed client methods take as `&str`.
+fn is_string(ty: &syn::Type) -> bool {
+    let syn::Type::Path(type_path) = ty else {
+        return false;
+    };
+    type_path
+        .path
+        .segments
+        .last()
+        .is_some_and(|seg| seg.ident == "String" && seg.arguments.is_empty())
+}
+
+/// Whether `ty` is `Vec<u8>`, binary output.
+fn is_bytes(ty: &syn::Type) -> bool {
+    type_param(ty, "Vec").is_some_and(|elem| matches!(elem, syn::Type::Path(elem) if elem.path.is_ident("u8")))
+}
+
+/// The type a typed client method takes a parameter of type `ty` as.
+fn client_param_type(ty: &syn::Type) -> proc_macro2::TokenStream {
+    if is_string(ty) {
+        quote! { &str }
+    } else {
+        quote! { #ty }
+    }
+}
 
-    meta
+/// The type a typed client method returns for a command returning
+/// `output`: text as `String`, `()`, JSON as `serde_json::Value`, binary
+/// data as `Vec<u8>`, and anything else (tables, custom types) as
+/// `CommandOutput`. A background command returns its job's ID.
+fn client_return_type(output: &syn::ReturnType, background: bool) -> proc_macro2::TokenStream {
+    let ty = match output {
+        _ if background => return quote! { u64 },
+        syn::ReturnType::Default => return quote! { () },
+        syn::ReturnType::Type(_, ty) => ty,
+    };
+    if returns_impl(output, "Display") {
+        return quote! { String };
+    }
+    let ty = type_param(ty, "Result").unwrap_or(ty);
+    let named = |name: &str| match ty {
+        syn::Type::Path(type_path) => type_path.path.segments.last().is_some_and(|seg| seg.ident == name),
+        _ => false,
+    };
+    let is_str = |ty: &syn::Type| matches!(ty, syn::Type::Path(elem) if elem.path.is_ident("str"));
+    match ty {
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => quote! { () },
+        syn::Type::Reference(reference) if is_str(&reference.elem) => quote! { String },
+        _ if is_string(ty) => quote! { String },
+        _ if named("Value") => quote! { libnexus::serde_json::Value },
+        _ if is_bytes(ty) => quote! { Vec<u8> },
+        _ => quote! { libnexus::CommandOutput },
+    }
+}
+
+/// The typed client's name for the service `self_ty`: `VolumeClient` for
+/// `Volume`, at the same path.
+fn client_path(self_ty: &syn::Type) -> syn::Result<syn::Path> {
+    let syn::Type::Path(type_path) = self_ty else {
+        return Err(syn::Error::new_spanned(self_ty, "`client` needs the service to be a named type"));
+    };
+    let mut path = type_path.path.clone();
+    let last = path.segments.last_mut().expect("a path has a segment");
+    last.ident = format_ident!("{}Client", last.ident);
+    last.arguments = syn::PathArguments::None;
+    Ok(path)
+}
+
+/// Rebuild a command method without its `#[command]` and `#[arg]` attributes.
+fn strip_method(method: &syn::ImplItemFn) -> proc_macro2::TokenStream {
+    let remaining_attrs = strip_command_attr(&method.attrs);
+    let vis = &method.vis;
+    let sig = strip_arg_attrs(&method.sig);
+    let block = &method.block;
+    quote! {
+        #(#remaining_attrs)*
+        #vis #sig #block
+    }
 }
 
 /// Strip `#[arg(...)]` attributes from a function signature's parameters.
@@ -86,73 +691,606 @@ fn strip_arg_attrs(sig: &syn::Signature) -> syn::Signature {
 }
 
 #[proc_macro_attribute]
-pub fn nexus_service(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn nexus_service(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut service_meta = ServiceMeta::default();
+    let parser = syn::meta::parser(|meta| service_meta.parse(meta));
+    parse_macro_input!(attr with parser);
     let input = parse_macro_input!(item as ItemImpl);
+    if service_meta.extend && service_meta.has_settings() {
+        return syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`extend` takes no other settings; give them on the main `#[nexus_service]` block",
+        )
+        .to_compile_error()
+        .into();
+    }
 
-    // Extract the struct name and service-level doc comment from the impl block.
+    // Extract the service name (the lowercased struct name unless given) and
+    // service-level doc comment from the impl block.
     let self_ty = &input.self_ty;
     let struct_name = quote!(#self_ty).to_string();
-    let service_name = struct_name.to_lowercase();
+    let mut service_name = service_meta.name.unwrap_or_else(|| struct_name.to_lowercase());
+    // A nested service is named by its path under the parent.
+    if let Some(parent) = &service_meta.parent {
+        service_name = format!("{}.{}", parent, service_name);
+    }
+    let service_aliases = &service_meta.aliases;
+    let max_concurrency = option_tokens(service_meta.max_concurrency);
+    let max_queue = option_tokens(service_meta.max_queue);
     let service_doc = extract_doc_comment(&input.attrs);
 
+    // The service commands run on: `self` in the `Service` impl, `service` in
+    // an extension's. Methods of a trait impl are called through the trait,
+    // which need not be in scope where the macro expands.
+    let receiver = if service_meta.extend { quote! { service } } else { quote! { self } };
+    let callee = |method: &syn::Ident| match &input.trait_ {
+        Some((_, path, _)) => quote! { <#self_ty as #path>::#method },
+        None => quote! { <#self_ty>::#method },
+    };
+
     let mut command_infos = Vec::new();
+    let mut client_methods = Vec::new();
     let mut match_arms = Vec::new();
+    let mut stream_arms = Vec::new();
     let mut cleaned_methods = Vec::new();
+    let mut start_calls = Vec::new();
+    let mut stop_calls = Vec::new();
+    let mut shutdown_calls = Vec::new();
+    let mut health_impl = None;
+    let mut rollback_impl = None;
+    let mut errors = Vec::new();
 
     for item in &input.items {
         if let ImplItem::Fn(method) = item {
-            if has_command_attr(&method.attrs) {
+            if let Some(hook) = hook_attr(&method.attrs) {
+                // `#[on_start] async fn ...(&self) -> anyhow::Result<()>` (or
+                // with a `&libnexus::StartContext`) runs when the server
+                // starts, `#[on_stop]` when it begins to stop and `#[shutdown]`
+                // once it has; `#[health] async fn ...(&self) ->
+                // libnexus::Health` answers health checks, and `#[rollback]
+                // async fn ...(&self, action: &str, args: libnexus::Args) ->
+                // anyhow::Result<()>` (with a `&libnexus::Context` first, if
+                // it wants one) undoes a command of a failed batch.
+                let method_name = &method.sig.ident;
+                let call = callee(method_name);
+                let with_ctx = match hook {
+                    "on_start" => method.sig.inputs.len() == 2,
+                    "rollback" => method.sig.inputs.len() == 4,
+                    _ => false,
+                };
+                let extra = method.sig.inputs.iter().nth(if hook == "on_start" { 2 } else { 1 });
+                let signature = check_signature(&method.sig).and_then(|()| match extra {
+                    Some(extra) if hook == "on_start" => Err(syn::Error::new_spanned(
+                        extra,
+                        "`#[on_start]` methods take `&self` and optionally a `&libnexus::StartContext`",
+                    )),
+                    _ if hook == "rollback" && !matches!(method.sig.inputs.len(), 3 | 4) => {
+                        Err(syn::Error::new_spanned(
+                            &method.sig.inputs,
+                            "`#[rollback]` methods take `&self`, optionally a `&libnexus::Context`, \
+                             the command's name and its `libnexus::Args`",
+                        ))
+                    }
+                    Some(extra) if hook != "rollback" => Err(syn::Error::new_spanned(
+                        extra,
+                        format!("`#[{}]` methods take only `&self`", hook),
+                    )),
+                    _ => Ok(()),
+                });
+                if let Err(e) = signature {
+                    errors.push(e.to_compile_error());
+                } else if service_meta.extend {
+                    let message = format!("`#[{}]` belongs in the main `#[nexus_service]` block", hook);
+                    errors.push(syn::Error::new_spanned(method_name, message).to_compile_error());
+                } else if hook == "on_start" && with_ctx {
+                    start_calls.push(quote! { #call(self, ctx).await?; });
+                } else if hook == "on_start" {
+                    start_calls.push(quote! { #call(self).await?; });
+                } else if hook == "on_stop" {
+                    stop_calls.push(quote! { #call(self).await?; });
+                } else if hook == "shutdown" {
+                    shutdown_calls.push(quote! { #call(self).await?; });
+                } else if hook == "rollback" {
+                    let ctx = with_ctx.then(|| quote! { ctx, });
+                    rollback_impl = Some(quote! {
+                        #[allow(unused_variables)]
+                        async fn rollback(
+                            &self,
+                            ctx: &libnexus::Context,
+                            action: &str,
+                            args: libnexus::Args,
+                        ) -> anyhow::Result<()> {
+                            #call(self, #ctx action, args).await
+                        }
+                    });
+                } else {
+                    health_impl = Some(quote! {
+                        async fn health(&self) -> libnexus::Health {
+                            #call(self).await
+                        }
+                    });
+                }
+                let remaining_attrs: Vec<_> = method
+                    .attrs
+                    .iter()
+                    .filter(|attr| !attr.path().is_ident(hook))
+                    .collect();
+                let vis = &method.vis;
+                let sig = &method.sig;
+                let block = &method.block;
+                cleaned_methods.push(quote! {
+                    #(#remaining_attrs)*
+                    #vis #sig #block
+                });
+            } else if has_command_attr(&method.attrs) {
                 let method_name = &method.sig.ident;
                 let method_name_str = method_name.to_string();
                 let doc = extract_doc_comment(&method.attrs);
+                let help = extract_doc_text(&method.attrs);
+                // A signature the call cannot be generated for is only reported.
+                if let Err(e) = check_signature(&method.sig) {
+                    errors.push(e.to_compile_error());
+                    cleaned_methods.push(strip_method(method));
+                    continue;
+                }
+                let command_meta = parse_command_attr(&method.attrs).unwrap_or_else(|e| {
+                    errors.push(e.to_compile_error());
+                    CommandMeta::default()
+                });
+                let dry_run = command_meta.dry_run;
+                let background = command_meta.background;
+                let priority = &command_meta.priority;
+                let permission = &command_meta.permission;
+                let timeout = &command_meta.timeout;
+                let since = &command_meta.since;
+                let deprecated = &command_meta.deprecated;
+                let readonly = command_meta.readonly;
+                let cache = match command_meta.cache {
+                    Some(ms) => quote! { Some(std::time::Duration::from_millis(#ms)) },
+                    None => quote! { None },
+                };
+                let (lock, try_lock) = match &command_meta.lock {
+                    Some(lit) => (lit.value(), command_meta.try_lock),
+                    None => (String::new(), false),
+                };
+                let streaming = returns_stream(&method.sig.output);
 
                 // Collect parameter names, hints, completers, and docs (skip &self).
+                // A `&Context` parameter is not a command argument; it receives `ctx`.
+                // Neither is a `CancellationToken`, which receives the request's token,
+                // nor a `Progress`, which receives its progress handle, nor an
+                // `Upload` or `Download`, which receive the file sent with the
+                // request or the one sent back, nor a `Session`, which puts
+                // questions to the user, nor a `State<T>`, which receives the
+                // server's shared `T`.
+                let mut call_args = Vec::new();
+                let mut state_extractions = Vec::new();
                 let mut param_names = Vec::new();
+                let mut param_types = Vec::new();
                 let mut param_name_strings = Vec::new();
                 let mut param_hints = Vec::new();
                 let mut param_completers = Vec::new();
+                let mut param_completer_args = Vec::new();
                 let mut param_descriptions = Vec::new();
+                let mut param_globs = Vec::new();
+                let mut param_defaults = Vec::new();
+                let mut param_secrets = Vec::new();
+                let mut param_choices = Vec::new();
+                let mut param_checks = Vec::new();
+                let mut progress = false;
+                let mut upload = false;
+                let mut download = false;
+                let mut interactive = false;
 
                 for arg in method.sig.inputs.iter().skip(1) {
                     if let FnArg::Typed(pat_type) = arg {
-                        if let Pat::Ident(pat_ident) = &*pat_type.pat {
-                            let name = &pat_ident.ident;
-                            let arg_meta = parse_arg_attr(&pat_type.attrs);
-                            param_names.push(name.clone());
-                            param_name_strings.push(name.to_string());
-                            param_hints.push(arg_meta.hint);
-                            param_completers.push(arg_meta.completer);
-                            param_descriptions.push(arg_meta.description);
+                        if is_context_type(&pat_type.ty) {
+                            call_args.push(if command_meta.blocking {
+                                syn::Error::new_spanned(
+                                    &pat_type.ty,
+                                    "`blocking` commands cannot borrow the request's `Context`; \
+                                     take a `CancellationToken` or `Progress` instead",
+                                )
+                                .to_compile_error()
+                            } else {
+                                quote! { ctx }
+                            });
+                            continue;
                         }
+                        if is_cancellation_type(&pat_type.ty) {
+                            call_args.push(quote! { ctx.cancellation().clone() });
+                            continue;
+                        }
+                        if is_progress_type(&pat_type.ty) {
+                            progress = true;
+                            call_args.push(if streaming {
+                                syn::Error::new_spanned(
+                                    &pat_type.ty,
+                                    "streaming commands report progress through their output lines",
+                                )
+                                .to_compile_error()
+                            } else {
+                                quote! { ctx.progress().clone() }
+                            });
+                            continue;
+                        }
+                        if let Some(name) = ["Upload", "Download"]
+                            .into_iter()
+                            .find(|name| is_transfer_type(&pat_type.ty, name))
+                        {
+                            let taken = if name == "Upload" { &mut upload } else { &mut download };
+                            if std::mem::replace(taken, true) {
+                                let message = format!("a command takes at most one `{}`", name);
+                                errors.push(syn::Error::new_spanned(&pat_type.ty, message).to_compile_error());
+                            }
+                            if command_meta.background {
+                                let message = "background commands cannot transfer files: \
+                                               the request ends when the job starts";
+                                errors.push(syn::Error::new_spanned(&pat_type.ty, message).to_compile_error());
+                            }
+                            // Taken before the call, like `State<T>`.
+                            let transfer = format_ident!("__nexus_{}", name.to_lowercase());
+                            let ty = format_ident!("{}", name);
+                            call_args.push(quote! { #transfer });
+                            state_extractions.push(quote! {
+                                let #transfer = libnexus::#ty::from_context(ctx)?;
+                            });
+                            continue;
+                        }
+                        if is_transfer_type(&pat_type.ty, "Session") {
+                            if std::mem::replace(&mut interactive, true) {
+                                let message = "a command takes at most one `Session`";
+                                errors.push(syn::Error::new_spanned(&pat_type.ty, message).to_compile_error());
+                            }
+                            let unsupported = if streaming {
+                                Some("streaming commands cannot ask questions; show lines with `Session::say`")
+                            } else if command_meta.background {
+                                Some(
+                                    "background commands cannot ask questions: \
+                                     the request ends when the job starts",
+                                )
+                            } else if command_meta.blocking {
+                                Some("`blocking` commands cannot ask questions: `Session` is async")
+                            } else if command_meta.cache.is_some() {
+                                Some("commands asking questions cannot be cached")
+                            } else {
+                                None
+                            };
+                            if let Some(message) = unsupported {
+                                errors.push(syn::Error::new_spanned(&pat_type.ty, message).to_compile_error());
+                            }
+                            call_args.push(quote! { __nexus_session });
+                            state_extractions.push(quote! {
+                                let __nexus_session = libnexus::Session::from_context(ctx)?;
+                            });
+                            continue;
+                        }
+                        if is_state_type(&pat_type.ty) {
+                            // Looked up before the call, so a missing value fails
+                            // the request even where the call runs in a closure.
+                            let state = format_ident!("__nexus_state{}", state_extractions.len());
+                            call_args.push(quote! { #state });
+                            state_extractions.push(quote! {
+                                let #state = libnexus::State::from_context(ctx)?;
+                            });
+                            continue;
+                        }
+                        if is_by_value_context(&pat_type.ty) {
+                            let message = "take the request context by reference: `ctx: &Context`";
+                            call_args.push(syn::Error::new_spanned(&pat_type.ty, message).to_compile_error());
+                            continue;
+                        }
+                        if let syn::Type::ImplTrait(_) = &*pat_type.ty {
+                            let message = "arguments are parsed into the declared type, so it cannot be `impl Trait`";
+                            call_args.push(syn::Error::new_spanned(&pat_type.ty, message).to_compile_error());
+                            continue;
+                        }
+                        let Pat::Ident(pat_ident) = &*pat_type.pat else {
+                            let message = "command parameters must be plain names, which name the arguments";
+                            call_args.push(syn::Error::new_spanned(&pat_type.pat, message).to_compile_error());
+                            continue;
+                        };
+                        let name = &pat_ident.ident;
+                        call_args.push(quote! { #name });
+                        let arg_meta = parse_arg_attr(&pat_type.attrs).unwrap_or_else(|e| {
+                            errors.push(e.to_compile_error());
+                            ArgMeta::default()
+                        });
+                        param_checks.push(arg_checks(name, &arg_meta));
+                        param_names.push(name.clone());
+                        param_types.push((*pat_type.ty).clone());
+                        param_name_strings.push(name.to_string());
+                        param_hints.push(arg_meta.hint);
+                        param_completers.push(arg_meta.completer);
+                        param_completer_args.push((arg_meta.completer_args, arg_meta.completer_lit));
+                        param_descriptions.push(arg_meta.description);
+                        param_globs.push(arg_meta.glob);
+                        param_defaults.push(arg_meta.default);
+                        param_secrets.push(arg_meta.secret);
+                        let choices = &arg_meta.choices;
+                        param_choices.push(quote! { vec![#(#choices.to_string()),*] });
                     }
                 }
 
-                let num_params = param_names.len();
+                // Completers are passed the values of other arguments of the command.
+                for (i, (args, lit)) in param_completer_args.iter().enumerate() {
+                    for arg in args {
+                        if arg == &param_name_strings[i] || !param_name_strings.contains(arg) {
+                            let message = format!("`${}` is not another argument of this command", arg);
+                            errors.push(syn::Error::new_spanned(lit, message).to_compile_error());
+                        }
+                    }
+                }
+                // Lock keys name arguments of the command.
+                if let Some(lit) = &command_meta.lock {
+                    for arg in lock_args(lit).unwrap_or_default() {
+                        if !param_name_strings.contains(&arg) {
+                            let message = format!("`{{{}}}` is not an argument of this command", arg);
+                            errors.push(syn::Error::new_spanned(lit, message).to_compile_error());
+                        }
+                    }
+                }
+                let param_completer_args: Vec<_> = param_completer_args
+                    .iter()
+                    .map(|(args, _)| quote! { vec![#(#args.to_string()),*] })
+                    .collect();
 
-                // Generate the match arm for execute dispatch.
-                // Each parameter is extracted positionally from the args Vec<String>.
-                let param_extractions: Vec<_> = param_names
+                let param_optional: Vec<bool> = param_types
+                    .iter()
+                    .zip(&param_defaults)
+                    .map(|(ty, default)| option_inner(ty).is_some() || default.is_some())
+                    .collect();
+                let num_required = param_optional.iter().filter(|opt| !**opt).count();
+                // A trailing `Vec<T>` takes every remaining positional value.
+                let param_variadics: Vec<bool> = param_types
                     .iter()
                     .enumerate()
-                    .map(|(i, name)| {
-                        quote! {
-                            let #name = args.get(#i)
-                                .ok_or_else(|| anyhow::anyhow!(
-                                    "missing argument '{}' (expected {} args)",
+                    .map(|(i, ty)| i + 1 == param_types.len() && vec_inner(ty).is_some())
+                    .collect();
+                let variadic = param_variadics.last().copied().unwrap_or(false);
+                let bind = if variadic {
+                    quote! { let (slots, rest) = args.bind_variadic(&[#(#param_name_strings),*])?; }
+                } else {
+                    quote! { let slots = args.bind(&[#(#param_name_strings),*])?; }
+                };
+
+                // Generate the match arm for execute dispatch.
+                // `Args::bind` assigns each parameter its `--name` flag or the next
+                // positional value (`bind_variadic` gives a trailing `Vec<T>` the
+                // rest); the string is then parsed into the declared type via
+                // `FromStr`. Missing args fall back to `#[arg(default)]`, or
+                // `None` for `Option<T>`. `#[arg(regex, choices)]` check the string
+                // and `#[arg(range)]` the parsed value, before the method runs.
+                let param_extractions: Vec<_> = (0..param_names.len())
+                    .map(|i| {
+                        let name = &param_names[i];
+                        let ty = &param_types[i];
+                        let raw = match &param_defaults[i] {
+                            Some(default) => quote! {
+                                slots[#i].as_deref().or(Some(#default))
+                            },
+                            None => quote! { slots[#i].as_deref() },
+                        };
+                        let parse = |target: &syn::Type| {
+                            quote! {
+                                .parse::<#target>()
+                                .map_err(|e| libnexus::NexusError::invalid_argument(format!(
+                                    "invalid value for <{}>: {}",
                                     stringify!(#name),
-                                    #num_params
-                                ))?
-                                .clone();
+                                    e
+                                )))
+                            }
+                        };
+                        let (raw_checks, value_check) = &param_checks[i];
+                        if let Some(inner) = vec_inner(ty) {
+                            let misuse = if !param_variadics[i] {
+                                Some("only the last parameter can be a `Vec`")
+                            } else if param_defaults[i].is_some() {
+                                Some("a `Vec` parameter cannot have a default")
+                            } else {
+                                None
+                            };
+                            if let Some(message) = misuse {
+                                // Still bind the name so the call compiles.
+                                let error = syn::Error::new_spanned(ty, message).to_compile_error();
+                                return quote! { #error let #name: #ty = Vec::new(); };
+                            }
+                            let parse = parse(inner);
+                            quote! {
+                                let #name: #ty = rest
+                                    .iter()
+                                    .map(|raw| -> anyhow::Result<#inner> {
+                                        let raw = raw.as_str();
+                                        #raw_checks
+                                        let value = raw #parse?;
+                                        #value_check
+                                        Ok(value)
+                                    })
+                                    .collect::<anyhow::Result<_>>()?;
+                                if #name.is_empty() {
+                                    return Err(libnexus::NexusError::invalid_argument(format!(
+                                        "missing argument '{}' (expected at least one value)",
+                                        stringify!(#name)
+                                    ))
+                                    .into());
+                                }
+                            }
+                        } else if let Some(inner) = option_inner(ty) {
+                            let parse = parse(inner);
+                            quote! {
+                                let #name: #ty = match #raw {
+                                    Some(raw) => {
+                                        #raw_checks
+                                        let value = raw #parse?;
+                                        #value_check
+                                        Some(value)
+                                    }
+                                    None => None,
+                                };
+                            }
+                        } else {
+                            let parse = parse(ty);
+                            quote! {
+                                let #name: #ty = {
+                                    let raw = #raw
+                                        .ok_or_else(|| libnexus::NexusError::invalid_argument(format!(
+                                            "missing argument '{}' (expected {} args)",
+                                            stringify!(#name),
+                                            #num_required
+                                        )))?;
+                                    #raw_checks
+                                    let value = raw #parse?;
+                                    #value_check
+                                    value
+                                };
+                            }
                         }
                     })
                     .collect();
+                let param_default_strings: Vec<String> = param_defaults
+                    .iter()
+                    .map(|d| d.clone().unwrap_or_default())
+                    .collect();
+                let param_type_names: Vec<String> = param_types
+                    .iter()
+                    .map(|ty| quote!(#ty).to_string().replace(' ', ""))
+                    .collect();
 
-                match_arms.push(quote! {
-                    #method_name_str => {
-                        #(#param_extractions)*
-                        self.#method_name(#(#param_names),*).await
+                let call = callee(method_name);
+                if streaming && command_meta.blocking {
+                    let message = "streaming commands cannot be `blocking`; produce the lines from a blocking task instead";
+                    errors.push(syn::Error::new_spanned(method_name, message).to_compile_error());
+                }
+                if streaming && command_meta.cache.is_some() {
+                    let message = "streaming commands cannot be cached";
+                    errors.push(syn::Error::new_spanned(method_name, message).to_compile_error());
+                }
+                if interactive && (upload || download) {
+                    let message = "commands asking questions cannot transfer files: \
+                                   each runs through its own RPC";
+                    errors.push(syn::Error::new_spanned(method_name, message).to_compile_error());
+                }
+                if streaming {
+                    // Streaming commands are dispatched through `execute_stream`;
+                    // the method may be sync or async but must return a `'static` stream.
+                    let call_await = method.sig.asyncness.map(|_| quote! { .await });
+                    stream_arms.push(quote! {
+                        #method_name_str => Some(async {
+                            #bind
+                            #(#param_extractions)*
+                            #(#state_extractions)*
+                            let stream: libnexus::CommandStream =
+                                Box::pin(#call(#receiver, #(#call_args),*)#call_await);
+                            Ok::<_, anyhow::Error>(stream)
+                        }.await)
+                    });
+                } else {
+                    // A sync method may do blocking work, so it runs where that
+                    // does not hold up other requests. `#[command(blocking)]` moves
+                    // a clone of the service and the arguments to the blocking pool.
+                    let value = match (&method.sig.asyncness, command_meta.blocking) {
+                        (Some(asyncness), true) => {
+                            syn::Error::new_spanned(asyncness, "`blocking` commands must be sync `fn`s")
+                                .to_compile_error()
+                        }
+                        (Some(_), false) => quote! { #call(#receiver, #(#call_args),*).await },
+                        (None, false) => {
+                            quote! { libnexus::registry::blocking(|| #call(#receiver, #(#call_args),*)) }
+                        }
+                        (None, true) => {
+                            let moved: Vec<_> = (0..call_args.len()).map(|i| format_ident!("arg{}", i)).collect();
+                            // Reported at the method if the service is not `Clone`.
+                            let clone = quote_spanned! { method_name.span()=> Clone::clone(#receiver) };
+                            quote! {
+                                libnexus::registry::offload({
+                                    let moved = (#(#call_args,)*);
+                                    let service = #clone;
+                                    move || {
+                                        let (#(#moved,)*) = moved;
+                                        #call(&service, #(#moved),*)
+                                    }
+                                })
+                                .await?
+                            }
+                        }
+                    };
+                    let result = command_result(&method.sig.output, value);
+                    // Commands transferring files are run through `NexusClient::upload`
+                    // and `download`, and those asking questions through `session`,
+                    // so the typed client has no method for them.
+                    if service_meta.client && !upload && !download && !interactive {
+                        // Every value goes as its `--name` flag, except a variadic
+                        // parameter's, which are the positional arguments.
+                        let params = (0..param_names.len()).map(|i| {
+                            let name = &param_names[i];
+                            let ty = &param_types[i];
+                            if let Some(inner) = vec_inner(ty).filter(|_| param_variadics[i]) {
+                                let inner = client_param_type(inner);
+                                quote! { #name: &[#inner] }
+                            } else if param_optional[i] {
+                                let inner = client_param_type(option_inner(ty).unwrap_or(ty));
+                                quote! { #name: Option<#inner> }
+                            } else {
+                                let ty = client_param_type(ty);
+                                quote! { #name: #ty }
+                            }
+                        });
+                        let named_values = (0..param_names.len()).filter(|i| !param_variadics[*i]).map(|i| {
+                            let name = &param_names[i];
+                            let name_str = &param_name_strings[i];
+                            if param_optional[i] {
+                                quote! { (#name_str, #name.map(|value| value.to_string())) }
+                            } else {
+                                quote! { (#name_str, Some(#name.to_string())) }
+                            }
+                        });
+                        let positional = match param_variadics.iter().position(|variadic| *variadic) {
+                            Some(i) => {
+                                let name = &param_names[i];
+                                quote! { #name.iter().map(|value| value.to_string()).collect() }
+                            }
+                            None => quote! { Vec::new() },
+                        };
+                        let returns = client_return_type(&method.sig.output, background);
+                        let value = if background {
+                            quote! { response.job_id }
+                        } else {
+                            quote! { libnexus::client::FromResponse::from_response(&response) }
+                        };
+                        let docs = method.attrs.iter().filter(|attr| attr.path().is_ident("doc"));
+                        client_methods.push(quote! {
+                            #(#docs)*
+                            pub async fn #method_name(
+                                &self,
+                                #(#params),*
+                            ) -> Result<#returns, libnexus::ClientError> {
+                                let request = libnexus::proto::CommandRequest {
+                                    service: Self::SERVICE.to_string(),
+                                    action: #method_name_str.to_string(),
+                                    args: #positional,
+                                    named_args: [#(#named_values),*]
+                                        .into_iter()
+                                        .filter_map(|(name, value): (&str, Option<String>)| {
+                                            Some((name.to_string(), value?))
+                                        })
+                                        .collect(),
+                                    ..Default::default()
+                                };
+                                let response = self.nexus_client().execute_request(request).await?;
+                                Ok(#value)
+                            }
+                        });
                     }
-                });
+                    match_arms.push((
+                        method_name_str.clone(),
+                        quote! {
+                            #bind
+                            #(#param_extractions)*
+                            #(#state_extractions)*
+                            #result
+                        },
+                    ));
+                }
 
                 command_infos.push(quote! {
                     libnexus::CommandInfo {
@@ -161,21 +1299,38 @@ pub fn nexus_service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                             name: #param_name_strings.to_string(),
                             hint: #param_hints.to_string(),
                             completer: #param_completers.to_string(),
+                            completer_args: #param_completer_args,
                             description: #param_descriptions.to_string(),
+                            glob: #param_globs,
+                            optional: #param_optional,
+                            default: #param_default_strings.to_string(),
+                            type_name: #param_type_names.to_string(),
+                            secret: #param_secrets,
+                            choices: #param_choices,
+                            variadic: #param_variadics,
                         }),*],
                         description: #doc.to_string(),
+                        help: #help.to_string(),
+                        dry_run: #dry_run,
+                        priority: #priority,
+                        streaming: #streaming,
+                        permission: #permission.to_string(),
+                        timeout: #timeout,
+                        background: #background,
+                        progress: #progress,
+                        since: #since.to_string(),
+                        deprecated: #deprecated,
+                        readonly: #readonly,
+                        cache: #cache,
+                        lock: #lock.to_string(),
+                        try_lock: #try_lock,
+                        upload: #upload,
+                        download: #download,
+                        interactive: #interactive,
                     }
                 });
 
-                // Rebuild method without #[command] and #[arg] attributes.
-                let remaining_attrs = strip_command_attr(&method.attrs);
-                let vis = &method.vis;
-                let sig = strip_arg_attrs(&method.sig);
-                let block = &method.block;
-                cleaned_methods.push(quote! {
-                    #(#remaining_attrs)*
-                    #vis #sig #block
-                });
+                cleaned_methods.push(strip_method(method));
             } else {
                 // Non-command methods pass through unchanged.
                 cleaned_methods.push(quote! { #item });
@@ -185,12 +1340,141 @@ pub fn nexus_service(_attr: TokenStream, item: TokenStream) -> TokenStream {
         }
     }
 
+    // The block itself, without the `#[command]`, `#[arg]` and hook attributes.
+    let attrs = &input.attrs;
     let (impl_generics, _, where_clause) = input.generics.split_for_impl();
-
-    let expanded = quote! {
-        impl #impl_generics #self_ty #where_clause {
+    let trait_path = input.trait_.as_ref().map(|(_, path, for_token)| quote! { #path #for_token });
+    let block = quote! {
+        #(#attrs)*
+        impl #impl_generics #trait_path #self_ty #where_clause {
             #(#cleaned_methods)*
         }
+    };
+
+    // `#[nexus_service(client)]`: the typed client, with a method per
+    // non-streaming command. An extension's block adds its commands to the
+    // main block's client.
+    let client = match (service_meta.client, client_path(self_ty)) {
+        (false, _) => None,
+        (true, Err(e)) => Some(e.to_compile_error()),
+        (true, Ok(path)) if service_meta.extend => Some(quote! {
+            impl #path {
+                #(#client_methods)*
+            }
+        }),
+        (true, Ok(path)) => {
+            let client_name = &path.segments.last().expect("a path has a segment").ident;
+            let doc = format!(
+                "Typed client for the `{}` service: its commands as async methods taking their \
+                 arguments with their declared types. Streaming commands are not included.",
+                service_name
+            );
+            Some(quote! {
+                #[doc = #doc]
+                #[derive(Clone)]
+                pub struct #client_name {
+                    client: libnexus::NexusClient,
+                }
+
+                impl #client_name {
+                    /// The name of the service called.
+                    pub const SERVICE: &'static str = #service_name;
+
+                    /// Call the service through `client`.
+                    pub fn new(client: libnexus::NexusClient) -> Self {
+                        Self { client }
+                    }
+
+                    /// The connection the calls go through, e.g. to follow the
+                    /// jobs of background commands.
+                    pub fn nexus_client(&self) -> &libnexus::NexusClient {
+                        &self.client
+                    }
+
+                    #(#client_methods)*
+                }
+            })
+        }
+    };
+
+    if service_meta.extend {
+        let arms = match_arms.iter().map(|(name, body)| quote! { #name => Some(async { #body }.await) });
+        let expanded = quote! {
+            #block
+            #(#errors)*
+            #client
+
+            const _: () = {
+                struct Commands;
+
+                #[async_trait::async_trait]
+                impl libnexus::extension::Extension<#self_ty> for Commands {
+                    fn commands(&self) -> Vec<libnexus::CommandInfo> {
+                        vec![#(#command_infos),*]
+                    }
+
+                    #[allow(unused_variables)]
+                    async fn execute(
+                        &self,
+                        service: &#self_ty,
+                        ctx: &libnexus::Context,
+                        action: &str,
+                        args: libnexus::Args,
+                    ) -> Option<anyhow::Result<libnexus::CommandOutput>> {
+                        match action {
+                            #(#arms,)*
+                            _ => None,
+                        }
+                    }
+
+                    #[allow(unused_variables)]
+                    async fn execute_stream(
+                        &self,
+                        service: &#self_ty,
+                        ctx: &libnexus::Context,
+                        action: &str,
+                        args: libnexus::Args,
+                    ) -> Option<anyhow::Result<libnexus::CommandStream>> {
+                        match action {
+                            #(#stream_arms,)*
+                            _ => None,
+                        }
+                    }
+                }
+
+                // Run by the platform before `main`, like a C constructor.
+                #[used]
+                #[cfg_attr(
+                    any(
+                        target_os = "linux",
+                        target_os = "android",
+                        target_os = "freebsd",
+                        target_os = "netbsd",
+                        target_os = "openbsd",
+                        target_os = "dragonfly",
+                        target_os = "illumos",
+                        target_os = "solaris"
+                    ),
+                    unsafe(link_section = ".init_array")
+                )]
+                #[cfg_attr(target_vendor = "apple", unsafe(link_section = "__DATA,__mod_init_func"))]
+                #[cfg_attr(windows, unsafe(link_section = ".CRT$XCU"))]
+                static REGISTER: extern "C" fn() = {
+                    extern "C" fn register() {
+                        libnexus::extension::add::<#self_ty>(&Commands);
+                    }
+                    register
+                };
+            };
+        };
+        return TokenStream::from(expanded);
+    }
+
+    let match_arms = match_arms.iter().map(|(name, body)| quote! { #name => { #body } });
+    let expanded = quote! {
+        #block
+        #(#errors)*
+        #client
 
         #[async_trait::async_trait]
         impl libnexus::Service for #self_ty {
@@ -198,20 +1482,89 @@ pub fn nexus_service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 #service_name
             }
 
+            fn aliases(&self) -> &[&str] {
+                &[#(#service_aliases),*]
+            }
+
             fn description(&self) -> &str {
                 #service_doc
             }
 
             fn commands(&self) -> Vec<libnexus::CommandInfo> {
-                vec![#(#command_infos),*]
+                let mut commands = vec![#(#command_infos),*];
+                for extension in libnexus::extension::of::<Self>() {
+                    commands.extend(extension.commands());
+                }
+                commands
+            }
+
+            fn max_concurrency(&self) -> Option<usize> {
+                #max_concurrency
+            }
+
+            fn max_queue(&self) -> Option<usize> {
+                #max_queue
             }
 
-            async fn execute(&self, action: &str, args: Vec<String>) -> anyhow::Result<String> {
+            #[allow(unused_variables)]
+            async fn execute(
+                &self,
+                ctx: &libnexus::Context,
+                action: &str,
+                args: libnexus::Args,
+            ) -> anyhow::Result<libnexus::CommandOutput> {
                 match action {
                     #(#match_arms,)*
-                    _ => Err(anyhow::anyhow!("unknown command '{}'", action)),
+                    _ => {
+                        for extension in libnexus::extension::of::<Self>() {
+                            if let Some(result) = extension.execute(self, ctx, action, args.clone()).await {
+                                return result;
+                            }
+                        }
+                        Err(libnexus::NexusError::not_found(format!("unknown command '{}'", action)).into())
+                    }
+                }
+            }
+
+            #[allow(unused_variables)]
+            async fn execute_stream(
+                &self,
+                ctx: &libnexus::Context,
+                action: &str,
+                args: libnexus::Args,
+            ) -> Option<anyhow::Result<libnexus::CommandStream>> {
+                match action {
+                    #(#stream_arms,)*
+                    _ => {
+                        for extension in libnexus::extension::of::<Self>() {
+                            if let Some(result) = extension.execute_stream(self, ctx, action, args.clone()).await {
+                                return Some(result);
+                            }
+                        }
+                        None
+                    }
                 }
             }
+
+            #[allow(unused_variables)]
+            async fn on_start(&self, ctx: &libnexus::StartContext) -> anyhow::Result<()> {
+                #(#start_calls)*
+                Ok(())
+            }
+
+            async fn on_stop(&self) -> anyhow::Result<()> {
+                #(#stop_calls)*
+                Ok(())
+            }
+
+            async fn shutdown(&self) -> anyhow::Result<()> {
+                #(#shutdown_calls)*
+                Ok(())
+            }
+
+            #health_impl
+
+            #rollback_impl
         }
     };
 
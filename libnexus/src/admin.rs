@@ -0,0 +1,123 @@
+//! The built-in `admin` service (`NexusServer::with_admin`): the same
+//! operational commands in every daemon, to see how long it has been up,
+//! what it has run and who is connected, change its log level, put it in
+//! maintenance mode, and stop it.
+
+use crate::connections::Connections;
+use crate::metrics::Metrics;
+use crate::registry::{CommandOutput, Maintenance};
+use anyhow::Result;
+use nexus_derive::nexus_service;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+pub(crate) struct Admin {
+    pub(crate) started: Instant,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) connections: Arc<Connections>,
+    pub(crate) maintenance: Arc<Maintenance>,
+    /// Cancelled to shut the server down.
+    pub(crate) stop: CancellationToken,
+}
+
+/// Inspect and control the running daemon.
+#[nexus_service(name = "admin")]
+impl Admin {
+    /// Show how long the daemon has been running.
+    #[command]
+    async fn uptime(&self) -> Result<String> {
+        Ok(format!(
+            "Up {} (pid {}, libnexus {})",
+            format_age(self.started.elapsed()),
+            std::process::id(),
+            env!("CARGO_PKG_VERSION")
+        ))
+    }
+
+    /// Show how often each command has run and failed, and how long it takes.
+    #[command]
+    async fn stats(&self) -> Result<CommandOutput> {
+        let rows = self.metrics.totals().into_iter().map(|((service, command), stats)| {
+            let average = Duration::from_secs_f64(stats.seconds / stats.count.max(1) as f64);
+            [
+                format!("{} {}", service.replace('.', " "), command),
+                stats.count.to_string(),
+                stats.errors.to_string(),
+                format!("{:.1}ms", average.as_secs_f64() * 1000.0),
+            ]
+        });
+        Ok(CommandOutput::table(["command", "calls", "errors", "average"], rows))
+    }
+
+    /// List the open client connections.
+    #[command]
+    async fn connections(&self) -> Result<CommandOutput> {
+        let rows = self.connections.list().into_iter().map(|connection| {
+            [connection.endpoint, connection.peer, format_age(connection.since.elapsed())]
+        });
+        Ok(CommandOutput::table(["endpoint", "peer", "connected for"], rows))
+    }
+
+    /// Show the log level, or change it until the daemon restarts.
+    #[command(permission = "admin")]
+    async fn loglevel(
+        &self,
+        #[arg(doc = "New level", choices("error", "warn", "info", "debug", "trace"))] level: Option<String>,
+    ) -> Result<String> {
+        let Some(level) = level else {
+            return match crate::trace::max_level() {
+                Some(level) => Ok(format!("Log level: {}", level.as_str().to_lowercase())),
+                None => Ok("Log level: set by the daemon's own tracing subscriber".to_string()),
+            };
+        };
+        crate::trace::set_max_level(level.parse()?)?;
+        tracing::info!(level = %level, "log level changed");
+        Ok(format!("Log level set to {}", level))
+    }
+
+    /// Show maintenance mode, or turn it on or off. While it is on, only
+    /// read-only commands run; the others fail with the message.
+    #[command(permission = "admin")]
+    async fn maintenance(
+        &self,
+        #[arg(doc = "Turn maintenance mode on or off", choices("on", "off"))] mode: Option<String>,
+        #[arg(doc = "Message for the callers turned away, e.g. the reason")] message: Option<String>,
+    ) -> Result<String> {
+        match mode.as_deref() {
+            None => match self.maintenance.get() {
+                Some(message) if message.is_empty() => Ok("Maintenance mode: on".to_string()),
+                Some(message) => Ok(format!("Maintenance mode: on ({})", message)),
+                None => Ok("Maintenance mode: off".to_string()),
+            },
+            Some("on") => {
+                self.maintenance.set(Some(message.unwrap_or_default()));
+                Ok("Maintenance mode on; only read-only commands run".to_string())
+            }
+            _ => {
+                self.maintenance.set(None);
+                Ok("Maintenance mode off".to_string())
+            }
+        }
+    }
+
+    /// Stop the daemon gracefully, letting in-flight commands finish.
+    #[command(permission = "admin")]
+    async fn shutdown(&self) -> Result<String> {
+        tracing::info!("shutdown requested");
+        self.stop.cancel();
+        Ok("Shutting down".to_string())
+    }
+}
+
+/// A duration in whole seconds, e.g. "2d 3h 4m 5s", "3m 0s" or "12s".
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let parts = [(secs / 86_400, "d"), (secs / 3_600 % 24, "h"), (secs / 60 % 60, "m"), (secs % 60, "s")];
+    let first = parts.iter().position(|(n, _)| *n > 0).unwrap_or(parts.len() - 1);
+    parts[first..]
+        .iter()
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
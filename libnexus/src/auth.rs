@@ -0,0 +1,210 @@
+use crate::context::Context;
+use crate::connections::Connections;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Metadata key carrying the caller's credentials (`Bearer <token>`).
+pub const AUTHORIZATION_KEY: &str = "authorization";
+
+/// Validates request tokens and decides which permissions a role holds.
+///
+/// Install one with `NexusServer::with_auth`. Commands annotated
+/// `#[command(permission = "...")]` then only run for callers whose role
+/// grants that permission; unannotated commands stay open to everyone.
+pub trait Authenticator: Send + Sync + 'static {
+    /// Map a bearer token to the caller's role, or `None` if the token is not
+    /// valid. `tenant` is the tenant the request acts for, if any.
+    fn authenticate(&self, token: &str, tenant: Option<&str>) -> Option<String>;
+
+    /// Map the local user at the other end of a Unix socket to a role, for
+    /// requests that carry no token. `None` (the default) leaves them
+    /// anonymous.
+    fn authenticate_peer(&self, peer: &PeerCredentials, tenant: Option<&str>) -> Option<String> {
+        let _ = (peer, tenant);
+        None
+    }
+
+    /// Whether `role` may run commands requiring `permission`. By default a
+    /// role grants only the permission of the same name.
+    fn allows(&self, role: &str, permission: &str) -> bool {
+        role == permission
+    }
+}
+
+/// The process on the other end of a Unix socket connection, as reported by
+/// the kernel (`SO_PEERCRED`), so it cannot be forged by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub uid: u32,
+    /// The primary group; supplementary groups are not reported.
+    pub gid: u32,
+    pub pid: Option<i32>,
+}
+
+/// Authenticator backed by a fixed table of tokens and, for local callers
+/// on a Unix socket, of user and group IDs.
+///
+/// ```ignore
+/// let auth = TokenAuth::new()
+///     .token("s3cret", "admin")
+///     .token_for_tenant("acme", "acme-ops", "operator")
+///     .peer_uid(0, "admin")
+///     .grant("admin", "operator");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TokenAuth {
+    /// token -> role, valid for any tenant
+    tokens: HashMap<String, String>,
+    /// (tenant, token) -> role
+    tenant_tokens: HashMap<(String, String), String>,
+    /// peer uid -> role
+    uids: HashMap<u32, String>,
+    /// peer primary gid -> role
+    gids: HashMap<u32, String>,
+    /// role -> extra permissions beyond its own name
+    grants: HashMap<String, HashSet<String>>,
+}
+
+impl TokenAuth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept `token` as `role` for requests from any tenant.
+    pub fn token(mut self, token: &str, role: &str) -> Self {
+        self.tokens.insert(token.to_string(), role.to_string());
+        self
+    }
+
+    /// Accept `token` as `role` only for requests acting for `tenant`.
+    pub fn token_for_tenant(mut self, tenant: &str, token: &str, role: &str) -> Self {
+        self.tenant_tokens
+            .insert((tenant.to_string(), token.to_string()), role.to_string());
+        self
+    }
+
+    /// Give tokenless callers on a Unix socket running as user `uid` the
+    /// role `role`.
+    pub fn peer_uid(mut self, uid: u32, role: &str) -> Self {
+        self.uids.insert(uid, role.to_string());
+        self
+    }
+
+    /// Give tokenless callers on a Unix socket whose primary group is `gid`
+    /// the role `role`. A role for the caller's user takes precedence.
+    pub fn peer_gid(mut self, gid: u32, role: &str) -> Self {
+        self.gids.insert(gid, role.to_string());
+        self
+    }
+
+    /// Let `role` also run commands requiring `permission`.
+    pub fn grant(mut self, role: &str, permission: &str) -> Self {
+        self.grants
+            .entry(role.to_string())
+            .or_default()
+            .insert(permission.to_string());
+        self
+    }
+}
+
+impl Authenticator for TokenAuth {
+    fn authenticate(&self, token: &str, tenant: Option<&str>) -> Option<String> {
+        tenant
+            .and_then(|tenant| {
+                self.tenant_tokens
+                    .get(&(tenant.to_string(), token.to_string()))
+            })
+            .or_else(|| self.tokens.get(token))
+            .cloned()
+    }
+
+    fn authenticate_peer(&self, peer: &PeerCredentials, _tenant: Option<&str>) -> Option<String> {
+        self.uids.get(&peer.uid).or_else(|| self.gids.get(&peer.gid)).cloned()
+    }
+
+    fn allows(&self, role: &str, permission: &str) -> bool {
+        role == permission
+            || self
+                .grants
+                .get(role)
+                .is_some_and(|grants| grants.contains(permission))
+    }
+}
+
+/// Role of an authenticated caller, stored in the request extensions.
+#[derive(Debug, Clone)]
+pub(crate) struct CallerRole(pub(crate) String);
+
+/// Server interceptor that validates the bearer token of every request.
+/// Requests without a token are authenticated by their peer credentials if
+/// they arrived on a Unix socket, and otherwise pass through anonymously; an
+/// invalid token is rejected with `UNAUTHENTICATED`. The peer credentials
+/// are stored in the request extensions either way, as is the connection
+/// the request arrived on (`ConnectionRef`).
+#[derive(Clone)]
+pub(crate) struct AuthInterceptor {
+    pub(crate) auth: Option<Arc<dyn Authenticator>>,
+    /// The server's connections, in which the request's is looked up.
+    pub(crate) connections: Arc<Connections>,
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        self.connections.identify(request.extensions_mut());
+        let peer = peer_credentials(&request);
+        if let Some(peer) = peer {
+            request.extensions_mut().insert(peer);
+        }
+        let Some(auth) = &self.auth else {
+            return Ok(request);
+        };
+        let Some(header) = request.metadata().get(AUTHORIZATION_KEY) else {
+            let tenant = Context::tenant_from_metadata(request.metadata());
+            if let Some(role) = peer.and_then(|peer| auth.authenticate_peer(&peer, tenant.as_deref())) {
+                request.extensions_mut().insert(CallerRole(role));
+            }
+            return Ok(request);
+        };
+        let token = header
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| {
+                tracing::warn!("rejected request: malformed authorization header");
+                Status::unauthenticated("malformed authorization header")
+            })?;
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        let role = auth.authenticate(token, tenant.as_deref()).ok_or_else(|| {
+            tracing::warn!(
+                tenant = tenant.as_deref().unwrap_or("-"),
+                "rejected request: invalid token"
+            );
+            Status::unauthenticated("invalid token")
+        })?;
+        request.extensions_mut().insert(CallerRole(role));
+        Ok(request)
+    }
+}
+
+/// Credentials of the peer, if the request arrived on a Unix socket.
+fn peer_credentials<T>(request: &Request<T>) -> Option<PeerCredentials> {
+    #[cfg(unix)]
+    {
+        let cred = request
+            .extensions()
+            .get::<tonic::transport::server::UdsConnectInfo>()?
+            .peer_cred?;
+        Some(PeerCredentials {
+            uid: cred.uid(),
+            gid: cred.gid(),
+            pid: cred.pid(),
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = request;
+        None
+    }
+}
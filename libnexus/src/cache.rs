@@ -0,0 +1,182 @@
+//! Cached results of read-only commands (`#[command(readonly, cache =
+//! "5s")]`): completers and watch mode run `block list` over and over, and
+//! within the TTL the same arguments get the first output back without
+//! the command running again, if the caller has the same tenant, role and
+//! preferences, which may shape the output. A successful command of the same service
+//! that is not read-only may have changed what they show, so it drops the
+//! service's entries.
+
+use crate::context::{Context, Preferences};
+use crate::registry::{Args, CommandOutput};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many results are kept at once; past that the oldest are dropped.
+pub(crate) const MAX_CACHED_RESULTS: usize = 1_000;
+
+/// Whose result it is: the caller's tenant, role and preferences, the
+/// service, the command and its arguments, flags sorted by name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Key {
+    tenant: Option<String>,
+    role: Option<String>,
+    preferences: Preferences,
+    service: String,
+    action: String,
+    positional: Vec<String>,
+    named: Vec<(String, String)>,
+}
+
+/// The key of `action` of the service run with `ctx` and `args`.
+pub(crate) fn key(ctx: &Context, service: &str, action: &str, args: &Args) -> Key {
+    let mut named: Vec<_> = args.named.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    named.sort();
+    Key {
+        tenant: ctx.tenant.clone(),
+        role: ctx.role.clone(),
+        preferences: ctx.preferences.clone(),
+        service: service.to_string(),
+        action: action.to_string(),
+        positional: args.positional.clone(),
+        named,
+    }
+}
+
+struct Entry {
+    output: CommandOutput,
+    /// Warnings the command recorded, given again with every hit.
+    warnings: Vec<String>,
+    expires: Instant,
+}
+
+#[derive(Default)]
+pub(crate) struct ResultCache {
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl ResultCache {
+    /// The cached output and warnings of the command `key` names, if it
+    /// ran less than its TTL ago.
+    pub(crate) fn get(&self, key: &Key) -> Option<(CommandOutput, Vec<String>)> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.expires > Instant::now() => Some((entry.output.clone(), entry.warnings.clone())),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Keep the result of a successful run for `ttl`.
+    pub(crate) fn insert(&self, key: Key, output: CommandOutput, warnings: Vec<String>, ttl: Duration) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED_RESULTS {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= MAX_CACHED_RESULTS {
+                let oldest = entries.iter().min_by_key(|(_, entry)| entry.expires).map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key, Entry { output, warnings, expires: now + ttl });
+    }
+
+    /// Drop the results of the service's commands, for every caller.
+    pub(crate) fn invalidate(&self, service: &str) {
+        self.entries.lock().unwrap().retain(|key, _| key.service != service);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached(cache: &ResultCache, ctx: &Context) -> Option<CommandOutput> {
+        cache.get(&key(ctx, "pool", "list", &Args::new(Vec::new()))).map(|(output, _)| output)
+    }
+
+    fn insert(cache: &ResultCache, service: &str, text: &str, ttl: Duration) {
+        let key = key(&Context::new(), service, "list", &Args::new(Vec::new()));
+        cache.insert(key, CommandOutput::Text(text.to_string()), vec!["degraded".to_string()], ttl);
+    }
+
+    #[test]
+    fn hits_return_the_output_and_warnings() {
+        let cache = ResultCache::default();
+        insert(&cache, "pool", "tank", Duration::from_secs(60));
+        let (output, warnings) = cache.get(&key(&Context::new(), "pool", "list", &Args::new(Vec::new()))).unwrap();
+        assert!(matches!(output, CommandOutput::Text(text) if text == "tank"));
+        assert_eq!(warnings, ["degraded"]);
+    }
+
+    #[test]
+    fn arguments_are_part_of_the_key_whatever_their_order() {
+        let named = |flags: &[(&str, &str)]| Args {
+            positional: vec!["p0".to_string()],
+            named: flags.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect(),
+        };
+        let ctx = Context::new();
+        assert_eq!(
+            key(&ctx, "volume", "list", &named(&[("a", "1"), ("b", "2")])),
+            key(&ctx, "volume", "list", &named(&[("b", "2"), ("a", "1")]))
+        );
+        assert_ne!(
+            key(&ctx, "volume", "list", &named(&[("a", "1")])),
+            key(&ctx, "volume", "list", &named(&[("a", "2")]))
+        );
+        assert_ne!(key(&ctx, "volume", "list", &named(&[])), key(&ctx, "volume", "show", &named(&[])));
+    }
+
+    #[test]
+    fn results_expire_after_their_ttl() {
+        let cache = ResultCache::default();
+        insert(&cache, "pool", "tank", Duration::ZERO);
+        assert!(cached(&cache, &Context::new()).is_none());
+        // The expired entry is dropped when looked up.
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalidating_a_service_drops_only_its_results() {
+        let cache = ResultCache::default();
+        insert(&cache, "pool", "tank", Duration::from_secs(60));
+        insert(&cache, "volume", "vol1", Duration::from_secs(60));
+        cache.invalidate("pool");
+        assert!(cached(&cache, &Context::new()).is_none());
+        assert!(cache.get(&key(&Context::new(), "volume", "list", &Args::new(Vec::new()))).is_some());
+    }
+
+    #[test]
+    fn the_oldest_results_make_room() {
+        let cache = ResultCache::default();
+        for n in 0..MAX_CACHED_RESULTS {
+            insert(&cache, &format!("service{}", n), "", Duration::from_secs(60 + n as u64));
+        }
+        insert(&cache, "pool", "tank", Duration::from_secs(60));
+        let entries = cache.entries.lock().unwrap();
+        assert_eq!(entries.len(), MAX_CACHED_RESULTS);
+        assert!(!entries.keys().any(|key| key.service == "service0"));
+    }
+
+    #[test]
+    fn results_are_kept_per_role_and_preferences() {
+        let cache = ResultCache::default();
+        let admin = Context::new().with_role(Some("admin".to_string()));
+        let output = CommandOutput::Text("tank".to_string());
+        cache.insert(key(&admin, "pool", "list", &Args::new(Vec::new())), output, Vec::new(), Duration::from_secs(60));
+        assert!(cached(&cache, &admin).is_some());
+
+        assert!(cached(&cache, &Context::new()).is_none());
+        assert!(cached(&cache, &Context::new().with_role(Some("viewer".to_string()))).is_none());
+        let admin = || Context::new().with_role(Some("admin".to_string()));
+        let json = admin().with_preferences(Preferences::new().format("json"));
+        assert!(cached(&cache, &json).is_none());
+        let tenant = admin().with_tenant(Some("acme".to_string()));
+        assert!(cached(&cache, &tenant).is_none());
+    }
+}
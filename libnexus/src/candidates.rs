@@ -0,0 +1,170 @@
+//! Values the interactive shell offers for arguments on <Tab>: what
+//! completer commands return and the caller's job IDs.
+//!
+//! A background task on the shell's runtime fetches them. The synchronous
+//! completer asks for values over a channel and waits at most
+//! `COMPLETION_TIMEOUT` for ones it has never seen; values already fetched
+//! are answered from memory straight away. Past `CACHE_TTL` they are still
+//! offered while the task refreshes them, and values being fetched are not
+//! asked for again however fast <Tab> is pressed.
+//!
+//! The completer blocks the thread reading the line while it waits, so the
+//! task needs another worker: the runtime has to be multi-threaded.
+
+use crate::cli::{response_output, Client};
+use crate::completion::{CACHE_TTL, COMPLETION_TIMEOUT};
+use crate::proto::{CommandRequest, JobListRequest, JobState};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How long a fetch may take before it is given up, so that the values can
+/// be asked for again.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Values to fetch.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Lookup {
+    /// What a completer command returns when passed `args`.
+    Values {
+        service: String,
+        action: String,
+        args: Vec<String>,
+    },
+    /// The IDs of the caller's jobs, only running ones if `running`.
+    Jobs { running: bool },
+}
+
+impl Lookup {
+    /// The values a completer returns for `request` (see
+    /// `completer_request`).
+    pub(crate) fn values(request: CommandRequest) -> Self {
+        Lookup::Values {
+            service: request.service,
+            action: request.action,
+            args: request.args,
+        }
+    }
+}
+
+enum Message {
+    Fetch(Lookup),
+    /// Fetch from this client from now on, e.g. after reconnecting.
+    Connect(Box<Client>),
+}
+
+struct Entry {
+    values: Vec<String>,
+    fetched: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Lookup, Entry>,
+    /// Lookups the task is fetching.
+    pending: HashSet<Lookup>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Notified whenever a fetch ends.
+    fetched: Condvar,
+}
+
+/// The shell's side of the task; dropping it ends the task.
+pub(crate) struct Candidates {
+    shared: Arc<Shared>,
+    requests: mpsc::UnboundedSender<Message>,
+}
+
+impl Candidates {
+    /// Start the task, fetching with `client`. Must be called on the
+    /// shell's runtime.
+    pub(crate) fn spawn(client: Client) -> Self {
+        let shared = Arc::new(Shared::default());
+        let (requests, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run(client, receiver, shared.clone()));
+        Self { shared, requests }
+    }
+
+    /// Fetch with `client` from now on, forgetting the values fetched so far.
+    pub(crate) fn connect(&self, client: Client) {
+        self.shared.state.lock().unwrap().entries.clear();
+        let _ = self.requests.send(Message::Connect(Box::new(client)));
+    }
+
+    /// The values of `lookup`: those in memory, refreshed in the background
+    /// once stale, or else the ones fetched within `COMPLETION_TIMEOUT`.
+    pub(crate) fn get(&self, lookup: Lookup) -> Vec<String> {
+        let mut state = self.shared.state.lock().unwrap();
+        let fresh = state.entries.get(&lookup).is_some_and(|e| e.fetched.elapsed() < CACHE_TTL);
+        if !fresh
+            && state.pending.insert(lookup.clone())
+            && self.requests.send(Message::Fetch(lookup.clone())).is_err()
+        {
+            state.pending.remove(&lookup);
+        }
+        let deadline = Instant::now() + COMPLETION_TIMEOUT;
+        while !state.entries.contains_key(&lookup) && state.pending.contains(&lookup) {
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            state = self.shared.fetched.wait_timeout(state, left).unwrap().0;
+        }
+        state.entries.get(&lookup).map(|e| e.values.clone()).unwrap_or_default()
+    }
+}
+
+/// Serve the requests of `Candidates` until it is dropped, each fetch on its
+/// own task so a slow completer holds up no other.
+async fn run(mut client: Client, mut requests: mpsc::UnboundedReceiver<Message>, shared: Arc<Shared>) {
+    while let Some(message) = requests.recv().await {
+        match message {
+            Message::Connect(new) => client = *new,
+            Message::Fetch(lookup) => {
+                let client = client.clone();
+                let shared = shared.clone();
+                tokio::spawn(async move {
+                    let values = tokio::time::timeout(FETCH_TIMEOUT, fetch(client, &lookup)).await.ok().flatten();
+                    let mut state = shared.state.lock().unwrap();
+                    state.pending.remove(&lookup);
+                    if let Some(values) = values {
+                        let fetched = Instant::now();
+                        state.entries.insert(lookup, Entry { values, fetched });
+                    }
+                    shared.fetched.notify_all();
+                });
+            }
+        }
+    }
+}
+
+/// Ask the server for the values of `lookup`; `None` if it failed.
+async fn fetch(mut client: Client, lookup: &Lookup) -> Option<Vec<String>> {
+    match lookup {
+        Lookup::Values { service, action, args } => {
+            let request = CommandRequest {
+                service: service.clone(),
+                action: action.clone(),
+                args: args.clone(),
+                ..Default::default()
+            };
+            let response = client.execute(request).await.ok()?.into_inner();
+            if !response.success {
+                return None;
+            }
+            Some(response_output(&response).values())
+        }
+        Lookup::Jobs { running } => {
+            let jobs = client.job_list(JobListRequest {}).await.ok()?.into_inner().jobs;
+            let ids = jobs
+                .iter()
+                .filter(|job| !running || job.state() == JobState::Running)
+                .map(|job| job.id.to_string())
+                .collect();
+            Some(ids)
+        }
+    }
+}
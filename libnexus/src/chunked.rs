@@ -0,0 +1,262 @@
+//! Responses too large for one gRPC message. A response larger than the
+//! server's limit (`NexusServer::with_max_message_size`) is kept on the
+//! server for a while and replaced by a stub naming it in its `chunked`
+//! output; the client fetches it with the `ReadResponse`
+//! RPC, which streams its encoding in chunks, and decodes it again.
+//! `NexusClient` and the CLI do so for every response they receive, so
+//! their callers only ever see the full one.
+//!
+//! Both ends also compress messages (`COMPRESSION`), so a response rarely
+//! gets that large on the wire; the limit is on the uncompressed size.
+
+use crate::cli::Client;
+use crate::error::ErrorCode;
+use crate::proto::command_response::Output;
+use crate::proto::{ChunkedResponse, CommandResponse, JobInfo, ReadResponseRequest, RecentResult};
+use crate::server::error_code_to_proto;
+use crate::transfer::CHUNK_SIZE;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tonic::codec::CompressionEncoding;
+use tonic::Status;
+
+/// The default for `NexusServer::with_max_message_size`: 4 MiB, the most
+/// a tonic client accepts unless told otherwise.
+pub(crate) const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// The encodings servers and clients compress messages with, the
+/// preferred first: those of the `gzip` (on by default) and `zstd`
+/// features.
+pub(crate) const COMPRESSION: &[CompressionEncoding] = &[
+    #[cfg(feature = "zstd")]
+    CompressionEncoding::Zstd,
+    #[cfg(feature = "gzip")]
+    CompressionEncoding::Gzip,
+];
+
+/// How long a kept response waits to be read.
+const KEEP_FOR: Duration = Duration::from_secs(60);
+
+/// The most bytes of responses kept at once; the oldest are dropped to
+/// make room, and a single response larger than this fails instead.
+pub(crate) const MAX_KEPT_BYTES: usize = 256 * 1024 * 1024;
+
+/// The most a client reserves up front for a response read in chunks,
+/// whatever size the stub announces; the rest grows as chunks arrive.
+const MAX_PREALLOCATION: usize = 16 * CHUNK_SIZE;
+
+/// A response waiting to be read, encoded.
+struct Kept {
+    tenant: Option<String>,
+    data: Vec<u8>,
+    expires: Instant,
+}
+
+/// The responses too large to send that wait for `ReadResponse`.
+pub(crate) struct LargeResponses {
+    limit: usize,
+    next_id: AtomicU64,
+    kept: Mutex<HashMap<u64, Kept>>,
+}
+
+impl LargeResponses {
+    /// Keep the responses whose encoding is larger than `limit` bytes.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            next_id: AtomicU64::new(1),
+            kept: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `response` as sent to `tenant`: as is if it fits in a message, else
+    /// the stub for reading it, or an error if it is too large to keep
+    /// (`MAX_KEPT_BYTES`).
+    pub(crate) fn fit(&self, tenant: Option<&str>, response: CommandResponse) -> CommandResponse {
+        let size = response.encoded_len();
+        if size <= self.limit {
+            return response;
+        }
+        if size > MAX_KEPT_BYTES {
+            tracing::warn!(size, "response too large to send");
+            return CommandResponse {
+                success: false,
+                message: format!(
+                    "the response of {} bytes is larger than the {} bytes the server sends",
+                    size, MAX_KEPT_BYTES
+                ),
+                error_code: error_code_to_proto(ErrorCode::ResourceExhausted) as i32,
+                services_revision: response.services_revision,
+                job_id: response.job_id,
+                ..Default::default()
+            };
+        }
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let stub = CommandResponse {
+            success: response.success,
+            message: format!("(a response of {} bytes, sent in chunks; read it with ReadResponse)", size),
+            busy: response.busy,
+            services_revision: response.services_revision,
+            error_code: response.error_code,
+            job_id: response.job_id,
+            replayed: response.replayed,
+            output: Some(Output::Chunked(ChunkedResponse { id, size: size as u64 })),
+            ..Default::default()
+        };
+        let now = Instant::now();
+        let mut kept = self.kept.lock().unwrap();
+        kept.retain(|_, kept| kept.expires > now);
+        let mut total: usize = kept.values().map(|kept| kept.data.len()).sum();
+        while total + size > MAX_KEPT_BYTES {
+            let Some((&oldest, _)) = kept.iter().min_by_key(|(_, kept)| kept.expires) else {
+                break;
+            };
+            let dropped = kept.remove(&oldest).map_or(0, |kept| kept.data.len());
+            tracing::debug!(id = oldest, size = dropped, "kept response dropped to make room");
+            total -= dropped;
+        }
+        kept.insert(
+            id,
+            Kept {
+                tenant: tenant.map(str::to_string),
+                data: response.encode_to_vec(),
+                expires: now + KEEP_FOR,
+            },
+        );
+        tracing::debug!(id, size, "response kept for reading in chunks");
+        stub
+    }
+
+    /// Take the response kept as `id` for `tenant`, in chunks, or `None`
+    /// if there is none (read already, expired, or another tenant's).
+    pub(crate) fn take(&self, tenant: Option<&str>, id: u64) -> Option<Vec<Vec<u8>>> {
+        let mut kept = self.kept.lock().unwrap();
+        if !kept.get(&id).is_some_and(|kept| kept.tenant.as_deref() == tenant && kept.expires > Instant::now()) {
+            return None;
+        }
+        let data = kept.remove(&id)?.data;
+        Some(data.chunks(CHUNK_SIZE).map(<[u8]>::to_vec).collect())
+    }
+}
+
+/// The full response for `response`: read with `ReadResponse` if it is a
+/// stub, else `response` itself.
+pub(crate) async fn complete(client: &mut Client, response: CommandResponse) -> Result<CommandResponse, Status> {
+    let Some(Output::Chunked(ChunkedResponse { id, size })) = response.output else {
+        return Ok(response);
+    };
+    let mut chunks = client.read_response(ReadResponseRequest { id }).await?.into_inner();
+    // The size is the server's word; it does not decide what is reserved.
+    let mut data = Vec::with_capacity(size.min(MAX_PREALLOCATION as u64) as usize);
+    while let Some(chunk) = chunks.message().await? {
+        data.extend_from_slice(&chunk.data);
+        if data.len() as u64 > size {
+            return Err(Status::data_loss(format!("the response is longer than the {} bytes announced", size)));
+        }
+    }
+    if data.len() as u64 != size {
+        let message = format!("the response ended after {} of {} bytes", data.len(), size);
+        return Err(Status::data_loss(message));
+    }
+    CommandResponse::decode(data.as_slice()).map_err(|e| Status::internal(format!("invalid response: {}", e)))
+}
+
+/// `job` with its full result.
+pub(crate) async fn complete_job(client: &mut Client, mut job: JobInfo) -> Result<JobInfo, Status> {
+    if let Some(result) = job.result.take() {
+        job.result = Some(complete(client, result).await?);
+    }
+    Ok(job)
+}
+
+/// `recent` with its full result.
+pub(crate) async fn complete_recent(
+    client: &mut Client,
+    mut recent: RecentResult,
+) -> Result<RecentResult, Status> {
+    if let Some(result) = recent.result.take() {
+        recent.result = Some(complete(client, result).await?);
+    }
+    Ok(recent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(len: usize) -> CommandResponse {
+        CommandResponse {
+            success: true,
+            job_id: 7,
+            output: Some(Output::Text("x".repeat(len))),
+            ..Default::default()
+        }
+    }
+
+    fn stub_id(stub: &CommandResponse) -> u64 {
+        match stub.output {
+            Some(Output::Chunked(ChunkedResponse { id, size })) => {
+                assert!(size as usize > CHUNK_SIZE);
+                id
+            }
+            ref other => panic!("expected a chunked stub, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn small_responses_are_sent_as_they_are() {
+        let large = LargeResponses::new(1024);
+        let response = text(100);
+        assert_eq!(large.fit(None, response.clone()), response);
+        assert!(large.kept.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn large_responses_round_trip_in_chunks() {
+        let large = LargeResponses::new(1024);
+        let response = text(CHUNK_SIZE * 2 + 17);
+        let stub = large.fit(Some("acme"), response.clone());
+        assert!(stub.success);
+        assert_eq!(stub.job_id, 7);
+        let id = stub_id(&stub);
+
+        // Only the tenant it was sent to may read it.
+        assert!(large.take(None, id).is_none());
+        assert!(large.take(Some("other"), id).is_none());
+        let chunks = large.take(Some("acme"), id).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= CHUNK_SIZE));
+        let data = chunks.concat();
+        assert_eq!(CommandResponse::decode(data.as_slice()).unwrap(), response);
+
+        // It is read once.
+        assert!(large.take(Some("acme"), id).is_none());
+    }
+
+    #[test]
+    fn the_oldest_kept_responses_make_room() {
+        let large = LargeResponses::new(1024);
+        let size = MAX_KEPT_BYTES / 3;
+        let first = stub_id(&large.fit(None, text(size)));
+        let second = stub_id(&large.fit(None, text(size)));
+        let third = stub_id(&large.fit(None, text(size)));
+
+        assert!(large.take(None, first).is_none());
+        assert!(large.take(None, second).is_some());
+        assert!(large.take(None, third).is_some());
+    }
+
+    #[test]
+    fn responses_too_large_to_keep_fail() {
+        let large = LargeResponses::new(1024);
+        let failure = large.fit(None, text(MAX_KEPT_BYTES));
+        assert!(!failure.success);
+        assert_eq!(failure.error_code, error_code_to_proto(ErrorCode::ResourceExhausted) as i32);
+        assert_eq!(failure.job_id, 7);
+        assert!(failure.output.is_none());
+        assert!(large.kept.lock().unwrap().is_empty());
+    }
+}
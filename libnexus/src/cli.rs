@@ -1,16 +1,248 @@
+use crate::auth::AUTHORIZATION_KEY;
+use crate::candidates::{Candidates, Lookup};
+use crate::chunked::{self, COMPRESSION};
+use crate::config::{self, CliConfig, OutputFormat};
+use crate::context::{Preferences, TENANT_KEY};
+use crate::endpoint::Endpoint;
+use crate::error::ErrorCode;
+#[cfg(feature = "tls")]
+use crate::endpoint::Keepalive;
+use crate::favorites::{self, Favorites};
+use crate::interceptor::{ClientInterceptor, Intercepted, Interceptors};
+use crate::manual;
+use crate::openapi;
+use crate::tokenize::{self, Link};
+use crate::registry::{format_duration, CommandOutput};
+use crate::server::{error_code_from_proto, AbortOnDrop};
+use crate::session::{self, Prompt, SessionService};
+use crate::theme::{Style, Theme};
+use crate::transfer;
+use crate::proto::command_response::Output;
 use crate::proto::nexus_service_client::NexusServiceClient;
-use crate::proto::{ArgDef, CommandRequest, ListServicesRequest, ServiceInfo};
-use hyper_util::rt::TokioIo;
+use crate::proto::stream_frame::Frame;
+use crate::proto::{
+    ArgDef, CommandDef, CommandRequest, CommandResponse, EventMessage, HealthRequest, JobInfo, JobListRequest,
+    JobRequest, JobState, ListServicesRequest, ListServicesResponse, PlanChange, ProgressUpdate,
+    RecentRequest, RecentResult, ServiceInfo, SubscribeRequest,
+};
+use async_trait::async_trait;
 use rustyline::completion::{Completer, Pair};
 use rustyline::error::ReadlineError;
-use rustyline::highlight::Highlighter;
+use rustyline::highlight::{CmdKind, Highlighter};
 use rustyline::hint::{Hint, Hinter};
 use rustyline::validate::Validator;
-use rustyline::{Context, Editor, Helper};
-use std::collections::HashMap;
-use tokio::net::UnixStream;
-use tonic::transport::{Channel, Endpoint};
-use tower::service_fn;
+use rustyline::history::DefaultHistory;
+use rustyline::{Cmd, Config, Context, Editor, Helper, KeyCode, KeyEvent, Modifiers};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write as _};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::{InterceptedService, Interceptor};
+use tonic::transport::Channel;
+
+/// Attaches the session's preferences, tenant, and token as metadata to
+/// every outgoing request.
+#[derive(Clone)]
+pub(crate) struct SessionInterceptor {
+    pub(crate) preferences: Preferences,
+    pub(crate) tenant: Option<String>,
+    pub(crate) token: Option<String>,
+}
+
+impl Interceptor for SessionInterceptor {
+    fn call(&mut self, mut request: tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> {
+        self.preferences.apply_to(request.metadata_mut());
+        if let Some(tenant) = &self.tenant {
+            if let Ok(value) = MetadataValue::try_from(tenant.as_str()) {
+                request.metadata_mut().insert(TENANT_KEY, value);
+            }
+        }
+        if let Some(token) = &self.token {
+            if let Ok(value) = MetadataValue::try_from(format!("Bearer {}", token)) {
+                request.metadata_mut().insert(AUTHORIZATION_KEY, value);
+            }
+        }
+        Ok(request)
+    }
+}
+
+pub(crate) type Client = NexusServiceClient<InterceptedService<Intercepted, SessionInterceptor>>;
+
+/// A client on `channel` accepting compressed responses, and responses of
+/// any size: the server decides how large a message may be, and sends
+/// larger responses in chunks.
+pub(crate) fn new_client(channel: Intercepted, session: SessionInterceptor) -> Client {
+    let mut client = NexusServiceClient::with_interceptor(channel, session).max_decoding_message_size(usize::MAX);
+    for &encoding in COMPRESSION {
+        client = client.accept_compressed(encoding);
+    }
+    client
+}
+
+/// Everything needed to open a client: address, session metadata,
+/// interceptors and TLS.
+#[derive(Clone)]
+pub(crate) struct Connection {
+    pub(crate) addr: String,
+    pub(crate) session: SessionInterceptor,
+    pub(crate) interceptors: Interceptors,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<tonic::transport::ClientTlsConfig>,
+}
+
+impl Connection {
+    pub(crate) async fn connect(&self) -> anyhow::Result<Client> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let channel = Endpoint::parse_tls(&self.addr)?
+                .connect_tls(Keepalive::default(), tls.clone())
+                .await?;
+            return Ok(self.client(channel));
+        }
+        let channel = self.addr.parse::<Endpoint>()?.connect().await?;
+        Ok(self.client(channel))
+    }
+
+    fn client(&self, channel: Channel) -> Client {
+        let channel = Intercepted {
+            channel,
+            interceptors: self.interceptors.clone(),
+        };
+        new_client(channel, self.session.clone())
+    }
+}
+
+/// A server the shell connected to with `connect`, besides its own.
+/// Commands are sent to it by prefixing them with the connection's name.
+struct Remote {
+    connection: Connection,
+    client: Client,
+    /// Follows the server's service changes into its completion data.
+    _watcher: AbortOnDrop,
+}
+
+/// How the CLI retries commands the server rejected as busy.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one (1 disables retries).
+    pub max_attempts: u32,
+    /// Delay used when the server gives no retry-after hint.
+    pub default_delay: Duration,
+    /// Upper bound on a single wait, whatever the server suggests.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            default_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay to wait before retrying a busy response.
+    fn delay_for(&self, response: &CommandResponse) -> Duration {
+        let hinted = if response.retry_after_ms > 0 {
+            Duration::from_millis(response.retry_after_ms)
+        } else {
+            self.default_delay
+        };
+        hinted.min(self.max_delay)
+    }
+}
+
+/// Execute a command, waiting and retrying while the server reports busy,
+/// until `deadline` if there is one.
+async fn execute_with_retry(
+    client: &mut Client,
+    request: CommandRequest,
+    policy: &RetryPolicy,
+    deadline: Option<Instant>,
+) -> Result<CommandResponse, tonic::Status> {
+    retry_busy(policy, async || {
+        let response = client.execute(with_deadline(request.clone(), deadline)).await?.into_inner();
+        chunked::complete(client, response).await
+    })
+    .await
+}
+
+/// `message` as a request that the server gives up on at `deadline` (by its
+/// `grpc-timeout`), if there is one.
+pub(crate) fn with_deadline<T>(message: T, deadline: Option<Instant>) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    if let Some(deadline) = deadline {
+        request.set_timeout(deadline.saturating_duration_since(Instant::now()));
+    }
+    request
+}
+
+/// The response for `command` abandoned at `deadline` by the client, which
+/// gives up on a call once its `grpc-timeout` has passed: the timeout error
+/// the server sends just before, had it arrived in time. Other errors are
+/// returned as they are.
+fn expired(error: anyhow::Error, deadline: Option<Instant>, command: &str) -> anyhow::Result<CommandResponse> {
+    let cancelled = error
+        .downcast_ref::<tonic::Status>()
+        .is_some_and(|status| status.code() == tonic::Code::Cancelled);
+    let passed = deadline.is_some_and(|deadline| deadline <= Instant::now());
+    if !(cancelled && passed) {
+        return Err(error);
+    }
+    Ok(CommandResponse {
+        success: false,
+        message: format!("'{}' did not finish before the request's deadline", command),
+        error_code: crate::proto::ErrorCode::Timeout as i32,
+        ..Default::default()
+    })
+}
+
+/// Make `call` until the server is not busy or `policy` gives up.
+async fn retry_busy<E>(
+    policy: &RetryPolicy,
+    mut call: impl AsyncFnMut() -> Result<CommandResponse, E>,
+) -> Result<CommandResponse, E> {
+    let mut attempt = 1;
+    loop {
+        let response = call().await?;
+        if !response.busy || attempt >= policy.max_attempts {
+            return Ok(response);
+        }
+        let delay = policy.delay_for(&response);
+        println!("Server busy, retrying in {}ms...", delay.as_millis());
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Options a command line may start with, before the service.
+const COMMAND_OPTIONS: [&str; 2] = ["--dry-run", "--timeout"];
+
+/// How one command line runs, from the options it starts with.
+#[derive(Debug, Clone, Copy)]
+struct CommandOptions {
+    /// `--dry-run`, or the session in dry-run mode.
+    dry_run: bool,
+    /// `--timeout <duration>`: when the server gives up on the command.
+    deadline: Option<Instant>,
+}
+
+/// The usage of a command line.
+const COMMAND_USAGE: &str = "Usage: [--dry-run] [--timeout <duration>] <service> <command> [args...]";
+
+/// Commands the shell runs itself instead of sending to the server.
+const BUILTINS: [&str; 22] = [
+    "help", "health", "describe", "history", "preview", "pin", "unpin", "favorites", "jobs", "job", "last",
+    "set", "unset", "watch", "subscribe", "put", "get", "connect", "connections", "disconnect", "quit", "exit",
+];
 
 /// Inline hint shown as grayed-out text after the cursor.
 struct ArgHint(String);
@@ -25,46 +257,168 @@ impl Hint for ArgHint {
     }
 }
 
-/// Rustyline helper that provides tab-completion for service names, commands,
-/// and argument values, plus inline hints showing expected argument placeholders.
-struct NexusHelper {
+/// A service list and the completion data derived from it.
+#[derive(Default)]
+struct ServiceIndex {
+    services: Vec<ServiceInfo>,
+    revision: u64,
     /// service name -> list of command names
     commands: HashMap<String, Vec<String>>,
     /// (service, command) -> argument definitions
     arg_info: HashMap<(String, String), Vec<ArgDef>>,
-    /// gRPC client for dynamic completion calls.
-    client: NexusServiceClient<Channel>,
-    /// Tokio runtime handle for bridging async calls from the sync completer.
-    handle: tokio::runtime::Handle,
+}
+
+impl ServiceIndex {
+    fn new(listing: ListServicesResponse) -> Self {
+        let mut index = Self {
+            revision: listing.revision,
+            ..Default::default()
+        };
+        for svc in &listing.services {
+            let cmds: Vec<String> = svc.commands.iter().map(|c| c.name.clone()).collect();
+            // Aliases complete and hint exactly like the service name. Those
+            // of nested services are only meaningful after their parent and
+            // are resolved through `resolve_service` instead.
+            let aliases = if svc.name.contains('.') { &[][..] } else { &svc.aliases[..] };
+            for name in std::iter::once(&svc.name).chain(aliases) {
+                for cmd in &svc.commands {
+                    index.arg_info.insert((name.clone(), cmd.name.clone()), cmd.args.clone());
+                }
+                index.commands.insert(name.clone(), cmds.clone());
+            }
+        }
+        index.services = listing.services;
+        index
+    }
+}
+
+/// Rustyline helper that provides tab-completion for service names, commands,
+/// and argument values, plus inline hints showing expected argument placeholders.
+struct NexusHelper {
+    /// Completion data, replaced by `watch_services` as the server's
+    /// services change.
+    index: Arc<std::sync::Mutex<ServiceIndex>>,
+    /// Completer values and job IDs, fetched in the background.
+    candidates: Candidates,
     /// Length of the last input line seen (updated by the hinter on each keystroke).
     last_input_len: std::sync::Mutex<usize>,
+    /// Pinned commands, offered before other completions.
+    favorites: std::sync::Mutex<Favorites>,
+    /// Colors for the line being typed; plain when color is off.
+    theme: Theme,
+    /// Names of the shell's aliases, highlighted like builtins.
+    aliases: Vec<String>,
+    /// Completion data of the servers added with `connect`, by connection
+    /// name; lines starting with a name complete against its server.
+    connections: BTreeMap<String, NexusHelper>,
+    /// Shell variables set with `set NAME=<value>`, by name; `$NAME`
+    /// completes to them.
+    variables: BTreeMap<String, String>,
 }
 
 impl NexusHelper {
-    fn from_services(
-        services: &[ServiceInfo],
-        client: NexusServiceClient<Channel>,
-        handle: tokio::runtime::Handle,
-    ) -> Self {
-        let mut commands = HashMap::new();
-        let mut arg_info = HashMap::new();
-        for svc in services {
-            let cmds = svc.commands.iter().map(|c| c.name.clone()).collect();
-            for cmd in &svc.commands {
-                arg_info.insert(
-                    (svc.name.clone(), cmd.name.clone()),
-                    cmd.args.clone(),
-                );
-            }
-            commands.insert(svc.name.clone(), cmds);
-        }
+    fn from_services(listing: ListServicesResponse, client: Client, favorites: Favorites) -> Self {
         Self {
-            commands,
-            arg_info,
-            client,
-            handle,
+            index: Arc::new(std::sync::Mutex::new(ServiceIndex::new(listing))),
+            candidates: Candidates::spawn(client),
             last_input_len: std::sync::Mutex::new(0),
+            favorites: std::sync::Mutex::new(favorites),
+            theme: Theme::PLAIN,
+            aliases: Vec::new(),
+            connections: BTreeMap::new(),
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// Color input with `theme`, on every connection.
+    fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        for remote in self.connections.values_mut() {
+            remote.set_theme(theme);
+        }
+    }
+
+    /// If `line` starts with a connection name and whitespace: the name,
+    /// the connection's completion data and the rest of the line.
+    fn remote<'l>(&self, line: &'l str) -> Option<(&'l str, &NexusHelper, &'l str)> {
+        let (name, rest) = line.split_once(char::is_whitespace)?;
+        let remote = self.connections.get(name)?;
+        Some((name, remote, rest.trim_start()))
+    }
+
+    /// Complete against `listing` using `client`, e.g. after reconnecting.
+    fn set_services(&mut self, listing: ListServicesResponse, client: Client) {
+        *self.index.lock().unwrap() = ServiceIndex::new(listing);
+        self.candidates.connect(client);
+    }
+
+    fn revision(&self) -> u64 {
+        self.index.lock().unwrap().revision
+    }
+
+    /// The current service list and its revision.
+    fn services(&self) -> (Vec<ServiceInfo>, u64) {
+        let index = self.index.lock().unwrap();
+        (index.services.clone(), index.revision)
+    }
+
+    /// Names of the top-level services (and aliases) matching `prefix`,
+    /// ranked like `pairs`.
+    fn service_names(&self, prefix: &str) -> Vec<Pair> {
+        let names = self
+            .index
+            .lock()
+            .unwrap()
+            .commands
+            .keys()
+            .filter(|s| !s.contains('.'))
+            .cloned()
+            .collect();
+        pairs(names, prefix)
+    }
+
+    /// The full name of the service named by the leading `words` and the
+    /// number of words naming it (see `resolve_service`).
+    fn resolve(&self, words: &[&str]) -> Option<(String, usize)> {
+        let index = self.index.lock().unwrap();
+        resolve_service(&index.services, words).map(|(svc, used)| (svc.name.clone(), used))
+    }
+
+    /// Names and aliases of the services nested under `service`.
+    fn nested_names(&self, service: &str) -> Vec<String> {
+        let index = self.index.lock().unwrap();
+        nested_services(&index.services, service)
+            .flat_map(|(name, svc)| std::iter::once(name.to_string()).chain(svc.aliases.iter().cloned()))
+            .collect()
+    }
+
+    /// Every command as typed in full, e.g. "volume snapshot list".
+    fn command_paths(&self) -> Vec<String> {
+        let index = self.index.lock().unwrap();
+        index
+            .services
+            .iter()
+            .flat_map(|svc| {
+                let path = svc.name.replace('.', " ");
+                svc.commands.iter().map(move |cmd| format!("{} {}", path, cmd.name))
+            })
+            .collect()
+    }
+
+    fn commands(&self, service: &str) -> Option<Vec<String>> {
+        self.index.lock().unwrap().commands.get(service).cloned()
+    }
+
+    /// Arguments of `command` of `service`, which may be abbreviated (see
+    /// `resolve_command`).
+    fn args(&self, service: &str, command: &str) -> Option<Vec<ArgDef>> {
+        let index = self.index.lock().unwrap();
+        if let Some(args) = index.arg_info.get(&(service.to_string(), command.to_string())) {
+            return Some(args.clone());
         }
+        let info = find_service(&index.services, service)?;
+        let command = resolve_command(&index.services, info, command).ok()??;
+        Some(command.args.clone())
     }
 
     /// Display label for an argument: use hint if set, otherwise the param name.
@@ -76,36 +430,64 @@ impl NexusHelper {
         }
     }
 
-    /// Call a completer (e.g. "block.list") by executing the referenced service
-    /// command on the server. Spawns a scoped thread to bridge sync -> async.
-    fn fetch_completions(&self, completer: &str) -> Vec<String> {
-        let Some((svc, cmd)) = completer.split_once('.') else {
-            return vec![];
-        };
-        let mut client = self.client.clone();
-        let request = CommandRequest {
-            service: svc.to_string(),
-            action: cmd.to_string(),
-            args: vec![],
-        };
-        let handle = self.handle.clone();
-        let result = std::thread::scope(|s| {
-            s.spawn(|| {
-                handle.block_on(async move { client.execute(request).await })
-            })
-            .join()
-        });
-        match result {
-            Ok(Ok(resp)) => resp
-                .into_inner()
-                .message
-                .split(',')
-                .map(|s| s.trim().to_string())
-                .filter(|s| !s.is_empty())
-                .collect(),
-            _ => vec![],
+    /// Values of the completer of `arg` (e.g. "block.list"), the referenced
+    /// service command run on the server and passed the values `tokens`
+    /// give the arguments it names.
+    fn fetch_completions(&self, arg: &ArgDef, args: &[ArgDef], tokens: &[&str]) -> Vec<String> {
+        match completer_request(arg, args, tokens) {
+            Some(request) => self.candidates.get(Lookup::values(request)),
+            None => vec![],
         }
     }
+
+    /// IDs of the caller's jobs, only running ones if `running`.
+    fn job_ids(&self, running: bool) -> Vec<String> {
+        self.candidates.get(Lookup::Jobs { running })
+    }
+}
+
+/// How well `word` matches the `typed` text, best first: 0 if it starts
+/// with it, 1 if it does ignoring case, 2 if it contains it (ignoring case)
+/// and 3 if it has its letters in order, e.g. `vcr` in `volume create`.
+fn match_rank(word: &str, typed: &str) -> Option<u8> {
+    if word.starts_with(typed) {
+        return Some(0);
+    }
+    let word = word.to_lowercase();
+    let typed = typed.to_lowercase();
+    if word.starts_with(&typed) {
+        Some(1)
+    } else if word.contains(&typed) {
+        Some(2)
+    } else {
+        let mut letters = word.chars();
+        typed.chars().all(|c| letters.any(|l| l == c)).then_some(3)
+    }
+}
+
+/// Sort `candidates` by how well they match `typed`, keeping their order
+/// among equally good matches and dropping repeats.
+fn rank(candidates: &mut Vec<Pair>, typed: &str) {
+    candidates.sort_by_key(|c| match_rank(&c.replacement, typed));
+    let mut seen = HashSet::new();
+    candidates.retain(|c| seen.insert(c.replacement.clone()));
+}
+
+/// Completion candidates for the `words` matching `prefix`, the best
+/// matches first (see `match_rank`) and sorted among themselves.
+fn pairs(words: Vec<String>, prefix: &str) -> Vec<Pair> {
+    let mut words: Vec<(u8, String)> = words
+        .into_iter()
+        .filter_map(|w| Some((match_rank(&w, prefix)?, w)))
+        .collect();
+    words.sort();
+    words
+        .into_iter()
+        .map(|(_, w)| Pair {
+            display: w.clone(),
+            replacement: w,
+        })
+        .collect()
 }
 
 impl Completer for NexusHelper {
@@ -118,112 +500,187 @@ impl Completer for NexusHelper {
         _ctx: &Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Pair>)> {
         let line = &line[..pos];
-        let parts: Vec<&str> = line.split_whitespace().collect();
+        // Past a `|` or `>` come local shell words and file names.
+        if tokenize::split_redirect(line).1.is_some() {
+            return Ok((pos, vec![]));
+        }
+
+        // "$..." completes the names of shell variables.
+        let partial = tokenize::split_partial(line);
+        if let Some(name) = line[partial.start..].strip_prefix('$') {
+            if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                let names = self.variables.keys().map(|name| format!("${}", name)).collect();
+                return Ok((partial.start, pairs(names, &line[partial.start..])));
+            }
+        }
+
+        // "<connection> ..." completes against that connection's server.
+        if let Some((_, remote, rest)) = self.remote(line) {
+            let (start, candidates) = remote.complete(rest, rest.len(), _ctx)?;
+            return Ok((line.len() - rest.len() + start, candidates));
+        }
+
+        // "preview <service> <command> ...", "--dry-run <service> <command> ...",
+        // "watch <interval> <service> <command> ..." and "put|get <file>
+        // <service> <command> ..." complete like the wrapped command;
+        // `offset` maps positions in the stripped line back to the full line.
+        let (line, offset) = match strip_wrapper(line) {
+            Some(rest) => (rest, line.len() - rest.len()),
+            None => (line, 0),
+        };
+        let partial = tokenize::split_partial(line);
+        let parts: Vec<&str> = partial.words.iter().map(String::as_str).collect();
+        let start = offset + partial.start;
 
         // Still typing the first word (or empty line): complete service names + builtins.
-        if parts.is_empty() || (parts.len() == 1 && !line.ends_with(' ')) {
+        if parts.is_empty() || (parts.len() == 1 && !partial.new_word) {
             let prefix = parts.first().copied().unwrap_or("");
-            let start = pos - prefix.len();
 
-            let mut services: Vec<Pair> = self
-                .commands
-                .keys()
-                .filter(|s| s.starts_with(prefix))
-                .map(|s| Pair {
-                    display: s.clone(),
-                    replacement: s.clone(),
-                })
-                .collect();
-            services.sort_by(|a, b| a.display.cmp(&b.display));
+            let mut services = self.service_names(prefix);
+
+            let mut builtin_pairs = pairs(BUILTINS.map(String::from).to_vec(), prefix);
 
-            let builtins = ["help", "quit", "exit"];
-            let mut builtin_pairs: Vec<Pair> = builtins
+            // Pinned commands come first and complete the whole command line.
+            let mut candidates: Vec<Pair> = self
+                .favorites
+                .lock()
+                .unwrap()
+                .commands()
                 .iter()
-                .filter(|b| b.starts_with(prefix))
-                .map(|b| Pair {
-                    display: b.to_string(),
-                    replacement: b.to_string(),
+                .filter(|c| match_rank(c, prefix).is_some())
+                .map(|c| Pair {
+                    display: c.clone(),
+                    replacement: c.clone(),
                 })
                 .collect();
-            builtin_pairs.sort_by(|a, b| a.display.cmp(&b.display));
-
-            let mut candidates = services;
+            candidates.append(&mut services);
+            candidates.append(&mut pairs(self.connections.keys().cloned().collect(), prefix));
             candidates.append(&mut builtin_pairs);
+            // Whole commands when the letters typed are in them but do not
+            // start a service name, e.g. `vcr` for `volume create`.
+            let mut commands = pairs(self.command_paths(), prefix);
+            commands.retain(|c| match_rank(&c.replacement, prefix) >= Some(2));
+            candidates.append(&mut commands);
+            rank(&mut candidates, prefix);
             return Ok((start, candidates));
         }
 
-        // Typing the second word after "help": complete service names.
-        if (parts.len() == 1 || (parts.len() == 2 && !line.ends_with(' ')))
-            && parts[0] == "help"
-        {
-            let prefix = if parts.len() == 2 { parts[1] } else { "" };
-            let start = pos - prefix.len();
+        // Words before the one being completed.
+        let (typed, prefix) = if partial.new_word {
+            (&parts[..], "")
+        } else {
+            (&parts[..parts.len() - 1], parts.last().copied().unwrap_or(""))
+        };
 
-            let mut candidates: Vec<Pair> = self
-                .commands
-                .keys()
-                .filter(|s| s.starts_with(prefix))
-                .map(|s| Pair {
-                    display: s.clone(),
-                    replacement: s.clone(),
+        // After "help": complete service names, then the commands and
+        // nested services of the service named so far.
+        if typed[0] == "help" {
+            if typed.len() == 1 {
+                return Ok((start, self.service_names(prefix)));
+            }
+            let candidates = match self.resolve(&typed[1..]) {
+                Some((service, used)) if used == typed.len() - 1 => {
+                    let mut words = self.nested_names(&service);
+                    words.extend(self.commands(&service).unwrap_or_default());
+                    pairs(words, prefix)
+                }
+                _ => vec![],
+            };
+            return Ok((start, candidates));
+        }
+
+        // After "job": its subcommands, then the ID of a job.
+        match typed {
+            ["job"] => return Ok((start, pairs(vec!["cancel".into(), "status".into()], prefix))),
+            ["job", action] => {
+                return Ok((start, pairs(self.job_ids(*action == "cancel"), prefix)));
+            }
+            ["job", ..] => return Ok((offset + pos, vec![])),
+            ["unset", ..] => return Ok((start, pairs(self.variables.keys().cloned().collect(), prefix))),
+            ["last", ..] if prefix.starts_with('-') => return Ok((start, pairs(vec!["--all".into()], prefix))),
+            // After "set": the setting, then its values.
+            ["set"] => {
+                let settings = ["color", "dry-run", "format", "paging"].map(String::from).to_vec();
+                return Ok((start, pairs(settings, prefix)));
+            }
+            ["set", "color" | "dry-run" | "paging"] => return Ok((start, pairs(vec!["on".into(), "off".into()], prefix))),
+            ["set", "format"] => {
+                let formats = ["plain", "json", "yaml", "table"].map(String::from).to_vec();
+                return Ok((start, pairs(formats, prefix)));
+            }
+            ["set", ..] => return Ok((offset + pos, vec![])),
+            ["disconnect"] => return Ok((start, pairs(self.connections.keys().cloned().collect(), prefix))),
+            ["connect" | "disconnect", ..] => return Ok((offset + pos, vec![])),
+            _ => {}
+        }
+
+        let Some((service, used)) = self.resolve(typed) else {
+            return Ok((offset + pos, vec![]));
+        };
+
+        // After the service: complete its command names and nested services.
+        if used == typed.len() {
+            let path = typed.join(" ");
+            // Pinned commands of this service first, then the rest.
+            let mut pinned: Vec<Pair> = self
+                .favorites
+                .lock()
+                .unwrap()
+                .commands()
+                .iter()
+                .filter_map(|c| c.strip_prefix(&path)?.strip_prefix(' '))
+                .filter(|rest| match_rank(rest, prefix).is_some())
+                .map(|rest| Pair {
+                    display: rest.to_string(),
+                    replacement: rest.to_string(),
                 })
                 .collect();
-            candidates.sort_by(|a, b| a.display.cmp(&b.display));
-            return Ok((start, candidates));
+            let mut words = self.commands(&service).unwrap_or_default();
+            words.extend(self.nested_names(&service));
+            pinned.append(&mut pairs(words, prefix));
+            rank(&mut pinned, prefix);
+            return Ok((start, pinned));
         }
 
-        // Typing the second word: complete command names for the given service.
-        if parts.len() == 1 || (parts.len() == 2 && !line.ends_with(' ')) {
-            let service = parts[0];
-            let prefix = if parts.len() == 2 { parts[1] } else { "" };
-            let start = pos - prefix.len();
+        // Typing arguments: complete `--flag` names, or the argument being
+        // filled from its declared choices or by calling its completer.
+        if let Some(args) = self.args(&service, typed[used]) {
+            let scan = ScannedArgs::scan(&typed[used + 1..]);
 
-            if let Some(cmds) = self.commands.get(service) {
-                let mut candidates: Vec<Pair> = cmds
+            if scan.pending_flag.is_none() && prefix.starts_with("--") {
+                let flags = args
                     .iter()
-                    .filter(|c| c.starts_with(prefix))
-                    .map(|c| Pair {
-                        display: c.clone(),
-                        replacement: c.clone(),
-                    })
+                    .filter(|a| !scan.named.contains(&a.name))
+                    .map(|a| format!("--{}", a.name))
                     .collect();
-                candidates.sort_by(|a, b| a.display.cmp(&b.display));
-                return Ok((start, candidates));
+                return Ok((start, pairs(flags, prefix)));
             }
-        }
-
-        // Typing arguments: call the completer dynamically if one is declared.
-        if parts.len() >= 2 {
-            let service = parts[0];
-            let command = parts[1];
 
-            if let Some(args) = self.arg_info.get(&(service.to_string(), command.to_string())) {
-                // Determine which arg position is being completed.
-                let (arg_index, prefix) = if line.ends_with(' ') {
-                    (parts.len() - 2, "")
-                } else {
-                    (parts.len() - 3, parts.last().copied().unwrap_or(""))
-                };
-
-                if let Some(arg_def) = args.get(arg_index) {
-                    if !arg_def.completer.is_empty() {
-                        let values = self.fetch_completions(&arg_def.completer);
-                        let start = pos - prefix.len();
-                        let candidates: Vec<Pair> = values
-                            .iter()
-                            .filter(|v| v.starts_with(prefix))
-                            .map(|v| Pair {
-                                display: v.clone(),
-                                replacement: v.clone(),
-                            })
-                            .collect();
-                        return Ok((start, candidates));
-                    }
+            if let Some(arg_def) = scan.current_arg(&args) {
+                if !arg_def.choices.is_empty() || !arg_def.completer.is_empty() {
+                    let values = if arg_def.choices.is_empty() {
+                        self.fetch_completions(arg_def, &args, &typed[used + 1..])
+                    } else {
+                        arg_def.choices.clone()
+                    };
+                    // Ranked by the values themselves, which are quoted when
+                    // inserted.
+                    let mut values: Vec<(u8, String)> =
+                        values.into_iter().filter_map(|v| Some((match_rank(&v, prefix)?, v))).collect();
+                    values.sort_by_key(|(rank, _)| *rank);
+                    let candidates: Vec<Pair> = values
+                        .into_iter()
+                        .map(|(_, v)| Pair {
+                            display: v.clone(),
+                            replacement: tokenize::quote(&v),
+                        })
+                        .collect();
+                    return Ok((start, candidates));
                 }
             }
         }
 
-        Ok((pos, vec![]))
+        Ok((offset + pos, vec![]))
     }
 }
 
@@ -233,33 +690,42 @@ impl Hinter for NexusHelper {
     fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<ArgHint> {
         *self.last_input_len.lock().unwrap() = line.len();
         let line = &line[..pos];
-        let parts: Vec<&str> = line.split_whitespace().collect();
-
-        if parts.len() < 2 {
+        if tokenize::split_redirect(line).1.is_some() {
             return None;
         }
+        if let Some((_, remote, rest)) = self.remote(line) {
+            return remote.hint(rest, rest.len(), _ctx);
+        }
+        let line = strip_wrapper(line).unwrap_or(line);
+        let partial = tokenize::split_partial(line);
+        let parts: Vec<&str> = partial.words.iter().map(String::as_str).collect();
 
-        let service = parts[0];
-        let command = parts[1];
-
-        let args = self
-            .arg_info
-            .get(&(service.to_string(), command.to_string()))?;
+        let (service, used) = self.resolve(&parts)?;
+        let command = parts.get(used)?;
 
-        // How many args are already fully typed.
-        let hint_start = parts.len() - 2;
+        let args = self.args(&service, command)?;
 
-        let remaining: Vec<String> = args
-            .iter()
-            .skip(hint_start)
-            .map(|a| format!("<{}>", Self::arg_label(a)))
-            .collect();
+        // Skip args already supplied positionally or as `--flag`s.
+        let scan = ScannedArgs::scan(&parts[used + 1..]);
+        let remaining: Vec<String> = match &scan.pending_flag {
+            Some(flag) => args
+                .iter()
+                .filter(|a| &a.name == flag)
+                .map(arg_placeholder)
+                .collect(),
+            None => args
+                .iter()
+                .filter(|a| !scan.named.contains(&a.name))
+                .skip(scan.positional)
+                .map(arg_placeholder)
+                .collect(),
+        };
 
         if remaining.is_empty() {
             return None;
         }
 
-        let hint = if line.ends_with(' ') {
+        let hint = if partial.new_word {
             remaining.join(" ")
         } else {
             format!(" {}", remaining.join(" "))
@@ -269,197 +735,2978 @@ impl Hinter for NexusHelper {
     }
 }
 
-impl Highlighter for NexusHelper {}
-impl Validator for NexusHelper {}
-impl Helper for NexusHelper {}
-
-/// Interactive CLI shell that connects to a Nexus gRPC server.
-pub struct NexusCli {
-    addr: String,
+/// Summary of the argument tokens typed so far, used by completion and hints.
+pub(crate) struct ScannedArgs {
+    /// Number of positional values.
+    pub(crate) positional: usize,
+    /// Parameter names already supplied as `--name value` or `--name=value`.
+    pub(crate) named: Vec<String>,
+    /// A trailing `--name` still waiting for its value.
+    pub(crate) pending_flag: Option<String>,
 }
 
-impl NexusCli {
-    pub fn new(addr: &str) -> Self {
-        Self {
-            addr: addr.to_string(),
+impl ScannedArgs {
+    pub(crate) fn scan(tokens: &[&str]) -> Self {
+        let mut scan = Self {
+            positional: 0,
+            named: Vec::new(),
+            pending_flag: None,
+        };
+        for token in tokens {
+            if let Some(flag) = token.strip_prefix("--") {
+                if let Some(name) = scan.pending_flag.take() {
+                    scan.named.push(name);
+                }
+                match flag.split_once('=') {
+                    Some((name, _)) => scan.named.push(name.to_string()),
+                    None => scan.pending_flag = Some(flag.to_string()),
+                }
+            } else if let Some(name) = scan.pending_flag.take() {
+                scan.named.push(name);
+            } else {
+                scan.positional += 1;
+            }
         }
+        scan
     }
 
-    pub async fn run(self) -> anyhow::Result<()> {
-        let mut client = if self.addr.contains(':') {
-            let addr = if self.addr.starts_with("http://") || self.addr.starts_with("https://") {
-                self.addr.clone()
-            } else {
-                format!("http://{}", self.addr)
-            };
-            NexusServiceClient::connect(addr).await?
-        } else {
-            let path = self.addr.clone();
-            // The URI is not used for routing; the connector below ignores it.
-            let channel = Endpoint::try_from("http://[::]:50051")?
-                .connect_with_connector(service_fn(move |_| {
-                    let path = path.clone();
-                    async move {
-                        UnixStream::connect(path).await.map(TokioIo::new)
-                    }
-                }))
-                .await?;
-            NexusServiceClient::new(channel)
-        };
-
-        // Fetch available services on startup.
-        let services = client
-            .list_services(ListServicesRequest {})
-            .await?
-            .into_inner()
-            .services;
-
-        println!("Connected. Type 'help' for available commands, 'quit' to exit.");
-
-        let handle = tokio::runtime::Handle::current();
-        let helper = NexusHelper::from_services(&services, client.clone(), handle);
-        let mut rl = Editor::new()?;
-        rl.set_helper(Some(helper));
+    /// The argument the next value fills: the pending flag's argument, or
+    /// the next positional argument not supplied by flag. A trailing variadic
+    /// argument keeps taking values.
+    pub(crate) fn current_arg<'a>(&self, args: &'a [ArgDef]) -> Option<&'a ArgDef> {
+        match &self.pending_flag {
+            Some(flag) => args.iter().find(|a| &a.name == flag),
+            None => args
+                .iter()
+                .filter(|a| !self.named.contains(&a.name))
+                .nth(self.positional)
+                .or_else(|| args.last().filter(|a| a.variadic)),
+        }
+    }
+}
 
-        loop {
-            let line = match rl.readline("cli> ") {
-                Ok(line) => line,
-                Err(ReadlineError::Interrupted) => {
-                    // Move cursor up to the input line and position right after the
-                    // text, then print ^C.  Column is 1-based: prompt "cli> " (5)
-                    // + input length + 1.
-                    let prompt_len = 5; // "cli> "
-                    let input_len = rl
-                        .helper()
-                        .map(|h| *h.last_input_len.lock().unwrap())
-                        .unwrap_or(0);
-                    let col = prompt_len + input_len + 1;
-                    print!("\x1b[A\x1b[{col}G^C\n");
-                    continue;
-                }
-                Err(ReadlineError::Eof) => break,
-                Err(e) => return Err(e.into()),
+/// The request that fetches the completions of `arg`, one of `args`: its
+/// completer command, passed the values already typed in `tokens` for the
+/// arguments the completer names, up to the first one not typed yet.
+pub(crate) fn completer_request(arg: &ArgDef, args: &[ArgDef], tokens: &[&str]) -> Option<CommandRequest> {
+    let (service, action) = arg.completer.rsplit_once('.')?;
+    // A trailing `--name` has no value yet.
+    let tokens = match ScannedArgs::scan(tokens).pending_flag {
+        Some(_) => &tokens[..tokens.len() - 1],
+        None => tokens,
+    };
+    let (positional, named) = split_args(tokens);
+    let mut positional = positional.into_iter();
+    let typed: HashMap<&str, String> = args
+        .iter()
+        .map_while(|a| {
+            let value = match named.get(&a.name) {
+                Some(value) => value.clone(),
+                None => positional.next()?,
             };
+            Some((a.name.as_str(), value))
+        })
+        .collect();
+    Some(CommandRequest {
+        service: service.to_string(),
+        action: action.to_string(),
+        args: arg.completer_args.iter().map_while(|name| typed.get(name.as_str()).cloned()).collect(),
+        ..Default::default()
+    })
+}
 
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-
-            let _ = rl.add_history_entry(line);
+/// A completer as declared, e.g. "volume.snapshot.list($volume)".
+pub(crate) fn completer_spec(arg: &ArgDef) -> String {
+    if arg.completer_args.is_empty() {
+        return arg.completer.clone();
+    }
+    format!("{}(${})", arg.completer, arg.completer_args.join(", $"))
+}
 
-            if line == "quit" || line == "exit" {
-                break;
-            }
+/// Split argument tokens into positional values and `--name value` flags.
+/// `--name=value` is also accepted; a `--name` with no value (last token or
+/// followed by another flag) is set to "true".
+pub(crate) fn split_args(tokens: &[&str]) -> (Vec<String>, HashMap<String, String>) {
+    let mut positional = Vec::new();
+    let mut named = HashMap::new();
+    let mut tokens = tokens.iter().peekable();
+    while let Some(token) = tokens.next() {
+        let Some(flag) = token.strip_prefix("--") else {
+            positional.push(token.to_string());
+            continue;
+        };
+        if let Some((name, value)) = flag.split_once('=') {
+            named.insert(name.to_string(), value.to_string());
+        } else if let Some(value) = tokens.next_if(|next| !next.starts_with("--")) {
+            named.insert(flag.to_string(), value.to_string());
+        } else {
+            named.insert(flag.to_string(), "true".to_string());
+        }
+    }
+    (positional, named)
+}
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
+/// Find a top-level service by its name or one of its aliases (or any
+/// service by its full name, e.g. "volume.snapshot").
+pub(crate) fn find_service<'a>(services: &'a [ServiceInfo], name: &str) -> Option<&'a ServiceInfo> {
+    services.iter().find(|s| {
+        s.name == name || (!s.name.contains('.') && s.aliases.iter().any(|a| a == name))
+    })
+}
 
-            if parts[0] == "help" {
-                if parts.len() >= 2 {
-                    print_service_help(&services, parts[1]);
-                } else {
-                    print_help(&services);
-                }
-                continue;
-            }
+/// The services nested directly under the service called `parent`, with
+/// their names relative to it.
+pub(crate) fn nested_services<'a>(
+    services: &'a [ServiceInfo],
+    parent: &'a str,
+) -> impl Iterator<Item = (&'a str, &'a ServiceInfo)> {
+    services.iter().filter_map(move |s| {
+        let (path, name) = s.name.rsplit_once('.')?;
+        (path == parent).then_some((name, s))
+    })
+}
 
-            if parts.len() < 2 {
-                println!("Usage: <service> <command> [args...]");
-                continue;
-            }
+/// The distinct entries one of whose names starts with `word`, to resolve
+/// an abbreviation. Each name comes with the entry it names, e.g. a service
+/// once for its name and once for each alias.
+fn abbreviated<'a, T>(word: &str, names: impl IntoIterator<Item = (&'a str, &'a T)>) -> Vec<&'a T> {
+    let mut found: Vec<&'a T> = Vec::new();
+    if word.is_empty() {
+        return found;
+    }
+    for (name, entry) in names {
+        if name.starts_with(word) && !found.iter().any(|f| std::ptr::eq(*f, entry)) {
+            found.push(entry);
+        }
+    }
+    found
+}
 
-            let service = parts[0].to_string();
-            let action = parts[1].to_string();
-            let args: Vec<String> = parts[2..].iter().map(|s| s.to_string()).collect();
+/// The names and aliases of the top-level services.
+fn top_level_names(services: &[ServiceInfo]) -> impl Iterator<Item = (&str, &ServiceInfo)> {
+    services.iter().filter(|s| !s.name.contains('.')).flat_map(|s| {
+        std::iter::once(s.name.as_str())
+            .chain(s.aliases.iter().map(String::as_str))
+            .map(move |name| (name, s))
+    })
+}
 
-            let response = client
-                .execute(CommandRequest {
-                    service,
-                    action,
-                    args,
-                })
-                .await?
-                .into_inner();
+/// The names and aliases of the services nested directly under `parent`.
+fn nested_names<'a>(services: &'a [ServiceInfo], parent: &'a str) -> impl Iterator<Item = (&'a str, &'a ServiceInfo)> {
+    nested_services(services, parent)
+        .flat_map(|(name, s)| std::iter::once(name).chain(s.aliases.iter().map(String::as_str)).map(move |n| (n, s)))
+}
 
-            if response.success {
-                println!("{}", response.message);
-            } else {
-                println!("Error: {}", response.message);
+/// Find the service named by the leading `words`: a top-level service
+/// followed by the names of services nested in it, as in `volume snapshot`.
+/// Each word may be abbreviated to a prefix no other service (or, after
+/// the first, command) of its parent shares, as in `vol snap`. Returns the
+/// service and the number of words naming it.
+pub(crate) fn resolve_service<'a>(
+    services: &'a [ServiceInfo],
+    words: &[&str],
+) -> Option<(&'a ServiceInfo, usize)> {
+    let first = words.first()?;
+    let mut service = match find_service(services, first) {
+        Some(service) => service,
+        None => match abbreviated(first, top_level_names(services))[..] {
+            [service] => service,
+            _ => return None,
+        },
+    };
+    let mut used = 1;
+    while let Some(word) = words.get(used) {
+        let nested = match nested_services(services, &service.name)
+            .find(|(name, s)| name == word || s.aliases.iter().any(|a| a == word))
+        {
+            Some((_, nested)) => nested,
+            None => {
+                let commands = service.commands.iter().map(|c| (c.name.as_str(), c));
+                match (&abbreviated(word, nested_names(services, &service.name))[..], &abbreviated(word, commands)[..]) {
+                    ([nested], []) => nested,
+                    _ => break,
+                }
             }
-        }
+        };
+        service = nested;
+        used += 1;
+    }
+    Some((service, used))
+}
 
-        Ok(())
+/// The command of `service` named by `word`: the one called `word`, else
+/// the only one whose name starts with it, when no nested service's does
+/// too. `Err` with the names of the commands and nested services `word`
+/// could stand for, if there are several.
+pub(crate) fn resolve_command<'a>(
+    services: &'a [ServiceInfo],
+    service: &'a ServiceInfo,
+    word: &str,
+) -> Result<Option<&'a CommandDef>, Vec<&'a str>> {
+    if let Some(command) = service.commands.iter().find(|c| c.name == word) {
+        return Ok(Some(command));
+    }
+    let commands = abbreviated(word, service.commands.iter().map(|c| (c.name.as_str(), c)));
+    let nested: Vec<&str> = abbreviated(word, nested_names(services, &service.name))
+        .into_iter()
+        .filter_map(|s| s.name.rsplit_once('.').map(|(_, name)| name))
+        .collect();
+    match (&commands[..], &nested[..]) {
+        ([command], []) => Ok(Some(command)),
+        ([], []) => Ok(None),
+        _ => Err(commands.iter().map(|c| c.name.as_str()).chain(nested).collect()),
     }
 }
 
-fn print_service_help(services: &[ServiceInfo], name: &str) {
-    let Some(svc) = services.iter().find(|s| s.name == name) else {
-        println!("Unknown service '{}'. Type 'help' to list all services.", name);
-        return;
+/// Split `<service...> <command> [args...]` into the full service name, the
+/// command and its arguments, with abbreviated names expanded (see
+/// `resolve_service` and `resolve_command`). An unknown service or command
+/// is passed on as typed, for the server to report; an abbreviation that
+/// several names share is an error. `None` if no command follows the
+/// service.
+pub(crate) fn split_command<'a, 'w>(
+    services: &[ServiceInfo],
+    parts: &'w [&'a str],
+) -> anyhow::Result<Option<(String, String, &'w [&'a str])>> {
+    let Some(first) = parts.first() else {
+        return Ok(None);
     };
-    if svc.description.is_empty() {
-        println!("{}:", svc.name);
-    } else {
-        println!("{}: {}", svc.name, svc.description);
-    }
-    println!();
-    for cmd in &svc.commands {
-        let args_str = cmd
-            .args
-            .iter()
-            .map(|a| {
-                let label = if a.hint.is_empty() { &a.name } else { &a.hint };
-                format!("<{}>", label)
+    let Some((service, used)) = resolve_service(services, parts) else {
+        let candidates: Vec<&str> = abbreviated(first, top_level_names(services))
+            .into_iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        if candidates.len() > 1 {
+            anyhow::bail!("ambiguous service '{}', candidates: {}", first, candidates.join(", "));
+        }
+        let command = parts[1..].split_first();
+        return Ok(command.map(|(action, args)| (first.to_string(), action.to_string(), args)));
+    };
+    let Some((action, args)) = parts[used..].split_first() else {
+        return Ok(None);
+    };
+    let action = match resolve_command(services, service, action) {
+        Ok(command) => command.map_or(action.to_string(), |c| c.name.clone()),
+        Err(candidates) => {
+            anyhow::bail!("ambiguous command '{}', candidates: {}", action, candidates.join(", "))
+        }
+    };
+    Ok(Some((service.name.clone(), action, args)))
+}
+
+/// A service name as typed on the command line: "volume.snapshot" is
+/// `volume snapshot`.
+pub(crate) fn command_path(name: &str) -> String {
+    name.replace('.', " ")
+}
+
+/// Placeholder for an argument in hints and help: `<name>` when required,
+/// `[name]` when optional, and `[name=default]` when it has a default.
+pub(crate) fn arg_placeholder(arg: &ArgDef) -> String {
+    let label = NexusHelper::arg_label(arg);
+    if arg.variadic {
+        format!("<{}...>", label)
+    } else if !arg.default_value.is_empty() {
+        format!("[{}={}]", label, arg.default_value)
+    } else if arg.optional {
+        format!("[{}]", label)
+    } else {
+        format!("<{}>", label)
+    }
+}
+
+/// The command wrapped by a leading `preview`, `watch <interval>`, `put|get
+/// <file>` or command options (`--dry-run`, `--timeout <duration>`), once
+/// the wrapper has been typed.
+fn strip_wrapper(line: &str) -> Option<&str> {
+    let rest = ["watch", "put", "get"].into_iter().find_map(|builtin| strip_builtin(line, builtin));
+    if let Some(rest) = rest {
+        let (_, command) = rest.split_once(char::is_whitespace)?;
+        return Some(command.trim_start());
+    }
+    strip_builtin(line, "preview").or_else(|| strip_options(line))
+}
+
+/// The command after the options a command line starts with, if it has any.
+fn strip_options(line: &str) -> Option<&str> {
+    let rest = match strip_builtin(line, "--timeout") {
+        Some(rest) => rest.split_once(char::is_whitespace)?.1.trim_start(),
+        None => strip_builtin(line, "--dry-run")?,
+    };
+    Some(strip_options(rest).unwrap_or(rest))
+}
+
+/// If `line` starts with the builtin word followed by whitespace, return the rest.
+fn strip_builtin<'a>(line: &'a str, builtin: &str) -> Option<&'a str> {
+    let rest = line.strip_prefix(builtin)?;
+    if rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+/// The name and the value as typed of `NAME=<value>`, the rest of a
+/// `set` line assigning a variable.
+fn assignment(rest: &str) -> Option<(&str, &str)> {
+    let (name, value) = rest.split_once('=')?;
+    tokenize::is_variable_name(name).then_some((name, value))
+}
+
+impl NexusHelper {
+    /// The styles of the words of `line`, a command line without its
+    /// redirection: service names, the command (red if the service has no
+    /// such command), builtins, aliases and `--name` flags. Only the word
+    /// being typed at the end of the line may be the start of a name.
+    fn word_styles(&self, line: &str) -> Vec<(Range<usize>, Style)> {
+        let theme = &self.theme;
+        if let Some((name, remote, rest)) = self.remote(line) {
+            let offset = line.len() - rest.len();
+            let rest = remote.word_styles(rest);
+            let rest = rest.into_iter().map(|(span, style)| (span.start + offset..span.end + offset, style));
+            return std::iter::once((0..name.len(), theme.connection)).chain(rest).collect();
+        }
+        let mut words = tokenize::spans(line);
+        let typing = !line.ends_with(char::is_whitespace);
+        let mut styles = Vec::new();
+        // Leading command options; the duration of `--timeout` is left as
+        // typed.
+        while words.first().is_some_and(|(_, word)| COMMAND_OPTIONS.contains(&word.as_str())) {
+            let (span, option) = words.remove(0);
+            styles.push((span, theme.command));
+            if option == "--timeout" && !words.is_empty() {
+                words.remove(0);
+            }
+        }
+        match words.first().map(|(_, word)| word.as_str()) {
+            Some("preview") => styles.push((words.remove(0).0, theme.command)),
+            Some("watch" | "put" | "get") => {
+                // The interval or file is left as typed.
+                styles.push((words.remove(0).0, theme.command));
+                if words.is_empty() {
+                    return styles;
+                }
+                words.remove(0);
+            }
+            Some(word) if BUILTINS.contains(&word) || self.aliases.iter().any(|alias| alias == word) => {
+                return vec![(words[0].0.clone(), theme.command)];
+            }
+            Some(word) if self.connections.contains_key(word) => return vec![(words[0].0.clone(), theme.connection)],
+            _ => {}
+        }
+        let names: Vec<&str> = words.iter().map(|(_, word)| word.as_str()).collect();
+        if names.is_empty() {
+            return styles;
+        }
+        // Word `i` is not (yet) wrong if it is the last one, still being
+        // typed, and one of `candidates` starts with it.
+        let unknown = |i: usize, mut candidates: Box<dyn Iterator<Item = &str> + '_>| {
+            let partial = typing && i + 1 == names.len() && candidates.any(|name| name.starts_with(names[i]));
+            if partial { Style::PLAIN } else { theme.unknown }
+        };
+
+        let index = self.index.lock().unwrap();
+        let Some((service, used)) = resolve_service(&index.services, &names) else {
+            let candidates = index
+                .commands
+                .keys()
+                .chain(self.connections.keys())
+                .map(String::as_str)
+                .chain(BUILTINS)
+                .chain(COMMAND_OPTIONS);
+            styles.push((words[0].0.clone(), unknown(0, Box::new(candidates))));
+            return styles;
+        };
+        styles.extend(words[..used].iter().map(|(span, _)| (span.clone(), theme.service)));
+        let Some((span, command)) = words.get(used) else {
+            return styles;
+        };
+        let commands = index.commands.get(&service.name).map(Vec::as_slice).unwrap_or_default();
+        let style = if resolve_command(&index.services, service, command).is_ok_and(|c| c.is_some()) {
+            theme.command
+        } else {
+            let nested = nested_services(&index.services, &service.name)
+                .flat_map(|(name, svc)| std::iter::once(name).chain(svc.aliases.iter().map(String::as_str)));
+            unknown(used, Box::new(commands.iter().map(String::as_str).chain(nested)))
+        };
+        styles.push((span.clone(), style));
+        styles.extend(
+            words[used + 1..]
+                .iter()
+                .filter(|(_, word)| word.starts_with("--"))
+                .map(|(span, _)| (span.clone(), theme.flag)),
+        );
+        styles
+    }
+}
+
+impl Highlighter for NexusHelper {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        // What follows a `|` or `>` is for the local shell.
+        let (command, _) = tokenize::split_redirect(line);
+        let styles = self.word_styles(command);
+        if styles.iter().all(|(_, style)| style.is_plain()) {
+            return Cow::Borrowed(line);
+        }
+        let mut highlighted = String::with_capacity(line.len() * 2);
+        let mut end = 0;
+        for (span, style) in styles {
+            highlighted.push_str(&line[end..span.start]);
+            highlighted.push_str(&style.paint(&line[span.clone()]));
+            end = span.end;
+        }
+        highlighted.push_str(&line[end..]);
+        Cow::Owned(highlighted)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        match self.theme.hint {
+            style if style.is_plain() => Cow::Borrowed(hint),
+            style => Cow::Owned(style.paint(hint)),
+        }
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        // Any edit can change what a word names; moving the cursor cannot.
+        kind != CmdKind::MoveCursor && self.theme != Theme::PLAIN
+    }
+}
+impl Validator for NexusHelper {}
+impl Helper for NexusHelper {}
+
+/// Interactive CLI shell that connects to a Nexus gRPC server.
+pub struct NexusCli {
+    addr: String,
+    preferences: Preferences,
+    tenant: Option<String>,
+    token: Option<String>,
+    interceptors: Vec<Arc<dyn ClientInterceptor>>,
+    #[cfg(feature = "tls")]
+    tls: Option<tonic::transport::ClientTlsConfig>,
+    retry_policy: RetryPolicy,
+    /// How results are printed; `set format` changes it mid-session.
+    format: Mutex<OutputFormat>,
+    keep_going: bool,
+    config_dir: Option<PathBuf>,
+    history_file: Option<PathBuf>,
+    history_size: usize,
+    /// Command-line shortcuts: alias -> the command it stands for.
+    aliases: BTreeMap<String, String>,
+    /// Send commands as dry runs (`with_dry_run`, `set dry-run on`).
+    dry_run: AtomicBool,
+    /// Page long results in the interactive shell (`with_paging`, `set
+    /// paging on|off`).
+    paging: AtomicBool,
+    /// Colors of input and output, when `color` is on (`with_color`, `set
+    /// color on|off`).
+    theme: Theme,
+    color: AtomicBool,
+    /// Service list revision reported by the latest command response.
+    services_revision: AtomicU64,
+}
+
+/// Attempts the interactive shell makes to reconnect to a server that went
+/// away, waiting `RECONNECT_DELAY` after the first and doubling the wait
+/// (up to `RECONNECT_MAX_DELAY`) after each further one.
+pub const RECONNECT_ATTEMPTS: u32 = 5;
+pub const RECONNECT_DELAY: Duration = Duration::from_millis(250);
+pub const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(4);
+
+/// Whether a call failed because the server could not be reached.
+fn is_disconnected(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<tonic::Status>()
+        .is_some_and(|status| status.code() == tonic::Code::Unavailable)
+}
+
+/// The message of a gRPC status error, or the error itself.
+fn status_message(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<tonic::Status>() {
+        Some(status) => status.message().to_string(),
+        None => e.to_string(),
+    }
+}
+
+/// A new idempotency key for a command the user ran, sent again with its
+/// retries so that the server runs it only once.
+fn idempotency_key() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("cli-{:x}-{:x}-{}", std::process::id(), now.as_millis(), NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Exit status of a malformed one-shot command line, the same as for an
+/// `ErrorCode::InvalidArgument` error.
+const USAGE_EXIT: u8 = 2;
+
+/// Entries kept in the interactive shell's history by default.
+pub const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Expand a history reference at the start of `line`: `!!` (the last
+/// command), `!<n>` (entry n of `history`) or `!<prefix>` (the latest command
+/// starting with prefix). Words after it are appended. `None` if the line
+/// does not start with one.
+fn expand_history(line: &str, history: &DefaultHistory) -> anyhow::Result<Option<String>> {
+    let (reference, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let Some(designator) = reference.strip_prefix('!').filter(|d| !d.is_empty()) else {
+        return Ok(None);
+    };
+    let entry = if designator == "!" {
+        history
+            .iter()
+            .next_back()
+            .ok_or_else(|| anyhow::anyhow!("history is empty"))?
+    } else if let Ok(number) = designator.parse::<usize>() {
+        number
+            .checked_sub(1)
+            .and_then(|i| history.iter().nth(i))
+            .ok_or_else(|| anyhow::anyhow!("no history entry {}", number))?
+    } else {
+        history
+            .iter()
+            .rev()
+            .find(|entry| entry.starts_with(designator))
+            .ok_or_else(|| anyhow::anyhow!("no command in history starts with '{}'", designator))?
+    };
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok(Some(entry.clone()));
+    }
+    Ok(Some(format!("{} {}", entry, rest)))
+}
+
+/// The default history file: `~/.nexus_history`.
+fn default_history_file() -> Option<PathBuf> {
+    Some(PathBuf::from(std::env::var_os("HOME")?).join(".nexus_history"))
+}
+
+impl NexusCli {
+    pub fn new(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            preferences: Preferences::default(),
+            tenant: None,
+            token: None,
+            interceptors: Vec::new(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            retry_policy: RetryPolicy::default(),
+            format: Mutex::new(OutputFormat::Plain),
+            keep_going: false,
+            config_dir: favorites::default_config_dir(),
+            history_file: default_history_file(),
+            history_size: DEFAULT_HISTORY_SIZE,
+            aliases: BTreeMap::new(),
+            dry_run: AtomicBool::new(false),
+            paging: AtomicBool::new(true),
+            theme: Theme::default(),
+            // https://no-color.org: any non-empty value turns color off.
+            color: AtomicBool::new(std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())),
+            services_revision: AtomicU64::new(0),
+        }
+    }
+
+    /// A CLI set up from the `[cli]` table of the config file at `path`,
+    /// with environment overrides applied (see `crate::config`). It connects
+    /// to the configured `endpoint`, or `DEFAULT_ENDPOINT`.
+    pub fn from_config(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let config = config::Config::load(path)?.cli;
+        Self::new(config.endpoint.as_deref().unwrap_or(crate::DEFAULT_ENDPOINT)).with_config(&config)
+    }
+
+    /// Apply config file settings other than `endpoint` (which is passed to
+    /// `new`), each through the `with_*` method it stands for. Fails if a
+    /// TLS file cannot be read or TLS support is not built in.
+    pub fn with_config(mut self, config: &CliConfig) -> anyhow::Result<Self> {
+        if let Some(path) = &config.history_file {
+            self = self.with_history_file(path);
+        }
+        if let Some(size) = config.history_size {
+            self = self.with_history_size(size);
+        }
+        if let Some(format) = config.format {
+            self = self.with_format(format);
+        }
+        if let Some(paging) = config.paging {
+            self = self.with_paging(paging);
+        }
+        if let Some(color) = config.color {
+            self = self.with_color(color);
+        }
+        if let Some(theme) = config.theme {
+            self = self.with_theme(theme);
+        }
+        if let Some(tenant) = &config.tenant {
+            self = self.with_tenant(tenant);
+        }
+        if let Some(token) = &config.token {
+            self = self.with_token(token);
+        }
+        for (alias, command) in &config.aliases {
+            self = self.with_alias(alias, command);
+        }
+        if let Some(tls) = &config.tls {
+            #[cfg(feature = "tls")]
+            {
+                use anyhow::Context as _;
+                use tonic::transport::{Certificate, Identity};
+                let read = |path: &PathBuf| {
+                    std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+                };
+                let client_cert = match (&tls.cert, &tls.key) {
+                    (Some(cert), Some(key)) => Some(Identity::from_pem(read(cert)?, read(key)?)),
+                    (None, None) => None,
+                    _ => anyhow::bail!("[cli.tls] needs both `cert` and `key` for a client certificate"),
+                };
+                self = self.with_tls(Certificate::from_pem(read(&tls.ca)?), client_cert);
+            }
+            #[cfg(not(feature = "tls"))]
+            anyhow::bail!("TLS with {} needs the `tls` feature", tls.ca.display());
+        }
+        Ok(self)
+    }
+
+    /// Print results as raw JSON (tables become arrays of objects) instead of
+    /// rendering them for the terminal: `with_format(OutputFormat::Json)`,
+    /// or plain output for `false`.
+    pub fn with_json(self, json: bool) -> Self {
+        self.with_format(if json { OutputFormat::Json } else { OutputFormat::Plain })
+    }
+
+    /// Print results in `format`: JSON or YAML for piping into other tools,
+    /// or plain text or tables for the terminal. Also declares it as the
+    /// session's preferred format, and each request asks the server to
+    /// render its `message` that way.
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        *self.format.get_mut().unwrap() = format;
+        self.preferences.format = (format != OutputFormat::Plain).then(|| format.as_str().to_string());
+        self
+    }
+
+    /// The format results are printed in.
+    fn format(&self) -> OutputFormat {
+        *self.format.lock().unwrap()
+    }
+
+    /// Send every command as a dry run, as if each were prefixed with
+    /// `--dry-run`: commands report the changes they would make (see
+    /// `Context::propose`) without making them. `set dry-run on|off` changes
+    /// this from the shell or a script.
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        self.dry_run.store(dry_run, Ordering::Relaxed);
+        self
+    }
+
+    /// Show a result taller than the terminal in the pager (`$PAGER`, or
+    /// `less`) in the interactive shell. On by default; `set paging on|off`
+    /// changes this from the shell. Scripts and one-shot commands never page.
+    pub fn with_paging(self, paging: bool) -> Self {
+        self.paging.store(paging, Ordering::Relaxed);
+        self
+    }
+
+    /// Color typed input and printed results. On by default unless the
+    /// `NO_COLOR` environment variable is set; `set color on|off` changes
+    /// this from the shell or a script.
+    pub fn with_color(self, color: bool) -> Self {
+        self.color.store(color, Ordering::Relaxed);
+        self
+    }
+
+    /// Colors to use instead of `Theme::default()`.
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// The colors to print with: the theme, or none when color is off.
+    fn theme(&self) -> Theme {
+        if self.color.load(Ordering::Relaxed) {
+            self.theme
+        } else {
+            Theme::PLAIN
+        }
+    }
+
+    /// Keep running a script after a command fails instead of stopping at
+    /// the first failure.
+    pub fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Directory for the CLI's saved state, such as pinned commands. Defaults
+    /// to `$XDG_CONFIG_HOME/nexus` (or `~/.config/nexus`).
+    pub fn with_config_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config_dir = Some(dir.into());
+        self
+    }
+
+    /// File the interactive shell loads its history from and appends each
+    /// command line to. Defaults to `~/.nexus_history`.
+    pub fn with_history_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history_file = Some(path.into());
+        self
+    }
+
+    /// Keep at most `size` history entries (`DEFAULT_HISTORY_SIZE` by
+    /// default); older ones are dropped from the file too.
+    pub fn with_history_size(mut self, size: usize) -> Self {
+        self.history_size = size;
+        self
+    }
+
+    /// Make `alias` at the start of a command line stand for `command`, e.g.
+    /// `with_alias("vols", "volume list")`; words after the alias are
+    /// appended to it.
+    pub fn with_alias(mut self, alias: &str, command: &str) -> Self {
+        self.aliases.insert(alias.to_string(), command.to_string());
+        self
+    }
+
+    /// `words` with a leading alias replaced by the command it stands for.
+    fn expand_alias(&self, words: Vec<String>) -> Vec<String> {
+        let Some(command) = words.first().and_then(|first| self.aliases.get(first)) else {
+            return words;
+        };
+        let mut expanded = tokenize::split(command)
+            .unwrap_or_else(|_| command.split_whitespace().map(String::from).collect());
+        expanded.extend(words.into_iter().skip(1));
+        expanded
+    }
+
+    /// Set how commands rejected as busy are retried.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Declare session preferences (locale, output format, timezone) sent
+    /// with every request instead of being repeated per command.
+    pub fn with_preferences(mut self, preferences: Preferences) -> Self {
+        self.preferences = preferences;
+        self
+    }
+
+    /// Act on behalf of `tenant`: the server resolves services (and shows
+    /// only those visible) for that tenant.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// Authenticate with a bearer token; the server maps it to a role that
+    /// decides which permission-gated commands may run.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    /// Run `interceptor` around every call to the server (and to those
+    /// `connect` adds), after those installed before it.
+    pub fn with_interceptor(mut self, interceptor: impl ClientInterceptor) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Connect over TLS, trusting servers whose certificate chains to `ca`.
+    /// Pass `client_cert` when the server requires mutual TLS. Only TCP
+    /// endpoints can use TLS.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(
+        mut self,
+        ca: tonic::transport::Certificate,
+        client_cert: Option<tonic::transport::Identity>,
+    ) -> Self {
+        let mut tls = tonic::transport::ClientTlsConfig::new().ca_certificate(ca);
+        if let Some(identity) = client_cert {
+            tls = tls.identity(identity);
+        }
+        self.tls = Some(tls);
+        self
+    }
+
+    fn connection(&self) -> Connection {
+        Connection {
+            addr: self.addr.clone(),
+            session: SessionInterceptor {
+                preferences: self.preferences.clone(),
+                tenant: self.tenant.clone(),
+                token: self.token.clone(),
+            },
+            interceptors: Arc::new(self.interceptors.clone()),
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone(),
+        }
+    }
+
+    /// Print completion candidates for a one-shot command line, one per line.
+    /// Called by shell completion scripts as `cli-shell __complete <words...>`,
+    /// where the last word is the one being completed. Never fails: an
+    /// unreachable server yields cached or no candidates.
+    pub async fn complete(&self, words: &[String]) {
+        for candidate in crate::completion::complete(self.connection(), words).await {
+            println!("{}", candidate);
+        }
+    }
+
+    /// A completion script for `shell` ("bash", "zsh" or "fish") that
+    /// completes `program`'s one-shot command lines. Services, commands,
+    /// flags and argument choices are read from the server now and baked in;
+    /// completer values are fetched through `program __complete` on <Tab>.
+    pub async fn completion_script(&self, shell: &str, program: &str) -> anyhow::Result<String> {
+        let mut client = self.connection().connect().await?;
+        let services = list_services(&mut client).await?.services;
+        crate::completion_script::script(shell, program, &services)
+    }
+
+    /// Reference pages for the server's services, as file names and
+    /// contents: Markdown for `format` "md", with an `index.md` linking
+    /// them, or man pages for "man". Command lines are spelled as `program`
+    /// runs them.
+    pub async fn docs(&self, format: &str, program: &str) -> anyhow::Result<Vec<(String, String)>> {
+        let mut client = self.connection().connect().await?;
+        let services = list_services(&mut client).await?.services;
+        crate::docs::pages(format, program, &services)
+    }
+
+    /// A Python module with a method per command of the server's
+    /// services, connecting to this CLI's server by default (see
+    /// `crate::python`). `program` is named as the one generating it.
+    pub async fn python_bindings(&self, program: &str) -> anyhow::Result<String> {
+        let mut client = self.connection().connect().await?;
+        let services = list_services(&mut client).await?.services;
+        Ok(crate::python::module(program, &self.addr, &services))
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut client = self.connection().connect().await?;
+
+        // Fetch available services on startup.
+        let listing = list_services(&mut client).await?;
+        let mut services = listing.services.clone();
+        let mut revision = listing.revision;
+        self.services_revision.store(revision, Ordering::Relaxed);
+
+        println!("Connected. Type 'help' for available commands, 'quit' to exit.");
+
+        let favorites = Favorites::load(self.config_dir.as_deref());
+        let mut helper = NexusHelper::from_services(listing, client.clone(), favorites);
+        let mut remotes = BTreeMap::new();
+        helper.set_theme(self.theme());
+        helper.aliases = self.aliases.keys().cloned().collect();
+        // Follow service changes so completion is current even mid-line.
+        let _watcher = AbortOnDrop(tokio::spawn(watch_services(client.clone(), helper.index.clone())));
+        let config = Config::builder()
+            .max_history_size(self.history_size)?
+            .history_ignore_dups(true)?
+            .build();
+        let mut rl = Editor::with_config(config)?;
+        rl.set_helper(Some(helper));
+        // Ctrl-R / Ctrl-S search the history incrementally (rustyline's
+        // defaults); PageUp / PageDown recall entries starting with the text
+        // before the cursor.
+        rl.bind_sequence(KeyEvent(KeyCode::PageUp, Modifiers::NONE), Cmd::HistorySearchBackward);
+        rl.bind_sequence(KeyEvent(KeyCode::PageDown, Modifiers::NONE), Cmd::HistorySearchForward);
+        if let Some(path) = &self.history_file {
+            // A missing file just means no history yet.
+            let _ = rl.load_history(path);
+        }
+
+        // The commands of the line being run still to come, after `&&` or
+        // `;`, and the exit status of the one before.
+        let mut pending: VecDeque<(Link, String)> = VecDeque::new();
+        let mut status = 0;
+        loop {
+            if pending.is_empty() {
+                // The prompt shows when commands only report what they would do.
+                let prompt = if self.dry_run.load(Ordering::Relaxed) {
+                    "cli[dry-run]> "
+                } else {
+                    "cli> "
+                };
+                let line = match rl.readline(prompt) {
+                    Ok(line) => line,
+                    Err(ReadlineError::Interrupted) => {
+                        // Move cursor up to the input line and position right after the
+                        // text, then print ^C.  Column is 1-based: prompt length
+                        // + input length + 1.
+                        let prompt_len = prompt.len();
+                        let input_len = rl
+                            .helper()
+                            .map(|h| *h.last_input_len.lock().unwrap())
+                            .unwrap_or(0);
+                        let col = prompt_len + input_len + 1;
+                        println!("\x1b[A\x1b[{col}G^C");
+                        continue;
+                    }
+                    Err(ReadlineError::Eof) => break,
+                    Err(e) => return Err(e.into()),
+                };
+
+                let mut line = line.trim().to_string();
+                if line.is_empty() {
+                    continue;
+                }
+
+                match expand_history(&line, rl.history()) {
+                    Ok(Some(expanded)) => {
+                        println!("{}", expanded);
+                        line = expanded;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                }
+
+                let _ = rl.add_history_entry(line.as_str());
+                if let Some(path) = &self.history_file {
+                    let _ = rl.append_history(path);
+                }
+
+                match tokenize::split_chain(&line) {
+                    Ok(chain) => pending = chain.into_iter().map(|(link, line)| (link, line.to_string())).collect(),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        continue;
+                    }
+                }
+                status = 0;
+            }
+            let Some((link, line)) = pending.pop_front() else {
+                continue;
+            };
+            // `&&` skips a command once one before it has failed.
+            if link == Link::IfSucceeded && status != 0 {
+                continue;
+            }
+            let line = line.as_str();
+
+            // Adopt the service list the server pushed; failing that (e.g. an
+            // older server), refetch it once a command reports a change.
+            let helper = rl.helper_mut().expect("helper is set");
+            let latest = self.services_revision.load(Ordering::Relaxed);
+            if helper.revision() == revision && latest != revision {
+                if let Ok(listing) = client.list_services(ListServicesRequest::new()).await {
+                    helper.set_services(listing.into_inner(), client.clone());
+                }
+            }
+            if helper.revision() != revision {
+                (services, revision) = helper.services();
+                self.services_revision.store(revision, Ordering::Relaxed);
+            }
+
+            if line == "quit" || line == "exit" {
+                break;
+            }
+
+            // `... | grep x` and `... > file` send what the line prints
+            // there instead, until the end of this iteration.
+            let (line, redirect) = tokenize::split_redirect(line);
+            let _capture = match redirect {
+                Some(_) if line.trim().is_empty() => {
+                    println!("Error: expected a command before '|' or '>'");
+                    status = USAGE_EXIT;
+                    continue;
+                }
+                Some(redirect) => match capture(line, redirect) {
+                    Ok(capture) => Some(capture),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        status = 1;
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            // `set NAME=<value>` assigns a shell variable, and
+            // `set NAME=$(<command>)` the output of a command line.
+            if let Some((name, value)) = strip_builtin(line, "set").and_then(assignment) {
+                let helper = rl.helper_mut().expect("helper is set");
+                let command = value.trim().strip_prefix("$(").and_then(|command| command.strip_suffix(')'));
+                let value = match command {
+                    Some(command) => match tokenize::expand(command, &helper.variables) {
+                        Ok(command) => {
+                            let run = self.reconnecting(&mut client, &mut services, helper, async |client, services| {
+                                self.collect_output(client, services, &command).await
+                            });
+                            tokio::select! {
+                                result = run => result?.flatten(),
+                                _ = tokio::signal::ctrl_c() => {
+                                    println!("\nCancelled.");
+                                    pending.clear();
+                                    None
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            None
+                        }
+                    },
+                    None => match tokenize::expand(value, &helper.variables).and_then(|value| tokenize::split(&value)) {
+                        Ok(words) if words.len() <= 1 => words.into_iter().next().or(Some(String::new())),
+                        Ok(_) => {
+                            println!("Error: the value of {} has several words; quote it", name);
+                            None
+                        }
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            None
+                        }
+                    },
+                };
+                status = match value {
+                    Some(value) => {
+                        let helper = rl.helper_mut().expect("helper is set");
+                        helper.variables.insert(name.to_string(), value);
+                        0
+                    }
+                    None => 1,
+                };
+                continue;
+            }
+
+            let expanded = match tokenize::expand(line, &rl.helper().expect("helper is set").variables) {
+                Ok(expanded) => expanded,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    status = USAGE_EXIT;
+                    continue;
+                }
+            };
+            let words = match tokenize::split(&expanded) {
+                Ok(words) => self.expand_alias(words),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    status = USAGE_EXIT;
+                    continue;
+                }
+            };
+            let parts: Vec<&str> = words.iter().map(String::as_str).collect();
+
+            if parts[0] == "help" {
+                show_help(&services, &parts[1..]);
+                status = 0;
+                continue;
+            }
+
+            // `<service> <command> ... ??` opens the command's long help.
+            if parts.len() > 1 && parts[parts.len() - 1] == "??" {
+                let words = &parts[..parts.len() - 1];
+                match resolve_service(&services, words) {
+                    Some((_, used)) if used < words.len() => {
+                        show_help(&services, &[&words[..=used], &["--full"]].concat())
+                    }
+                    _ => show_help(&services, words),
+                }
+                status = 0;
+                continue;
+            }
+
+            if parts == ["health"] {
+                let helper = rl.helper_mut().expect("helper is set");
+                let healthy = self
+                    .reconnecting(&mut client, &mut services, helper, async |client, _| {
+                        print_health(client, self.format(), &self.theme()).await
+                    })
+                    .await?;
+                status = if healthy == Some(true) { 0 } else { 1 };
+                continue;
+            }
+
+            if parts[0] == "jobs" || parts[0] == "job" {
+                let helper = rl.helper_mut().expect("helper is set");
+                status = self
+                    .reconnecting(&mut client, &mut services, helper, async |client, _| {
+                        job_builtin(client, &parts, self.format(), &self.theme()).await
+                    })
+                    .await?
+                    .unwrap_or(1);
+                continue;
+            }
+
+            if parts[0] == "last" {
+                let helper = rl.helper_mut().expect("helper is set");
+                status = self
+                    .reconnecting(&mut client, &mut services, helper, async |client, _| {
+                        last_builtin(client, &parts, self.format(), &self.theme()).await
+                    })
+                    .await?
+                    .unwrap_or(1);
+                continue;
+            }
+
+            if parts[0] == "subscribe" {
+                let helper = rl.helper_mut().expect("helper is set");
+                let run = self.reconnecting(&mut client, &mut services, helper, async |client, _| {
+                    subscribe_builtin(client, &parts[1..], self.format(), &self.theme()).await
+                });
+                // Events are printed until Ctrl-C, which also ends the line.
+                tokio::select! {
+                    result = run => {
+                        status = if result?.is_some() { 0 } else { 1 };
+                    }
+                    _ = tokio::signal::ctrl_c() => {
+                        println!();
+                        pending.clear();
+                    }
+                }
+                continue;
+            }
+
+            if parts[0] == "put" || parts[0] == "get" {
+                let helper = rl.helper_mut().expect("helper is set");
+                let key = idempotency_key();
+                let run = self.reconnecting(&mut client, &mut services, helper, async |client, services| {
+                    self.transfer(client, services, &parts, &key).await
+                });
+                tokio::select! {
+                    result = run => {
+                        status = result?.unwrap_or(1);
+                    }
+                    _ = tokio::signal::ctrl_c() => {
+                        println!("\nCancelled.");
+                        pending.clear();
+                    }
+                }
+                continue;
+            }
+
+            if parts[0] == "watch" {
+                let helper = rl.helper_mut().expect("helper is set");
+                let run = self.reconnecting(&mut client, &mut services, helper, async |client, services| {
+                    self.watch(client, services, &parts[1..]).await
+                });
+                // Watching goes on until Ctrl-C, which also ends the line.
+                tokio::select! {
+                    result = run => {
+                        status = if result?.is_some() { 0 } else { 1 };
+                    }
+                    _ = tokio::signal::ctrl_c() => {
+                        println!();
+                        pending.clear();
+                    }
+                }
+                continue;
+            }
+
+            if parts == ["history"] {
+                for (i, entry) in rl.history().iter().enumerate() {
+                    println!("{:>5}  {}", i + 1, entry);
+                }
+                status = 0;
+                continue;
+            }
+
+            if parts[0] == "describe" {
+                status = if describe(&services, &parts[1..]) { 0 } else { USAGE_EXIT };
+                continue;
+            }
+
+            if matches!(parts[0], "pin" | "unpin" | "favorites") {
+                let helper = rl.helper().expect("helper is set");
+                let mut favorites = helper.favorites.lock().unwrap();
+                pin_builtin(&mut favorites, &services, &parts);
+                status = 0;
+                continue;
+            }
+
+            if matches!(parts[0], "connect" | "connections" | "disconnect") {
+                let helper = rl.helper_mut().expect("helper is set");
+                status = if self.connection_builtin(&mut remotes, helper, &services, &parts).await { 0 } else { 1 };
+                continue;
+            }
+
+            // "<connection> ..." runs the rest of the line on that server.
+            if let Some(remote) = remotes.get_mut(parts[0]) {
+                let helper = rl.helper_mut().expect("helper is set");
+                let helper = helper.connections.get_mut(parts[0]).expect("every connection has completion data");
+                let (mut remote_services, remote_revision) = helper.services();
+                let paging = self.paging.load(Ordering::Relaxed);
+                let key = idempotency_key();
+                let run = self.reconnecting_to(
+                    &remote.connection,
+                    &mut remote.client,
+                    &mut remote_services,
+                    helper,
+                    async |client, services| self.run_line(client, services, &parts[1..], paging, &key).await,
+                );
+                tokio::select! {
+                    result = run => {
+                        status = result?.unwrap_or(1);
+                    }
+                    _ = tokio::signal::ctrl_c() => {
+                        println!("\nCancelled.");
+                        pending.clear();
+                    }
+                }
+                // The response reported the revision of that server's
+                // services, not this one's; refetch them if they changed.
+                let latest = self.services_revision.swap(revision, Ordering::Relaxed);
+                if latest != remote_revision && helper.revision() == remote_revision {
+                    if let Ok(listing) = remote.client.list_services(ListServicesRequest::new()).await {
+                        helper.set_services(listing.into_inner(), remote.client.clone());
+                    }
+                }
+                continue;
+            }
+
+            if parts == ["set"] {
+                let helper = rl.helper().expect("helper is set");
+                if helper.variables.is_empty() {
+                    println!("No variables. Set one with 'set NAME=<value>'.");
+                }
+                for (name, value) in &helper.variables {
+                    println!("{}={}", name, tokenize::quote(value));
+                }
+                status = 0;
+                continue;
+            }
+
+            if parts[0] == "set" {
+                status = if self.set_builtin(&parts[1..]) { 0 } else { USAGE_EXIT };
+                rl.helper_mut().expect("helper is set").set_theme(self.theme());
+                continue;
+            }
+
+            if parts[0] == "unset" {
+                let helper = rl.helper_mut().expect("helper is set");
+                status = 0;
+                if parts.len() == 1 {
+                    println!("Usage: unset <name>...");
+                    status = USAGE_EXIT;
+                }
+                for name in &parts[1..] {
+                    if helper.variables.remove(*name).is_none() {
+                        println!("Error: unknown variable '{}'", name);
+                        status = 1;
+                    }
+                }
+                continue;
+            }
+
+            if parts[0] == "preview" {
+                let (service, action, args) = match split_command(&services, &parts[1..]) {
+                    Ok(Some(command)) => command,
+                    Ok(None) => {
+                        println!("Usage: preview <service> <command> [args...]");
+                        status = USAGE_EXIT;
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        status = USAGE_EXIT;
+                        continue;
+                    }
+                };
+                let (args, named_args) = split_args(args);
+                let request = CommandRequest {
+                    service,
+                    action,
+                    args,
+                    named_args,
+                    dry_run: true,
+                    ..Default::default()
+                };
+                let helper = rl.helper_mut().expect("helper is set");
+                let response = self
+                    .reconnecting(&mut client, &mut services, helper, async |client, _| {
+                        Ok(execute_with_retry(client, request.clone(), &self.retry_policy, None).await?)
+                    })
+                    .await?;
+                let Some(response) = response else {
+                    status = 1;
+                    continue;
+                };
+                self.observe(&response);
+                if !response.success {
+                    print_response(&response, self.format(), &self.theme());
+                    status = exit_status(&response);
+                    continue;
+                }
+                if !response.message.is_empty() {
+                    print_response(&response, self.format(), &self.theme());
+                }
+                if response.plan.is_empty() {
+                    println!("No changes.");
+                    status = 0;
+                    continue;
+                }
+                print_plan(&response.plan, &self.theme());
+                let apply = matches!(
+                    rl.readline("Apply these changes? [y/N] "),
+                    Ok(answer) if matches!(answer.trim(), "y" | "Y" | "yes")
+                );
+                if !apply {
+                    println!("Cancelled.");
+                    status = 1;
+                    continue;
+                }
+                let request = CommandRequest {
+                    dry_run: false,
+                    idempotency_key: idempotency_key(),
+                    ..request
+                };
+                let helper = rl.helper_mut().expect("helper is set");
+                let response = self
+                    .reconnecting(&mut client, &mut services, helper, async |client, _| {
+                        Ok(execute_with_retry(client, request.clone(), &self.retry_policy, None).await?)
+                    })
+                    .await?;
+                status = match response {
+                    Some(response) => {
+                        self.observe(&response);
+                        print_response(&response, self.format(), &self.theme());
+                        exit_status(&response)
+                    }
+                    None => 1,
+                };
+                continue;
+            }
+
+            let (options, parts) = match self.command_options(&parts) {
+                Ok((options, parts @ [_, _, ..])) => (options, parts),
+                Ok(_) => {
+                    println!("{}", COMMAND_USAGE);
+                    status = USAGE_EXIT;
+                    continue;
+                }
+                Err(e) => {
+                    println!("Error: {}", e);
+                    status = USAGE_EXIT;
+                    continue;
+                }
+            };
+
+            let helper = rl.helper_mut().expect("helper is set");
+            // One key for the command, so that resending it after a
+            // reconnect does not run it twice.
+            let key = idempotency_key();
+            let run = self.reconnecting(&mut client, &mut services, helper, async |client, services| {
+                self.dispatch(client, services, parts, options, self.paging.load(Ordering::Relaxed), &key).await
+            });
+            // Ctrl-C abandons the call, which cancels the command on the
+            // server, and returns to the prompt without running the rest of
+            // the line.
+            tokio::select! {
+                result = run => {
+                    status = result?.unwrap_or(1);
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    println!("\nCancelled.");
+                    pending.clear();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the shell line `line` (`set NAME=$(<line>)`) with its output
+    /// collected instead of printed, and return that output without its
+    /// trailing newline. If the line fails, its output is printed after all
+    /// and `None` returned.
+    #[cfg(unix)]
+    async fn collect_output(
+        &self,
+        client: &mut Client,
+        services: &[ServiceInfo],
+        line: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let chain = match tokenize::split_chain(line) {
+            Ok(chain) if chain.is_empty() => {
+                println!("Error: expected a command inside '$(...)'");
+                return Ok(None);
+            }
+            Ok(chain) => chain,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(None);
+            }
+        };
+        let (capture, output) = crate::redirect::Capture::collect()?;
+        let status = self.run_chain(client, services, &chain).await;
+        drop(capture);
+        let output = String::from_utf8_lossy(&output.lock().unwrap()).trim_end_matches('\n').to_string();
+        match status? {
+            0 => Ok(Some(output)),
+            _ => {
+                if !output.is_empty() {
+                    println!("{}", output);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn collect_output(
+        &self,
+        _client: &mut Client,
+        _services: &[ServiceInfo],
+        _line: &str,
+    ) -> anyhow::Result<Option<String>> {
+        println!("Error: '$(...)' is only supported on Unix");
+        Ok(None)
+    }
+
+    /// `watch <interval> <service> <command> [args...]`: run the command
+    /// every `interval` until interrupted, each time clearing the screen and
+    /// printing the result with the lines that changed since the previous
+    /// run highlighted.
+    async fn watch(&self, client: &mut Client, services: &[ServiceInfo], args: &[&str]) -> anyhow::Result<()> {
+        let parsed = match args.split_first() {
+            Some((interval, parts)) => split_command(services, parts).map(|command| Some((*interval, parts, command?))),
+            None => Ok(None),
+        };
+        let (interval, parts, (service, action, args)) = match parsed {
+            Ok(Some(parsed)) => parsed,
+            Ok(None) => {
+                println!("Usage: watch <interval> <service> <command> [args...]");
+                return Ok(());
+            }
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+        let Some(interval) = parse_interval(interval) else {
+            println!(
+                "Error: invalid interval '{}': expected seconds or a duration such as \"500ms\" or \"1m\"",
+                interval
+            );
+            return Ok(());
+        };
+        let (args, named_args) = split_args(args);
+        let format = self.format();
+        let request = CommandRequest {
+            service,
+            action,
+            args,
+            named_args,
+            dry_run: self.dry_run.load(Ordering::Relaxed),
+            format: format.as_str().to_string(),
+            ..Default::default()
+        };
+        let title = format!("Every {}: {}", format_duration(interval), tokenize::join(parts));
+
+        let mut previous: Option<Vec<String>> = None;
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let response = execute_with_retry(client, request.clone(), &self.retry_policy, None).await?;
+            self.observe(&response);
+            let theme = self.theme();
+            // Lines are compared without their colors.
+            let plain = response_text(&response, format, &Theme::PLAIN);
+            let styled = response_text(&response, format, &theme);
+            let lines: Vec<String> = plain.lines().map(String::from).collect();
+
+            print!("\x1b[2J\x1b[H");
+            println!("{}\n", theme.header.paint(&title));
+            for (i, (line, styled)) in lines.iter().zip(styled.lines()).enumerate() {
+                let changed = previous.as_ref().is_some_and(|previous| previous.get(i) != Some(line));
+                if changed {
+                    println!("{}", theme.changed.paint(line));
+                } else {
+                    println!("{}", styled);
+                }
+            }
+            for warning in &response.warnings {
+                eprintln!("{}", theme.warning.paint(format_args!("Warning: {}", warning)));
+            }
+            std::io::stdout().flush()?;
+            previous = Some(lines);
+        }
+    }
+
+    /// Run `call`; if the server turns out to be unreachable (e.g. it is
+    /// restarting), reconnect with backoff, refresh `services` and the
+    /// completion data, and run it once more. `None` means the server could
+    /// not be reached again; that has been reported and the shell goes on.
+    async fn reconnecting<T>(
+        &self,
+        client: &mut Client,
+        services: &mut Vec<ServiceInfo>,
+        helper: &mut NexusHelper,
+        call: impl AsyncFnMut(&mut Client, &[ServiceInfo]) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<T>> {
+        self.reconnecting_to(&self.connection(), client, services, helper, call).await
+    }
+
+    /// `reconnecting`, for the server of `connection`.
+    async fn reconnecting_to<T>(
+        &self,
+        connection: &Connection,
+        client: &mut Client,
+        services: &mut Vec<ServiceInfo>,
+        helper: &mut NexusHelper,
+        mut call: impl AsyncFnMut(&mut Client, &[ServiceInfo]) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Option<T>> {
+        match call(client, services).await {
+            Err(e) if is_disconnected(&e) => {}
+            result => return result.map(Some),
+        }
+        println!("Connection lost, reconnecting...");
+        let mut delay = RECONNECT_DELAY;
+        let mut attempt = 1;
+        let (new_client, listing) = loop {
+            match connect_and_list(connection).await {
+                Ok(connected) => break connected,
+                Err(e) if attempt >= RECONNECT_ATTEMPTS => {
+                    println!("Error: server unavailable: {:#}", e);
+                    return Ok(None);
+                }
+                Err(_) => {}
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            attempt += 1;
+        };
+        println!("Reconnected.");
+        *client = new_client;
+        services.clone_from(&listing.services);
+        helper.set_services(listing, client.clone());
+        match call(client, services).await {
+            Err(e) if is_disconnected(&e) => {
+                println!("Error: server unavailable: {}", status_message(&e));
+                Ok(None)
+            }
+            result => result.map(Some),
+        }
+    }
+
+    /// The `connect <name> <address>`, `connections` and `disconnect
+    /// <name>` builtins: open a connection to another server (with this
+    /// shell's tenant, token and TLS settings), list the connections, and
+    /// close one. Returns whether the builtin succeeded.
+    async fn connection_builtin(
+        &self,
+        remotes: &mut BTreeMap<String, Remote>,
+        helper: &mut NexusHelper,
+        services: &[ServiceInfo],
+        parts: &[&str],
+    ) -> bool {
+        match parts {
+            ["connect", name, addr] => {
+                if BUILTINS.contains(name) || COMMAND_OPTIONS.contains(name) {
+                    println!("Error: '{}' is a builtin; pick another connection name", name);
+                    return false;
+                } else if find_service(services, name).is_some() {
+                    println!("Error: '{}' is a service; pick another connection name", name);
+                    return false;
+                } else if remotes.contains_key(*name) {
+                    println!("Error: already connected as '{}'; disconnect it first", name);
+                    return false;
+                }
+                let connection = Connection {
+                    addr: addr.to_string(),
+                    ..self.connection()
+                };
+                match connect_and_list(&connection).await {
+                    Ok((client, listing)) => {
+                        println!("Connected to {} as '{}' ({} services).", addr, name, listing.services.len());
+                        let favorites = Favorites::load(None);
+                        let mut remote = NexusHelper::from_services(listing, client.clone(), favorites);
+                        remote.set_theme(self.theme());
+                        let watcher = tokio::spawn(watch_services(client.clone(), remote.index.clone()));
+                        helper.connections.insert(name.to_string(), remote);
+                        remotes.insert(
+                            name.to_string(),
+                            Remote {
+                                connection,
+                                client,
+                                _watcher: AbortOnDrop(watcher),
+                            },
+                        );
+                    }
+                    Err(e) => {
+                        println!("Error: failed to connect to {}: {:#}", addr, e);
+                        return false;
+                    }
+                }
+            }
+            ["connections"] => {
+                // The shell's own server first, without a name.
+                let remote_rows = remotes.iter().map(|(name, remote)| {
+                    let services = helper.connections[name].index.lock().unwrap().services.len();
+                    (Some(name.as_str()), remote.connection.addr.as_str(), services)
+                });
+                let connections = std::iter::once((None, self.addr.as_str(), services.len())).chain(remote_rows);
+                let format = self.format();
+                if format.is_machine_readable() {
+                    let connections = connections
+                        .map(|(name, addr, services)| {
+                            serde_json::json!({ "name": name, "address": addr, "services": services })
+                        })
+                        .collect();
+                    print_value(&serde_json::Value::Array(connections), format);
+                    return true;
+                }
+                let headers = ["connection", "address", "services"].map(String::from).to_vec();
+                let rows: Vec<Vec<String>> = connections
+                    .map(|(name, addr, services)| {
+                        vec![name.unwrap_or("(default)").to_string(), addr.to_string(), services.to_string()]
+                    })
+                    .collect();
+                let rendered = crate::registry::format_table(&headers, &rows);
+                let (header, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+                println!("{}", self.theme().header.paint(header));
+                println!("{}", body);
+            }
+            ["disconnect", name] => {
+                if remotes.remove(*name).is_some() {
+                    helper.connections.remove(*name);
+                    println!("Disconnected '{}'.", name);
+                } else {
+                    println!("Error: no connection named '{}'", name);
+                    return false;
+                }
+            }
+            _ => {
+                println!("Usage: connect <name> <address> | connections | disconnect <name>");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Execute a single command line (`<service> <command> [args...]` or
+    /// `help [service [command [--full]]]`) without starting the interactive shell. Returns
+    /// the process exit status: 0 on success, the `ErrorCode::exit_code` of
+    /// a failed command, 2 for usage errors and 1 for other failures.
+    pub async fn run_once(&self, words: &[String]) -> anyhow::Result<u8> {
+        let mut client = self.connection().connect().await?;
+        let services = list_services(&mut client).await?.services;
+
+        let words = self.expand_alias(words.to_vec());
+        let parts: Vec<&str> = words.iter().map(String::as_str).collect();
+        self.run_line(&mut client, &services, &parts, false, &idempotency_key()).await
+    }
+
+    /// Execute the commands in the file at `path`, one per line, in order.
+    /// Blank lines and lines starting with `#` are skipped. Stops at the
+    /// first failing command unless `with_keep_going` is set; returns whether
+    /// every command succeeded.
+    pub async fn run_script(&self, path: impl AsRef<Path>) -> anyhow::Result<bool> {
+        let path = path.as_ref();
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("failed to read script {}: {}", path.display(), e))?;
+
+        let mut client = self.connection().connect().await?;
+        let services = list_services(&mut client).await?.services;
+
+        let mut success = true;
+        for (number, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let ran = match tokenize::split_chain(line) {
+                Ok(chain) => self.run_chain(&mut client, &services, &chain).await? == 0,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    false
+                }
+            };
+            if !ran {
+                eprintln!("{}:{}: command failed: {}", path.display(), number + 1, line);
+                success = false;
+                if !self.keep_going {
+                    break;
+                }
+            }
+        }
+        Ok(success)
+    }
+
+    /// Run the commands of a script line (or of `$(...)`) in turn, those
+    /// after `&&` only if the one before succeeded. Returns the exit status of the last one
+    /// run, as a shell does.
+    async fn run_chain(&self, client: &mut Client, services: &[ServiceInfo], chain: &[(Link, &str)]) -> anyhow::Result<u8> {
+        let mut status = 0;
+        for (link, command) in chain {
+            if *link == Link::IfSucceeded && status != 0 {
+                continue;
+            }
+            status = match tokenize::split(command) {
+                Ok(words) => {
+                    let words = self.expand_alias(words);
+                    let parts: Vec<&str> = words.iter().map(String::as_str).collect();
+                    self.run_line(client, services, &parts, false, &idempotency_key()).await?
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    USAGE_EXIT
+                }
+            };
+        }
+        Ok(status)
+    }
+
+    /// Execute one command line without the shell's own builtins, as in
+    /// one-shot mode, scripts and lines for another connection. With `page`,
+    /// a long result is shown in the pager. The command is sent with the
+    /// idempotency `key`. Returns its exit status (see `run_once`).
+    async fn run_line(
+        &self,
+        client: &mut Client,
+        services: &[ServiceInfo],
+        parts: &[&str],
+        page: bool,
+        key: &str,
+    ) -> anyhow::Result<u8> {
+        match parts {
+            ["help", rest @ ..] => {
+                show_help(services, rest);
+                return Ok(0);
+            }
+            ["health"] => return Ok(if print_health(client, self.format(), &self.theme()).await? { 0 } else { 1 }),
+            ["describe", rest @ ..] => return Ok(if describe(services, rest) { 0 } else { USAGE_EXIT }),
+            ["jobs" | "job", ..] => return job_builtin(client, parts, self.format(), &self.theme()).await,
+            ["last", ..] => return last_builtin(client, parts, self.format(), &self.theme()).await,
+            ["put" | "get", ..] => return self.transfer(client, services, parts, key).await,
+            ["set", rest @ ..] => return Ok(if self.set_builtin(rest) { 0 } else { USAGE_EXIT }),
+            _ => {}
+        }
+        match self.command_options(parts) {
+            Ok((options, parts @ [_, _, ..])) => self.dispatch(client, services, parts, options, page, key).await,
+            Ok(_) => {
+                eprintln!("{}", COMMAND_USAGE);
+                Ok(USAGE_EXIT)
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                Ok(USAGE_EXIT)
+            }
+        }
+    }
+
+    /// Split the leading options, in any order, off a command line: with
+    /// `--dry-run` (or the session in dry-run mode) it runs as a dry run,
+    /// and with `--timeout <duration>` the server gives up on it once the
+    /// duration has passed. Fails for a duration it cannot read.
+    fn command_options<'a, 'b>(&self, parts: &'a [&'b str]) -> Result<(CommandOptions, &'a [&'b str]), String> {
+        let mut options = CommandOptions {
+            dry_run: self.dry_run.load(Ordering::Relaxed),
+            deadline: None,
+        };
+        let mut parts = parts;
+        loop {
+            parts = match parts {
+                ["--dry-run", rest @ ..] => {
+                    options.dry_run = true;
+                    rest
+                }
+                ["--timeout", timeout, rest @ ..] => {
+                    let timeout = crate::config::parse_duration(timeout).ok_or_else(|| {
+                        format!("invalid timeout '{}': expected a duration such as 500ms, 30s, 5m or 1h", timeout)
+                    })?;
+                    options.deadline = Some(Instant::now() + timeout);
+                    rest
+                }
+                _ => return Ok((options, parts)),
+            };
+        }
+    }
+
+    /// `set dry-run on|off`: whether the following commands run as dry
+    /// runs; `set format <format>`: how their results are printed. Returns
+    /// false (after printing the usage) for anything else.
+    fn set_builtin(&self, args: &[&str]) -> bool {
+        match args {
+            ["dry-run", "on"] => self.dry_run.store(true, Ordering::Relaxed),
+            ["dry-run", "off"] => self.dry_run.store(false, Ordering::Relaxed),
+            ["paging", "on"] => self.paging.store(true, Ordering::Relaxed),
+            ["paging", "off"] => self.paging.store(false, Ordering::Relaxed),
+            ["color", "on"] => self.color.store(true, Ordering::Relaxed),
+            ["color", "off"] => self.color.store(false, Ordering::Relaxed),
+            ["format", format] => match format.parse() {
+                Ok(format) => *self.format.lock().unwrap() = format,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    return false;
+                }
+            },
+            _ => {
+                eprintln!("Usage: set dry-run on|off | set paging on|off | set color on|off | set format plain|json|yaml|table");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Send `<service> <command> [args...]` and print the result, streaming
+    /// it line by line for streaming commands. A dry run prints the planned
+    /// changes instead. With `page`, a result taller than the terminal is
+    /// shown in the pager. The request carries the idempotency `key`.
+    /// Returns its exit status (see `run_once`).
+    async fn dispatch(
+        &self,
+        client: &mut Client,
+        services: &[ServiceInfo],
+        parts: &[&str],
+        options: CommandOptions,
+        page: bool,
+        key: &str,
+    ) -> anyhow::Result<u8> {
+        let CommandOptions { dry_run, deadline } = options;
+        let (service, action, args) = match split_command(services, parts) {
+            Ok(Some(command)) => command,
+            Ok(None) => {
+                eprintln!("Usage: {} <command> [args...]", parts.join(" "));
+                return Ok(USAGE_EXIT);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Ok(USAGE_EXIT);
+            }
+        };
+        let (args, named_args) = split_args(args);
+
+        let command = find_service(services, &service).and_then(|s| s.commands.iter().find(|c| c.name == action));
+        if let Some(deprecation) = command.and_then(manual::deprecation) {
+            let warning = format!("Warning: '{} {}' is {}", command_path(&service), action, deprecation);
+            eprintln!("{}", self.theme().warning.paint(warning));
+        }
+        // Dry runs are rejected before anything streams or makes progress.
+        let streaming = command.is_some_and(|c| c.streaming) && !dry_run;
+        let interactive = command.is_some_and(|c| c.interactive);
+        // A progress bar needs a terminal to draw on; elsewhere only the
+        // result is shown.
+        let format = self.format();
+        let progress = command.is_some_and(|c| c.progress && !c.interactive)
+            && !dry_run
+            && !format.is_machine_readable()
+            && std::io::stderr().is_terminal();
+
+        let command = format!("{} {}", command_path(&service), action);
+        let request = CommandRequest {
+            service,
+            action,
+            args,
+            named_args,
+            dry_run,
+            format: format.as_str().to_string(),
+            idempotency_key: key.to_string(),
+            ..Default::default()
+        };
+        let response = if streaming {
+            // A busy command (say, one whose `try_lock` is taken) fails
+            // before its first line, so retrying repeats no output.
+            let response = retry_busy(&self.retry_policy, async || {
+                execute_streaming(client, request.clone(), deadline, format).await
+            })
+            .await
+            .or_else(|e| expired(e, deadline, &command))?;
+            if !response.success {
+                print_response(&response, format, &self.theme());
+            }
+            response
+        } else if progress {
+            let response = retry_busy(&self.retry_policy, async || {
+                execute_with_progress(client, request.clone(), deadline).await
             })
-            .collect::<Vec<_>>()
-            .join(" ");
-        println!("  {} {}", cmd.name, args_str);
-        if !cmd.description.is_empty() {
-            println!("    {}", cmd.description);
-        }
-        for arg in &cmd.args {
-            let label = if arg.hint.is_empty() { &arg.name } else { &arg.hint };
-            let has_desc = !arg.description.is_empty();
-            let has_comp = !arg.completer.is_empty();
-            if has_desc || has_comp {
-                let mut parts = vec![format!("    <{}>", label)];
-                if has_desc {
-                    parts.push(arg.description.clone());
+            .await
+            .or_else(|e| expired(e, deadline, &command))?;
+            show_response(&response, format, &self.theme(), page);
+            response
+        } else {
+            let response = if interactive {
+                session::run(client, request, deadline, &InlinePrompts).await
+            } else {
+                execute_with_retry(client, request, &self.retry_policy, deadline).await
+            };
+            let response = response
+                .map_err(anyhow::Error::from)
+                .or_else(|e| expired(e, deadline, &command))?;
+            show_response(&response, format, &self.theme(), page);
+            if dry_run && response.success && !format.is_machine_readable() {
+                match response.plan.as_slice() {
+                    [] => println!("No changes."),
+                    plan => print_plan(plan, &self.theme()),
+                }
+            }
+            response
+        };
+        self.observe(&response);
+        Ok(exit_status(&response))
+    }
+
+    /// `put <file> <service> <command> [args...]`: run a command that takes
+    /// a file, sending `file` with it. `get <file> <service> <command>
+    /// [args...]`: run one that sends a file back, saving it as `file`.
+    /// Returns the exit status (see `run_once`).
+    async fn transfer(
+        &self,
+        client: &mut Client,
+        services: &[ServiceInfo],
+        parts: &[&str],
+        key: &str,
+    ) -> anyhow::Result<u8> {
+        let usage = || {
+            eprintln!("Usage: {} <file> <service> <command> [args...]", parts[0]);
+            Ok(USAGE_EXIT)
+        };
+        let [builtin, file, command @ ..] = parts else {
+            return usage();
+        };
+        let (service, action, args) = match split_command(services, command) {
+            Ok(Some(command)) => command,
+            Ok(None) => return usage(),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return Ok(USAGE_EXIT);
+            }
+        };
+        let (args, named_args) = split_args(args);
+        let format = self.format();
+        let request = CommandRequest {
+            service,
+            action,
+            args,
+            named_args,
+            format: format.as_str().to_string(),
+            idempotency_key: key.to_string(),
+            ..Default::default()
+        };
+        let path = Path::new(file);
+        let result = if *builtin == "put" {
+            transfer::send_file(client, request, path).await.map(|response| (response, None))
+        } else {
+            let received = transfer::receive_file(client, request, path).await;
+            received.map(|(response, size)| (response, Some(size)))
+        };
+        let (response, saved) = match result {
+            Ok(result) => result,
+            // Failed calls are left to the caller, which reconnects if the
+            // server went away.
+            Err(e) if e.downcast_ref::<tonic::Status>().is_some() => return Err(e),
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                return Ok(1);
+            }
+        };
+        print_response(&response, format, &self.theme());
+        if let Some(size) = saved.filter(|_| response.success && !format.is_machine_readable()) {
+            println!("{}", self.theme().hint.paint(format!("Saved {} bytes to {}", size, file)));
+        }
+        self.observe(&response);
+        Ok(exit_status(&response))
+    }
+
+    /// Note the service list revision a response reports, so the shell can
+    /// refresh its completion and help data once services change.
+    fn observe(&self, response: &CommandResponse) {
+        self.services_revision
+            .store(response.services_revision, Ordering::Relaxed);
+    }
+}
+
+/// The exit status of a command with `response`: 0 on success, else the
+/// exit code of its error.
+fn exit_status(response: &CommandResponse) -> u8 {
+    if response.success {
+        return 0;
+    }
+    error_code_from_proto(response).exit_code()
+}
+
+/// Redirect the output of the shell line `line` as `redirect` asks.
+#[cfg(unix)]
+fn capture(line: &str, redirect: tokenize::Redirect<'_>) -> anyhow::Result<crate::redirect::Capture> {
+    // Its confirmation prompt would disappear into the redirection.
+    if strip_builtin(line, "preview").is_some() {
+        anyhow::bail!("the output of preview cannot be redirected; use --dry-run");
+    }
+    crate::redirect::Capture::start(redirect)
+}
+
+#[cfg(not(unix))]
+fn capture(_line: &str, _redirect: tokenize::Redirect<'_>) -> anyhow::Result<()> {
+    anyhow::bail!("output redirection is only supported on Unix")
+}
+
+/// Connect to the server of `connection` and fetch its service list.
+async fn connect_and_list(connection: &Connection) -> anyhow::Result<(Client, ListServicesResponse)> {
+    let mut client = connection.connect().await?;
+    let listing = list_services(&mut client).await?;
+    Ok((client, listing))
+}
+
+/// Fetch the server's service list, refusing a server that speaks a newer
+/// protocol than this client: it may no longer understand the requests.
+async fn list_services(client: &mut Client) -> anyhow::Result<ListServicesResponse> {
+    let listing = client.list_services(ListServicesRequest::new()).await?.into_inner();
+    if listing.protocol_version > crate::PROTOCOL_VERSION {
+        anyhow::bail!(
+            "the server speaks protocol version {}, newer than this client's {}; upgrade the client",
+            listing.protocol_version,
+            crate::PROTOCOL_VERSION
+        );
+    }
+    Ok(listing)
+}
+
+/// Keep `index` in step with the server's services by following
+/// `WatchServices`, re-subscribing after the connection drops. Gives up on
+/// servers without the RPC; the shell then refreshes after commands instead.
+async fn watch_services(mut client: Client, index: Arc<std::sync::Mutex<ServiceIndex>>) {
+    loop {
+        match client.watch_services(ListServicesRequest::new()).await {
+            Ok(response) => {
+                let mut listings = response.into_inner();
+                while let Ok(Some(listing)) = listings.message().await {
+                    *index.lock().unwrap() = ServiceIndex::new(listing);
+                }
+            }
+            Err(status) if status.code() == tonic::Code::Unimplemented => return,
+            Err(_) => {}
+        }
+        tokio::time::sleep(RECONNECT_MAX_DELAY).await;
+    }
+}
+
+/// Run a streaming command, printing its output lines as they arrive. In
+/// JSON mode each line is printed as a JSON string, and in YAML mode as an
+/// item of a list. The server ends the stream at `deadline`, if there is
+/// one. Returns the final result.
+async fn execute_streaming(
+    client: &mut Client,
+    request: CommandRequest,
+    deadline: Option<Instant>,
+    format: OutputFormat,
+) -> anyhow::Result<CommandResponse> {
+    let mut frames = client.execute_stream(with_deadline(request, deadline)).await?.into_inner();
+    while let Some(frame) = frames.message().await? {
+        match frame.frame {
+            Some(Frame::Line(line)) if format == OutputFormat::Json => {
+                println!("{}", serde_json::Value::String(line))
+            }
+            Some(Frame::Line(line)) if format == OutputFormat::Yaml => {
+                print!("{}", crate::yaml::to_string(&serde_json::json!([line])))
+            }
+            Some(Frame::Line(line)) => println!("{}", line),
+            Some(Frame::Result(response)) => return Ok(chunked::complete(client, response).await?),
+            Some(Frame::Heartbeat(_) | Frame::Progress(_)) | None => {}
+        }
+    }
+    anyhow::bail!("stream ended without a result")
+}
+
+/// Run a command that reports progress, drawing a progress bar on stderr
+/// until its result arrives (or `deadline`, if there is one, passes).
+/// Returns the result, not yet printed.
+async fn execute_with_progress(
+    client: &mut Client,
+    request: CommandRequest,
+    deadline: Option<Instant>,
+) -> anyhow::Result<CommandResponse> {
+    let mut frames = client.execute_stream(with_deadline(request, deadline)).await?.into_inner();
+    let mut drawn = false;
+    while let Some(frame) = frames.message().await? {
+        match frame.frame {
+            Some(Frame::Progress(update)) => {
+                eprint!("\r\x1b[2K{}", progress_bar(&update));
+                let _ = std::io::stderr().flush();
+                drawn = true;
+            }
+            Some(Frame::Result(response)) => {
+                if drawn {
+                    eprint!("\r\x1b[2K");
                 }
+                return Ok(chunked::complete(client, response).await?);
+            }
+            Some(Frame::Line(_) | Frame::Heartbeat(_)) | None => {}
+        }
+    }
+    anyhow::bail!("stream ended without a result")
+}
+
+/// The shell's end of an interactive command: its prompts are read on the
+/// shell's terminal (or input) as `question [a/b] (default) `, one line
+/// each, and its messages printed.
+struct InlinePrompts;
+
+#[async_trait]
+impl SessionService for InlinePrompts {
+    async fn prompt(&self, prompt: Prompt) -> anyhow::Result<String> {
+        let mut line = prompt.text.clone();
+        if !prompt.choices.is_empty() {
+            let _ = write!(line, " [{}]", prompt.choices.join("/"));
+        }
+        if let Some(default) = &prompt.default {
+            let _ = write!(line, " ({})", default);
+        }
+        line.push(' ');
+        // Without a terminal the editor shows no prompt, so the question
+        // is printed for the transcript.
+        if !std::io::stdin().is_terminal() {
+            println!("{}", line.trim_end());
+        }
+        // The editor blocks until the line is in; Ctrl-C or Ctrl-D give
+        // no answer.
+        let answer = tokio::task::spawn_blocking(move || {
+            if prompt.secret {
+                let mut editor = Editor::<Masked, DefaultHistory>::new()?;
+                editor.set_helper(Some(Masked));
+                editor.readline(&line)
+            } else {
+                rustyline::DefaultEditor::new()?.readline(&line)
+            }
+        })
+        .await?;
+        Ok(answer?)
+    }
+
+    async fn say(&self, message: &str) -> anyhow::Result<()> {
+        println!("{}", message);
+        Ok(())
+    }
+}
+
+/// Shows a secret answer as `*`s while it is typed.
+struct Masked;
+
+impl Highlighter for Masked {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        Cow::Owned("*".repeat(line.chars().count()))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        true
+    }
+}
+
+impl Completer for Masked {
+    type Candidate = String;
+}
+
+impl Hinter for Masked {
+    type Hint = String;
+}
+
+impl Validator for Masked {}
+
+impl Helper for Masked {}
+
+/// A progress update as one line: `[#######-------]  50%  [2/4] Copying data`.
+fn progress_bar(update: &ProgressUpdate) -> String {
+    const WIDTH: usize = 30;
+    let percent = update.percent.min(100);
+    let filled = percent as usize * WIDTH / 100;
+    let mut bar = format!("[{}{}] {:>3}%", "#".repeat(filled), "-".repeat(WIDTH - filled), percent);
+    if update.total_steps > 0 {
+        let _ = write!(bar, "  [{}/{}]", update.step, update.total_steps);
+    }
+    if !update.message.is_empty() {
+        let _ = write!(bar, " {}", update.message);
+    }
+    bar
+}
+
+/// A `watch` interval: whole seconds ("2"), or a duration such as "500ms".
+fn parse_interval(s: &str) -> Option<Duration> {
+    match s.parse::<u64>() {
+        Ok(0) => None,
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => config::parse_duration(s),
+    }
+}
+
+/// Structured output of a response, falling back to its plain-text message
+/// for servers that do not send one.
+pub(crate) fn response_output(response: &CommandResponse) -> CommandOutput {
+    match &response.output {
+        Some(Output::Text(text)) => CommandOutput::Text(text.clone()),
+        Some(Output::Json(json)) => serde_json::from_str(json)
+            .map(CommandOutput::Json)
+            .unwrap_or_else(|_| CommandOutput::Text(json.clone())),
+        Some(Output::Table(table)) => CommandOutput::Table {
+            headers: table.headers.clone(),
+            rows: table.rows.iter().map(|row| row.cells.clone()).collect(),
+        },
+        Some(Output::Payload(bytes)) => CommandOutput::Bytes(bytes.clone()),
+        Some(Output::Chunked(_)) | None => CommandOutput::Text(response.message.clone()),
+    }
+}
+
+/// Print a command response, as JSON or rendered for the terminal.
+///
+/// Warnings are printed highlighted on stderr; in JSON mode a result with
+/// warnings is wrapped as `{"result": ..., "warnings": [...]}` and an error
+/// gains a `"warnings"` list.
+fn print_response(response: &CommandResponse, format: OutputFormat, theme: &Theme) {
+    show_response(response, format, theme, false);
+}
+
+/// Like `print_response`, but with `page` a result taller than the terminal
+/// is shown in the pager.
+fn show_response(response: &CommandResponse, format: OutputFormat, theme: &Theme, page: bool) {
+    if !format.is_machine_readable() {
+        for warning in &response.warnings {
+            eprintln!("{}", theme.warning.paint(format_args!("Warning: {}", warning)));
+        }
+        if let Some(Output::Payload(bytes)) = response.output.as_ref().filter(|_| response.success) {
+            show_payload(bytes, theme);
+            return;
+        }
+    }
+    let text = response_text(response, format, theme);
+    if page {
+        manual::page_long(&text);
+    } else {
+        print!("{}", text);
+    }
+}
+
+/// Binary output, which would garble the terminal: written as is when
+/// standard output is a file or pipe the shell was started with, else saved
+/// to a new file in the current directory. Redirections inside the shell
+/// drop color codes, so they cannot take it either.
+fn show_payload(bytes: &[u8], theme: &Theme) {
+    let mut stdout = std::io::stdout();
+    if !stdout.is_terminal() && !redirected() {
+        if let Err(e) = stdout.write_all(bytes).and_then(|()| stdout.flush()) {
+            eprintln!("Error: cannot write the binary output: {}", e);
+        }
+        return;
+    }
+    match save_payload(bytes) {
+        Ok(path) => {
+            let notice = format!("Saved {} bytes of binary output to {}", bytes.len(), path.display());
+            // Inside a redirection it would end up among what was captured.
+            if stdout.is_terminal() {
+                println!("{}", theme.hint.paint(notice));
+            } else {
+                eprintln!("{}", theme.hint.paint(notice));
+            }
+        }
+        Err(e) => eprintln!("Error: cannot save the binary output: {:#}", e),
+    }
+}
+
+/// Write `bytes` to `output.bin`, or `output-2.bin` and so on if taken,
+/// and return the file's path.
+fn save_payload(bytes: &[u8]) -> anyhow::Result<PathBuf> {
+    use anyhow::Context as _;
+    let mut path = PathBuf::from("output.bin");
+    for n in 2.. {
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(bytes).with_context(|| format!("cannot write {}", path.display()))?;
+                break;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                path = PathBuf::from(format!("output-{}.bin", n));
+            }
+            Err(e) => return Err(e).with_context(|| format!("cannot write {}", path.display())),
+        }
+    }
+    Ok(path)
+}
+
+/// Whether the shell redirected standard output (`... > file`,
+/// `... | command`, `$(...)`).
+#[cfg(unix)]
+fn redirected() -> bool {
+    crate::redirect::active()
+}
+
+#[cfg(not(unix))]
+fn redirected() -> bool {
+    false
+}
+
+/// A response as `print_response` prints it, warnings aside.
+fn response_text(response: &CommandResponse, format: OutputFormat, theme: &Theme) -> String {
+    if format.is_machine_readable() {
+        return value_text(&response_json(response), format);
+    }
+    if !response.success {
+        return error_text(response, theme);
+    }
+    let output = match format {
+        OutputFormat::Table => response_output(response).tabulated(),
+        _ => response_output(response),
+    };
+    let mut text = match output {
+        CommandOutput::Table { headers, rows } => {
+            let rendered = crate::registry::format_table(&headers, &rows);
+            let (header, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+            let mut text = format!("{}\n", theme.header.paint(header));
+            if !body.is_empty() {
+                text += &format!("{}\n", body);
+            }
+            text
+        }
+        // Commands returning `()` print nothing.
+        CommandOutput::Text(text) if text.is_empty() => text,
+        output => format!("{}\n", output.to_plain()),
+    };
+    if response.job_id != 0 {
+        let follow = format!("(follow it with 'job status {}')", response.job_id);
+        text += &format!("{}\n", theme.hint.paint(follow));
+    }
+    text
+}
+
+/// Print `value` in a machine-readable `format`: pretty-printed JSON, or
+/// YAML.
+fn print_value(value: &serde_json::Value, format: OutputFormat) {
+    print!("{}", value_text(value, format));
+}
+
+/// `value` as `print_value` prints it, ending in a newline.
+fn value_text(value: &serde_json::Value, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Yaml => crate::yaml::to_string(value),
+        _ => format!("{}\n", serde_json::to_string_pretty(value).unwrap_or_default()),
+    }
+}
+
+/// A response as printed with `--json`: the output, `{"job_id": ...}` for
+/// a started job, or the error with its code. Warnings and a dry run's
+/// planned changes wrap the output as `{"result": ..., "warnings": [...],
+/// "plan": [...]}`.
+fn response_json(response: &CommandResponse) -> serde_json::Value {
+    let result = match response.job_id {
+        0 => response_output(response).to_json(),
+        id => serde_json::json!({ "job_id": id }),
+    };
+    match (response.success, response.warnings.is_empty() && response.plan.is_empty()) {
+        (true, true) => result,
+        (true, false) => {
+            let mut wrapped = serde_json::json!({ "result": result });
+            if !response.warnings.is_empty() {
+                wrapped["warnings"] = serde_json::json!(response.warnings);
+            }
+            if !response.plan.is_empty() {
+                let plan: Vec<_> = response
+                    .plan
+                    .iter()
+                    .map(|change| {
+                        serde_json::json!({ "resource": change.resource, "before": change.before, "after": change.after })
+                    })
+                    .collect();
+                wrapped["plan"] = plan.into();
+            }
+            wrapped
+        }
+        (false, _) => {
+            let mut error = serde_json::json!({
+                "error": response.message,
+                "code": error_code_from_proto(response).as_str(),
+            });
+            if !response.error_details.is_empty() {
+                error["details"] = serde_json::json!(response.error_details);
+            }
+            if !response.suggestions.is_empty() {
+                error["suggestions"] = serde_json::json!(response.suggestions);
+            }
+            if response.retry_after_ms > 0 {
+                error["retry_after_ms"] = response.retry_after_ms.into();
+            }
+            if !response.warnings.is_empty() {
+                error["warnings"] = serde_json::json!(response.warnings);
+            }
+            error
+        }
+    }
+}
+
+/// A failed response as printed: `Error (<code>): <message>`, in yellow
+/// when the server was busy and red otherwise, followed by its details.
+/// Busy and internal errors are not labelled; the message says all there is.
+fn error_text(response: &CommandResponse, theme: &Theme) -> String {
+    let code = error_code_from_proto(response);
+    let label = match code {
+        ErrorCode::Busy => theme.warning.paint("Error:"),
+        ErrorCode::ResourceExhausted | ErrorCode::Unavailable => {
+            theme.warning.paint(format_args!("Error ({}):", code))
+        }
+        ErrorCode::Internal => theme.error.paint("Error:"),
+        code => theme.error.paint(format_args!("Error ({}):", code)),
+    };
+    let mut text = format!("{} {}\n", label, response.message);
+    let mut details: Vec<_> = response.error_details.iter().collect();
+    details.sort();
+    for (key, value) in details {
+        text += &format!("  {}: {}\n", key, value);
+    }
+    text
+}
+
+/// Render a dry-run plan: `+` creates (green), `-` removes (red), `~` updates (yellow).
+fn print_plan(plan: &[PlanChange], theme: &Theme) {
+    let (create, remove, update) = (theme.success, theme.error, theme.warning);
+    println!("Planned changes:");
+    for change in plan {
+        match (change.before.is_empty(), change.after.is_empty()) {
+            (true, _) => {
+                println!("{}", create.paint(format_args!("  + {}", change.resource)));
+                println!("{}", create.paint(format_args!("      {}", change.after)));
+            }
+            (false, true) => {
+                println!("{}", remove.paint(format_args!("  - {}", change.resource)));
+                println!("{}", remove.paint(format_args!("      {}", change.before)));
+            }
+            (false, false) => {
+                println!("{}", update.paint(format_args!("  ~ {}", change.resource)));
+                println!("{}", remove.paint(format_args!("    - {}", change.before)));
+                println!("{}", create.paint(format_args!("    + {}", change.after)));
+            }
+        }
+    }
+}
+
+/// `help <service>`: the service's description, the help of each of its
+/// commands and the services nested in it.
+fn service_help(out: &mut String, services: &[ServiceInfo], svc: &ServiceInfo) {
+    let path = command_path(&svc.name);
+    if svc.description.is_empty() {
+        let _ = writeln!(out, "{}:", path);
+    } else {
+        let _ = writeln!(out, "{}: {}", path, svc.description);
+    }
+    if !svc.aliases.is_empty() {
+        let _ = writeln!(out, "  (also: {})", svc.aliases.join(", "));
+    }
+    out.push('\n');
+    for cmd in &svc.commands {
+        command_help(out, cmd, false);
+    }
+    for (name, nested) in nested_services(services, &svc.name) {
+        let _ = writeln!(out, "  {} <command> [args...]", name);
+        if !nested.description.is_empty() {
+            let _ = writeln!(out, "    {}", nested.description);
+        }
+        let _ = writeln!(out, "    (type 'help {}' for its commands)", command_path(&nested.name));
+        out.push('\n');
+    }
+}
+
+/// The help of a single command: its synopsis, summary and arguments. With
+/// `detailed` every argument is listed with its type and default, as for
+/// `help <service> <command>`; otherwise only documented arguments are.
+fn command_help(out: &mut String, cmd: &CommandDef, detailed: bool) {
+    let args_str = cmd
+        .args
+        .iter()
+        .map(arg_placeholder)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if cmd.dry_run {
+        let _ = writeln!(out, "  {} {} (supports preview)", cmd.name, args_str);
+    } else if cmd.streaming {
+        let _ = writeln!(out, "  {} {} (streams output)", cmd.name, args_str);
+    } else if cmd.background {
+        let _ = writeln!(out, "  {} {} (runs in the background)", cmd.name, args_str);
+    } else if cmd.upload {
+        let _ = writeln!(out, "  {} {} (takes a file: put <file> ...)", cmd.name, args_str);
+    } else if cmd.download {
+        let _ = writeln!(out, "  {} {} (sends a file: get <file> ...)", cmd.name, args_str);
+    } else if cmd.interactive {
+        let _ = writeln!(out, "  {} {} (asks questions)", cmd.name, args_str);
+    } else {
+        let _ = writeln!(out, "  {} {}", cmd.name, args_str);
+    }
+    if !cmd.description.is_empty() {
+        let _ = writeln!(out, "    {}", cmd.description);
+    }
+    if !cmd.permission.is_empty() {
+        let _ = writeln!(out, "    (requires permission '{}')", cmd.permission);
+    }
+    if cmd.timeout_ms > 0 {
+        let limit = format_duration(Duration::from_millis(cmd.timeout_ms));
+        let _ = writeln!(out, "    (times out after {})", limit);
+    }
+    if !cmd.since.is_empty() {
+        let _ = writeln!(out, "    (since {})", cmd.since);
+    }
+    if let Some(deprecation) = manual::deprecation(cmd) {
+        let _ = writeln!(out, "    ({})", deprecation);
+    }
+    for arg in &cmd.args {
+        let has_desc = !arg.description.is_empty();
+        let has_comp = !arg.completer.is_empty();
+        let has_choices = !arg.choices.is_empty();
+        if detailed || has_desc || has_comp || has_choices || arg.glob {
+            let mut parts = vec![format!("    {}", arg_placeholder(arg))];
+            if has_desc {
+                parts.push(arg.description.clone());
+            }
+            if detailed {
+                parts.push(format!("({})", manual::arg_constraints(arg).join(", ")));
                 if has_comp {
-                    parts.push(format!("(completions from {})", arg.completer));
+                    parts.push(format!("(completions from {})", completer_spec(arg)));
+                }
+            } else {
+                if has_choices {
+                    parts.push(format!("(one of: {})", arg.choices.join(", ")));
+                } else if has_comp {
+                    parts.push(format!("(completions from {})", completer_spec(arg)));
+                }
+                if arg.glob {
+                    parts.push("(accepts glob patterns)".to_string());
+                }
+            }
+            let _ = writeln!(out, "{}", parts.join(" - "));
+        }
+    }
+    out.push('\n');
+}
+
+/// The `health` builtin: print each service's health. Returns whether all
+/// services are serving.
+async fn print_health(client: &mut Client, format: OutputFormat, theme: &Theme) -> anyhow::Result<bool> {
+    let services = client
+        .check_health(HealthRequest {})
+        .await?
+        .into_inner()
+        .services;
+    let healthy = services.iter().all(|s| s.serving);
+    if format.is_machine_readable() {
+        let report: Vec<serde_json::Value> = services
+            .iter()
+            .map(|s| serde_json::json!({"service": s.name, "serving": s.serving, "message": s.message}))
+            .collect();
+        print_value(&serde_json::Value::Array(report), format);
+        return Ok(healthy);
+    }
+    let width = services.iter().map(|s| s.name.len()).max().unwrap_or(0);
+    for service in &services {
+        if service.serving {
+            println!("  {:width$}  {}", service.name, theme.success.paint("serving"));
+        } else if service.message.is_empty() {
+            println!("  {:width$}  {}", service.name, theme.error.paint("not serving"));
+        } else {
+            println!(
+                "  {:width$}  {}: {}",
+                service.name,
+                theme.error.paint("not serving"),
+                service.message
+            );
+        }
+    }
+    Ok(healthy)
+}
+
+/// The `jobs`, `job status <id>` and `job cancel <id>` builtins. Returns
+/// the exit status: that of a `NotFound` error for an unknown job.
+async fn job_builtin(
+    client: &mut Client,
+    parts: &[&str],
+    format: OutputFormat,
+    theme: &Theme,
+) -> anyhow::Result<u8> {
+    let (action, id) = match parts {
+        ["jobs"] => {
+            let mut jobs = Vec::new();
+            for job in client.job_list(JobListRequest {}).await?.into_inner().jobs {
+                jobs.push(chunked::complete_job(client, job).await?);
+            }
+            print_jobs(&jobs, format, theme);
+            return Ok(0);
+        }
+        ["job", action @ ("status" | "cancel"), id] => (*action, *id),
+        _ => {
+            println!("Usage: jobs | job status <id> | job cancel <id>");
+            return Ok(USAGE_EXIT);
+        }
+    };
+    let Ok(id) = id.parse::<u64>() else {
+        println!("Error: invalid job ID '{}'", id);
+        return Ok(USAGE_EXIT);
+    };
+    let response = if action == "status" {
+        client.job_status(JobRequest { id }).await
+    } else {
+        client.job_cancel(JobRequest { id }).await
+    };
+    match response {
+        Ok(job) => {
+            let job = chunked::complete_job(client, job.into_inner()).await?;
+            print_job(&job, format, theme);
+            Ok(0)
+        }
+        Err(status) if status.code() == tonic::Code::NotFound => {
+            if format.is_machine_readable() {
+                print_value(&serde_json::json!({ "error": status.message(), "code": "not_found" }), format);
+            } else {
+                println!("{} {}", theme.error.paint("Error (not found):"), status.message());
+            }
+            Ok(ErrorCode::NotFound.exit_code())
+        }
+        Err(status) => Err(status.into()),
+    }
+}
+
+/// A job's state in lower case, as in the CLI and JSON output: "running".
+pub(crate) fn job_state(job: &JobInfo) -> &'static str {
+    match job.state() {
+        JobState::Running | JobState::Unspecified => "running",
+        JobState::Succeeded => "succeeded",
+        JobState::Failed => "failed",
+        JobState::Cancelled => "cancelled",
+    }
+}
+
+/// Run time in seconds with one decimal: "12.3s".
+fn elapsed(job: &JobInfo) -> String {
+    format!("{:.1}s", job.elapsed_ms as f64 / 1000.0)
+}
+
+fn job_json(job: &JobInfo) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "id": job.id,
+        "command": format!("{} {}", command_path(&job.service), job.action),
+        "args": job.args,
+        "state": job_state(job),
+        "started_at_ms": job.started_at_ms,
+        "elapsed_ms": job.elapsed_ms,
+    });
+    if let Some(progress) = &job.progress {
+        value["progress"] = serde_json::json!({
+            "percent": progress.percent,
+            "step": progress.step,
+            "total_steps": progress.total_steps,
+            "message": progress.message,
+        });
+    }
+    if let Some(result) = &job.result {
+        value["result"] = response_json(result);
+    }
+    value
+}
+
+/// `jobs`: one row per job, oldest first.
+fn print_jobs(jobs: &[JobInfo], format: OutputFormat, theme: &Theme) {
+    if format.is_machine_readable() {
+        let jobs: Vec<serde_json::Value> = jobs.iter().map(job_json).collect();
+        print_value(&serde_json::Value::Array(jobs), format);
+        return;
+    }
+    if jobs.is_empty() {
+        println!("No jobs.");
+        return;
+    }
+    let headers = ["ID", "COMMAND", "ARGS", "STATE", "ELAPSED"].map(String::from).to_vec();
+    let rows = jobs
+        .iter()
+        .map(|job| {
+            vec![
+                job.id.to_string(),
+                format!("{} {}", command_path(&job.service), job.action),
+                job.args.clone(),
+                match &job.progress {
+                    Some(progress) => format!("{} {}%", job_state(job), progress.percent),
+                    None => job_state(job).to_string(),
+                },
+                elapsed(job),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let rendered = crate::registry::format_table(&headers, &rows);
+    let (header, body) = rendered.split_once('\n').unwrap_or((&rendered, ""));
+    println!("{}", theme.header.paint(header));
+    println!("{}", body);
+}
+
+/// `job status` / `job cancel`: the job's command and state, then its
+/// output or error once it has finished.
+fn print_job(job: &JobInfo, format: OutputFormat, theme: &Theme) {
+    if format.is_machine_readable() {
+        print_value(&job_json(job), format);
+        return;
+    }
+    let command = format!("{} {} {}", command_path(&job.service), job.action, job.args);
+    println!("Job {}: {}", job.id, command.trim_end());
+    let state = match job.state() {
+        JobState::Succeeded => format!("{} after {}", theme.success.paint("succeeded"), elapsed(job)),
+        JobState::Failed => format!("{} after {}", theme.error.paint("failed"), elapsed(job)),
+        JobState::Cancelled => format!("{} after {}", theme.warning.paint("cancelled"), elapsed(job)),
+        JobState::Running | JobState::Unspecified => format!("running for {}", elapsed(job)),
+    };
+    println!("  {}", state);
+    if let Some(progress) = &job.progress {
+        println!("  {}", progress_bar(progress));
+    }
+    if let Some(result) = &job.result {
+        print_response(result, format, theme);
+    }
+}
+
+/// `last [<n>] [--all]`: the results of the latest `n` (by default one)
+/// commands this client ran, or everyone ran with `--all`, oldest first.
+/// Returns the exit status: that of a `PermissionDenied` error if the
+/// server refuses `--all`.
+async fn last_builtin(
+    client: &mut Client,
+    parts: &[&str],
+    format: OutputFormat,
+    theme: &Theme,
+) -> anyhow::Result<u8> {
+    let mut request = RecentRequest { limit: 0, all: false };
+    for word in &parts[1..] {
+        match *word {
+            "--all" => request.all = true,
+            count if request.limit == 0 => match count.parse::<u32>() {
+                Ok(limit @ 1..) => request.limit = limit,
+                _ => {
+                    println!("Error: invalid count '{}'", count);
+                    return Ok(USAGE_EXIT);
+                }
+            },
+            _ => {
+                println!("Usage: last [<n>] [--all]");
+                return Ok(USAGE_EXIT);
+            }
+        }
+    }
+    request.limit = request.limit.max(1);
+    let all = request.all;
+    let listed = match client.recent_results(request).await {
+        Ok(response) => response.into_inner().results,
+        Err(status) if status.code() == tonic::Code::PermissionDenied => {
+            if format.is_machine_readable() {
+                print_value(&serde_json::json!({ "error": status.message(), "code": "permission_denied" }), format);
+            } else {
+                println!("{} {}", theme.error.paint("Error (permission denied):"), status.message());
+            }
+            return Ok(ErrorCode::PermissionDenied.exit_code());
+        }
+        Err(status) => return Err(status.into()),
+    };
+    let mut results = Vec::with_capacity(listed.len());
+    for recent in listed {
+        results.push(chunked::complete_recent(client, recent).await?);
+    }
+    if format.is_machine_readable() {
+        let results: Vec<serde_json::Value> = results.iter().map(recent_json).collect();
+        print_value(&serde_json::Value::Array(results), format);
+        return Ok(0);
+    }
+    if results.is_empty() {
+        println!("No recent results.");
+    }
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    for recent in &results {
+        let command = format!("{} {} {}", command_path(&recent.service), recent.action, recent.args);
+        let when = match now.saturating_sub(recent.finished_at_ms) / 1000 {
+            0 => "just now".to_string(),
+            secs => format!("{} ago", format_duration(Duration::from_secs(secs))),
+        };
+        let by = match (all, recent.tenant.is_empty()) {
+            (false, _) => String::new(),
+            (true, true) => format!("by {}, ", recent.caller),
+            (true, false) => format!("by {} (tenant {}), ", recent.caller, recent.tenant),
+        };
+        println!("{} {}", theme.header.paint(command.trim_end()), theme.hint.paint(format!("({}{})", by, when)));
+        if let Some(result) = &recent.result {
+            print_response(result, format, theme);
+        }
+    }
+    Ok(0)
+}
+
+fn recent_json(recent: &RecentResult) -> serde_json::Value {
+    let mut value = serde_json::json!({
+        "command": format!("{} {}", command_path(&recent.service), recent.action),
+        "args": recent.args,
+        "caller": recent.caller,
+        "tenant": (!recent.tenant.is_empty()).then_some(&recent.tenant),
+        "finished_at_ms": recent.finished_at_ms,
+        "elapsed_ms": recent.elapsed_ms,
+    });
+    if let Some(result) = &recent.result {
+        value["result"] = response_json(result);
+    }
+    value
+}
+
+/// `subscribe [<topic>...]`: print the events services emit on the topics
+/// (names or glob patterns; all topics without any) as they arrive, until
+/// interrupted or the server shuts down.
+async fn subscribe_builtin(
+    client: &mut Client,
+    topics: &[&str],
+    format: OutputFormat,
+    theme: &Theme,
+) -> anyhow::Result<()> {
+    let topics = topics.iter().map(|topic| topic.to_string()).collect();
+    let mut events = client.subscribe(SubscribeRequest { topics }).await?.into_inner();
+    if !format.is_machine_readable() {
+        println!("{}", theme.hint.paint("Waiting for events; press Ctrl-C to stop."));
+    }
+    while let Some(event) = events.message().await? {
+        if event.missed > 0 {
+            let missed = format!("Warning: missed {} events", event.missed);
+            eprintln!("{}", theme.warning.paint(missed));
+        }
+        if format.is_machine_readable() {
+            print_value(&event_json(&event), format);
+        } else {
+            let time = event_time(event.emitted_at_ms);
+            println!("{} {} {}", theme.hint.paint(time), theme.header.paint(&event.topic), event.payload);
+        }
+        std::io::stdout().flush()?;
+    }
+    println!("Server closed the subscription.");
+    Ok(())
+}
+
+fn event_json(event: &EventMessage) -> serde_json::Value {
+    serde_json::json!({
+        "topic": event.topic,
+        "payload": event.payload,
+        "emitted_at_ms": event.emitted_at_ms,
+    })
+}
+
+/// The time of day (UTC) of a Unix timestamp in milliseconds: "14:03:27Z".
+fn event_time(ms: u64) -> String {
+    let secs = ms / 1000 % 86_400;
+    format!("{:02}:{:02}:{:02}Z", secs / 3_600, secs % 3_600 / 60, secs % 60)
+}
+
+/// `pin <service> <command> [args...]`, `unpin <...>` and `favorites`.
+fn pin_builtin(favorites: &mut Favorites, services: &[ServiceInfo], parts: &[&str]) {
+    let command = tokenize::join(&parts[1..]);
+    let result = match parts {
+        ["favorites"] => {
+            if favorites.commands().is_empty() {
+                println!("No pinned commands. Pin one with 'pin <service> <command> [args...]'.");
+            }
+            for (i, command) in favorites.commands().iter().enumerate() {
+                println!("  {}. {}", i + 1, command);
+            }
+            return;
+        }
+        ["pin", words @ ..] if words.len() >= 2 => {
+            let resolved = resolve_service(services, words);
+            let used = resolved.map_or(1, |(_, used)| used);
+            let known = resolved
+                .zip(words.get(used))
+                .is_some_and(|((s, _), action)| s.commands.iter().any(|c| c.name == *action));
+            if !known {
+                println!(
+                    "Unknown command '{}'. Type 'help' to list all services.",
+                    words[..(used + 1).min(words.len())].join(" ")
+                );
+                return;
+            }
+            favorites.pin(&command).map(|pinned| {
+                if pinned {
+                    println!("Pinned '{}'.", command);
+                } else {
+                    println!("'{}' is already pinned.", command);
                 }
-                println!("{}", parts.join(" - "));
+            })
+        }
+        ["unpin", _, ..] => favorites.unpin(&command).map(|unpinned| {
+            if unpinned {
+                println!("Unpinned '{}'.", command);
+            } else {
+                println!("'{}' is not pinned.", command);
+            }
+        }),
+        [builtin, ..] => {
+            println!("Usage: {} <service> <command> [args...]", builtin);
+            return;
+        }
+        [] => return,
+    };
+    if let Err(e) = result {
+        println!("Error: failed to save favorites: {}", e);
+    }
+}
+
+/// `help [service [command [--full]]]`. With `--full` the command's long
+/// help is shown in the pager; other help is paged when it is taller than
+/// the terminal.
+fn show_help(services: &[ServiceInfo], words: &[&str]) {
+    let mut out = String::new();
+    let Some(service) = words.first() else {
+        overview(&mut out, services);
+        manual::page_long(&out);
+        return;
+    };
+    let Some((svc, used)) = resolve_service(services, words) else {
+        println!("Unknown service '{}'. Type 'help' to list all services.", service);
+        return;
+    };
+    match &words[used..] {
+        [] => service_help(&mut out, services, svc),
+        [command, rest @ ..] => {
+            let Some(cmd) = svc.commands.iter().find(|c| c.name == *command) else {
+                println!(
+                    "Unknown command '{}'. Type 'help {}' to list its commands.",
+                    command,
+                    words[..used].join(" ")
+                );
+                return;
+            };
+            if rest.contains(&"--full") {
+                manual::page(&manual::render(svc, cmd));
+                return;
             }
+            command_help(&mut out, cmd, true);
         }
-        println!();
     }
+    manual::page_long(&out);
 }
 
-fn print_help(services: &[ServiceInfo]) {
-    println!("Available commands:");
+/// The `describe --openapi` builtin: print the OpenAPI document of the
+/// server's HTTP gateway. Returns whether the arguments were valid.
+fn describe(services: &[ServiceInfo], words: &[&str]) -> bool {
+    if words != ["--openapi"] {
+        println!("Usage: describe --openapi");
+        return false;
+    }
+    let document = openapi::document(services);
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&document).unwrap_or_else(|_| document.to_string())
+    );
+    true
+}
+
+/// `help`: every service with its commands, synopses and descriptions in
+/// aligned columns.
+fn overview(out: &mut String, services: &[ServiceInfo]) {
+    let synopsis = |cmd: &CommandDef| {
+        let mut words = vec![cmd.name.clone()];
+        words.extend(cmd.args.iter().map(arg_placeholder));
+        words.join(" ")
+    };
+    let width = services
+        .iter()
+        .flat_map(|svc| &svc.commands)
+        .map(|cmd| synopsis(cmd).chars().count())
+        .max()
+        .unwrap_or(0);
+    out.push_str("Available commands:\n");
     for svc in services {
+        let path = command_path(&svc.name);
         if svc.description.is_empty() {
-            println!("  {}:", svc.name);
+            let _ = writeln!(out, "  {}:", path);
         } else {
-            println!("  {}: {}", svc.name, svc.description);
+            let _ = writeln!(out, "  {}: {}", path, svc.description);
         }
         for cmd in &svc.commands {
-            let args_str = cmd
-                .args
-                .iter()
-                .map(|a| {
-                    let label = if a.hint.is_empty() { &a.name } else { &a.hint };
-                    format!("<{}>", label)
-                })
-                .collect::<Vec<_>>()
-                .join(" ");
-            let desc = if cmd.description.is_empty() {
-                String::new()
+            if cmd.description.is_empty() {
+                let _ = writeln!(out, "    {}", synopsis(cmd));
             } else {
-                format!(" - {}", cmd.description)
-            };
-            println!("    {} {}{}", cmd.name, args_str, desc);
+                let _ = writeln!(out, "    {:width$}  {}", synopsis(cmd), cmd.description);
+            }
         }
     }
 }
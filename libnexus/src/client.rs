@@ -0,0 +1,404 @@
+//! Programmatic client for calling Nexus daemons from other Rust programs
+//! (tests, orchestration tools) without the interactive shell.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), libnexus::ClientError> {
+//! let client = libnexus::NexusClient::connect("/tmp/nexus.sock").await?;
+//! let output = client.execute("volume", "create", &["vol0", "sda", "--size", "20"]).await?;
+//! println!("{}", output.to_plain());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Services declared with `#[nexus_service(client)]` also get a typed
+//! client wrapping a `NexusClient`, e.g. `VolumeClient`, with a method per
+//! command.
+
+use crate::chunked;
+use crate::cli::{new_client, response_output, split_args, SessionInterceptor};
+use crate::context::Preferences;
+use crate::endpoint::Endpoint;
+use crate::error::{Busy, ErrorCode};
+use crate::interceptor::{ClientInterceptor, Intercepted};
+use crate::proto::{
+    BatchRequest, BatchResponse, CommandRequest, CommandResponse, EventMessage, JobInfo, JobListRequest,
+    JobRequest, ListServicesRequest, RecentRequest, RecentResult, ServiceInfo, SubscribeRequest,
+};
+use crate::registry::CommandOutput;
+use crate::server::error_code_from_proto;
+use crate::session::{self, SessionService};
+use crate::transfer;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::Channel;
+use tonic::Streaming;
+
+/// Why a `NexusClient` call failed.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The address is invalid or the server could not be reached.
+    Connect(anyhow::Error),
+    /// The call itself failed: transport error, authentication, or an
+    /// unsupported request.
+    Rpc(tonic::Status),
+    /// The server could not take the command right now; retry later.
+    Busy(Busy),
+    /// The local file of an upload or download could not be read or
+    /// written.
+    File(anyhow::Error),
+    /// The command was rejected or ran and reported an error.
+    Command {
+        code: ErrorCode,
+        message: String,
+        /// Structured details the service attached (`NexusError::detail`).
+        details: BTreeMap<String, String>,
+        /// What may have been meant instead (`NexusError::suggest`).
+        suggestions: Vec<String>,
+        warnings: Vec<String>,
+        /// How long to wait before sending the request again, for
+        /// `ErrorCode::ResourceExhausted`.
+        retry_after: Option<Duration>,
+    },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect(e) => write!(f, "failed to connect: {}", e),
+            Self::Rpc(status) => write!(f, "request failed: {}", status.message()),
+            Self::Busy(busy) => busy.fmt(f),
+            Self::File(e) => write!(f, "{:#}", e),
+            Self::Command { message, .. } => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<tonic::Status> for ClientError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Rpc(status)
+    }
+}
+
+/// A connection to a Nexus server.
+///
+/// Cloning is cheap and clones share the connection.
+#[derive(Clone)]
+pub struct NexusClient {
+    channel: Intercepted,
+    session: SessionInterceptor,
+}
+
+impl NexusClient {
+    /// Connect to the server at `addr` (a Unix socket path or TCP
+    /// `host:port`, as for `NexusServer::serve`).
+    pub async fn connect(addr: &str) -> Result<Self, ClientError> {
+        let endpoint = addr.parse::<Endpoint>().map_err(ClientError::Connect)?;
+        let channel = endpoint.connect().await.map_err(ClientError::Connect)?;
+        Ok(Self::from_channel(channel))
+    }
+
+    /// Connect over TLS, trusting servers whose certificate chains to `ca`.
+    /// Pass `client_cert` when the server requires mutual TLS.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        addr: &str,
+        ca: tonic::transport::Certificate,
+        client_cert: Option<tonic::transport::Identity>,
+    ) -> Result<Self, ClientError> {
+        let mut tls = tonic::transport::ClientTlsConfig::new().ca_certificate(ca);
+        if let Some(identity) = client_cert {
+            tls = tls.identity(identity);
+        }
+        let endpoint = Endpoint::parse_tls(addr).map_err(ClientError::Connect)?;
+        let channel = endpoint
+            .connect_tls(crate::endpoint::Keepalive::default(), tls)
+            .await
+            .map_err(ClientError::Connect)?;
+        Ok(Self::from_channel(channel))
+    }
+
+    fn from_channel(channel: Channel) -> Self {
+        Self {
+            channel: Intercepted {
+                channel,
+                interceptors: Arc::default(),
+            },
+            session: SessionInterceptor {
+                preferences: Preferences::default(),
+                tenant: None,
+                token: None,
+            },
+        }
+    }
+
+    /// Act on behalf of `tenant` in subsequent calls.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.session.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// Authenticate subsequent calls with a bearer token.
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.session.token = Some(token.to_string());
+        self
+    }
+
+    /// Session preferences (locale, output format, timezone) sent with every
+    /// call.
+    pub fn with_preferences(mut self, preferences: Preferences) -> Self {
+        self.session.preferences = preferences;
+        self
+    }
+
+    /// Run `interceptor` around subsequent calls, after those installed
+    /// before it.
+    pub fn with_interceptor(mut self, interceptor: impl ClientInterceptor) -> Self {
+        Arc::make_mut(&mut self.channel.interceptors).push(Arc::new(interceptor));
+        self
+    }
+
+    fn client(&self) -> crate::cli::Client {
+        new_client(self.channel.clone(), self.session.clone())
+    }
+
+    /// The services (and their commands) visible to this client.
+    pub async fn list_services(&self) -> Result<Vec<ServiceInfo>, ClientError> {
+        let response = self.client().list_services(ListServicesRequest::new()).await?;
+        Ok(response.into_inner().services)
+    }
+
+    /// Run a command and return its output. `args` are the words that would
+    /// follow `<service> <action>` on the CLI: positional values and
+    /// `--name value` / `--name=value` flags.
+    ///
+    /// Streaming commands cannot be run this way. A background command
+    /// returns as soon as its job has started; `execute_request` reports the
+    /// job's ID.
+    pub async fn execute(&self, service: &str, action: &str, args: &[&str]) -> Result<CommandOutput, ClientError> {
+        let response = self.execute_request(command_request(service, action, args)).await?;
+        Ok(response_output(&response))
+    }
+
+    /// Send a prepared request, e.g. with `dry_run` or a priority set.
+    /// Failed and busy responses are returned as errors; a successful one
+    /// carries the output, warnings and (for dry runs) the plan.
+    pub async fn execute_request(&self, request: CommandRequest) -> Result<CommandResponse, ClientError> {
+        let mut client = self.client();
+        let response = client.execute(request).await?.into_inner();
+        let response = chunked::complete(&mut client, response).await?;
+        match response_error(&response) {
+            Some(e) => Err(e),
+            None => Ok(response),
+        }
+    }
+
+    /// Run a command that takes a file (`CommandDef.upload`), sending the
+    /// file at `path` with it. `args` are as for `execute`.
+    pub async fn upload(
+        &self,
+        service: &str,
+        action: &str,
+        args: &[&str],
+        path: impl AsRef<Path>,
+    ) -> Result<CommandOutput, ClientError> {
+        let request = command_request(service, action, args);
+        let response = transfer::send_file(&mut self.client(), request, path.as_ref()).await.map_err(file_error)?;
+        match response_error(&response) {
+            Some(e) => Err(e),
+            None => Ok(response_output(&response)),
+        }
+    }
+
+    /// Run a command that sends a file back (`CommandDef.download`), saving
+    /// the file to `path`. If the command fails, `path` is left as it was.
+    pub async fn download(
+        &self,
+        service: &str,
+        action: &str,
+        args: &[&str],
+        path: impl AsRef<Path>,
+    ) -> Result<CommandOutput, ClientError> {
+        let request = command_request(service, action, args);
+        let (response, _) = transfer::receive_file(&mut self.client(), request, path.as_ref())
+            .await
+            .map_err(file_error)?;
+        match response_error(&response) {
+            Some(e) => Err(e),
+            None => Ok(response_output(&response)),
+        }
+    }
+
+    /// Run a command that asks questions (`CommandDef.interactive`),
+    /// answering them with `user`. `args` are as for `execute`.
+    pub async fn session(
+        &self,
+        service: &str,
+        action: &str,
+        args: &[&str],
+        user: &impl SessionService,
+    ) -> Result<CommandOutput, ClientError> {
+        let request = command_request(service, action, args);
+        let response = session::run(&mut self.client(), request, None, user).await?;
+        match response_error(&response) {
+            Some(e) => Err(e),
+            None => Ok(response_output(&response)),
+        }
+    }
+
+    /// Run `commands` in order, stopping at the first that fails. With
+    /// `atomic`, the ones that succeeded before it are then rolled back.
+    /// A failed command is reported in the response, not as an error: see
+    /// `BatchResponse::success` and its `results`.
+    pub async fn execute_batch(
+        &self,
+        commands: Vec<CommandRequest>,
+        atomic: bool,
+    ) -> Result<BatchResponse, ClientError> {
+        let mut client = self.client();
+        let mut response = client.execute_batch(BatchRequest { commands, atomic }).await?.into_inner();
+        for result in &mut response.results {
+            *result = chunked::complete(&mut client, std::mem::take(result)).await?;
+        }
+        Ok(response)
+    }
+
+    /// The background jobs started by this client's tenant, oldest first.
+    pub async fn list_jobs(&self) -> Result<Vec<JobInfo>, ClientError> {
+        let mut client = self.client();
+        let mut jobs = Vec::new();
+        for job in client.job_list(JobListRequest {}).await?.into_inner().jobs {
+            jobs.push(chunked::complete_job(&mut client, job).await?);
+        }
+        Ok(jobs)
+    }
+
+    /// The state of job `id` (`CommandResponse.job_id`) and, once it has
+    /// finished, its result. An unknown ID fails with a `NotFound` status.
+    pub async fn job_status(&self, id: u64) -> Result<JobInfo, ClientError> {
+        let mut client = self.client();
+        let job = client.job_status(JobRequest { id }).await?.into_inner();
+        Ok(chunked::complete_job(&mut client, job).await?)
+    }
+
+    /// Cancel job `id` if it is still running, returning its state.
+    pub async fn cancel_job(&self, id: u64) -> Result<JobInfo, ClientError> {
+        let mut client = self.client();
+        let job = client.job_cancel(JobRequest { id }).await?.into_inner();
+        Ok(chunked::complete_job(&mut client, job).await?)
+    }
+
+    /// The results of the latest `limit` commands (all the server keeps
+    /// with 0) this client ran, oldest first. With `all`, those of every
+    /// caller, which needs the `admin` permission.
+    pub async fn recent_results(&self, limit: u32, all: bool) -> Result<Vec<RecentResult>, ClientError> {
+        let mut client = self.client();
+        let mut results = Vec::new();
+        for recent in client.recent_results(RecentRequest { limit, all }).await?.into_inner().results {
+            results.push(chunked::complete_recent(&mut client, recent).await?);
+        }
+        Ok(results)
+    }
+
+    /// Follow the events services emit on `topics` (names or glob patterns
+    /// such as "block.*"; all topics if empty), as they happen. The stream
+    /// ends when the server shuts down.
+    pub async fn subscribe(&self, topics: Vec<String>) -> Result<Streaming<EventMessage>, ClientError> {
+        let response = self.client().subscribe(SubscribeRequest { topics }).await?;
+        Ok(response.into_inner())
+    }
+}
+
+/// The request for `action` of the service with `args` as for `execute`.
+fn command_request(service: &str, action: &str, args: &[&str]) -> CommandRequest {
+    let (args, named_args) = split_args(args);
+    CommandRequest {
+        service: service.to_string(),
+        action: action.to_string(),
+        args,
+        named_args,
+        ..Default::default()
+    }
+}
+
+/// The error `response` reports if it is busy or failed.
+fn response_error(response: &CommandResponse) -> Option<ClientError> {
+    if response.busy {
+        // The message is the server-side `Busy` rendered as text.
+        let reason = response.message.strip_prefix("busy: ").unwrap_or(&response.message);
+        let reason = reason.split(" (retry after ").next().unwrap_or(reason);
+        let mut busy = Busy::new(reason);
+        if response.retry_after_ms > 0 {
+            busy = busy.retry_after(Duration::from_millis(response.retry_after_ms));
+        }
+        return Some(ClientError::Busy(busy));
+    }
+    if !response.success {
+        return Some(ClientError::Command {
+            code: error_code_from_proto(response),
+            message: response.message.clone(),
+            details: response.error_details.clone().into_iter().collect(),
+            suggestions: response.suggestions.clone(),
+            warnings: response.warnings.clone(),
+            retry_after: (response.retry_after_ms > 0).then(|| Duration::from_millis(response.retry_after_ms)),
+        });
+    }
+    None
+}
+
+/// An error of an upload or download: the call's status if the call
+/// failed, otherwise that of the local file.
+fn file_error(e: anyhow::Error) -> ClientError {
+    match e.downcast::<tonic::Status>() {
+        Ok(status) => ClientError::Rpc(status),
+        Err(e) => ClientError::File(e),
+    }
+}
+
+/// The value a typed client method (`#[nexus_service(client)]`) returns
+/// for a command's successful response, chosen by the command's return
+/// type. Used by the code `#[nexus_service]` generates.
+#[doc(hidden)]
+pub trait FromResponse {
+    fn from_response(response: &CommandResponse) -> Self;
+}
+
+/// The text of text output, other output rendered as for `to_plain`.
+impl FromResponse for String {
+    fn from_response(response: &CommandResponse) -> Self {
+        match response_output(response) {
+            CommandOutput::Text(text) => text,
+            output => output.to_plain(),
+        }
+    }
+}
+
+impl FromResponse for () {
+    fn from_response(_: &CommandResponse) -> Self {}
+}
+
+impl FromResponse for serde_json::Value {
+    fn from_response(response: &CommandResponse) -> Self {
+        response_output(response).to_json()
+    }
+}
+
+/// Binary data as sent, other output rendered as for `to_plain`.
+impl FromResponse for Vec<u8> {
+    fn from_response(response: &CommandResponse) -> Self {
+        match response_output(response) {
+            CommandOutput::Bytes(bytes) => bytes,
+            output => output.to_plain().into_bytes(),
+        }
+    }
+}
+
+impl FromResponse for CommandOutput {
+    fn from_response(response: &CommandResponse) -> Self {
+        response_output(response)
+    }
+}
@@ -0,0 +1,281 @@
+use crate::cli::{
+    completer_request, nested_services, resolve_service, response_output, Client, Connection, ScannedArgs,
+};
+use crate::proto::{ArgDef, CommandDef, CommandRequest, ListServicesRequest, ListServicesResponse};
+use prost::Message;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long a completion request may wait on the server before falling back
+/// to cached (possibly stale) candidates.
+pub const COMPLETION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long fetched services and completer values are reused without asking
+/// the server again.
+pub const CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// The user's cache directory for completion data:
+/// `$XDG_CACHE_HOME/nexus/completion`, else `~/.cache/nexus/completion`.
+fn default_cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(base.join("nexus").join("completion"))
+}
+
+/// `dir`, created if missing, if only its owner can use it: a directory
+/// (not a symlink to one) owned by the current user with mode 0700. The
+/// cache is not used otherwise, so nobody else can plant entries in it or
+/// have it write through a link.
+fn private_dir(dir: &Path) -> Option<&Path> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        let _ = std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir);
+        let metadata = std::fs::symlink_metadata(dir).ok()?;
+        let private = metadata.is_dir()
+            && metadata.uid() == nix::unistd::geteuid().as_raw()
+            && metadata.permissions().mode() & 0o077 == 0;
+        if !private {
+            tracing::debug!(dir = %dir.display(), "completion cache not private; not using it");
+            return None;
+        }
+    }
+    #[cfg(not(unix))]
+    std::fs::create_dir_all(dir).ok()?;
+    Some(dir)
+}
+
+/// On-disk cache of completion data for one server address, shared by the
+/// short-lived processes a shell spawns on every <Tab>. Kept in the user's
+/// own cache directory; without a usable one nothing is cached.
+struct Cache {
+    dir: Option<PathBuf>,
+}
+
+impl Cache {
+    fn new(addr: &str, tenant: Option<&str>) -> Self {
+        let mut key = addr.to_string();
+        if let Some(tenant) = tenant {
+            key = format!("{}@{}", tenant, key);
+        }
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        // Both levels are checked: the server's directory could be a link
+        // left by an earlier, less careful version.
+        let dir = default_cache_dir()
+            .filter(|base| private_dir(base).is_some())
+            .map(|base| base.join(name))
+            .filter(|dir| private_dir(dir).is_some());
+        Self { dir }
+    }
+
+    /// The file for `key`, unless it is something other than a regular file.
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        let path = self.dir.as_ref()?.join(key);
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if !metadata.is_file() => None,
+            _ => Some(path),
+        }
+    }
+
+    /// Cached entry and whether it is still within `CACHE_TTL`.
+    fn read(&self, key: &str) -> Option<(Vec<u8>, bool)> {
+        let path = self.path(key)?;
+        let data = std::fs::read(&path).ok()?;
+        let fresh = std::fs::symlink_metadata(&path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age < CACHE_TTL);
+        Some((data, fresh))
+    }
+
+    fn write(&self, key: &str, data: &[u8]) {
+        // Best effort: completion still works, only slower, without a cache.
+        if let Some(path) = self.path(key) {
+            let _ = std::fs::write(path, data);
+        }
+    }
+}
+
+/// Lazily connected client backed by the completion cache.
+struct Fetcher {
+    connection: Connection,
+    client: Option<Client>,
+    cache: Cache,
+}
+
+impl Fetcher {
+    async fn client(&mut self) -> Option<&mut Client> {
+        if self.client.is_none() {
+            self.client = Some(self.connection.connect().await.ok()?);
+        }
+        self.client.as_mut()
+    }
+
+    /// Return a fresh cache entry, else ask the server (within the timeout),
+    /// else fall back to a stale entry.
+    async fn cached<F>(&mut self, key: &str, fetch: F) -> Option<Vec<u8>>
+    where
+        F: AsyncFnOnce(&mut Client) -> Option<Vec<u8>>,
+    {
+        let cached = self.cache.read(key);
+        if let Some((data, true)) = &cached {
+            return Some(data.clone());
+        }
+        let fetched = tokio::time::timeout(COMPLETION_TIMEOUT, async {
+            let client = self.client().await?;
+            fetch(client).await
+        })
+        .await
+        .ok()
+        .flatten();
+        match fetched {
+            Some(data) => {
+                self.cache.write(key, &data);
+                Some(data)
+            }
+            None => cached.map(|(data, _)| data),
+        }
+    }
+
+    async fn services(&mut self) -> ListServicesResponse {
+        self.cached("services", async |client: &mut Client| {
+            let response = client.list_services(ListServicesRequest::new()).await.ok()?;
+            Some(response.into_inner().encode_to_vec())
+        })
+        .await
+        .and_then(|data| ListServicesResponse::decode(data.as_slice()).ok())
+        .unwrap_or_default()
+    }
+
+    /// Values a completer command returns for `request`.
+    async fn values(&mut self, request: CommandRequest) -> Vec<String> {
+        // Values passed to the completer are cached apart, but cannot be
+        // part of a file name.
+        let mut key = format!("values-{}.{}", request.service, request.action);
+        if !request.args.is_empty() {
+            let mut hasher = DefaultHasher::new();
+            request.args.hash(&mut hasher);
+            key = format!("{}-{:016x}", key, hasher.finish());
+        }
+        self.cached(&key, async |client: &mut Client| {
+            let response = client.execute(request).await.ok()?.into_inner();
+            if !response.success {
+                return None;
+            }
+            Some(response_output(&response).values().join("\n").into_bytes())
+        })
+        .await
+        .map(|data| {
+            String::from_utf8_lossy(&data)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// Completion candidates for a one-shot command line. `words` are the words
+/// typed after the program name; the last one is the word being completed
+/// (empty when the cursor follows a space).
+pub(crate) async fn complete(connection: Connection, words: &[String]) -> Vec<String> {
+    let mut fetcher = Fetcher {
+        cache: Cache::new(&connection.addr, connection.session.tenant.as_deref()),
+        connection,
+        client: None,
+    };
+    let (prefix, typed) = match words.split_last() {
+        Some((last, typed)) => (last.as_str(), typed),
+        None => ("", words),
+    };
+    let services = fetcher.services().await.services;
+
+    let typed: Vec<&str> = typed.iter().map(String::as_str).collect();
+
+    let mut candidates: Vec<String> = match resolve_service(&services, &typed) {
+        None if typed.is_empty() => services
+            .iter()
+            .filter(|s| !s.name.contains('.'))
+            .flat_map(|s| std::iter::once(&s.name).chain(&s.aliases).cloned())
+            .collect(),
+        None => vec![],
+        Some((service, used)) if used == typed.len() => service
+            .commands
+            .iter()
+            .map(|c| c.name.clone())
+            .chain(
+                nested_services(&services, &service.name)
+                    .flat_map(|(name, s)| std::iter::once(name.to_string()).chain(s.aliases.iter().cloned())),
+            )
+            .collect(),
+        Some((service, used)) => {
+            let Some(cmd) = service.commands.iter().find(|c| c.name == typed[used]) else {
+                return vec![];
+            };
+            let tokens = &typed[used + 1..];
+            let scan = ScannedArgs::scan(tokens);
+            if scan.pending_flag.is_none() && prefix.starts_with("--") {
+                return complete_flag(&mut fetcher, cmd, tokens, &scan, prefix).await;
+            }
+            match scan.current_arg(&cmd.args) {
+                Some(arg) => arg_values(&mut fetcher, arg, cmd, tokens).await,
+                None => vec![],
+            }
+        }
+    };
+    candidates.retain(|c| c.starts_with(prefix));
+    candidates.sort();
+    candidates
+}
+
+/// Values for an argument of `cmd`: its declared choices, else its
+/// completer's values given what `tokens` already fill in.
+async fn arg_values(fetcher: &mut Fetcher, arg: &ArgDef, cmd: &CommandDef, tokens: &[&str]) -> Vec<String> {
+    if !arg.choices.is_empty() {
+        return arg.choices.clone();
+    }
+    match completer_request(arg, &cmd.args, tokens) {
+        Some(request) => fetcher.values(request).await,
+        None => vec![],
+    }
+}
+
+/// Complete `--name` flags not yet given, or the value part of `--name=value`.
+async fn complete_flag(
+    fetcher: &mut Fetcher,
+    cmd: &CommandDef,
+    tokens: &[&str],
+    scan: &ScannedArgs,
+    prefix: &str,
+) -> Vec<String> {
+    if let Some((name, _)) = prefix[2..].split_once('=') {
+        let Some(arg) = cmd.args.iter().find(|a| a.name == name) else {
+            return vec![];
+        };
+        let mut candidates: Vec<String> = arg_values(fetcher, arg, cmd, tokens)
+            .await
+            .into_iter()
+            .map(|value| format!("--{}={}", name, value))
+            .filter(|flag| flag.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        return candidates;
+    }
+    let mut candidates: Vec<String> = cmd
+        .args
+        .iter()
+        .filter(|a| !scan.named.contains(&a.name))
+        .map(|a| format!("--{}", a.name))
+        .filter(|flag| flag.starts_with(prefix))
+        .collect();
+    candidates.sort();
+    candidates
+}
@@ -0,0 +1,544 @@
+//! Completion scripts for bash, zsh and fish (`cli-shell completions <shell>`).
+//!
+//! Service, command and flag names and `#[arg(choices(...))]` values are
+//! baked into the script from `ListServices`, so completing them needs no
+//! server. Only values of arguments with a completer are fetched when <Tab>
+//! is pressed, through the `__complete` backend (see `completion`).
+//!
+//! Each command's arguments are embedded as one word per argument, in
+//! order: the name, prefixed with `+` when variadic, followed by `=a,b` for
+//! its choices or `@` when it has a completer.
+//!
+//! The scripts fold the words naming a nested service into its full name
+//! (`volume snapshot` becomes `volume.snapshot`) before looking it up.
+
+use crate::cli::nested_services;
+use crate::proto::{ArgDef, ServiceInfo};
+use std::fmt::Write as _;
+
+/// Commands `run_once` handles itself, offered next to the services.
+const BUILTINS: &str = "help health describe jobs job";
+
+/// Lookup functions shared by the bash and zsh scripts.
+const SH_LOOKUPS: &str = r#"# Commands of a service (or the services after `help`, the actions of `job`).
+{{FUNC}}_commands() {
+    case $1 in
+        help) echo "{{SERVICES}}" ;;
+        job) echo "cancel status" ;;
+{{COMMANDS}}    esac
+}
+
+# Arguments of a command in order: "+" marks a variadic argument, "=a,b"
+# lists its choices and "@" means its values come from the server.
+{{FUNC}}_args() {
+    case "$1 $2" in
+{{ARGS}}    esac
+}
+
+# Full name of the service nested in service $1 as $2.
+{{FUNC}}_nested() {
+    case "$1 $2" in
+{{NESTED}}    esac
+}
+"#;
+
+const BASH: &str = r#"# bash completion for {{PROG}}, generated by `{{PROG}} completions bash`.
+# Load it with `source <({{PROG}} completions bash)`.
+
+{{LOOKUPS}}
+# Candidates for the arguments of "${words[0]} ${words[1]}".
+{{FUNC}}_arguments() {
+    local -a specs named=() choices
+    local w spec name pending="" prefix="" target="" position=0
+    read -ra specs <<< "$({{FUNC}}_args "${words[0]}" "${words[1]}")"
+    for w in "${words[@]:2}"; do
+        if [[ $w == --* ]]; then
+            [[ -n $pending ]] && named+=("$pending")
+            pending=""
+            if [[ $w == *=* ]]; then
+                name=${w%%=*}
+                named+=("${name#--}")
+            else
+                pending=${w#--}
+            fi
+        elif [[ -n $pending ]]; then
+            named+=("$pending")
+            pending=""
+        else
+            position=$((position + 1))
+        fi
+    done
+    if [[ -z $pending && $cur == --* ]]; then
+        if [[ $cur != *=* ]]; then
+            for spec in "${specs[@]}"; do
+                name=${spec#+}
+                name=${name%%[=@]*}
+                [[ " ${named[*]} " == *" $name "* ]] || candidates+=("--$name")
+            done
+            return
+        fi
+        pending=${cur%%=*}
+        pending=${pending#--}
+        prefix="--$pending="
+    fi
+    # The pending flag's argument, else the next positional one.
+    for spec in "${specs[@]}"; do
+        name=${spec#+}
+        name=${name%%[=@]*}
+        if [[ -n $pending ]]; then
+            [[ $name == "$pending" ]] && target=$spec && break
+        elif [[ " ${named[*]} " != *" $name "* ]]; then
+            if ((position == 0)) || [[ $spec == +* ]]; then
+                target=$spec
+                break
+            fi
+            position=$((position - 1))
+        fi
+    done
+    case $target in
+        *@)
+            mapfile -t -O "${#candidates[@]}" candidates \
+                < <("${COMP_WORDS[0]}" "${lead[@]}" __complete "${words[@]}" "$cur" 2>/dev/null)
+            ;;
+        *=*)
+            IFS=, read -ra choices <<< "${target#*=}"
+            for w in "${choices[@]}"; do
+                candidates+=("$prefix$w")
+            done
+            ;;
+    esac
+}
+
+{{FUNC}}() {
+    local line=${COMP_LINE:0:COMP_POINT} cur="" head="" w
+    local -a tokens lead=() words=() candidates=()
+    local -i s=0
+    read -ra tokens <<< "$line"
+    if [[ $line != *[[:space:]] ]]; then
+        cur=${tokens[${#tokens[@]}-1]}
+        unset 'tokens[${#tokens[@]}-1]'
+    fi
+    # Options and the server address come before the command.
+    for w in "${tokens[@]:1}"; do
+        if ((${#words[@]} == 0)) && [[ $w == -* || $w == */* || $w == *:* ]]; then
+            lead+=("$w")
+        else
+            words+=("$w")
+        fi
+    done
+    # Fold nested service names: "volume snapshot" -> "volume.snapshot".
+    [[ ${words[0]} == help ]] && s=1
+    while ((${#words[@]} > s + 1)) && w=$({{FUNC}}_nested "${words[s]}" "${words[s+1]}") && [[ -n $w ]]; do
+        words=("${words[@]:0:s}" "$w" "${words[@]:s+2}")
+    done
+    if [[ ${words[0]} == help ]]; then
+        case ${#words[@]} in
+            1) read -ra candidates <<< "$({{FUNC}}_commands help)" ;;
+            2) read -ra candidates <<< "$({{FUNC}}_commands "${words[1]}")" ;;
+        esac
+    else
+        case ${#words[@]} in
+            0) read -ra candidates <<< "{{SERVICES}} {{BUILTINS}}" ;;
+            1) read -ra candidates <<< "$({{FUNC}}_commands "${words[0]}")" ;;
+            *) {{FUNC}}_arguments ;;
+        esac
+    fi
+    # bash splits words at '=' and ':', so replies start after the last one.
+    [[ $cur == *[=:]* ]] && head=${cur%"${cur##*[=:]}"}
+    COMPREPLY=()
+    for w in "${candidates[@]}"; do
+        [[ $w == "$cur"* ]] && COMPREPLY+=("${w#"$head"}")
+    done
+}
+
+complete -F {{FUNC}} {{PROG}}
+"#;
+
+const ZSH: &str = r#"# zsh completion for {{PROG}}, generated by `{{PROG}} completions zsh`.
+# Load it with `source <({{PROG}} completions zsh)` after `compinit`.
+
+{{LOOKUPS}}
+{{FUNC}}() {
+    local cur=${words[CURRENT]} w spec name pending="" prefix="" target=""
+    local -a lead cmd specs named candidates
+    local -i position=0 s=1
+    # Options and the server address come before the command.
+    for w in "${(@)words[2,CURRENT-1]}"; do
+        if ((! $#cmd)) && [[ $w == -* || $w == */* || $w == *:* ]]; then
+            lead+=("$w")
+        else
+            cmd+=("$w")
+        fi
+    done
+    # Fold nested service names: "volume snapshot" -> "volume.snapshot".
+    [[ $cmd[1] == help ]] && s=2
+    while (($#cmd > s)) && w=$({{FUNC}}_nested "$cmd[s]" "$cmd[s+1]") && [[ -n $w ]]; do
+        cmd[s,s+1]=("$w")
+    done
+    if [[ $cmd[1] == help ]]; then
+        case $#cmd in
+            1) candidates=(${=$({{FUNC}}_commands help)}) ;;
+            2) candidates=(${=$({{FUNC}}_commands "$cmd[2]")}) ;;
+        esac
+        compadd -a candidates
+        return
+    fi
+    case $#cmd in
+        0) candidates=({{SERVICES}} {{BUILTINS}}) ;;
+        1) candidates=(${=$({{FUNC}}_commands "$cmd[1]")}) ;;
+        *)
+            specs=(${=$({{FUNC}}_args "$cmd[1]" "$cmd[2]")})
+            for w in "${(@)cmd[3,-1]}"; do
+                if [[ $w == --* ]]; then
+                    [[ -n $pending ]] && named+=("$pending")
+                    pending=""
+                    if [[ $w == *=* ]]; then
+                        name=${w%%=*}
+                        named+=("${name#--}")
+                    else
+                        pending=${w#--}
+                    fi
+                elif [[ -n $pending ]]; then
+                    named+=("$pending")
+                    pending=""
+                else
+                    ((position += 1))
+                fi
+            done
+            if [[ -z $pending && $cur == --* && $cur != *=* ]]; then
+                for spec in $specs; do
+                    name=${${spec#+}%%[=@]*}
+                    ((${named[(Ie)$name]})) || candidates+=("--$name")
+                done
+                compadd -a candidates
+                return
+            fi
+            if [[ -z $pending && $cur == --* ]]; then
+                pending=${${cur%%=*}#--}
+                prefix="--$pending="
+            fi
+            # The pending flag's argument, else the next positional one.
+            for spec in $specs; do
+                name=${${spec#+}%%[=@]*}
+                if [[ -n $pending ]]; then
+                    [[ $name == $pending ]] && target=$spec && break
+                elif ((! ${named[(Ie)$name]})); then
+                    if ((position == 0)) || [[ $spec == +* ]]; then
+                        target=$spec
+                        break
+                    fi
+                    ((position -= 1))
+                fi
+            done
+            case $target in
+                *@) candidates=(${(f)"$("$words[1]" "${lead[@]}" __complete "${cmd[@]}" "$cur" 2>/dev/null)"}) ;;
+                *=*) for w in ${(s:,:)${target#*=}}; do candidates+=("$prefix$w"); done ;;
+            esac
+            ;;
+    esac
+    compadd -a candidates
+}
+
+compdef {{FUNC}} {{PROG}}
+"#;
+
+const FISH: &str = r#"# fish completion for {{PROG}}, generated by `{{PROG}} completions fish`.
+# Load it with `{{PROG}} completions fish | source`.
+
+# Commands of a service (or the services after `help`, the actions of `job`).
+function {{FUNC}}_commands
+    switch $argv[1]
+        case help
+            printf '%s\n' {{SERVICES}}
+        case job
+            printf '%s\n' cancel status
+{{COMMANDS}}    end
+end
+
+# Arguments of a command in order: "+" marks a variadic argument, "=a,b"
+# lists its choices and "@" means its values come from the server.
+function {{FUNC}}_args
+    switch "$argv[1] $argv[2]"
+{{ARGS}}    end
+end
+
+# Full name of the service nested in service $argv[1] as $argv[2].
+function {{FUNC}}_nested
+    switch "$argv[1] $argv[2]"
+{{NESTED}}    end
+end
+
+function {{FUNC}}
+    set -l tokens (commandline -opc)
+    set -l cur (commandline -ct)
+    set -l lead
+    set -l cmd
+    # Options and the server address come before the command.
+    for w in $tokens[2..-1]
+        if test (count $cmd) -eq 0; and string match -qr -- '^-|/|:' "$w"
+            set -a lead $w
+        else
+            set -a cmd $w
+        end
+    end
+    # Fold nested service names: "volume snapshot" -> "volume.snapshot".
+    set -l s 1
+    test "$cmd[1]" = help; and set s 2
+    while test (count $cmd) -gt $s
+        set -l nested ({{FUNC}}_nested $cmd[$s] $cmd[(math $s + 1)])
+        test -n "$nested"; or break
+        set cmd[$s] $nested
+        set -e cmd[(math $s + 1)]
+    end
+    if test "$cmd[1]" = help
+        switch (count $cmd)
+            case 1
+                {{FUNC}}_commands help
+            case 2
+                {{FUNC}}_commands $cmd[2]
+        end
+        return
+    end
+    switch (count $cmd)
+        case 0
+            printf '%s\n' {{SERVICES}} {{BUILTINS}}
+            return
+        case 1
+            {{FUNC}}_commands $cmd[1]
+            return
+    end
+
+    set -l specs ({{FUNC}}_args $cmd[1] $cmd[2])
+    set -l named
+    set -l pending
+    set -l prefix
+    set -l position 0
+    for w in $cmd[3..-1]
+        if string match -q -- '--*' "$w"
+            test -n "$pending"; and set -a named $pending
+            set pending
+            if string match -q -- '*=*' "$w"
+                set -a named (string replace -r -- '^--([^=]*)=.*' '$1' "$w")
+            else
+                set pending (string sub -s 3 -- "$w")
+            end
+        else if test -n "$pending"
+            set -a named $pending
+            set pending
+        else
+            set position (math $position + 1)
+        end
+    end
+    if test -z "$pending"; and string match -q -- '--*' "$cur"
+        if not string match -q -- '*=*' "$cur"
+            for spec in $specs
+                set -l name (string replace -r -- '^\+?([^=@]*).*' '$1' "$spec")
+                contains -- $name $named; or printf '%s\n' "--$name"
+            end
+            return
+        end
+        set pending (string replace -r -- '^--([^=]*)=.*' '$1' "$cur")
+        set prefix "--$pending="
+    end
+    # The pending flag's argument, else the next positional one.
+    set -l target
+    for spec in $specs
+        set -l name (string replace -r -- '^\+?([^=@]*).*' '$1' "$spec")
+        if test -n "$pending"
+            if test "$name" = "$pending"
+                set target $spec
+                break
+            end
+        else if not contains -- $name $named
+            if test $position -eq 0; or string match -q -- '+*' "$spec"
+                set target $spec
+                break
+            end
+            set position (math $position - 1)
+        end
+    end
+    if string match -q -- '*@' "$target"
+        $tokens[1] $lead __complete $cmd "$cur" 2>/dev/null
+    else if string match -q -- '*=*' "$target"
+        for choice in (string split , -- (string replace -r -- '^[^=]*=' '' "$target"))
+            printf '%s\n' "$prefix$choice"
+        end
+    end
+end
+
+complete -c {{PROG}} -f -a '({{FUNC}})'
+"#;
+
+/// A service's names and, per command, its argument words.
+struct Entry {
+    names: Vec<String>,
+    commands: Vec<(String, Vec<String>)>,
+    /// The names of each service nested in this one, with its full name.
+    nested: Vec<(Vec<String>, String)>,
+}
+
+/// The completion script for `shell` ("bash", "zsh" or "fish") completing
+/// `program`'s command lines for `services`.
+pub(crate) fn script(shell: &str, program: &str, services: &[ServiceInfo]) -> anyhow::Result<String> {
+    if !is_plain(program) {
+        anyhow::bail!("cannot generate completions for program name '{}'", program);
+    }
+    let sanitized: String = program
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let entries = entries(services);
+    let (template, func, (commands, args, nested)) = match shell {
+        "bash" => (BASH.replace("{{LOOKUPS}}", SH_LOOKUPS), format!("_{}", sanitized), sh_lookups(&entries)),
+        "zsh" => (ZSH.replace("{{LOOKUPS}}", SH_LOOKUPS), format!("_{}", sanitized), sh_lookups(&entries)),
+        "fish" => (FISH.to_string(), format!("__{}", sanitized), fish_lookups(&entries)),
+        _ => anyhow::bail!("unsupported shell '{}' (expected bash, zsh or fish)", shell),
+    };
+    let services: Vec<&str> = entries
+        .iter()
+        .filter(|entry| !entry.names[0].contains('.'))
+        .flat_map(|entry| entry.names.iter().map(String::as_str))
+        .collect();
+    Ok(template
+        .replace("{{COMMANDS}}", &commands)
+        .replace("{{ARGS}}", &args)
+        .replace("{{NESTED}}", &nested)
+        .replace("{{FUNC}}", &func)
+        .replace("{{PROG}}", program)
+        .replace("{{SERVICES}}", &services.join(" "))
+        .replace("{{BUILTINS}}", BUILTINS))
+}
+
+/// Whether `word` can be embedded in a script unquoted, and in an argument
+/// word without being mistaken for its markers.
+fn is_plain(word: &str) -> bool {
+    !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | ':'))
+}
+
+fn entries(services: &[ServiceInfo]) -> Vec<Entry> {
+    // Aliases of a nested service only apply after its parent's name.
+    let names = |service: &ServiceInfo, name: &str| -> Vec<String> {
+        std::iter::once(name)
+            .chain(service.aliases.iter().map(String::as_str))
+            .filter(|name| is_plain(name))
+            .map(str::to_string)
+            .collect()
+    };
+    services
+        .iter()
+        .map(|service| Entry {
+            names: if service.name.contains('.') {
+                Some(service.name.clone()).filter(|name| is_plain(name)).into_iter().collect()
+            } else {
+                names(service, &service.name)
+            },
+            commands: service
+                .commands
+                .iter()
+                .filter(|command| is_plain(&command.name))
+                .map(|command| (command.name.clone(), command.args.iter().filter_map(arg_word).collect()))
+                .collect(),
+            nested: nested_services(services, &service.name)
+                .map(|(name, nested)| (names(nested, name), nested.name.clone()))
+                .filter(|(names, full)| !names.is_empty() && is_plain(full))
+                .collect(),
+        })
+        .filter(|entry| !entry.names.is_empty())
+        .collect()
+}
+
+/// The embedded form of an argument, e.g. `+disks@` or `fs=xfs,ext4`.
+fn arg_word(arg: &ArgDef) -> Option<String> {
+    if !is_plain(&arg.name) {
+        return None;
+    }
+    let mut word = String::new();
+    if arg.variadic {
+        word.push('+');
+    }
+    word.push_str(&arg.name);
+    let choices: Vec<&str> = arg
+        .choices
+        .iter()
+        .map(String::as_str)
+        .filter(|choice| is_plain(choice))
+        .collect();
+    if !choices.is_empty() {
+        let _ = write!(word, "={}", choices.join(","));
+    } else if !arg.completer.is_empty() {
+        word.push('@');
+    }
+    Some(word)
+}
+
+/// The words that can follow a service: its commands and the names of the
+/// services nested in it.
+fn next_words(entry: &Entry) -> Vec<&str> {
+    let commands = entry.commands.iter().map(|(name, _)| name.as_str());
+    let nested = entry.nested.iter().flat_map(|(names, _)| names.iter().map(String::as_str));
+    commands.chain(nested).collect()
+}
+
+/// `"<parent> <name>"` patterns matching each way of naming a nested
+/// service after one of its parent's names.
+fn nested_patterns(entry: &Entry, names: &[String]) -> Vec<String> {
+    entry
+        .names
+        .iter()
+        .flat_map(|parent| names.iter().map(move |name| format!("\"{} {}\"", parent, name)))
+        .collect()
+}
+
+/// The `case` arms of the bash/zsh `_commands`, `_args` and `_nested`
+/// functions.
+fn sh_lookups(entries: &[Entry]) -> (String, String, String) {
+    let mut commands = String::new();
+    let mut args = String::new();
+    let mut nested = String::new();
+    for entry in entries {
+        let names = next_words(entry);
+        let _ = writeln!(commands, "        {}) echo \"{}\" ;;", entry.names.join("|"), names.join(" "));
+        for (command, words) in entry.commands.iter().filter(|(_, words)| !words.is_empty()) {
+            let patterns: Vec<String> = entry
+                .names
+                .iter()
+                .map(|name| format!("\"{} {}\"", name, command))
+                .collect();
+            let _ = writeln!(args, "        {}) echo \"{}\" ;;", patterns.join("|"), words.join(" "));
+        }
+        for (names, full) in &entry.nested {
+            let patterns = nested_patterns(entry, names);
+            let _ = writeln!(nested, "        {}) echo \"{}\" ;;", patterns.join("|"), full);
+        }
+    }
+    (commands, args, nested)
+}
+
+/// The `case` arms of the fish `_commands`, `_args` and `_nested` functions.
+fn fish_lookups(entries: &[Entry]) -> (String, String, String) {
+    let mut commands = String::new();
+    let mut args = String::new();
+    let mut nested = String::new();
+    for entry in entries {
+        let names = next_words(entry);
+        let _ = writeln!(commands, "        case {}", entry.names.join(" "));
+        let _ = writeln!(commands, "            printf '%s\\n' {}", names.join(" "));
+        for (command, words) in entry.commands.iter().filter(|(_, words)| !words.is_empty()) {
+            let patterns: Vec<String> = entry
+                .names
+                .iter()
+                .map(|name| format!("\"{} {}\"", name, command))
+                .collect();
+            let _ = writeln!(args, "        case {}", patterns.join(" "));
+            let _ = writeln!(args, "            printf '%s\\n' {}", words.join(" "));
+        }
+        for (names, full) in &entry.nested {
+            let _ = writeln!(nested, "        case {}", nested_patterns(entry, names).join(" "));
+            let _ = writeln!(nested, "            echo {}", full);
+        }
+    }
+    (commands, args, nested)
+}
@@ -0,0 +1,338 @@
+//! Daemon and CLI settings read from a TOML file, so deployments configure
+//! both sides in one place instead of through code or flags:
+//!
+//! ```toml
+//! [server]
+//! listen = ["/run/storage-daemon.sock", "0.0.0.0:50051"]
+//! http_gateway = "127.0.0.1:8080"
+//! metrics = "127.0.0.1:9100"
+//! # Commands as MCP tools for AI assistants.
+//! mcp = "127.0.0.1:8090"
+//! # gRPC-Web for browser consoles, callable from pages of these origins.
+//! grpc_web = { listen = "127.0.0.1:8081", allow_origins = ["https://console.example.com"] }
+//! # gRPC server reflection, for grpcurl and similar tools.
+//! reflection = true
+//! # The `admin` service: uptime, stats, connections, loglevel, shutdown.
+//! admin = true
+//! max_concurrency = 8
+//! default_timeout = "60s"
+//! plugin_dir = "/usr/lib/storage-daemon/plugins"
+//! # Where `schedule add` saves scheduled commands.
+//! schedules = "/var/lib/storage-daemon/schedules.json"
+//! # Served on the TCP `listen` addresses.
+//! tls = { cert = "server.pem", key = "server.key", client_ca = "ca.pem" }
+//! # Applied to the Unix `listen` sockets.
+//! socket = { mode = 0o660, group = "storage-admin" }
+//! # Requests each client may send, overall and for particular commands.
+//! rate_limit = { per_peer = "20/s", burst = 40, commands = { "pool destroy" = "1/m" } }
+//!
+//! [cli]
+//! endpoint = "/run/storage-daemon.sock"
+//! history_file = "~/.storage_history"
+//! format = "json"
+//!
+//! [cli.aliases]
+//! vols = "volume list"
+//! ```
+//!
+//! Every key is optional. Relative paths are resolved against the file's
+//! directory and `~/` against the home directory. These environment
+//! variables take precedence over the file: `NEXUS_LISTEN` (addresses
+//! separated by commas), `NEXUS_HTTP_ADDR`, `NEXUS_METRICS_ADDR` and
+//! `NEXUS_PLUGIN_DIR` for the server; `NEXUS_ENDPOINT`, `NEXUS_HISTORY_FILE`, `NEXUS_FORMAT`,
+//! `NEXUS_TENANT` and `NEXUS_TOKEN` for the CLI.
+
+use crate::endpoint::UdsOptions;
+use crate::ratelimit::Rate;
+use crate::theme::Theme;
+use anyhow::Context as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The contents of a config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub cli: CliConfig,
+}
+
+/// The `[server]` table, applied by `NexusServer::with_config`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Addresses to serve on, as for `NexusServer::serve`: one string, or a
+    /// list to serve on several at once.
+    #[serde(deserialize_with = "one_or_many")]
+    pub listen: Vec<String>,
+    /// Address of the HTTP/JSON gateway (`with_http_gateway`).
+    pub http_gateway: Option<String>,
+    /// Address of the Prometheus endpoint (`with_metrics`).
+    pub metrics: Option<String>,
+    /// Address of the MCP tool server (`with_mcp`).
+    pub mcp: Option<String>,
+    pub grpc_web: Option<GrpcWebConfig>,
+    /// Serve gRPC server reflection (`with_reflection`).
+    pub reflection: bool,
+    /// Register the `admin` service (`with_admin`).
+    pub admin: bool,
+    pub max_concurrency: Option<usize>,
+    /// In bytes; see `with_max_message_size`.
+    pub max_message_size: Option<usize>,
+    /// `false` turns off `with_compression`.
+    pub compression: Option<bool>,
+    /// e.g. "60s"; see `with_default_timeout`.
+    #[serde(deserialize_with = "duration")]
+    pub default_timeout: Option<Duration>,
+    pub plugin_dir: Option<PathBuf>,
+    /// File the scheduled commands are kept in (`with_scheduler`).
+    pub schedules: Option<PathBuf>,
+    /// Control socket for zero-downtime upgrades (`with_takeover`).
+    pub takeover: Option<PathBuf>,
+    pub tls: Option<ServerTls>,
+    /// Mode and ownership of the Unix `listen` sockets, e.g.
+    /// `socket = { mode = 0o660, group = "storage-admin" }`.
+    pub socket: UdsOptions,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Request rates for `crate::ratelimit::RateLimit`, written like "20/s" or
+/// "1/m". `burst` applies to `per_peer`; `commands` maps a command as typed
+/// on the CLI ("pool destroy") to its own limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub per_peer: Option<Rate>,
+    pub burst: Option<u32>,
+    pub commands: BTreeMap<String, Rate>,
+}
+
+/// The gRPC-Web endpoint (`with_grpc_web`): its address and the browser
+/// origins allowed to call it (`"*"` for any).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcWebConfig {
+    pub listen: String,
+    #[serde(default)]
+    pub allow_origins: Vec<String>,
+}
+
+/// PEM files for serving TLS on the TCP `listen` addresses; `client_ca`
+/// turns on mutual TLS.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerTls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The `[cli]` table, applied by `NexusCli::with_config`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CliConfig {
+    /// Address of the server to connect to.
+    pub endpoint: Option<String>,
+    pub history_file: Option<PathBuf>,
+    pub history_size: Option<usize>,
+    /// How results are printed unless `--json` is given.
+    pub format: Option<OutputFormat>,
+    /// Page interactive output taller than the terminal (default on).
+    pub paging: Option<bool>,
+    /// Color input and output (default on unless `NO_COLOR` is set).
+    pub color: Option<bool>,
+    /// Colors to use instead of the default ones, as the `[cli.theme]` table.
+    pub theme: Option<Theme>,
+    pub tenant: Option<String>,
+    pub token: Option<String>,
+    pub tls: Option<CliTls>,
+    /// Shortcuts expanded at the start of a command line, e.g.
+    /// `vols = "volume list"` makes `vols --pool p0` run `volume list --pool p0`.
+    pub aliases: BTreeMap<String, String>,
+}
+
+/// PEM files for connecting over TLS; `cert` and `key` are the client
+/// certificate for mutual TLS.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CliTls {
+    pub ca: PathBuf,
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+/// How the CLI prints results, and the server renders a response's
+/// `message` (see `CommandOutput::render`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Rendered for the terminal.
+    #[default]
+    Plain,
+    /// Raw JSON, as with `--json`.
+    Json,
+    /// The JSON document as YAML.
+    Yaml,
+    /// Like `Plain`, but JSON arrays of objects and JSON objects are shown
+    /// as tables.
+    Table,
+}
+
+impl OutputFormat {
+    /// The name used in config files and the `nexus-format` preference.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Plain => "plain",
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+            Self::Table => "table",
+        }
+    }
+
+    /// Whether the format is meant for programs (`jq`, scripts) rather
+    /// than people.
+    pub fn is_machine_readable(self) -> bool {
+        matches!(self, Self::Json | Self::Yaml)
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            "table" => Ok(Self::Table),
+            _ => anyhow::bail!("unknown output format '{}' (expected plain, json, yaml or table)", s),
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file at `path` and apply the environment overrides.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        let mut config = Self::parse(&text).with_context(|| format!("invalid config {}", path.display()))?;
+        config.resolve_paths(path.parent().unwrap_or(Path::new(".")));
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Settings from the environment overrides alone, for running without
+    /// a config file.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        config.apply_env()?;
+        Ok(config)
+    }
+
+    /// Parse TOML config text. Paths are kept as written and the environment
+    /// is not consulted.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        let server = &mut self.server;
+        let cli = &mut self.cli;
+        let tls_paths = server
+            .tls
+            .iter_mut()
+            .flat_map(|tls| [Some(&mut tls.cert), Some(&mut tls.key), tls.client_ca.as_mut()])
+            .chain(
+                cli.tls
+                    .iter_mut()
+                    .flat_map(|tls| [Some(&mut tls.ca), tls.cert.as_mut(), tls.key.as_mut()]),
+            );
+        let paths = [
+            server.plugin_dir.as_mut(),
+            server.schedules.as_mut(),
+            server.takeover.as_mut(),
+            cli.history_file.as_mut(),
+        ]
+        .into_iter()
+        .chain(tls_paths)
+        .flatten();
+        for path in paths {
+            *path = resolve(base, path);
+        }
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let server = &mut self.server;
+        let cli = &mut self.cli;
+        if let Some(addrs) = var("NEXUS_LISTEN") {
+            server.listen = addrs.split(',').map(str::to_string).collect();
+        }
+        server.http_gateway = var("NEXUS_HTTP_ADDR").or(server.http_gateway.take());
+        server.metrics = var("NEXUS_METRICS_ADDR").or(server.metrics.take());
+        if let Some(dir) = var("NEXUS_PLUGIN_DIR") {
+            server.plugin_dir = Some(dir.into());
+        }
+        cli.endpoint = var("NEXUS_ENDPOINT").or(cli.endpoint.take());
+        if let Some(path) = var("NEXUS_HISTORY_FILE") {
+            cli.history_file = Some(resolve(Path::new("."), Path::new(&path)));
+        }
+        if let Some(format) = var("NEXUS_FORMAT") {
+            cli.format = Some(format.parse().context("invalid NEXUS_FORMAT")?);
+        }
+        cli.tenant = var("NEXUS_TENANT").or(cli.tenant.take());
+        cli.token = var("NEXUS_TOKEN").or(cli.token.take());
+        Ok(())
+    }
+}
+
+/// `path` with a leading `~/` expanded and, if relative, joined to `base`.
+fn resolve(base: &Path, path: &Path) -> PathBuf {
+    if let (Ok(rest), Some(home)) = (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        return PathBuf::from(home).join(rest);
+    }
+    base.join(path)
+}
+
+/// Parse a duration written as a whole number with `ms`, `s`, `m` or `h`,
+/// as in `#[command(timeout = "...")]`. Zero is rejected.
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let value: u64 = s[..split].parse().ok().filter(|&v| v > 0)?;
+    let ms = match &s[split..] {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        _ => return None,
+    };
+    value.checked_mul(ms).map(Duration::from_millis)
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let Some(text) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    parse_duration(&text).map(Some).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "invalid duration '{}': expected a duration such as \"500ms\", \"30s\", \"5m\" or \"1h\"",
+            text
+        ))
+    })
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged, expecting = "an address or a list of addresses")]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(addr) => vec![addr],
+        OneOrMany::Many(addrs) => addrs,
+    })
+}
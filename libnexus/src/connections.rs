@@ -0,0 +1,387 @@
+//! The client connections a server has open, listed by `admin
+//! connections`: each accepted socket is wrapped so it is tracked until it
+//! closes. Each also keeps the preferences its client declared, for the
+//! requests on it that do not declare their own.
+//!
+//! A request finds its connection (`ConnectionRef`) through what tonic
+//! records of the socket: a Unix socket records a `TrackedInfo` naming it,
+//! while a TCP connection keeps tonic's own `TcpConnectInfo`, which TLS
+//! wraps and `Request::remote_addr` and `Request::peer_certs` look for, and
+//! is found by its addresses.
+
+use crate::context::Preferences;
+use crate::endpoint::Endpoint;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+/// An open connection.
+#[derive(Debug, Clone)]
+pub(crate) struct Connection {
+    /// The endpoint it was accepted on.
+    pub(crate) endpoint: String,
+    /// Who is on the other end: "192.0.2.7:53124" or "uid 1000, pid 4242".
+    pub(crate) peer: String,
+    pub(crate) since: Instant,
+    /// What the client declared so far (see `ConnectionRef::preferences`).
+    pub(crate) preferences: Preferences,
+}
+
+/// The local and remote address of a TCP connection, which tell it apart
+/// from the others open.
+type Addrs = (SocketAddr, SocketAddr);
+
+/// The open connections of a server, across its endpoints.
+#[derive(Default)]
+pub(crate) struct Connections {
+    next_id: AtomicU64,
+    open: Mutex<Open>,
+}
+
+#[derive(Default)]
+struct Open {
+    connections: BTreeMap<u64, Connection>,
+    /// The TCP connections among them, by address.
+    tcp: HashMap<Addrs, u64>,
+}
+
+impl Connections {
+    /// The open connections, oldest first.
+    pub(crate) fn list(&self) -> Vec<Connection> {
+        self.open.lock().unwrap().connections.values().cloned().collect()
+    }
+
+    /// Store the `ConnectionRef` of the request whose `extensions` tonic
+    /// filled. For a Unix socket, this splits the `TrackedInfo` into the
+    /// socket's info, where the peer credentials are looked for, and the
+    /// `ConnectionRef`; a TCP connection is looked up by its addresses.
+    pub(crate) fn identify(self: &Arc<Self>, extensions: &mut tonic::Extensions) {
+        #[cfg(unix)]
+        if let Some(tracked) = extensions.remove::<TrackedInfo<tonic::transport::server::UdsConnectInfo>>() {
+            extensions.insert(tracked.info);
+            extensions.insert(tracked.connection);
+            return;
+        }
+        let Some(addrs) = extensions
+            .get::<TcpConnectInfo>()
+            .and_then(|info| info.local_addr().zip(info.remote_addr()))
+        else {
+            return;
+        };
+        if let Some(&id) = self.open.lock().unwrap().tcp.get(&addrs) {
+            extensions.insert(ConnectionRef {
+                connections: self.clone(),
+                id,
+            });
+        }
+    }
+
+    /// The connections `incoming` accepts on `endpoint`, each tracked until
+    /// it is closed.
+    pub(crate) fn track<S, IO>(
+        self: &Arc<Self>,
+        endpoint: &Endpoint,
+        incoming: S,
+    ) -> impl Stream<Item = io::Result<Tracked<IO>>>
+    where
+        S: Stream<Item = io::Result<IO>>,
+        IO: Peer,
+    {
+        let connections = self.clone();
+        let endpoint = endpoint.to_string();
+        incoming.map(move |accepted| {
+            let io = accepted?;
+            let id = connections.next_id.fetch_add(1, Ordering::Relaxed);
+            let connection = Connection {
+                endpoint: endpoint.clone(),
+                peer: io.peer(),
+                since: Instant::now(),
+                preferences: Preferences::default(),
+            };
+            let addrs = io.addrs();
+            let mut open = connections.open.lock().unwrap();
+            open.connections.insert(id, connection);
+            if let Some(addrs) = addrs {
+                open.tcp.insert(addrs, id);
+            }
+            drop(open);
+            Ok(Tracked {
+                io,
+                connections: connections.clone(),
+                id,
+                addrs,
+            })
+        })
+    }
+}
+
+/// The connection a request arrived on, stored in its extensions.
+#[derive(Clone)]
+pub(crate) struct ConnectionRef {
+    connections: Arc<Connections>,
+    id: u64,
+}
+
+impl ConnectionRef {
+    /// The preferences of a request on the connection that declares
+    /// `declared`: each it sets, else the latest an earlier request on the
+    /// connection set. What it sets is kept for the later ones, so a client
+    /// can declare its preferences once, on its first request.
+    pub(crate) fn preferences(&self, declared: Preferences) -> Preferences {
+        let mut open = self.connections.open.lock().unwrap();
+        let Some(connection) = open.connections.get_mut(&self.id) else {
+            return declared;
+        };
+        let kept = &mut connection.preferences;
+        for (declared, kept) in [
+            (declared.locale, &mut kept.locale),
+            (declared.format, &mut kept.format),
+            (declared.timezone, &mut kept.timezone),
+        ] {
+            if declared.is_some() {
+                *kept = declared;
+            }
+        }
+        kept.clone()
+    }
+}
+
+/// What tonic records about a tracked Unix socket for its requests: the
+/// socket's own info, and the connection.
+#[derive(Clone)]
+pub(crate) struct TrackedInfo<T> {
+    info: T,
+    connection: ConnectionRef,
+}
+
+/// A socket that can say who is on its other end.
+pub(crate) trait Peer {
+    fn peer(&self) -> String;
+
+    /// The addresses of a TCP connection.
+    fn addrs(&self) -> Option<Addrs> {
+        None
+    }
+}
+
+impl Peer for tokio::net::TcpStream {
+    fn peer(&self) -> String {
+        self.peer_addr().map_or_else(|_| "unknown".to_string(), |addr| addr.to_string())
+    }
+
+    fn addrs(&self) -> Option<Addrs> {
+        self.local_addr().ok().zip(self.peer_addr().ok())
+    }
+}
+
+#[cfg(unix)]
+impl Peer for tokio::net::UnixStream {
+    fn peer(&self) -> String {
+        match self.peer_cred() {
+            Ok(cred) => match cred.pid() {
+                Some(pid) => format!("uid {}, pid {}", cred.uid(), pid),
+                None => format!("uid {}", cred.uid()),
+            },
+            Err(_) => "unknown".to_string(),
+        }
+    }
+}
+
+/// An accepted socket, listed among the open connections until dropped.
+pub(crate) struct Tracked<IO> {
+    io: IO,
+    connections: Arc<Connections>,
+    id: u64,
+    addrs: Option<Addrs>,
+}
+
+impl<IO> Drop for Tracked<IO> {
+    fn drop(&mut self) {
+        let mut open = self.connections.open.lock().unwrap();
+        open.connections.remove(&self.id);
+        if let Some(addrs) = self.addrs {
+            if open.tcp.get(&addrs) == Some(&self.id) {
+                open.tcp.remove(&addrs);
+            }
+        }
+    }
+}
+
+impl Connected for Tracked<tokio::net::TcpStream> {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.io.connect_info()
+    }
+}
+
+#[cfg(unix)]
+impl Connected for Tracked<tokio::net::UnixStream> {
+    type ConnectInfo = TrackedInfo<tonic::transport::server::UdsConnectInfo>;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        TrackedInfo {
+            info: self.io.connect_info(),
+            connection: ConnectionRef {
+                connections: self.connections.clone(),
+                id: self.id,
+            },
+        }
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for Tracked<IO> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for Tracked<IO> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::nexus_service_client::NexusServiceClient;
+    use crate::proto::CommandRequest;
+    use crate::{nexus_service, Context, NexusServer};
+    use std::time::Duration;
+    use tonic::transport::Channel;
+
+    struct Probe;
+
+    /// What the server knows of a request's connection.
+    #[nexus_service]
+    impl Probe {
+        /// The request's locale and whether its address is known.
+        #[command]
+        async fn whoami(&self, ctx: &Context) -> anyhow::Result<String> {
+            let locale = ctx.preferences.locale.as_deref().unwrap_or("-");
+            Ok(format!("{} {}", locale, ctx.remote_addr.is_some()))
+        }
+    }
+
+    /// An address on the loopback interface no one listens on.
+    fn free_address() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("tcp://{}", listener.local_addr().unwrap())
+    }
+
+    /// Connect with `connect` until the server is up.
+    async fn connect<F, Fut>(connect: F) -> NexusServiceClient<Channel>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = anyhow::Result<Channel>>,
+    {
+        for _ in 0..50 {
+            if let Ok(channel) = connect().await {
+                return NexusServiceClient::new(channel);
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the server did not start");
+    }
+
+    async fn whoami(client: &mut NexusServiceClient<Channel>, locale: Option<&str>) -> String {
+        let mut request = tonic::Request::new(CommandRequest {
+            service: "probe".to_string(),
+            action: "whoami".to_string(),
+            ..Default::default()
+        });
+        if let Some(locale) = locale {
+            request.metadata_mut().insert("nexus-locale", locale.parse().unwrap());
+        }
+        client.execute(request).await.unwrap().into_inner().message
+    }
+
+    /// Declared preferences outlive their request on one connection, but
+    /// not on another, and the client's address is known throughout if it
+    /// has one (`remote`).
+    async fn check_connections(
+        mut first: NexusServiceClient<Channel>,
+        mut second: NexusServiceClient<Channel>,
+        remote: bool,
+    ) {
+        assert_eq!(whoami(&mut first, Some("de-DE")).await, format!("de-DE {}", remote));
+        assert_eq!(whoami(&mut first, None).await, format!("de-DE {}", remote));
+        assert_eq!(whoami(&mut first, Some("fr-FR")).await, format!("fr-FR {}", remote));
+        assert_eq!(whoami(&mut first, None).await, format!("fr-FR {}", remote));
+        assert_eq!(whoami(&mut second, None).await, format!("- {}", remote));
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_keep_declared_preferences() {
+        let addr = free_address();
+        let serve = NexusServer::new().register(Probe);
+        tokio::spawn({
+            let addr = addr.clone();
+            async move { serve.serve(&addr).await }
+        });
+        let channel = || async { addr.parse::<crate::Endpoint>()?.connect().await };
+        check_connections(connect(channel).await, connect(channel).await, true).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_connections_keep_declared_preferences() {
+        let path = std::env::temp_dir().join(format!("nexus-connections-{}.sock", std::process::id()));
+        let addr = format!("unix://{}", path.display());
+        let serve = NexusServer::new().register(Probe);
+        tokio::spawn({
+            let addr = addr.clone();
+            async move { serve.serve(&addr).await }
+        });
+        let channel = || async { addr.parse::<crate::Endpoint>()?.connect().await };
+        check_connections(connect(channel).await, connect(channel).await, false).await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn tls_connections_keep_declared_preferences() {
+        use tonic::transport::{Certificate, ClientTlsConfig, Identity};
+
+        let addr = free_address();
+        let identity = Identity::from_pem(include_str!("testdata/server.pem"), include_str!("testdata/server.key"));
+        let serve = NexusServer::new().register(Probe);
+        tokio::spawn({
+            let addr = addr.clone();
+            async move { serve.serve_tls(&addr, identity).await }
+        });
+        let channel = || async {
+            let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(include_str!("testdata/ca.pem")));
+            crate::Endpoint::parse_tls(&addr)?.connect_tls(Default::default(), tls).await
+        };
+        check_connections(connect(channel).await, connect(channel).await, true).await;
+    }
+}
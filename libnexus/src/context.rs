@@ -0,0 +1,451 @@
+use crate::auth::PeerCredentials;
+use crate::error::NexusError;
+use crate::events::{Event, EventBus};
+use crate::plan::Change;
+use crate::progress::Progress;
+use crate::registry::{Args, CommandOutput, Priority, RegistryHandle};
+use crate::state::States;
+use crate::session::Session;
+use crate::transfer::{Download, Upload};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+/// Metadata key carrying the client's preferred locale.
+pub const LOCALE_KEY: &str = "nexus-locale";
+/// Metadata key carrying the client's preferred output format.
+pub const FORMAT_KEY: &str = "nexus-format";
+/// Metadata key carrying the client's timezone for rendering timestamps.
+pub const TIMEZONE_KEY: &str = "nexus-timezone";
+
+/// Metadata key carrying the tenant (namespace) a request acts on behalf of.
+pub const TENANT_KEY: &str = "nexus-tenant";
+
+/// Metadata key carrying the request ID, sent by clients that pick their own
+/// and returned by the server in the response metadata.
+pub const REQUEST_ID_KEY: &str = "nexus-request-id";
+
+/// Client preferences, declared as gRPC metadata and kept by the server
+/// for the rest of the connection they were declared on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Preferences {
+    /// Locale tag (e.g. "en-US").
+    pub locale: Option<String>,
+    /// Preferred output format (e.g. "json", "plain").
+    pub format: Option<String>,
+    /// Timezone for timestamps (e.g. "UTC", "Asia/Taipei").
+    pub timezone: Option<String>,
+}
+
+impl Preferences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn locale(mut self, locale: &str) -> Self {
+        self.locale = Some(locale.to_string());
+        self
+    }
+
+    pub fn format(mut self, format: &str) -> Self {
+        self.format = Some(format.to_string());
+        self
+    }
+
+    pub fn timezone(mut self, timezone: &str) -> Self {
+        self.timezone = Some(timezone.to_string());
+        self
+    }
+
+    /// Read preferences from incoming request metadata. Missing or non-ASCII
+    /// values are left unset.
+    pub fn from_metadata(metadata: &MetadataMap) -> Self {
+        let get = |key: &str| {
+            metadata
+                .get(key)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        Self {
+            locale: get(LOCALE_KEY),
+            format: get(FORMAT_KEY),
+            timezone: get(TIMEZONE_KEY),
+        }
+    }
+
+    /// Write the set preferences into outgoing request metadata.
+    pub fn apply_to(&self, metadata: &mut MetadataMap) {
+        let fields = [
+            (LOCALE_KEY, &self.locale),
+            (FORMAT_KEY, &self.format),
+            (TIMEZONE_KEY, &self.timezone),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                if let Ok(value) = MetadataValue::try_from(value.as_str()) {
+                    metadata.insert(key, value);
+                }
+            }
+        }
+    }
+}
+
+/// Per-request context handed to `Service::execute`. Command methods receive
+/// it by declaring a `ctx: &Context` parameter.
+#[derive(Debug, Default)]
+pub struct Context {
+    /// Preferences the client declared for this session.
+    pub preferences: Preferences,
+    /// The client asked for a preview: report changes via `propose` instead
+    /// of applying them. Only set for `#[command(dry_run)]` commands.
+    pub dry_run: bool,
+    /// Priority requested by the client, overriding the command's default.
+    pub priority: Option<Priority>,
+    /// Tenant the request acts for, if the client declared one. Shared
+    /// services use it to keep each customer's resources apart.
+    pub tenant: Option<String>,
+    /// Role of the authenticated caller, or `None` for anonymous requests.
+    pub role: Option<String>,
+    /// The local process that sent the request, if it came over a Unix
+    /// socket.
+    pub peer: Option<PeerCredentials>,
+    /// Address of the client, if it connected over TCP.
+    pub remote_addr: Option<SocketAddr>,
+    /// Identifies the request in the server's logs and to the client: the
+    /// ID the client sent as `nexus-request-id`, or one the server assigned.
+    pub request_id: String,
+    /// The command the client asked for, as typed on the CLI: "pool destroy".
+    pub command: String,
+    /// Values attached by `Middleware`, read with `extension`.
+    pub extensions: BTreeMap<String, String>,
+    states: Arc<States>,
+    /// Where `emit` publishes; unset outside a server.
+    events: Option<EventBus>,
+    /// The server's registry, for `call`; unset outside a server.
+    registry: Option<RegistryHandle>,
+    /// The commands the request is running, each as (service, action),
+    /// outermost first: the one the client asked for and those it called.
+    calls: Mutex<Vec<(String, String)>>,
+    /// Keys of the `#[command(lock)]` locks the request's commands hold,
+    /// which the commands they call do not wait for.
+    locks: Mutex<Vec<String>>,
+    /// The file sent with the request, until the command takes it.
+    upload: Mutex<Option<Upload>>,
+    /// Where the file the client receives goes, for `Download` requests.
+    download: Option<Download>,
+    /// The user's end of an interactive command, for `Session` requests.
+    session: Option<Session>,
+    deadline: Mutex<Option<Instant>>,
+    cancellation: CancellationToken,
+    progress: Progress,
+    plan: Mutex<Vec<Change>>,
+    warnings: Mutex<Vec<String>>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_preferences(mut self, preferences: Preferences) -> Self {
+        self.preferences = preferences;
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Option<Priority>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: Option<String>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    pub fn with_role(mut self, role: Option<String>) -> Self {
+        self.role = role;
+        self
+    }
+
+    pub fn with_peer(mut self, peer: Option<PeerCredentials>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub fn with_remote_addr(mut self, addr: Option<SocketAddr>) -> Self {
+        self.remote_addr = addr;
+        self
+    }
+
+    pub fn with_request_id(mut self, id: impl Into<String>) -> Self {
+        self.request_id = id.into();
+        self
+    }
+
+    pub fn with_command(mut self, command: impl Into<String>) -> Self {
+        self.command = command.into();
+        self
+    }
+
+    /// Attach `value` under `key`, replacing any earlier value.
+    pub fn with_extension(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extensions.insert(key.into(), value.into());
+        self
+    }
+
+    /// The value a middleware attached under `key`.
+    pub fn extension(&self, key: &str) -> Option<&str> {
+        self.extensions.get(key).map(String::as_str)
+    }
+
+    /// Reach the server's shared state (`NexusServer::with_state`) through
+    /// `states`.
+    pub(crate) fn with_states(mut self, states: Arc<States>) -> Self {
+        self.states = states;
+        self
+    }
+
+    /// The server's shared value of type `T`, if it was given one with
+    /// `NexusServer::with_state`. Command methods can declare a `State<T>`
+    /// parameter instead.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.states.get()
+    }
+
+    pub(crate) fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Publish `event` to the clients subscribed to its topic. A request
+    /// acting for a tenant publishes to that tenant's subscribers only.
+    pub fn emit(&self, event: Event) {
+        if let Some(events) = &self.events {
+            events.publish(self.tenant.clone(), event);
+        }
+    }
+
+    pub(crate) fn with_registry(mut self, registry: RegistryHandle) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// The registry of the server running the request, e.g. to add a
+    /// service from a command. `None` outside a server.
+    pub fn registry(&self) -> Option<&RegistryHandle> {
+        self.registry.as_ref()
+    }
+
+    /// Run `action` of another service (`service` as a client would name
+    /// it, e.g. "block" or "volume.snapshot") as part of this command, and
+    /// return its output. The call acts for the same tenant and role,
+    /// shares the cancellation token and deadline, and its warnings and
+    /// proposed changes are added to this request's. A call back into a
+    /// command already running in the chain, or a chain deeper than the
+    /// server allows (`NexusServer::with_max_call_depth`), fails with an
+    /// internal error instead of recursing.
+    pub async fn call(&self, service: &str, action: &str, args: Args) -> anyhow::Result<CommandOutput> {
+        let Some(registry) = &self.registry else {
+            return Err(NexusError::internal(format!(
+                "cannot call '{} {}' outside a server",
+                crate::cli::command_path(service),
+                action
+            ))
+            .into());
+        };
+        registry.registry.call(self, service, action, args).await
+    }
+
+    /// The commands being run, outermost first.
+    pub(crate) fn calls(&self) -> Vec<(String, String)> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub(crate) fn with_calls(self, calls: Vec<(String, String)>) -> Self {
+        *self.calls.lock().unwrap() = calls;
+        self
+    }
+
+    pub(crate) fn with_locks(self, locks: Vec<String>) -> Self {
+        *self.locks.lock().unwrap() = locks;
+        self
+    }
+
+    pub(crate) fn with_upload(self, upload: Upload) -> Self {
+        *self.upload.lock().unwrap() = Some(upload);
+        self
+    }
+
+    /// Take the file sent with the request, if any is left.
+    pub(crate) fn take_upload(&self) -> Option<Upload> {
+        self.upload.lock().unwrap().take()
+    }
+
+    pub(crate) fn with_download(mut self, download: Download) -> Self {
+        self.download = Some(download);
+        self
+    }
+
+    pub(crate) fn download(&self) -> Option<&Download> {
+        self.download.as_ref()
+    }
+
+    /// Put the questions of interactive commands (those with a `Session`
+    /// parameter) to `session`'s user. The `Session` RPC sets the one
+    /// relaying to its client.
+    pub fn with_session(mut self, session: Session) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    pub(crate) fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Whether a command of the request holds the lock named `key`.
+    pub(crate) fn holds_lock(&self, key: &str) -> bool {
+        self.locks.lock().unwrap().iter().any(|held| held == key)
+    }
+
+    /// Note that a command of the request took the lock named `key`.
+    pub(crate) fn hold_lock(&self, key: String) {
+        self.locks.lock().unwrap().push(key);
+    }
+
+    /// Note the command the request runs, unless it already runs one: a
+    /// call made with `call` is recorded when its context is made.
+    pub(crate) fn enter(&self, service: &str, action: &str) {
+        let mut calls = self.calls.lock().unwrap();
+        if calls.is_empty() {
+            calls.push((service.to_string(), action.to_string()));
+        }
+    }
+
+    /// Whether the request runs a command called by another one.
+    pub(crate) fn is_nested(&self) -> bool {
+        self.calls.lock().unwrap().len() > 1
+    }
+
+    /// Give up on the request at `deadline`, as the client asked to.
+    pub fn with_deadline(self, deadline: Option<Instant>) -> Self {
+        *self.deadline.lock().unwrap() = deadline;
+        self
+    }
+
+    /// When the request will be abandoned: the earlier of the client's
+    /// deadline (its gRPC timeout) and, once the command has started, the
+    /// end of its `#[command(timeout)]`. Lets long-running commands
+    /// budget their work, e.g. skip a step that would not finish in time.
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.deadline.lock().unwrap()
+    }
+
+    /// How long is left until the deadline, zero once it has passed, or
+    /// `None` if the request has none.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline().map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Move the deadline forward to `at` if that is sooner.
+    pub(crate) fn limit_deadline(&self, at: Instant) {
+        let mut deadline = self.deadline.lock().unwrap();
+        *deadline = Some(deadline.map_or(at, |deadline| deadline.min(at)));
+    }
+
+    /// Use `token` as the request's cancellation token.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Cancelled when the request is abandoned: the client went away (e.g.
+    /// Ctrl-C in the CLI), the command timed out or its job was cancelled.
+    /// Command methods receive a clone by declaring a `CancellationToken`
+    /// parameter, to stop spawned or blocking work that dropping the
+    /// command's future does not reach.
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Report progress through `progress`.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Where the command reports progress. Command methods receive a clone
+    /// by declaring a `Progress` parameter; updates are discarded unless the
+    /// client follows them.
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// A context with the same settings, cancellation token, progress
+    /// handle, shared state, held locks, file transfer (the upload moves
+    /// over if not taken yet) and session but nothing recorded yet, for work that
+    /// runs on another runtime or for a nested call.
+    pub(crate) fn detached(&self) -> Context {
+        let mut ctx = Context::new()
+            .with_preferences(self.preferences.clone())
+            .with_dry_run(self.dry_run)
+            .with_priority(self.priority)
+            .with_tenant(self.tenant.clone())
+            .with_role(self.role.clone())
+            .with_peer(self.peer)
+            .with_remote_addr(self.remote_addr)
+            .with_request_id(self.request_id.clone())
+            .with_command(self.command.clone())
+            .with_deadline(self.deadline())
+            .with_cancellation(self.cancellation.clone())
+            .with_progress(self.progress.clone())
+            .with_states(self.states.clone())
+            .with_calls(self.calls())
+            .with_locks(self.locks.lock().unwrap().clone());
+        *ctx.upload.lock().unwrap() = self.take_upload();
+        ctx.download = self.download.clone();
+        ctx.session = self.session.clone();
+        ctx.events = self.events.clone();
+        ctx.registry = self.registry.clone();
+        ctx.extensions = self.extensions.clone();
+        ctx
+    }
+
+    /// Read the tenant ID from incoming request metadata.
+    pub fn tenant_from_metadata(metadata: &MetadataMap) -> Option<String> {
+        metadata
+            .get(TENANT_KEY)
+            .and_then(|v| v.to_str().ok())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+    }
+
+    /// Record a change the command would make. Sent back to the client as
+    /// part of the dry-run plan.
+    pub fn propose(&self, change: Change) {
+        self.plan.lock().unwrap().push(change);
+    }
+
+    /// Drain the changes proposed so far.
+    pub fn take_plan(&self) -> Vec<Change> {
+        std::mem::take(&mut *self.plan.lock().unwrap())
+    }
+
+    /// Attach a non-fatal warning to the response (e.g. "disk sdb is
+    /// degraded"). Warnings are sent whether the command succeeds or fails.
+    pub fn warn(&self, warning: impl Into<String>) {
+        self.warnings.lock().unwrap().push(warning.into());
+    }
+
+    /// Drain the warnings recorded so far.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+}
@@ -0,0 +1,211 @@
+//! Reference documentation generated from `ListServices` (`cli-shell docs`):
+//! a page per service with the syntax, arguments and description of each of
+//! its commands, as Markdown or as man pages. Generated from the running
+//! server, the pages always match the commands it offers.
+//!
+//! The text comes from the same places as `help`: command summaries and
+//! doc comments (`manual::help_sections`), argument hints and descriptions,
+//! and `manual::arg_constraints` and `manual::notes`.
+
+use crate::cli::{arg_placeholder, command_path, completer_spec, nested_services};
+use crate::manual::{arg_constraints, help_sections, notes};
+use crate::proto::{ArgDef, CommandDef, ServiceInfo};
+use std::fmt::Write as _;
+
+/// The pages documenting `services` for the program called `program`, as
+/// file names and contents sorted by service. `format` is "md", which
+/// adds an `index.md` linking the pages, or "man" (section 1).
+pub(crate) fn pages(
+    format: &str,
+    program: &str,
+    services: &[ServiceInfo],
+) -> anyhow::Result<Vec<(String, String)>> {
+    if program.is_empty() || program.contains(['/', '\\']) {
+        anyhow::bail!("cannot generate documentation for program name '{}'", program);
+    }
+    let mut sorted: Vec<&ServiceInfo> = services.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let pages = match format {
+        "md" => std::iter::once(("index.md".to_string(), markdown_index(program, &sorted)))
+            .chain(sorted.iter().map(|s| (markdown_file(s), markdown(program, services, s))))
+            .collect(),
+        "man" => sorted
+            .iter()
+            .map(|s| (format!("{}-{}.1", program, file_stem(s)), man(program, services, s)))
+            .collect(),
+        _ => anyhow::bail!("unsupported format '{}' (expected md or man)", format),
+    };
+    Ok(pages)
+}
+
+/// A service's name in file names: "volume.snapshot" is `volume-snapshot`.
+fn file_stem(service: &ServiceInfo) -> String {
+    service.name.replace('.', "-")
+}
+
+fn markdown_file(service: &ServiceInfo) -> String {
+    format!("{}.md", file_stem(service))
+}
+
+/// The `program <service> <command> <args...>` line of a command.
+fn synopsis(program: &str, service: &ServiceInfo, cmd: &CommandDef) -> String {
+    let mut line = format!("{} {} {}", program, command_path(&service.name), cmd.name);
+    for arg in &cmd.args {
+        line.push(' ');
+        line.push_str(&arg_placeholder(arg));
+    }
+    line
+}
+
+/// An argument's description, followed by where its completions come from.
+fn arg_text(arg: &ArgDef) -> String {
+    let mut text = arg.description.clone();
+    if !arg.completer.is_empty() {
+        if !text.is_empty() && !text.ends_with('.') {
+            text.push('.');
+        }
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&format!("Completions from `{}`.", completer_spec(arg)));
+    }
+    text
+}
+
+fn markdown_index(program: &str, services: &[&ServiceInfo]) -> String {
+    let mut out = format!("# {} services\n\n", program);
+    out.push_str("| Service | Description |\n|---------|-------------|\n");
+    for service in services {
+        let name = command_path(&service.name);
+        let _ = writeln!(out, "| [{}]({}) | {} |", name, markdown_file(service), cell(&service.description));
+    }
+    out
+}
+
+fn markdown(program: &str, services: &[ServiceInfo], service: &ServiceInfo) -> String {
+    let mut out = format!("# {}\n\n", command_path(&service.name));
+    if !service.description.is_empty() {
+        let _ = writeln!(out, "{}\n", service.description);
+    }
+    if !service.aliases.is_empty() {
+        let aliases: Vec<String> = service.aliases.iter().map(|a| format!("`{}`", a)).collect();
+        let _ = writeln!(out, "Aliases: {}\n", aliases.join(", "));
+    }
+    let nested: Vec<String> = nested_services(services, &service.name)
+        .map(|(_, s)| format!("[{}]({})", command_path(&s.name), markdown_file(s)))
+        .collect();
+    if !nested.is_empty() {
+        let _ = writeln!(out, "Nested services: {}\n", nested.join(", "));
+    }
+
+    for cmd in &service.commands {
+        let _ = writeln!(out, "## {} {}\n", command_path(&service.name), cmd.name);
+        if !cmd.description.is_empty() {
+            let _ = writeln!(out, "{}\n", cmd.description);
+        }
+        let _ = writeln!(out, "```text\n{}\n```\n", synopsis(program, service, cmd));
+        let (description, examples) = help_sections(cmd);
+        if !description.is_empty() {
+            let _ = writeln!(out, "{}\n", description.join("\n"));
+        }
+        if !cmd.args.is_empty() {
+            out.push_str("| Argument | Accepts | Description |\n|----------|---------|-------------|\n");
+            for arg in &cmd.args {
+                let _ = writeln!(
+                    out,
+                    "| `{}`, `--{}` | {} | {} |",
+                    arg_placeholder(arg),
+                    arg.name,
+                    cell(&arg_constraints(arg).join(", ")),
+                    cell(&arg_text(arg))
+                );
+            }
+            out.push('\n');
+        }
+        if !examples.is_empty() {
+            let _ = writeln!(out, "Examples:\n\n```text\n{}\n```\n", examples.join("\n"));
+        }
+        let notes = notes(cmd);
+        if !notes.is_empty() {
+            for note in notes {
+                let _ = writeln!(out, "- {}", note);
+            }
+            out.push('\n');
+        }
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+/// `text` as a Markdown table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn man(program: &str, services: &[ServiceInfo], service: &ServiceInfo) -> String {
+    let page = format!("{}-{}", program, file_stem(service));
+    let mut out = format!(".TH {} 1\n.SH NAME\n{}", roff(&page.to_uppercase()), roff(&page));
+    if !service.description.is_empty() {
+        let _ = write!(out, " \\- {}", roff(&service.description));
+    }
+    out.push_str("\n.SH SYNOPSIS\n");
+    for cmd in &service.commands {
+        let _ = writeln!(out, ".B {}\n.br", roff(&synopsis(program, service, cmd)));
+    }
+    if !service.aliases.is_empty() {
+        let _ = writeln!(out, ".SH ALIASES\n{}", roff(&service.aliases.join(", ")));
+    }
+    out.push_str(".SH COMMANDS\n");
+    for cmd in &service.commands {
+        let _ = writeln!(out, ".SS {} {}", roff(&command_path(&service.name)), roff(&cmd.name));
+        if !cmd.description.is_empty() {
+            let _ = writeln!(out, "{}", roff(&cmd.description));
+        }
+        let (description, examples) = help_sections(cmd);
+        for paragraph in description.split(|line| line.trim().is_empty()).filter(|p| !p.is_empty()) {
+            let _ = writeln!(out, ".PP\n{}", roff(&paragraph.join(" ")));
+        }
+        for arg in &cmd.args {
+            let _ = writeln!(
+                out,
+                ".TP\n.B {}, \\-\\-{}\n{}",
+                roff(&arg_placeholder(arg)),
+                roff(&arg.name),
+                roff(&arg_constraints(arg).join(", "))
+            );
+            let text = arg_text(arg);
+            if !text.is_empty() {
+                let _ = writeln!(out, ".br\n{}", roff(&text));
+            }
+        }
+        if !examples.is_empty() {
+            out.push_str(".PP\nExamples:\n.nf\n.RS\n");
+            for line in examples {
+                let _ = writeln!(out, "{}", roff(line));
+            }
+            out.push_str(".RE\n.fi\n");
+        }
+        for note in notes(cmd) {
+            let _ = writeln!(out, ".PP\n{}", roff(&note));
+        }
+    }
+    let see_also: Vec<String> = nested_services(services, &service.name)
+        .map(|(_, s)| format!("{}-{}(1)", program, file_stem(s)))
+        .collect();
+    if !see_also.is_empty() {
+        let _ = writeln!(out, ".SH SEE ALSO\n{}", roff(&see_also.join(", ")));
+    }
+    out
+}
+
+/// `text` as a line of roff: backslashes escaped, and a leading `.` or `'`
+/// kept from starting a request.
+fn roff(text: &str) -> String {
+    let text = text.replace('\\', "\\e");
+    if text.starts_with(['.', '\'']) {
+        format!("\\&{}", text)
+    } else {
+        text
+    }
+}
@@ -0,0 +1,401 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Once;
+use std::time::Duration;
+use tonic::transport::{Channel, Endpoint as ChannelEndpoint};
+
+/// HTTP/2 keepalive settings used to detect dead peers.
+///
+/// A ping is sent every `interval`; if it is not acknowledged within
+/// `timeout` the connection is closed. On the server this drops the
+/// in-flight handlers of a vanished client, cancelling the commands at
+/// their next await point so their resources are released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Permissions given to a Unix socket file once it is bound, so access can
+/// be limited to, say, a management group rather than left to the umask.
+///
+/// `owner` and `group` are user/group names or numeric IDs; changing the
+/// owner needs root, changing the group needs membership of it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UdsOptions {
+    /// File mode, e.g. `0o660`.
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl UdsOptions {
+    /// Bind a Unix socket at `path` that is only reachable once the options
+    /// apply: it is bound in a fresh directory only the server can enter,
+    /// given its mode and owner there, then moved into place. Whatever is at
+    /// `path` already is only replaced if it is a stale socket, one nobody
+    /// listens on any more.
+    #[cfg(unix)]
+    pub(crate) fn bind(&self, path: &std::path::Path) -> anyhow::Result<std::os::unix::net::UnixListener> {
+        use anyhow::Context as _;
+        use std::os::unix::fs::DirBuilderExt;
+
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => std::path::Path::new("."),
+        };
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let private = parent.join(format!(".{}.{}.bind", name, std::process::id()));
+        // Left behind by a server with this PID that died while binding.
+        let _ = std::fs::remove_dir_all(&private);
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private)
+            .with_context(|| format!("failed to create {}", private.display()))?;
+        let bound = (|| {
+            let socket = private.join("socket");
+            let listener = std::os::unix::net::UnixListener::bind(&socket)
+                .with_context(|| format!("failed to bind {}", path.display()))?;
+            self.apply(&socket)?;
+            ensure_replaceable(path)?;
+            std::fs::rename(&socket, path).with_context(|| format!("failed to bind {}", path.display()))?;
+            Ok(listener)
+        })();
+        let _ = std::fs::remove_dir_all(&private);
+        bound
+    }
+
+    /// Apply the options to the socket file at `path`.
+    #[cfg(unix)]
+    fn apply(&self, path: &std::path::Path) -> anyhow::Result<()> {
+        use anyhow::Context as _;
+        use nix::unistd::{Group, User};
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(mode) = self.mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("failed to set the mode of {}", path.display()))?;
+        }
+        let uid = match &self.owner {
+            Some(owner) => Some(match owner.parse() {
+                Ok(uid) => uid,
+                Err(_) => User::from_name(owner)?
+                    .ok_or_else(|| anyhow::anyhow!("unknown user '{}'", owner))?
+                    .uid
+                    .as_raw(),
+            }),
+            None => None,
+        };
+        let gid = match &self.group {
+            Some(group) => Some(match group.parse() {
+                Ok(gid) => gid,
+                Err(_) => Group::from_name(group)?
+                    .ok_or_else(|| anyhow::anyhow!("unknown group '{}'", group))?
+                    .gid
+                    .as_raw(),
+            }),
+            None => None,
+        };
+        if uid.is_some() || gid.is_some() {
+            std::os::unix::fs::chown(path, uid, gid)
+                .with_context(|| format!("failed to change the owner of {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fail unless `path` is free for a socket to be moved to: missing, or a
+/// socket left behind by a server that is gone (connecting to it fails).
+#[cfg(unix)]
+fn ensure_replaceable(path: &std::path::Path) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+    use std::os::unix::fs::FileTypeExt;
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to bind {}", path.display())),
+    };
+    if !metadata.file_type().is_socket() {
+        anyhow::bail!("failed to bind {}: it exists and is not a socket", path.display());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        anyhow::bail!("failed to bind {}: another server is listening on it", path.display());
+    }
+    Ok(())
+}
+
+/// A transport address the server can listen on and the CLI can connect to.
+///
+/// Parsed from strings such as:
+///
+/// - `unix:///tmp/nexus.sock`
+/// - `tcp://[::1]:50051` or `http://localhost:50051`
+/// - `pipe://nexus` or `\\.\pipe\nexus` (Windows named pipe)
+/// - `vsock://3:50051` (`cid:port`)
+///
+/// `https://host:port` is refused: parsing cannot turn TLS on, so it is
+/// only accepted where TLS is configured (`NexusClient::connect_tls`,
+/// `NexusCli::with_tls`, `NexusServer::serve_tls`). Strings without a
+/// scheme are still read as before, a bare `host:port` as TCP and anything
+/// else as a socket path, but that is deprecated and logs a warning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// TCP `host:port` authority.
+    Tcp(String),
+    /// Unix domain socket path.
+    Unix(PathBuf),
+    /// Windows named pipe name (without the `\\.\pipe\` prefix).
+    NamedPipe(String),
+    /// Linux vsock context ID and port.
+    Vsock { cid: u32, port: u32 },
+}
+
+impl Endpoint {
+    /// Short transport name used in diagnostics.
+    pub fn transport(&self) -> &'static str {
+        match self {
+            Endpoint::Tcp(_) => "tcp",
+            Endpoint::Unix(_) => "unix",
+            Endpoint::NamedPipe(_) => "named pipe",
+            Endpoint::Vsock { .. } => "vsock",
+        }
+    }
+
+    /// Whether this transport is available on the current platform.
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Endpoint::Tcp(_) => true,
+            Endpoint::Unix(_) => cfg!(unix),
+            // Parsed so configs stay portable, but no listener/connector yet.
+            Endpoint::NamedPipe(_) | Endpoint::Vsock { .. } => false,
+        }
+    }
+
+    /// Return an error if this transport is unavailable on this platform.
+    pub fn ensure_supported(&self) -> anyhow::Result<()> {
+        if self.is_supported() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(
+                "{} transport is not supported on this platform ({})",
+                self.transport(),
+                self
+            ))
+        }
+    }
+
+    /// Open a gRPC channel to this endpoint with default keepalive settings.
+    pub async fn connect(&self) -> anyhow::Result<Channel> {
+        self.connect_with(Keepalive::default()).await
+    }
+
+    /// Open a gRPC channel to this endpoint, pinging the server per `keepalive`.
+    pub async fn connect_with(&self, keepalive: Keepalive) -> anyhow::Result<Channel> {
+        self.ensure_supported()?;
+        match self {
+            Endpoint::Tcp(authority) => {
+                let endpoint = ChannelEndpoint::from_shared(format!("http://{}", authority))?;
+                let channel = with_keepalive(endpoint, keepalive).connect().await?;
+                Ok(channel)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                use hyper_util::rt::TokioIo;
+                use tokio::net::UnixStream;
+                use tower::service_fn;
+
+                let path = path.clone();
+                // The URI is not used for routing; the connector below ignores it.
+                let endpoint = ChannelEndpoint::try_from("http://[::]:50051")?;
+                let channel = with_keepalive(endpoint, keepalive)
+                    .connect_with_connector(service_fn(move |_| {
+                        let path = path.clone();
+                        async move { UnixStream::connect(path).await.map(TokioIo::new) }
+                    }))
+                    .await?;
+                Ok(channel)
+            }
+            _ => unreachable!("ensure_supported rejects unavailable transports"),
+        }
+    }
+
+    /// Parse `addr` for a connection or listener that uses TLS: as
+    /// `from_str`, but also accepting `https://host:port`.
+    #[cfg(feature = "tls")]
+    pub(crate) fn parse_tls(addr: &str) -> anyhow::Result<Self> {
+        match addr.strip_prefix("https://") {
+            Some(authority) => Ok(Endpoint::Tcp(authority.trim_end_matches('/').to_string())),
+            None => addr.parse(),
+        }
+    }
+
+    /// Open a TLS-protected gRPC channel. Only TCP endpoints are supported;
+    /// local sockets are already protected by filesystem permissions.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        &self,
+        keepalive: Keepalive,
+        tls: tonic::transport::ClientTlsConfig,
+    ) -> anyhow::Result<Channel> {
+        let Endpoint::Tcp(authority) = self else {
+            anyhow::bail!("TLS is only supported for TCP endpoints, not {}", self);
+        };
+        let endpoint = ChannelEndpoint::from_shared(format!("https://{}", authority))?;
+        let channel = with_keepalive(endpoint, keepalive)
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(channel)
+    }
+}
+
+/// Apply HTTP/2 keepalive settings to a channel builder.
+fn with_keepalive(endpoint: ChannelEndpoint, keepalive: Keepalive) -> ChannelEndpoint {
+    endpoint
+        .http2_keep_alive_interval(keepalive.interval)
+        .keep_alive_timeout(keepalive.timeout)
+        .keep_alive_while_idle(true)
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix://") {
+            return Ok(Endpoint::Unix(PathBuf::from(path)));
+        }
+        if let Some(authority) = s.strip_prefix("tcp://").or_else(|| s.strip_prefix("http://")) {
+            return Ok(Endpoint::Tcp(authority.trim_end_matches('/').to_string()));
+        }
+        if let Some(authority) = s.strip_prefix("https://") {
+            anyhow::bail!(
+                "'{}' asks for TLS, which the address alone does not set up: configure TLS \
+                 (with_tls, connect_tls or [cli.tls]) for tcp://{}",
+                s,
+                authority.trim_end_matches('/')
+            );
+        }
+        if let Some(name) = s
+            .strip_prefix("pipe://")
+            .or_else(|| s.strip_prefix(r"\\.\pipe\"))
+        {
+            return Ok(Endpoint::NamedPipe(name.to_string()));
+        }
+        if let Some(rest) = s.strip_prefix("vsock://") {
+            let (cid, port) = rest
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("invalid vsock endpoint '{}': expected cid:port", s))?;
+            return Ok(Endpoint::Vsock {
+                cid: cid.parse()?,
+                port: port.parse()?,
+            });
+        }
+        if s.is_empty() {
+            return Err(anyhow::anyhow!("empty endpoint"));
+        }
+        // Unprefixed, the legacy form: `host:port` is TCP, anything else is
+        // a socket path.
+        let endpoint = if s.contains(':') {
+            Endpoint::Tcp(s.to_string())
+        } else {
+            Endpoint::Unix(PathBuf::from(s))
+        };
+        static WARNED: Once = Once::new();
+        WARNED.call_once(|| {
+            // Logged once, not on every reconnect.
+            tracing::warn!(address = s, endpoint = %endpoint, "address without a scheme is deprecated; use the endpoint");
+        });
+        Ok(endpoint)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(authority) => write!(f, "tcp://{}", authority),
+            Endpoint::Unix(path) => write!(f, "unix://{}", path.display()),
+            Endpoint::NamedPipe(name) => write!(f, r"\\.\pipe\{}", name),
+            Endpoint::Vsock { cid, port } => write!(f, "vsock://{}:{}", cid, port),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    /// A fresh socket path for `test`, in a directory of its own.
+    fn socket_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nexus-uds-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        dir.join("nexus.sock")
+    }
+
+    fn cleanup(path: &Path) {
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn bound_sockets_get_their_mode_and_owner() {
+        let path = socket_path("mode");
+        let uid = nix::unistd::geteuid().as_raw();
+        let gid = nix::unistd::getegid().as_raw();
+        let options = UdsOptions {
+            mode: Some(0o640),
+            owner: Some(uid.to_string()),
+            group: Some(gid.to_string()),
+        };
+        let _listener = options.bind(&path).unwrap();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o777, 0o640);
+        assert_eq!((metadata.uid(), metadata.gid()), (uid, gid));
+        // Nothing is left of the directory it was bound in.
+        let entries: Vec<_> = std::fs::read_dir(path.parent().unwrap()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn stale_sockets_are_replaced() {
+        let path = socket_path("stale");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = UdsOptions::default().bind(&path).unwrap();
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+        drop(listener);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn sockets_in_use_are_left_alone() {
+        let path = socket_path("live");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let err = UdsOptions::default().bind(&path).unwrap_err();
+        assert!(err.to_string().contains("another server is listening on it"), "{:#}", err);
+        cleanup(&path);
+    }
+
+    #[test]
+    fn other_files_are_not_replaced() {
+        let path = socket_path("file");
+        std::fs::write(&path, "data").unwrap();
+        let err = UdsOptions::default().bind(&path).unwrap_err();
+        assert!(err.to_string().contains("is not a socket"), "{:#}", err);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        cleanup(&path);
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Error a service returns when it cannot take a command right now (e.g. a
+/// concurrency limit or a device lock is held). The server reports it as a
+/// busy response so clients can back off and retry.
+#[derive(Debug, Clone)]
+pub struct Busy {
+    pub reason: String,
+    /// How long the client should wait before retrying, if known.
+    pub retry_after: Option<Duration>,
+}
+
+impl Busy {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after = Some(delay);
+        self
+    }
+}
+
+impl fmt::Display for Busy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "busy: {}", self.reason)?;
+        if let Some(delay) = self.retry_after {
+            write!(f, " (retry after {}ms)", delay.as_millis())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Busy {}
+
+/// Error a request is rejected with when its client has used up its
+/// request budget (see `crate::ratelimit`). Unlike `Busy`, retrying at
+/// once will not help; the client should wait `retry_after`.
+#[derive(Debug, Clone)]
+pub struct RateLimited {
+    /// The limit that was hit, e.g. "20/s" or "1/m for 'pool destroy'".
+    pub limit: String,
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rate limit of {} exceeded (retry after {}ms)",
+            self.limit,
+            self.retry_after.as_millis()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// What kind of failure a command reported, sent to clients alongside the
+/// message. The CLI colors errors by code and exits with `exit_code` in
+/// one-shot mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The service, command or object the command refers to does not exist.
+    NotFound,
+    /// An argument is missing, malformed or out of range.
+    InvalidArgument,
+    /// The caller's role lacks the command's permission.
+    PermissionDenied,
+    /// Any other failure; errors that carry no code are reported as this.
+    Internal,
+    /// The server could not take the command right now (see `Busy`).
+    Busy,
+    /// The command did not finish within its time limit.
+    Timeout,
+    /// The client sent more requests than its rate limit allows (see
+    /// `RateLimited`).
+    ResourceExhausted,
+    /// The server is in maintenance mode and only runs read-only commands.
+    Unavailable,
+}
+
+impl ErrorCode {
+    /// The code of a command's error: that of a `NexusError`, `Busy` or
+    /// `RateLimited` in its chain, else `Internal`.
+    pub fn of(error: &anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<NexusError>() {
+            error.code
+        } else if error.downcast_ref::<Busy>().is_some() {
+            Self::Busy
+        } else if error.downcast_ref::<RateLimited>().is_some() {
+            Self::ResourceExhausted
+        } else {
+            Self::Internal
+        }
+    }
+
+    /// Exit status of a one-shot CLI command failing with this code.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Internal => 1,
+            Self::InvalidArgument => 2,
+            Self::NotFound => 3,
+            Self::PermissionDenied => 4,
+            Self::Busy => 5,
+            Self::Timeout => 6,
+            Self::ResourceExhausted => 7,
+            Self::Unavailable => 8,
+        }
+    }
+
+    /// The code in snake case, as in JSON output: `"not_found"`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::InvalidArgument => "invalid_argument",
+            Self::PermissionDenied => "permission_denied",
+            Self::Internal => "internal",
+            Self::Busy => "busy",
+            Self::Timeout => "timeout",
+            Self::ResourceExhausted => "resource_exhausted",
+            Self::Unavailable => "unavailable",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_str().replace('_', " "))
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = anyhow::Error;
+
+    /// Parse a code as `as_str` writes it.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let codes = [
+            Self::NotFound,
+            Self::InvalidArgument,
+            Self::PermissionDenied,
+            Self::Internal,
+            Self::Busy,
+            Self::Timeout,
+            Self::ResourceExhausted,
+            Self::Unavailable,
+        ];
+        codes
+            .into_iter()
+            .find(|code| code.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown error code '{}'", s))
+    }
+}
+
+/// Error a service returns to report what kind of failure occurred, with
+/// optional details (e.g. the name of the missing volume) for clients that
+/// act on them.
+///
+/// ```
+/// # use libnexus::NexusError;
+/// # let name = "vol9";
+/// let error = NexusError::not_found(format!("volume '{}' does not exist", name))
+///     .detail("volume", name);
+/// # let _: anyhow::Error = error.into();
+/// ```
+#[derive(Debug, Clone)]
+pub struct NexusError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub details: BTreeMap<String, String>,
+    /// What the caller may have meant instead, e.g. the commands named
+    /// closest to an unknown one.
+    pub suggestions: Vec<String>,
+}
+
+impl NexusError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            details: BTreeMap::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::NotFound, message)
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::InvalidArgument, message)
+    }
+
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::PermissionDenied, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    /// Attach a detail, shown below the message in the CLI.
+    pub fn detail(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.details.insert(key.into(), value.into());
+        self
+    }
+
+    /// Offer `suggestions` for what was meant, added to the message as
+    /// "did you mean 'a' or 'b'?". Nothing changes if there are none.
+    pub fn suggest(mut self, suggestions: Vec<String>) -> Self {
+        let quoted: Vec<String> = suggestions.iter().map(|s| format!("'{}'", s)).collect();
+        if let Some((last, rest)) = quoted.split_last() {
+            let names = match rest {
+                [] => last.clone(),
+                rest => format!("{} or {}", rest.join(", "), last),
+            };
+            self.message = format!("{}, did you mean {}?", self.message, names);
+        }
+        self.suggestions = suggestions;
+        self
+    }
+}
+
+/// The `candidates` closest to the unknown `name`, at most three and best
+/// first, among those close enough by edit distance to be a likely typo.
+pub(crate) fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let limit = (name.chars().count() / 3).max(2);
+    let mut close: Vec<(usize, &str)> = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit && distance < name.chars().count())
+        .collect();
+    close.sort();
+    close.dedup();
+    close.into_iter().take(3).map(|(_, candidate)| candidate.to_string()).collect()
+}
+
+/// Levenshtein distance: the fewest single-character insertions, deletions
+/// and substitutions turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+impl fmt::Display for NexusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for NexusError {}
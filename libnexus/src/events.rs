@@ -0,0 +1,90 @@
+//! Events services publish to clients: a command calls `ctx.emit(...)`, or
+//! a task started in `on_start` emits through `StartContext::events`, and
+//! every client following the topic through the `Subscribe` RPC (the CLI's
+//! `subscribe <topic>`) receives it as it happens. Events are not stored;
+//! a client only gets those emitted while it is subscribed.
+
+use crate::glob;
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+
+/// How many events a subscriber may fall behind by before it misses some.
+pub(crate) const EVENT_BUFFER: usize = 256;
+
+/// Something that happened, e.g. topic "block.hotplug" with payload "sdd
+/// added".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// What the event is about, dot-separated like service paths.
+    pub topic: String,
+    pub payload: String,
+}
+
+impl Event {
+    pub fn new(topic: impl Into<String>, payload: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+            payload: payload.into(),
+        }
+    }
+}
+
+/// An event on its way to subscribers.
+#[derive(Debug, Clone)]
+pub(crate) struct Published {
+    /// The tenant it was emitted for; only that tenant's subscribers get it.
+    /// `None` for events every subscriber gets.
+    pub(crate) tenant: Option<String>,
+    pub(crate) event: Event,
+    pub(crate) at: SystemTime,
+}
+
+impl Published {
+    /// Whether a subscriber for `tenant` to `topics` (names or glob
+    /// patterns, all topics if empty) gets the event.
+    pub(crate) fn is_for(&self, tenant: Option<&str>, topics: &[String]) -> bool {
+        let tenant_matches = self.tenant.is_none() || self.tenant.as_deref() == tenant;
+        tenant_matches && (topics.is_empty() || topics.iter().any(|topic| glob::matches(topic, &self.event.topic)))
+    }
+}
+
+/// Publishes events to the server's subscribers. Cloning is cheap; every
+/// clone publishes to the same subscribers.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Published>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl EventBus {
+    /// Publish `event` to every subscriber of its topic. Without any, it is
+    /// dropped.
+    pub fn emit(&self, event: Event) {
+        self.publish(None, event);
+    }
+
+    /// Publish `event` to the subscribers acting for `tenant` only.
+    pub fn emit_for_tenant(&self, tenant: &str, event: Event) {
+        self.publish(Some(tenant.to_string()), event);
+    }
+
+    pub(crate) fn publish(&self, tenant: Option<String>, event: Event) {
+        tracing::debug!(topic = event.topic, tenant = tenant.as_deref().unwrap_or("-"), "event emitted");
+        let _ = self.sender.send(Published {
+            tenant,
+            event,
+            at: SystemTime::now(),
+        });
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Published> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,68 @@
+//! Commands of a service spread over several impl blocks. The main
+//! `#[nexus_service]` block implements `Service`; each further
+//! `#[nexus_service(extend)]` block on the same type, in any module of the
+//! crate, is added here when the program starts, and the main block's
+//! `commands`, `execute` and `execute_stream` take its commands in as well.
+//!
+//! The blocks register themselves from a function the platform runs before
+//! `main` (`.init_array` on ELF targets, `__mod_init_func` on macOS,
+//! `.CRT$XCU` on Windows), so nothing else has to name them.
+
+use crate::context::Context;
+use crate::registry::{Args, CommandInfo, CommandOutput};
+use crate::stream::CommandStream;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::any::{Any, TypeId};
+use std::sync::Mutex;
+
+/// The commands of one `#[nexus_service(extend)]` block on the service `S`,
+/// generated by the macro.
+#[async_trait]
+pub trait Extension<S>: Send + Sync + 'static {
+    /// The commands the block declares.
+    fn commands(&self) -> Vec<CommandInfo>;
+
+    /// Run `action` on `service`. Returns `None` if it is not a
+    /// non-streaming command of this block.
+    async fn execute(
+        &self,
+        service: &S,
+        ctx: &Context,
+        action: &str,
+        args: Args,
+    ) -> Option<Result<CommandOutput>>;
+
+    /// Start `action` on `service`. Returns `None` if it is not a streaming
+    /// command of this block.
+    async fn execute_stream(
+        &self,
+        service: &S,
+        ctx: &Context,
+        action: &str,
+        args: Args,
+    ) -> Option<Result<CommandStream>>;
+}
+
+/// Each extension with the type of its service. The value is a
+/// `&'static dyn Extension<S>` for that type.
+static EXTENSIONS: Mutex<Vec<(TypeId, &'static (dyn Any + Send + Sync))>> = Mutex::new(Vec::new());
+
+/// Add `extension` to the service `S`. Called by the code
+/// `#[nexus_service(extend)]` generates, before `main`.
+#[doc(hidden)]
+pub fn add<S: 'static>(extension: &'static dyn Extension<S>) {
+    let entry: &'static (dyn Any + Send + Sync) = Box::leak(Box::new(extension));
+    EXTENSIONS.lock().unwrap().push((TypeId::of::<S>(), entry));
+}
+
+/// The extensions of the service `S`, in the order they were added.
+pub fn of<S: 'static>() -> Vec<&'static dyn Extension<S>> {
+    EXTENSIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(service, _)| *service == TypeId::of::<S>())
+        .filter_map(|(_, entry)| entry.downcast_ref::<&'static dyn Extension<S>>().copied())
+        .collect()
+}
@@ -0,0 +1,80 @@
+//! Commands the user pinned in the interactive shell, kept in the CLI config
+//! directory so they survive across sessions.
+
+use std::path::{Path, PathBuf};
+
+/// File in the config directory holding one pinned command line per line.
+const FAVORITES_FILE: &str = "favorites";
+
+/// The CLI config directory: `$XDG_CONFIG_HOME/nexus`, else `~/.config/nexus`.
+pub(crate) fn default_config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("nexus"))
+}
+
+/// Pinned command lines, in the order they were pinned.
+pub(crate) struct Favorites {
+    /// Where the list is saved; `None` keeps it in memory only.
+    path: Option<PathBuf>,
+    commands: Vec<String>,
+}
+
+impl Favorites {
+    /// Load the favorites saved in `config_dir` (empty if there are none).
+    pub(crate) fn load(config_dir: Option<&Path>) -> Self {
+        let path = config_dir.map(|dir| dir.join(FAVORITES_FILE));
+        let commands = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|text| {
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { path, commands }
+    }
+
+    pub(crate) fn commands(&self) -> &[String] {
+        &self.commands
+    }
+
+    /// Pin a command line. Returns `false` if it was already pinned.
+    pub(crate) fn pin(&mut self, command: &str) -> anyhow::Result<bool> {
+        if self.commands.iter().any(|c| c == command) {
+            return Ok(false);
+        }
+        self.commands.push(command.to_string());
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Unpin a command line. Returns `false` if it was not pinned.
+    pub(crate) fn unpin(&mut self, command: &str) -> anyhow::Result<bool> {
+        let before = self.commands.len();
+        self.commands.retain(|c| c != command);
+        if self.commands.len() == before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut text = self.commands.join("\n");
+        text.push('\n');
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
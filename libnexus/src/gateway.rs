@@ -0,0 +1,350 @@
+//! HTTP/JSON gateway (`NexusServer::with_http_gateway`): every command is
+//! also reachable as `POST /api/{service}/{action}` with its named arguments
+//! as a JSON object, for web UIs and curl. Nested services are addressed by
+//! their path, as in `/api/volume/snapshot/create`. Background jobs are
+//! listed at `GET /api/jobs`, followed at `GET /api/jobs/{id}` and cancelled
+//! with `DELETE /api/jobs/{id}`.
+//!
+//! Requests go through the same authentication and `Execute` handler as gRPC
+//! calls; `authorization` and `nexus-*` headers mean the same as the gRPC
+//! metadata keys.
+
+use crate::auth::AuthInterceptor;
+use crate::cli::{job_state, response_output};
+use crate::error::ErrorCode;
+use crate::openapi;
+use crate::proto::nexus_service_server::NexusService;
+use crate::proto::{CommandRequest, CommandResponse, JobInfo, JobListRequest, JobRequest, ListServicesRequest};
+use crate::registry::Priority;
+use crate::server::{error_code_from_proto, priority_to_proto, NexusGrpcService};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, ALLOW, CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+
+/// Largest request body accepted.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+pub(crate) struct Gateway {
+    pub(crate) grpc: NexusGrpcService,
+    pub(crate) auth: AuthInterceptor,
+}
+
+/// Serve the gateway on `listener` until the task is dropped.
+pub(crate) async fn serve(listener: TcpListener, gateway: Gateway) {
+    let gateway = Arc::new(gateway);
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let gateway = gateway.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(gateway.handle(request).await) }
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+impl Gateway {
+    async fn handle(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        let (parts, body) = request.into_parts();
+        if parts.uri.path() == "/api/openapi.json" {
+            if parts.method != Method::GET {
+                return method_not_allowed("GET");
+            }
+            return self.openapi(parts.headers).await;
+        }
+        // POST is left to commands of a service that happens to be called "jobs".
+        if let Some(rest) = parts.uri.path().strip_prefix("/api/jobs") {
+            if parts.method != Method::POST {
+                return self.jobs(parts.method, parts.headers, rest).await;
+            }
+        }
+        let Some((service, action)) = parts
+            .uri
+            .path()
+            .strip_prefix("/api/")
+            .and_then(|path| path.rsplit_once('/'))
+            .filter(|(service, action)| !service.is_empty() && !action.is_empty())
+        else {
+            return error(StatusCode::NOT_FOUND, "not found");
+        };
+        // Nested services are addressed by their path: /api/volume/snapshot/create.
+        let service = service.replace('/', ".");
+        if parts.method != Method::POST {
+            return method_not_allowed("POST");
+        }
+
+        let body = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => return error(StatusCode::BAD_REQUEST, &format!("failed to read body: {}", e)),
+        };
+        let (args, named_args) = match parse_args(&body) {
+            Ok(args) => args,
+            Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+        };
+        let mut command = CommandRequest {
+            service: service.clone(),
+            action: action.to_string(),
+            args,
+            named_args,
+            ..Default::default()
+        };
+        for (key, value) in query(parts.uri.query()) {
+            match key.as_str() {
+                "dry_run" => command.dry_run = value.is_empty() || value == "true",
+                "priority" => match value.parse::<Priority>() {
+                    Ok(priority) => command.priority = priority_to_proto(priority) as i32,
+                    Err(e) => return error(StatusCode::BAD_REQUEST, &e.to_string()),
+                },
+                _ => {}
+            }
+        }
+        if let Some(key) = parts.headers.get("idempotency-key").and_then(|key| key.to_str().ok()) {
+            command.idempotency_key = key.to_string();
+        }
+
+        tracing::debug!(service, action, "HTTP gateway request");
+        let request = match self.authorize(parts.headers, command) {
+            Ok(request) => request,
+            Err(message) => return error(StatusCode::UNAUTHORIZED, &message),
+        };
+        match self.grpc.execute(request).await {
+            Ok(response) => command_response(&response.into_inner()),
+            Err(status) => error(StatusCode::INTERNAL_SERVER_ERROR, status.message()),
+        }
+    }
+
+    /// Wrap `message` in a gRPC request carrying the HTTP headers as
+    /// metadata, and authenticate it like a gRPC call.
+    pub(crate) fn authorize<T>(&self, headers: hyper::HeaderMap, message: T) -> Result<tonic::Request<T>, String> {
+        let request = tonic::Request::from_parts(MetadataMap::from_headers(headers), Default::default(), ());
+        match self.auth.clone().call(request) {
+            Ok(request) => Ok(request.map(|()| message)),
+            Err(status) => Err(status.message().to_string()),
+        }
+    }
+
+    /// `GET /api/jobs`, `GET /api/jobs/{id}` and `DELETE /api/jobs/{id}`.
+    async fn jobs(&self, method: Method, headers: hyper::HeaderMap, rest: &str) -> Response<Full<Bytes>> {
+        let id = match rest.strip_prefix('/') {
+            None if rest.is_empty() => None,
+            Some(id) => match id.parse::<u64>() {
+                Ok(id) => Some(id),
+                Err(_) => return error(StatusCode::NOT_FOUND, "not found"),
+            },
+            None => return error(StatusCode::NOT_FOUND, "not found"),
+        };
+        let result = match (id, method) {
+            (None, Method::GET) => match self.authorize(headers, JobListRequest {}) {
+                Ok(request) => self.grpc.job_list(request).await.map(|response| {
+                    let jobs: Vec<Value> = response.into_inner().jobs.iter().map(job_body).collect();
+                    Value::Array(jobs)
+                }),
+                Err(message) => return error(StatusCode::UNAUTHORIZED, &message),
+            },
+            (None, _) => return method_not_allowed("GET"),
+            (Some(id), method @ (Method::GET | Method::DELETE)) => {
+                let request = match self.authorize(headers, JobRequest { id }) {
+                    Ok(request) => request,
+                    Err(message) => return error(StatusCode::UNAUTHORIZED, &message),
+                };
+                let response = if method == Method::GET {
+                    self.grpc.job_status(request).await
+                } else {
+                    self.grpc.job_cancel(request).await
+                };
+                response.map(|response| job_body(&response.into_inner()))
+            }
+            (Some(_), _) => return method_not_allowed("GET, DELETE"),
+        };
+        match result {
+            Ok(body) => reply(StatusCode::OK, body),
+            Err(status) if status.code() == tonic::Code::NotFound => error(StatusCode::NOT_FOUND, status.message()),
+            Err(status) => error(StatusCode::INTERNAL_SERVER_ERROR, status.message()),
+        }
+    }
+
+    /// The OpenAPI document for the services visible to the caller.
+    async fn openapi(&self, headers: hyper::HeaderMap) -> Response<Full<Bytes>> {
+        let request = match self.authorize(headers, ListServicesRequest::new()) {
+            Ok(request) => request,
+            Err(message) => return error(StatusCode::UNAUTHORIZED, &message),
+        };
+        match self.grpc.list_services(request).await {
+            Ok(response) => reply(StatusCode::OK, openapi::document(&response.into_inner().services)),
+            Err(status) => error(StatusCode::INTERNAL_SERVER_ERROR, status.message()),
+        }
+    }
+}
+
+/// Positional and named arguments from a JSON object body. Strings, numbers
+/// and booleans are named arguments; an array supplies positional values
+/// (e.g. for a variadic argument). An empty body means no arguments.
+fn parse_args(body: &[u8]) -> anyhow::Result<(Vec<String>, HashMap<String, String>)> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok((Vec::new(), HashMap::new()));
+    }
+    let Value::Object(object) = serde_json::from_slice(body)? else {
+        anyhow::bail!("request body must be a JSON object of arguments");
+    };
+    json_args(object)
+}
+
+/// Positional and named arguments from a JSON object of arguments, as for
+/// the gateway's request bodies.
+pub(crate) fn json_args(object: Map<String, Value>) -> anyhow::Result<(Vec<String>, HashMap<String, String>)> {
+    let mut positional = Vec::new();
+    let mut named = HashMap::new();
+    for (name, value) in object {
+        match value {
+            Value::Null => {}
+            Value::Array(values) => {
+                for value in values {
+                    positional.push(scalar(&name, value)?);
+                }
+            }
+            value => {
+                named.insert(name.clone(), scalar(&name, value)?);
+            }
+        }
+    }
+    Ok((positional, named))
+}
+
+/// The argument string for a JSON scalar.
+fn scalar(name: &str, value: Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => anyhow::bail!("argument '{}' must be a string, number, boolean or array of them", name),
+    }
+}
+
+/// `key=value` pairs of a query string (values are not percent-decoded).
+fn query(query: Option<&str>) -> Vec<(String, String)> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// JSON for a command response: `200` on success (`202` when it started a
+/// background job), `503` with `Retry-After` when busy, `429` with
+/// `Retry-After` when rate limited, `404`, `403` and `504` for not found,
+/// permission denied and timeout errors, `400` for other failures.
+fn command_response(response: &CommandResponse) -> Response<Full<Bytes>> {
+    let code = error_code_from_proto(response);
+    let status = match code {
+        _ if response.success && response.job_id != 0 => StatusCode::ACCEPTED,
+        _ if response.success => StatusCode::OK,
+        ErrorCode::Busy | ErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+        ErrorCode::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        ErrorCode::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::InvalidArgument | ErrorCode::Internal => StatusCode::BAD_REQUEST,
+    };
+    let mut reply = reply(status, result_body(response));
+    let backoff = matches!(code, ErrorCode::Busy | ErrorCode::ResourceExhausted);
+    if !response.success && backoff && response.retry_after_ms > 0 {
+        let seconds = response.retry_after_ms.div_ceil(1000);
+        reply.headers_mut().insert(RETRY_AFTER, HeaderValue::from(seconds));
+    }
+    reply
+}
+
+/// The JSON body of a command response, also used for a finished job's result.
+fn result_body(response: &CommandResponse) -> Value {
+    if response.success {
+        let plan: Vec<Value> = response
+            .plan
+            .iter()
+            .map(|change| json!({ "resource": change.resource, "before": change.before, "after": change.after }))
+            .collect();
+        let mut body = json!({
+            "success": true,
+            "output": response_output(response).to_json(),
+            "warnings": response.warnings,
+            "plan": plan,
+        });
+        if response.job_id != 0 {
+            body["job_id"] = response.job_id.into();
+        }
+        return body;
+    }
+    let mut body = Map::new();
+    body.insert("success".into(), false.into());
+    body.insert("error".into(), response.message.clone().into());
+    let code = error_code_from_proto(response);
+    body.insert("code".into(), code.as_str().into());
+    if !response.error_details.is_empty() {
+        body.insert("details".into(), json!(response.error_details));
+    }
+    if !response.suggestions.is_empty() {
+        body.insert("suggestions".into(), response.suggestions.clone().into());
+    }
+    body.insert("warnings".into(), response.warnings.clone().into());
+    if code == ErrorCode::Busy {
+        body.insert("busy".into(), true.into());
+    }
+    if matches!(code, ErrorCode::Busy | ErrorCode::ResourceExhausted) && response.retry_after_ms > 0 {
+        body.insert("retry_after_ms".into(), response.retry_after_ms.into());
+    }
+    Value::Object(body)
+}
+
+/// JSON for a background job.
+fn job_body(job: &JobInfo) -> Value {
+    let mut body = json!({
+        "id": job.id,
+        "service": job.service,
+        "action": job.action,
+        "args": job.args,
+        "state": job_state(job),
+        "started_at_ms": job.started_at_ms,
+        "elapsed_ms": job.elapsed_ms,
+    });
+    if let Some(result) = &job.result {
+        body["result"] = result_body(result);
+    }
+    body
+}
+
+fn method_not_allowed(allow: &'static str) -> Response<Full<Bytes>> {
+    let mut response = error(StatusCode::METHOD_NOT_ALLOWED, &format!("use {}", allow));
+    response.headers_mut().insert(ALLOW, HeaderValue::from_static(allow));
+    response
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Full<Bytes>> {
+    reply(status, json!({ "success": false, "error": message }))
+}
+
+fn reply(status: StatusCode, body: Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
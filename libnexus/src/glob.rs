@@ -0,0 +1,126 @@
+//! Minimal shell-style glob matching used for `#[arg(glob)]` expansion.
+
+/// Whether `s` contains glob metacharacters (`*`, `?`, `[`).
+pub fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Match `text` against a shell glob `pattern`.
+///
+/// Supports `*` (any run), `?` (any single char), and bracket classes such as
+/// `[abc]`, `[a-z]`, and `[!0-9]`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    match_from(&pattern, &text)
+}
+
+fn match_from(pattern: &[char], text: &[char]) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return text.is_empty();
+    };
+    match first {
+        '*' => (0..=text.len()).any(|skip| match_from(rest, &text[skip..])),
+        '?' => !text.is_empty() && match_from(rest, &text[1..]),
+        '[' => {
+            let Some((&c, text_rest)) = text.split_first() else {
+                return false;
+            };
+            match match_class(rest, c) {
+                Some((true, after)) => match_from(after, text_rest),
+                Some((false, _)) => false,
+                // Unterminated class: treat '[' literally.
+                None => c == '[' && match_from(rest, text_rest),
+            }
+        }
+        literal => text.first() == Some(&literal) && match_from(rest, &text[1..]),
+    }
+}
+
+/// Match `c` against a bracket class whose body starts at `class` (just after
+/// the `[`). Returns whether it matched plus the pattern after the closing `]`.
+fn match_class(class: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negate, mut i) = match class.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negate, &class[i + 1..]));
+        }
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            if class[i] <= c && c <= class[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_match_any_run_including_none() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "tank"));
+        assert!(matches("tank*", "tank"));
+        assert!(matches("*-backup", "tank-backup"));
+        assert!(matches("t*n*k", "tnk"));
+        assert!(matches("**", "a"));
+        assert!(!matches("tank*", "tan"));
+        assert!(!matches("*-backup", "tank-backup-old"));
+    }
+
+    #[test]
+    fn question_marks_match_exactly_one_char() {
+        assert!(matches("sd?", "sda"));
+        assert!(matches("sd?", "sdé"));
+        assert!(!matches("sd?", "sd"));
+        assert!(!matches("sd?", "sdab"));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn classes_match_sets_ranges_and_negations() {
+        assert!(matches("sd[ab]", "sdb"));
+        assert!(!matches("sd[ab]", "sdc"));
+        assert!(matches("disk[0-9]", "disk7"));
+        assert!(!matches("disk[0-9]", "diskx"));
+        assert!(matches("disk[!0-9]", "diskx"));
+        assert!(matches("disk[^0-9]", "diskx"));
+        assert!(!matches("disk[!0-9]", "disk7"));
+        assert!(!matches("[a]", ""));
+        // A `]` right after the `[` is part of the class, and so is a `-`
+        // at the end.
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn unterminated_classes_are_literal() {
+        assert!(matches("tank[1", "tank[1"));
+        assert!(!matches("tank[1", "tank1"));
+    }
+
+    #[test]
+    fn literals_match_only_themselves() {
+        assert!(matches("", ""));
+        assert!(!matches("", "tank"));
+        assert!(matches("tank", "tank"));
+        assert!(!matches("tank", "Tank"));
+        assert!(!is_pattern("tank"));
+        assert!(is_pattern("tank*"));
+        assert!(is_pattern("sd[ab]"));
+    }
+}
@@ -0,0 +1,247 @@
+//! gRPC-Web bridge (`NexusServer::with_grpc_web`): the gRPC services over
+//! HTTP/1.1 in the gRPC-Web wire format, so a browser-based console can
+//! call `ListServices`, `Execute` and the other `NexusService` methods (and
+//! `grpc.health.v1.Health`) with a stock gRPC-Web client.
+//!
+//! ```ignore
+//! server.with_grpc_web("127.0.0.1:8081", Cors::new().allow_origin("https://console.example.com"))
+//! ```
+//!
+//! Each request is translated into a gRPC call to the same services the
+//! gRPC endpoints serve, so authentication, tenants and permissions apply as
+//! usual through the `authorization` and `nexus-*` headers. Both the binary
+//! (`application/grpc-web`) and the base64 text (`application/grpc-web-text`)
+//! formats are accepted; server-streaming methods like `ExecuteStream`
+//! stream, while client streaming is not part of gRPC-Web.
+//!
+//! Browsers only let pages from other origins make the calls if the
+//! endpoint allows them: `Cors` lists the origins that may.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Empty, Full, Limited};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, ALLOW, CONTENT_LENGTH, CONTENT_TYPE, ORIGIN, VARY};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tonic::body::BoxBody;
+use tonic::service::Routes;
+use tower::ServiceExt;
+
+/// Largest request body accepted, as for the HTTP gateway.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// How long browsers may cache the answer to a preflight request.
+const PREFLIGHT_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Response headers a page's script may read.
+const EXPOSE_HEADERS: &str = "grpc-status, grpc-message, grpc-status-details-bin";
+
+/// Flag of the frame carrying the trailers in a gRPC-Web body.
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Headers of a call's outcome, sent in the trailers.
+const STATUS_HEADERS: [HeaderName; 3] = [
+    HeaderName::from_static("grpc-status"),
+    HeaderName::from_static("grpc-message"),
+    HeaderName::from_static("grpc-status-details-bin"),
+];
+
+/// The browser origins allowed to call the gRPC-Web endpoint from their
+/// pages. With none, only callers that send no `Origin` (tools, pages
+/// proxied through the same origin) can.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cors {
+    origins: Vec<String>,
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow pages from `origin`, e.g. `https://console.example.com`, or
+    /// from any origin for `*`.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins.push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+type Body = UnsyncBoxBody<Bytes, tonic::Status>;
+
+/// Serve gRPC-Web on `listener`, calling `routes`, until the task is
+/// dropped.
+pub(crate) async fn serve(listener: TcpListener, routes: Routes, cors: Cors) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let routes = routes.clone();
+        let cors = cors.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let routes = routes.clone();
+                let cors = cors.clone();
+                async move { Ok::<_, Infallible>(handle(routes, &cors, request).await) }
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn handle(routes: Routes, cors: &Cors, request: Request<Incoming>) -> Response<Body> {
+    let origin = request.headers().get(ORIGIN).cloned();
+    if let Some(origin) = &origin {
+        if !origin.to_str().is_ok_and(|origin| cors.allows(origin)) {
+            return plain(StatusCode::FORBIDDEN, "origin not allowed");
+        }
+    }
+    let mut response = match *request.method() {
+        Method::OPTIONS => preflight(request.headers()),
+        Method::POST => call(routes, request).await,
+        _ => {
+            let mut response = plain(StatusCode::METHOD_NOT_ALLOWED, "use POST");
+            response.headers_mut().insert(ALLOW, HeaderValue::from_static("POST, OPTIONS"));
+            response
+        }
+    };
+    if let Some(origin) = origin {
+        let headers = response.headers_mut();
+        headers.insert("access-control-allow-origin", origin);
+        headers.insert("access-control-expose-headers", HeaderValue::from_static(EXPOSE_HEADERS));
+        headers.insert(VARY, HeaderValue::from_static("origin"));
+    }
+    response
+}
+
+/// The answer to a CORS preflight: any headers the page asks to send (they
+/// are the gRPC-Web, `authorization` and `nexus-*` ones) are allowed.
+fn preflight(headers: &HeaderMap) -> Response<Body> {
+    let mut response = Response::new(empty());
+    *response.status_mut() = StatusCode::NO_CONTENT;
+    let allowed = response.headers_mut();
+    allowed.insert("access-control-allow-methods", HeaderValue::from_static("POST, OPTIONS"));
+    if let Some(requested) = headers.get("access-control-request-headers") {
+        allowed.insert("access-control-allow-headers", requested.clone());
+    }
+    allowed.insert("access-control-max-age", HeaderValue::from(PREFLIGHT_MAX_AGE.as_secs()));
+    response
+}
+
+/// Make the gRPC call `request` stands for and return its response in the
+/// request's format.
+async fn call(routes: Routes, request: Request<Incoming>) -> Response<Body> {
+    let (mut parts, body) = request.into_parts();
+    let content_type = parts.headers.get(CONTENT_TYPE).cloned();
+    let Some(text) = content_type.as_ref().and_then(|value| is_text(value.to_str().ok()?)) else {
+        return plain(StatusCode::UNSUPPORTED_MEDIA_TYPE, "expected application/grpc-web or grpc-web-text");
+    };
+    let body = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) => return plain(StatusCode::BAD_REQUEST, &format!("failed to read body: {}", e)),
+    };
+    let body = if text {
+        match STANDARD.decode(&body) {
+            Ok(body) => Bytes::from(body),
+            Err(e) => return plain(StatusCode::BAD_REQUEST, &format!("invalid base64 body: {}", e)),
+        }
+    } else {
+        body
+    };
+    parts.headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    parts.headers.insert("te", HeaderValue::from_static("trailers"));
+    parts.headers.remove(CONTENT_LENGTH);
+    let request = Request::from_parts(parts, tonic::body::boxed(Full::new(body)));
+
+    let response = match routes.oneshot(request).await {
+        Ok(response) => response,
+        Err(e) => return plain(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    let (mut parts, body) = response.into_parts();
+    // A call that fails before responding puts its status in the headers
+    // alone; gRPC-Web sends it in the trailers frame all the same.
+    let status: HeaderMap = STATUS_HEADERS
+        .into_iter()
+        .filter_map(|name| Some((name.clone(), parts.headers.remove(name)?)))
+        .collect();
+    let body = if status.is_empty() {
+        body
+    } else {
+        tonic::body::boxed(Full::new(trailers_frame(&status)))
+    };
+    parts.headers.insert(CONTENT_TYPE, content_type.expect("checked above"));
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, web_body(body, text))
+}
+
+/// Whether a gRPC-Web `content_type` is the text format; `None` if it is
+/// not gRPC-Web, or names a message encoding other than protobuf.
+fn is_text(content_type: &str) -> Option<bool> {
+    let (format, encoding) = match content_type.split_once('+') {
+        Some((format, encoding)) => (format, Some(encoding)),
+        None => (content_type, None),
+    };
+    if encoding.is_some_and(|encoding| encoding != "proto") {
+        return None;
+    }
+    match format {
+        "application/grpc-web" => Some(false),
+        "application/grpc-web-text" => Some(true),
+        _ => None,
+    }
+}
+
+/// A gRPC response `body` in the gRPC-Web format: its messages followed by
+/// its trailers as a frame, base64-encoded chunk by chunk if `text`.
+fn web_body(body: BoxBody, text: bool) -> Body {
+    body.map_frame(move |frame| {
+        let data = match frame.into_data() {
+            Ok(data) => data,
+            Err(frame) => trailers_frame(frame.trailers_ref().expect("a frame is data or trailers")),
+        };
+        if text {
+            Frame::data(Bytes::from(STANDARD.encode(data)))
+        } else {
+            Frame::data(data)
+        }
+    })
+    .boxed_unsync()
+}
+
+/// `trailers` as the last frame of a gRPC-Web body: `name:value` lines.
+fn trailers_frame(trailers: &HeaderMap) -> Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.push(b':');
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+    let mut frame = vec![TRAILERS_FLAG];
+    frame.extend_from_slice(&(block.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&block);
+    Bytes::from(frame)
+}
+
+fn empty() -> Body {
+    Empty::new().map_err(|never| match never {}).boxed_unsync()
+}
+
+fn plain(status: StatusCode, message: &str) -> Response<Body> {
+    let body = Full::new(Bytes::from(message.to_string())).map_err(|never| match never {});
+    let mut response = Response::new(body.boxed_unsync());
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+    response
+}
@@ -0,0 +1,120 @@
+//! Service health: the `Service::health` hook and the standard gRPC health
+//! checking service (`grpc.health.v1.Health`) served next to `NexusService`,
+//! so orchestration systems can probe the daemon.
+
+use crate::proto::health::health_check_response::ServingStatus;
+use crate::proto::health::health_server::Health as HealthRpc;
+use crate::proto::health::{HealthCheckRequest, HealthCheckResponse};
+use crate::registry::Registry;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+/// How often `Watch` re-checks health to report changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Result of a service's health check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    Serving,
+    /// Not able to serve requests, with the reason.
+    NotServing(String),
+}
+
+impl Health {
+    pub fn not_serving(reason: impl Into<String>) -> Self {
+        Health::NotServing(reason.into())
+    }
+
+    pub fn is_serving(&self) -> bool {
+        matches!(self, Health::Serving)
+    }
+}
+
+/// `grpc.health.v1.Health` backed by the services' `health` hooks.
+///
+/// The empty service name reports the whole daemon: serving only while every
+/// registered service is, and not serving once shutdown has begun, so load
+/// balancers stop routing to a draining daemon.
+pub(crate) struct HealthService {
+    pub(crate) registry: Arc<Registry>,
+    pub(crate) draining: Arc<AtomicBool>,
+}
+
+impl HealthService {
+    /// Current status of `service` (or the daemon for ""), `None` if unknown.
+    async fn status(registry: &Registry, draining: &AtomicBool, service: &str) -> Option<ServingStatus> {
+        let serving = if service.is_empty() {
+            registry
+                .health(None)
+                .await
+                .iter()
+                .all(|(_, health)| health.is_serving())
+        } else {
+            registry.service_health(service).await?.is_serving()
+        };
+        Some(if serving && !draining.load(Ordering::Relaxed) {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl HealthRpc for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        let status = Self::status(&self.registry, &self.draining, &service)
+            .await
+            .ok_or_else(|| Status::not_found(format!("unknown service '{}'", service)))?;
+        Ok(Response::new(HealthCheckResponse {
+            status: status as i32,
+        }))
+    }
+
+    type WatchStream = ReceiverStream<Result<HealthCheckResponse, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service = request.into_inner().service;
+        let registry = self.registry.clone();
+        let draining = self.draining.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let status = Self::status(&registry, &draining, &service)
+                    .await
+                    .unwrap_or(ServingStatus::ServiceUnknown);
+                if last != Some(status) {
+                    last = Some(status);
+                    let response = HealthCheckResponse {
+                        status: status as i32,
+                    };
+                    if tx.send(Ok(response)).await.is_err() {
+                        return;
+                    }
+                }
+                // End the stream once shutdown is reported so it does not
+                // hold up draining.
+                if draining.load(Ordering::Relaxed) {
+                    return;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(WATCH_INTERVAL) => {}
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,297 @@
+//! Idempotency keys: a command request carrying an `idempotency_key` runs
+//! once, and retries with the same key (a script re-sending after a
+//! timeout, the CLI after a lost connection) get the first response back
+//! instead of creating the volume twice. Keys belong to the caller, told
+//! apart like by `RateLimit` and by its tenant and role, so one caller
+//! cannot replay another's responses.
+
+use crate::context::Context;
+use crate::error::NexusError;
+use crate::proto::{CommandRequest, CommandResponse};
+use crate::ratelimit::Client;
+use crate::registry::Args;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// How long a response is kept for retries.
+pub(crate) const IDEMPOTENCY_TTL: Duration = Duration::from_secs(3600);
+
+/// How many keys are remembered at once; past that the oldest are forgotten.
+pub(crate) const MAX_IDEMPOTENCY_KEYS: usize = 10_000;
+
+/// Longest key accepted.
+const MAX_KEY_LEN: usize = 128;
+
+/// Whose key it is: the caller, its tenant and role, and the key itself.
+type Key = (Client, Option<String>, Option<String>, String);
+
+struct Entry {
+    /// The command the key was first sent with, to reject its reuse for
+    /// another.
+    command: (String, String, Args),
+    /// Set once the command has finished; retries arriving while it runs
+    /// wait for it.
+    response: Arc<OnceCell<CommandResponse>>,
+    added: Instant,
+}
+
+impl Entry {
+    /// Whether the key is forgotten at `now`, `IDEMPOTENCY_TTL` after it was
+    /// first sent: whether or not its command finished, since one whose first
+    /// run was cancelled never does.
+    fn expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.added) > IDEMPOTENCY_TTL
+    }
+}
+
+/// The responses of recent requests with an idempotency key.
+#[derive(Default)]
+pub(crate) struct Idempotency {
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl Idempotency {
+    /// The response to the command `req` carries, with `args`: from `run`
+    /// the first time its key is seen, from that first run afterwards (and
+    /// marked `replayed`). Only successful responses are kept, so a retry
+    /// after a failure runs the command again, as does one that waited for
+    /// a first run that failed.
+    pub(crate) async fn run(
+        &self,
+        ctx: &Context,
+        req: &CommandRequest,
+        args: &Args,
+        run: impl Future<Output = CommandResponse>,
+    ) -> Result<CommandResponse, NexusError> {
+        if req.idempotency_key.len() > MAX_KEY_LEN {
+            return Err(NexusError::invalid_argument(format!(
+                "idempotency key is longer than {} bytes",
+                MAX_KEY_LEN
+            )));
+        }
+        let key = (
+            Client::of(ctx),
+            ctx.tenant.clone(),
+            ctx.role.clone(),
+            req.idempotency_key.clone(),
+        );
+        let command = (req.service.clone(), req.action.clone(), args.clone());
+        let mut run = Some(run);
+        loop {
+            let response = {
+                let now = Instant::now();
+                let mut entries = self.entries.lock().unwrap();
+                match entries.get(&key) {
+                    Some(entry) if !entry.expired(now) && entry.command != command => {
+                        return Err(NexusError::invalid_argument(format!(
+                            "idempotency key '{}' was already used for another command",
+                            req.idempotency_key
+                        )));
+                    }
+                    Some(entry) if !entry.expired(now) => entry.response.clone(),
+                    _ => {
+                        if entries.len() >= MAX_IDEMPOTENCY_KEYS {
+                            make_room(&mut entries, now);
+                        }
+                        let response = Arc::new(OnceCell::new());
+                        let entry = Entry {
+                            command: command.clone(),
+                            response: response.clone(),
+                            added: now,
+                        };
+                        entries.insert(key.clone(), entry);
+                        response
+                    }
+                }
+            };
+            let mut replayed = true;
+            let result = response
+                .get_or_init(|| {
+                    replayed = false;
+                    // Only the first run gets here, and it returns below.
+                    run.take().expect("the request runs at most once")
+                })
+                .await
+                .clone();
+            if !result.success {
+                let mut entries = self.entries.lock().unwrap();
+                if entries.get(&key).is_some_and(|entry| Arc::ptr_eq(&entry.response, &response)) {
+                    entries.remove(&key);
+                }
+                // A retry that waited for a run that failed runs the command
+                // itself rather than sharing the failure.
+                if replayed {
+                    continue;
+                }
+                return Ok(result);
+            }
+            if replayed {
+                tracing::info!(
+                    request_id = ctx.request_id,
+                    service = req.service,
+                    action = req.action,
+                    "replayed the response to an idempotent retry"
+                );
+            }
+            return Ok(CommandResponse { replayed, ..result });
+        }
+    }
+}
+
+/// Forget expired keys and, if that is not enough, the oldest finished one,
+/// or the oldest if none has finished.
+fn make_room(entries: &mut HashMap<Key, Entry>, now: Instant) {
+    entries.retain(|_, entry| !entry.expired(now));
+    if entries.len() < MAX_IDEMPOTENCY_KEYS {
+        return;
+    }
+    let oldest = entries
+        .iter()
+        .min_by_key(|(_, entry)| (!entry.response.initialized(), entry.added))
+        .map(|(key, _)| key.clone());
+    if let Some(oldest) = oldest {
+        entries.remove(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: usize) -> Key {
+        (Client::Unknown, None, None, n.to_string())
+    }
+
+    fn entry(added: Instant, finished: bool) -> Entry {
+        let response = Arc::new(OnceCell::new());
+        if finished {
+            response.set(CommandResponse::default()).unwrap();
+        }
+        Entry {
+            command: (String::new(), String::new(), Args::new(Vec::new())),
+            response,
+            added,
+        }
+    }
+
+    fn request(action: &str) -> CommandRequest {
+        CommandRequest {
+            service: "volume".to_string(),
+            action: action.to_string(),
+            idempotency_key: "k1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn response(success: bool, message: &str) -> CommandResponse {
+        CommandResponse {
+            success,
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_get_the_first_response_back() {
+        let idempotency = Idempotency::default();
+        let (ctx, req, args) = (Context::new(), request("create"), Args::new(vec!["vol1".to_string()]));
+        let first = idempotency.run(&ctx, &req, &args, async { response(true, "created") }).await.unwrap();
+        assert_eq!((first.message.as_str(), first.replayed), ("created", false));
+
+        let retry = idempotency
+            .run(&ctx, &req, &args, async { panic!("a retry must not run the command") })
+            .await
+            .unwrap();
+        assert_eq!((retry.message.as_str(), retry.replayed), ("created", true));
+
+        // Another caller's key of the same name is its own.
+        let other = Context::new().with_tenant(Some("acme".to_string()));
+        let theirs = idempotency.run(&other, &req, &args, async { response(true, "theirs") }).await.unwrap();
+        assert_eq!((theirs.message.as_str(), theirs.replayed), ("theirs", false));
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_replayed() {
+        let idempotency = Idempotency::default();
+        let (ctx, req, args) = (Context::new(), request("create"), Args::new(Vec::new()));
+        let failed = idempotency.run(&ctx, &req, &args, async { response(false, "disk full") }).await.unwrap();
+        assert!(!failed.success);
+        let retry = idempotency.run(&ctx, &req, &args, async { response(true, "created") }).await.unwrap();
+        assert_eq!((retry.message.as_str(), retry.replayed), ("created", false));
+    }
+
+    #[tokio::test]
+    async fn retries_waiting_for_a_failed_run_run_again() {
+        let idempotency = Idempotency::default();
+        let (ctx, req, args) = (Context::new(), request("create"), Args::new(Vec::new()));
+        let fail = tokio::sync::Notify::new();
+        let first = idempotency.run(&ctx, &req, &args, async {
+            fail.notified().await;
+            response(false, "disk full")
+        });
+        let retry = idempotency.run(&ctx, &req, &args, async { response(true, "created") });
+        let (first, retry) = tokio::join!(first, async {
+            tokio::task::yield_now().await;
+            fail.notify_one();
+            retry.await
+        });
+        assert!(!first.unwrap().success);
+        let retry = retry.unwrap();
+        assert_eq!((retry.message.as_str(), retry.replayed), ("created", false));
+    }
+
+    #[tokio::test]
+    async fn keys_cannot_be_reused_for_another_command() {
+        let idempotency = Idempotency::default();
+        let ctx = Context::new();
+        let args = Args::new(vec!["vol1".to_string()]);
+        idempotency.run(&ctx, &request("create"), &args, async { response(true, "created") }).await.unwrap();
+
+        let error = idempotency
+            .run(&ctx, &request("delete"), &args, async { response(true, "deleted") })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, crate::error::ErrorCode::InvalidArgument);
+        let other_args = Args::new(vec!["vol2".to_string()]);
+        let error = idempotency
+            .run(&ctx, &request("create"), &other_args, async { response(true, "created") })
+            .await
+            .unwrap_err();
+        assert_eq!(error.code, crate::error::ErrorCode::InvalidArgument);
+
+        let long = CommandRequest {
+            idempotency_key: "k".repeat(MAX_KEY_LEN + 1),
+            ..request("create")
+        };
+        assert!(idempotency.run(&ctx, &long, &args, async { response(true, "") }).await.is_err());
+    }
+
+    #[test]
+    fn keys_whose_first_run_was_cancelled_expire() {
+        let added = Instant::now();
+        let later = added + IDEMPOTENCY_TTL + Duration::from_secs(1);
+        assert!(entry(added, false).expired(later));
+        assert!(entry(added, true).expired(later));
+        assert!(!entry(added, false).expired(added + IDEMPOTENCY_TTL));
+    }
+
+    #[test]
+    fn unfinished_keys_are_forgotten_to_make_room() {
+        let start = Instant::now();
+        let mut entries: HashMap<Key, Entry> = (0..MAX_IDEMPOTENCY_KEYS)
+            .map(|n| (key(n), entry(start + Duration::from_millis(n as u64), false)))
+            .collect();
+        make_room(&mut entries, start);
+        assert_eq!(entries.len(), MAX_IDEMPOTENCY_KEYS - 1);
+        assert!(!entries.contains_key(&key(0)));
+
+        // A finished key goes before older unfinished ones.
+        entries.insert(key(0), entry(start + Duration::from_secs(60), true));
+        make_room(&mut entries, start);
+        assert!(!entries.contains_key(&key(0)));
+        assert!(entries.contains_key(&key(1)));
+    }
+}
@@ -0,0 +1,129 @@
+//! Client interceptors: code `NexusClient` and `NexusCli` run around every
+//! call they make, the client-side counterpart of server `Middleware`: to
+//! attach credentials or request IDs to the metadata, or to time the calls.
+
+use hyper::{Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tonic::body::BoxBody;
+use tonic::metadata::MetadataMap;
+use tonic::transport::Channel;
+use tonic::Status;
+
+/// Runs around every gRPC call of a client; installed with
+/// `NexusClient::with_interceptor` or `NexusCli::with_interceptor`, in
+/// order. `method` is the call's path, e.g. `/nexus.NexusService/Execute`.
+///
+/// ```ignore
+/// use libnexus::{ClientInterceptor, MetadataMap, NexusClient, Status};
+///
+/// struct Latency;
+///
+/// impl ClientInterceptor for Latency {
+///     fn on_response(&self, method: &str, _: Result<&MetadataMap, &Status>, elapsed: Duration) {
+///         eprintln!("{} took {:?}", method, elapsed);
+///     }
+/// }
+///
+/// let client = NexusClient::connect("/tmp/nexus.sock").await?.with_interceptor(Latency);
+/// ```
+pub trait ClientInterceptor: Send + Sync + 'static {
+    /// Inspect or add to the request's metadata before it is sent, after
+    /// the session's tenant, token and preferences are set. An error fails
+    /// the call without sending it: a `tonic::Status` as itself, anything
+    /// else as a `Cancelled` status with its message.
+    fn on_request(&self, method: &str, metadata: &mut MetadataMap) -> anyhow::Result<()> {
+        let _ = (method, metadata);
+        Ok(())
+    }
+
+    /// Called when the response starts arriving, `elapsed` after the request
+    /// was sent: with its metadata (for a streaming call, before the stream
+    /// ends), or with the status of a call the server rejected or that
+    /// could not be made. The outcome of a command is in the response
+    /// message, not here.
+    fn on_response(&self, method: &str, response: Result<&MetadataMap, &Status>, elapsed: Duration) {
+        let _ = (method, response, elapsed);
+    }
+}
+
+/// A closure is an interceptor of the requests alone.
+///
+/// ```ignore
+/// cli.with_interceptor(|_: &str, metadata: &mut MetadataMap| {
+///     metadata.insert("nexus-request-id", next_id().parse().unwrap());
+///     Ok(())
+/// })
+/// ```
+impl<F> ClientInterceptor for F
+where
+    F: Fn(&str, &mut MetadataMap) -> anyhow::Result<()> + Send + Sync + 'static,
+{
+    fn on_request(&self, method: &str, metadata: &mut MetadataMap) -> anyhow::Result<()> {
+        self(method, metadata)
+    }
+}
+
+/// The interceptors of a client, shared by its connections.
+pub(crate) type Interceptors = Arc<Vec<Arc<dyn ClientInterceptor>>>;
+
+/// A channel that runs `interceptors` around each call.
+#[derive(Clone)]
+pub(crate) struct Intercepted {
+    pub(crate) channel: Channel,
+    pub(crate) interceptors: Interceptors,
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+impl tower::Service<Request<BoxBody>> for Intercepted {
+    type Response = Response<BoxBody>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.channel.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut request: Request<BoxBody>) -> Self::Future {
+        let interceptors = self.interceptors.clone();
+        if interceptors.is_empty() {
+            let call = self.channel.call(request);
+            return Box::pin(async move { call.await.map_err(Into::into) });
+        }
+        let method = request.uri().path().to_string();
+        let mut metadata = MetadataMap::from_headers(std::mem::take(request.headers_mut()));
+        for interceptor in interceptors.iter() {
+            if let Err(e) = interceptor.on_request(&method, &mut metadata) {
+                let status = match e.downcast::<Status>() {
+                    Ok(status) => status,
+                    Err(e) => Status::cancelled(e.to_string()),
+                };
+                return Box::pin(async move { Err(status.into()) });
+            }
+        }
+        *request.headers_mut() = metadata.into_headers();
+        let started = Instant::now();
+        let call = self.channel.call(request);
+        Box::pin(async move {
+            let result = call.await;
+            let elapsed = started.elapsed();
+            let outcome = match &result {
+                // A call that fails before responding has its status in the
+                // headers alone.
+                Ok(response) => match Status::from_header_map(response.headers()) {
+                    Some(status) if status.code() != tonic::Code::Ok => Err(status),
+                    _ => Ok(MetadataMap::from_headers(response.headers().clone())),
+                },
+                Err(e) => Err(Status::unavailable(e.to_string())),
+            };
+            for interceptor in interceptors.iter() {
+                interceptor.on_response(&method, outcome.as_ref(), elapsed);
+            }
+            result.map_err(Into::into)
+        })
+    }
+}
@@ -0,0 +1,225 @@
+//! The on-disk record of background jobs (`NexusServer::with_job_store`):
+//! one JSON file per job in a directory, written when the job starts and
+//! again when it ends, so `jobs` and `job status` still show finished jobs,
+//! with their output and warnings, after the daemon restarts.
+
+use crate::error::{ErrorCode, NexusError};
+use crate::jobs::{Job, JobState};
+use crate::registry::CommandOutput;
+use anyhow::{Context as _, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// A job as saved to its file.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    id: u64,
+    service: String,
+    action: String,
+    args: String,
+    tenant: Option<String>,
+    state: String,
+    started_at_ms: u64,
+    elapsed_ms: u64,
+    output: Option<Output>,
+    error: Option<Error>,
+    warnings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Output {
+    Text(String),
+    Json(serde_json::Value),
+    Table { headers: Vec<String>, rows: Vec<Vec<String>> },
+    /// Base64.
+    Bytes(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Error {
+    code: String,
+    message: String,
+    details: BTreeMap<String, String>,
+    suggestions: Vec<String>,
+}
+
+const STATES: [(JobState, &str); 4] = [
+    (JobState::Running, "running"),
+    (JobState::Succeeded, "succeeded"),
+    (JobState::Failed, "failed"),
+    (JobState::Cancelled, "cancelled"),
+];
+
+impl Record {
+    fn of(job: &Job) -> Self {
+        let (output, error) = match &job.outcome {
+            Some(Ok(output)) => (Some(Output::of(output.clone())), None),
+            Some(Err(e)) => (None, Some(Error::of(e))),
+            None => (None, None),
+        };
+        Self {
+            id: job.id,
+            service: job.service.clone(),
+            action: job.action.clone(),
+            args: job.args.clone(),
+            tenant: job.tenant.clone(),
+            state: STATES.iter().find(|(state, _)| *state == job.state).map_or("", |(_, name)| name).to_string(),
+            started_at_ms: job.started_at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+            elapsed_ms: job.elapsed.as_millis() as u64,
+            output,
+            error,
+            warnings: job.warnings.clone(),
+        }
+    }
+
+    /// The job the record describes. One saved as running did not end
+    /// while the daemon ran, so it is reported as failed.
+    fn into_job(self) -> Result<Job> {
+        let state = STATES
+            .iter()
+            .find(|(_, name)| *name == self.state)
+            .map(|(state, _)| *state)
+            .with_context(|| format!("unknown job state '{}'", self.state))?;
+        let outcome = match (state, self.output, self.error) {
+            (JobState::Running, ..) => Some(Err(NexusError::internal("the daemon stopped while the job ran"))),
+            (_, Some(output), _) => Some(Ok(output.into_output()?)),
+            (_, None, Some(error)) => Some(Err(error.into_error()?)),
+            (_, None, None) => None,
+        };
+        Ok(Job {
+            id: self.id,
+            service: self.service,
+            action: self.action,
+            args: self.args,
+            tenant: self.tenant,
+            state: if state == JobState::Running { JobState::Failed } else { state },
+            started_at: UNIX_EPOCH + Duration::from_millis(self.started_at_ms),
+            elapsed: Duration::from_millis(self.elapsed_ms),
+            progress: None,
+            outcome: outcome.map(|outcome| outcome.map_err(|e| Arc::new(e.into()))),
+            warnings: self.warnings,
+        })
+    }
+}
+
+impl Output {
+    fn of(output: CommandOutput) -> Self {
+        match output {
+            CommandOutput::Text(text) => Self::Text(text),
+            CommandOutput::Json(value) => Self::Json(value),
+            CommandOutput::Table { headers, rows } => Self::Table { headers, rows },
+            CommandOutput::Bytes(bytes) => Self::Bytes(STANDARD.encode(bytes)),
+        }
+    }
+
+    fn into_output(self) -> Result<CommandOutput> {
+        Ok(match self {
+            Self::Text(text) => CommandOutput::Text(text),
+            Self::Json(value) => CommandOutput::Json(value),
+            Self::Table { headers, rows } => CommandOutput::Table { headers, rows },
+            Self::Bytes(bytes) => CommandOutput::Bytes(STANDARD.decode(bytes).context("invalid job output")?),
+        })
+    }
+}
+
+impl Error {
+    fn of(error: &anyhow::Error) -> Self {
+        let nexus = error.downcast_ref::<NexusError>();
+        Self {
+            code: ErrorCode::of(error).as_str().to_string(),
+            message: error.to_string(),
+            details: nexus.map(|e| e.details.clone()).unwrap_or_default(),
+            suggestions: nexus.map(|e| e.suggestions.clone()).unwrap_or_default(),
+        }
+    }
+
+    fn into_error(self) -> Result<NexusError> {
+        Ok(NexusError {
+            code: self.code.parse()?,
+            message: self.message,
+            details: self.details,
+            suggestions: self.suggestions,
+        })
+    }
+}
+
+/// The directory jobs are saved in.
+pub(crate) struct JobStore {
+    dir: PathBuf,
+}
+
+impl JobStore {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// The jobs saved in the directory, if it exists. Those saved as
+    /// running are saved again as failed. A file that cannot be read is
+    /// reported and skipped, so one bad file does not keep the daemon from
+    /// starting.
+    pub(crate) fn load(&self) -> Result<Vec<Job>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read jobs from {}", self.dir.display())),
+        };
+        let mut jobs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let job = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| Ok(serde_json::from_str::<Record>(&text)?))
+                .and_then(|record| {
+                    let interrupted = record.state == "running";
+                    let job = record.into_job()?;
+                    if interrupted {
+                        self.save(&job);
+                    }
+                    Ok(job)
+                });
+            match job {
+                Ok(job) => jobs.push(job),
+                Err(e) => tracing::warn!(file = %path.display(), error = %e, "skipped unreadable job record"),
+            }
+        }
+        Ok(jobs)
+    }
+
+    /// Write the job's file, through a temporary one so a crash midway
+    /// leaves the previous version. Failing to is reported; the job goes on.
+    pub(crate) fn save(&self, job: &Job) {
+        let path = self.path(job.id);
+        let write = || -> std::io::Result<()> {
+            std::fs::create_dir_all(&self.dir)?;
+            let temporary = path.with_extension("tmp");
+            std::fs::write(&temporary, serde_json::to_string_pretty(&Record::of(job))?)?;
+            std::fs::rename(&temporary, &path)
+        };
+        if let Err(e) = write() {
+            tracing::warn!(job = job.id, file = %path.display(), error = %e, "failed to save job");
+        }
+    }
+
+    /// Delete the file of job `id`.
+    pub(crate) fn remove(&self, id: u64) {
+        let path = self.path(id);
+        if let Err(e) = std::fs::remove_file(&path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!(job = id, file = %path.display(), error = %e, "failed to delete job");
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Background jobs: commands marked `#[command(background)]` run detached
+//! from the request that started them, which returns the job's ID at once.
+//! Clients follow them with the `JobStatus`/`JobList` RPCs and stop them
+//! with `JobCancel`. With `NexusServer::with_job_store` they are also
+//! saved to disk (see `crate::job_store`) and outlive the daemon.
+
+use crate::job_store::JobStore;
+use crate::progress::ProgressUpdate;
+use crate::registry::CommandOutput;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
+use tokio::task::AbortHandle;
+
+/// How many finished jobs are kept for `JobStatus` by default; older ones
+/// are forgotten.
+pub const MAX_FINISHED_JOBS: usize = 100;
+
+/// Which finished jobs are kept (`NexusServer::with_job_retention`): at
+/// most `max_jobs` of the latest, and none that ended longer than
+/// `max_age` ago. Running jobs are always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobRetention {
+    pub max_jobs: usize,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for JobRetention {
+    fn default() -> Self {
+        Self {
+            max_jobs: MAX_FINISHED_JOBS,
+            max_age: None,
+        }
+    }
+}
+
+/// Where a job is in its life.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// A background command and, once it has finished, its outcome.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: u64,
+    /// Full name of the service, e.g. "volume".
+    pub service: String,
+    pub action: String,
+    /// The bound arguments as `name=value` pairs, secrets redacted.
+    pub args: String,
+    /// Tenant that started the job; only its requests see the job.
+    pub tenant: Option<String>,
+    pub state: JobState,
+    pub started_at: SystemTime,
+    /// Run time so far, or in total once finished.
+    pub elapsed: Duration,
+    /// Latest progress of a running job whose command reports it.
+    pub progress: Option<ProgressUpdate>,
+    /// The command's output or error, once it has succeeded or failed.
+    pub outcome: Option<Result<CommandOutput, Arc<anyhow::Error>>>,
+    pub warnings: Vec<String>,
+}
+
+/// The jobs of a registry, running and recently finished.
+#[derive(Default)]
+pub(crate) struct Jobs {
+    next_id: AtomicU64,
+    entries: Mutex<BTreeMap<u64, Entry>>,
+    pub(crate) retention: JobRetention,
+    /// Where jobs are saved, if anywhere.
+    pub(crate) store: Option<JobStore>,
+}
+
+struct Entry {
+    job: Job,
+    started: Instant,
+    /// Stops the job's task; `None` until the task is spawned.
+    abort: Option<AbortHandle>,
+    progress: Option<watch::Receiver<ProgressUpdate>>,
+}
+
+impl Entry {
+    fn snapshot(&self) -> Job {
+        let mut job = self.job.clone();
+        if job.state == JobState::Running {
+            job.elapsed = self.started.elapsed();
+            job.progress = self.progress.as_ref().map(|updates| updates.borrow().clone());
+        }
+        job
+    }
+
+    fn finish(&mut self, state: JobState) {
+        self.job.state = state;
+        self.job.elapsed = self.started.elapsed();
+    }
+}
+
+impl Jobs {
+    /// Read the jobs saved by earlier runs of the daemon, and go on
+    /// numbering after the latest.
+    pub(crate) fn load(&self) -> anyhow::Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let jobs = store.load()?;
+        let mut entries = self.entries.lock().unwrap();
+        for job in jobs {
+            self.next_id.fetch_max(job.id, Ordering::Relaxed);
+            let entry = Entry {
+                job,
+                started: Instant::now(),
+                abort: None,
+                progress: None,
+            };
+            entries.insert(entry.job.id, entry);
+        }
+        self.prune(&mut entries);
+        Ok(())
+    }
+
+    /// Record a new running job and return its ID. `progress` follows the
+    /// progress of commands that report it.
+    pub(crate) fn add(
+        &self,
+        service: &str,
+        action: &str,
+        args: String,
+        tenant: Option<String>,
+        progress: Option<watch::Receiver<ProgressUpdate>>,
+    ) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let job = Job {
+            id,
+            service: service.to_string(),
+            action: action.to_string(),
+            args,
+            tenant,
+            state: JobState::Running,
+            started_at: SystemTime::now(),
+            elapsed: Duration::ZERO,
+            progress: None,
+            outcome: None,
+            warnings: Vec::new(),
+        };
+        let entry = Entry {
+            job,
+            started: Instant::now(),
+            abort: None,
+            progress,
+        };
+        self.save(&entry);
+        self.entries.lock().unwrap().insert(id, entry);
+        id
+    }
+
+    /// Remember how to stop job `id`'s task.
+    pub(crate) fn attach(&self, id: u64, abort: AbortHandle) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&id) {
+            entry.abort = Some(abort);
+        }
+    }
+
+    /// Record how job `id` ended, unless it was cancelled in the meantime.
+    pub(crate) fn complete(&self, id: u64, outcome: anyhow::Result<CommandOutput>, warnings: Vec<String>) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.get_mut(&id).filter(|e| e.job.state == JobState::Running) else {
+            return;
+        };
+        entry.finish(if outcome.is_ok() { JobState::Succeeded } else { JobState::Failed });
+        entry.job.outcome = Some(outcome.map_err(Arc::new));
+        entry.job.warnings = warnings;
+        self.save(entry);
+        self.prune(&mut entries);
+    }
+
+    /// The jobs `tenant` can see, oldest first.
+    pub(crate) fn list(&self, tenant: Option<&str>) -> Vec<Job> {
+        let mut entries = self.entries.lock().unwrap();
+        self.prune(&mut entries);
+        entries
+            .values()
+            .filter(|e| e.job.tenant.as_deref() == tenant)
+            .map(Entry::snapshot)
+            .collect()
+    }
+
+    /// Job `id`, if `tenant` can see it.
+    pub(crate) fn get(&self, tenant: Option<&str>, id: u64) -> Option<Job> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&id)
+            .filter(|e| e.job.tenant.as_deref() == tenant)
+            .map(Entry::snapshot)
+    }
+
+    /// Stop job `id` if it is still running, and return it. A finished job
+    /// is returned unchanged.
+    pub(crate) fn cancel(&self, tenant: Option<&str>, id: u64) -> Option<Job> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get_mut(&id)
+            .filter(|e| e.job.tenant.as_deref() == tenant)?;
+        if entry.job.state == JobState::Running {
+            if let Some(abort) = &entry.abort {
+                abort.abort();
+            }
+            entry.finish(JobState::Cancelled);
+            self.save(entry);
+            tracing::info!(job = id, service = entry.job.service, action = entry.job.action, "job cancelled");
+        }
+        let job = entry.snapshot();
+        self.prune(&mut entries);
+        Some(job)
+    }
+
+    /// Stop every running job, e.g. when the server shuts down.
+    pub(crate) fn cancel_all(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        for entry in entries.values_mut().filter(|e| e.job.state == JobState::Running) {
+            if let Some(abort) = &entry.abort {
+                abort.abort();
+            }
+            entry.finish(JobState::Cancelled);
+            self.save(entry);
+        }
+    }
+
+    fn save(&self, entry: &Entry) {
+        if let Some(store) = &self.store {
+            store.save(&entry.job);
+        }
+    }
+
+    /// Forget the finished jobs the retention policy does not keep: those
+    /// that ended more than `max_age` ago, and the oldest beyond
+    /// `max_jobs`.
+    fn prune(&self, entries: &mut BTreeMap<u64, Entry>) {
+        let now = SystemTime::now();
+        let expired = |job: &Job| {
+            self.retention.max_age.is_some_and(|max_age| {
+                now.duration_since(job.started_at + job.elapsed).is_ok_and(|age| age > max_age)
+            })
+        };
+        let finished: Vec<(u64, bool)> = entries
+            .iter()
+            .filter(|(_, e)| e.job.state != JobState::Running)
+            .map(|(&id, e)| (id, expired(&e.job)))
+            .collect();
+        let excess = finished.len().saturating_sub(self.retention.max_jobs);
+        for (i, (id, expired)) in finished.into_iter().enumerate() {
+            if i < excess || expired {
+                entries.remove(&id);
+                if let Some(store) = &self.store {
+                    store.remove(id);
+                }
+            }
+        }
+    }
+}
@@ -1,14 +1,133 @@
+// Lets `#[nexus_service]` expansions inside this crate name it `libnexus`.
+extern crate self as libnexus;
+
+mod admin;
+pub mod auth;
+mod cache;
+mod candidates;
+mod chunked;
+pub mod client;
+pub mod completion;
+mod completion_script;
+pub mod config;
+mod connections;
+pub mod context;
+mod docs;
+pub mod endpoint;
+pub mod error;
+pub mod events;
+pub mod extension;
+mod favorites;
+mod gateway;
+pub mod glob;
+pub mod grpc_web;
+pub mod health;
+mod idempotency;
+pub mod interceptor;
+mod job_store;
+pub mod jobs;
+pub mod limiter;
+mod locks;
+mod manual;
+mod mcp;
+mod metrics;
+pub mod middleware;
+mod openapi;
+pub mod plan;
+#[cfg(unix)]
+pub mod plugin;
+pub mod progress;
+mod python;
+pub mod ratelimit;
+mod recent;
+#[cfg(unix)]
+mod redirect;
+mod reflection;
+mod reload;
 pub mod registry;
+mod schedule;
 pub mod server;
+pub mod session;
+pub mod state;
+pub mod stream;
+#[cfg(unix)]
+mod takeover;
+pub mod theme;
+mod tokenize;
+mod trace;
+pub mod transfer;
+mod yaml;
+pub mod validate;
 pub mod cli;
 
 pub mod proto {
     tonic::include_proto!("nexus");
+
+    /// The standard gRPC health checking protocol.
+    pub mod health {
+        tonic::include_proto!("grpc.health.v1");
+    }
+
+    /// The standard gRPC server reflection protocol.
+    pub mod reflection {
+        pub mod v1 {
+            tonic::include_proto!("grpc.reflection.v1");
+        }
+
+        pub mod v1alpha {
+            tonic::include_proto!("grpc.reflection.v1alpha");
+        }
+    }
+
+    /// Descriptors of the files above, written by `build.rs`.
+    pub(crate) const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("nexus_descriptor");
+
+    impl ListServicesRequest {
+        /// A request from a client speaking this `PROTOCOL_VERSION`.
+        pub fn new() -> Self {
+            Self {
+                protocol_version: crate::PROTOCOL_VERSION,
+            }
+        }
+    }
 }
 
-pub use registry::{ArgInfo, CommandInfo, Service};
+pub use auth::{Authenticator, PeerCredentials, TokenAuth};
+pub use client::{ClientError, NexusClient};
+pub use config::{Config, OutputFormat};
+pub use context::{Context, Preferences};
+pub use endpoint::{Endpoint, Keepalive, UdsOptions};
+pub use error::{Busy, ErrorCode, NexusError, RateLimited};
+pub use events::{Event, EventBus};
+pub use grpc_web::Cors;
+pub use health::Health;
+pub use interceptor::ClientInterceptor;
+pub use jobs::{Job, JobRetention, JobState};
+pub use middleware::Middleware;
+pub use plan::Change;
+pub use progress::{Progress, ProgressUpdate};
+pub use ratelimit::{Rate, RateLimit};
+pub use registry::{ArgInfo, Args, CommandInfo, CommandOutput, Priority, RegistryHandle, Service, StartContext};
 pub use server::NexusServer;
-pub use cli::NexusCli;
+pub use session::{Prompt, Session, SessionService};
+pub use state::State;
+pub use stream::CommandStream;
+pub use theme::{Style, Theme};
+pub use transfer::{Download, Upload};
+pub use cli::{NexusCli, RetryPolicy};
 pub use nexus_derive::nexus_service;
+pub use serde_json;
+pub use tokio_stream;
+pub use tokio_util::sync::CancellationToken;
+pub use tonic::metadata::MetadataMap;
+pub use tonic::Status;
+#[cfg(feature = "tls")]
+pub use tonic::transport::{Certificate, Identity};
+
+pub const DEFAULT_ENDPOINT: &str = "unix:///tmp/nexus.sock";
 
-pub const DEFAULT_ENDPOINT: &str = "/tmp/nexus.sock";
+/// Version of the protocol between clients and servers, exchanged by
+/// `ListServices`. Compatible additions keep it; it is raised when older
+/// clients could no longer work with the server, and clients refuse to
+/// talk to servers with a newer version than theirs.
+pub const PROTOCOL_VERSION: u32 = 1;
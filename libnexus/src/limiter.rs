@@ -0,0 +1,197 @@
+use crate::registry::Priority;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Counting semaphore that hands freed slots to the highest-priority waiter
+/// first (FIFO within a priority class).
+pub struct PriorityLimiter {
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    available: usize,
+    /// Most waiters allowed at once, if bounded.
+    max_queued: Option<usize>,
+    /// Waiters indexed by `Priority as usize`.
+    waiters: [VecDeque<oneshot::Sender<()>>; 3],
+}
+
+impl PriorityLimiter {
+    pub fn new(permits: usize) -> Arc<Self> {
+        Self::build(permits, None)
+    }
+
+    /// A limiter that lets at most `max_queued` callers wait for a slot;
+    /// `acquire` turns further ones away.
+    pub fn with_queue_limit(permits: usize, max_queued: usize) -> Arc<Self> {
+        Self::build(permits, Some(max_queued))
+    }
+
+    fn build(permits: usize, max_queued: Option<usize>) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(LimiterState {
+                available: permits,
+                max_queued,
+                waiters: Default::default(),
+            }),
+        })
+    }
+
+    /// Wait for a slot. Dropping the returned permit frees the slot.
+    /// Returns `None` without waiting if the queue limit is reached.
+    pub async fn acquire(self: &Arc<Self>, priority: Priority) -> Option<LimiterPermit> {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.available > 0 && state.waiters.iter().all(VecDeque::is_empty) {
+                state.available -= 1;
+                return Some(LimiterPermit {
+                    limiter: self.clone(),
+                });
+            }
+            if let Some(max_queued) = state.max_queued {
+                // Waiters whose caller gave up are still queued until a
+                // release skips them; they do not count.
+                for queue in &mut state.waiters {
+                    queue.retain(|tx| !tx.is_closed());
+                }
+                if state.waiters.iter().map(VecDeque::len).sum::<usize>() >= max_queued {
+                    return None;
+                }
+            }
+            let (tx, rx) = oneshot::channel();
+            state.waiters[priority as usize].push_back(tx);
+            rx
+        };
+
+        let mut wait = Waiting {
+            rx: Some(rx),
+            limiter: self.clone(),
+        };
+        // The sender lives until a slot is handed over, so this cannot fail.
+        let _ = wait.rx.as_mut().unwrap().await;
+        wait.rx = None;
+        Some(LimiterPermit {
+            limiter: self.clone(),
+        })
+    }
+
+    /// Hand the slot to the highest-priority live waiter, or return it to the pool.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        for queue in state.waiters.iter_mut().rev() {
+            while let Some(tx) = queue.pop_front() {
+                if tx.send(()).is_ok() {
+                    return;
+                }
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// Slot held while a command runs.
+pub struct LimiterPermit {
+    limiter: Arc<PriorityLimiter>,
+}
+
+impl Drop for LimiterPermit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+/// Cleans up a waiter whose future was dropped: if a slot was handed over
+/// in the meantime, pass it on instead of leaking it.
+struct Waiting {
+    rx: Option<oneshot::Receiver<()>>,
+    limiter: Arc<PriorityLimiter>,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            rx.close();
+            if rx.try_recv().is_ok() {
+                self.limiter.release();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Queue a waiter that records `label` once it gets a slot, then frees it.
+    fn queue(
+        limiter: &Arc<PriorityLimiter>,
+        priority: Priority,
+        label: &'static str,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> tokio::task::JoinHandle<()> {
+        let (limiter, order) = (limiter.clone(), order.clone());
+        tokio::spawn(async move {
+            let _permit = limiter.acquire(priority).await.unwrap();
+            order.lock().unwrap().push(label);
+        })
+    }
+
+    #[tokio::test]
+    async fn frees_slots_by_priority_then_arrival() {
+        let limiter = PriorityLimiter::new(1);
+        let held = limiter.acquire(Priority::Normal).await.unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for (priority, label) in [
+            (Priority::Low, "low"),
+            (Priority::Normal, "normal"),
+            (Priority::High, "high 1"),
+            (Priority::High, "high 2"),
+        ] {
+            waiters.push(queue(&limiter, priority, label, &order));
+            // Let the waiter queue before the next one arrives.
+            tokio::task::yield_now().await;
+        }
+        assert!(order.lock().unwrap().is_empty());
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), ["high 1", "high 2", "normal", "low"]);
+        // Every slot is back in the pool.
+        assert_eq!(limiter.state.lock().unwrap().available, 1);
+    }
+
+    #[tokio::test]
+    async fn turns_away_callers_past_the_queue_limit() {
+        let limiter = PriorityLimiter::with_queue_limit(1, 1);
+        let held = limiter.acquire(Priority::Normal).await.unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let waiter = queue(&limiter, Priority::Low, "queued", &order);
+        tokio::task::yield_now().await;
+
+        assert!(limiter.acquire(Priority::High).await.is_none());
+        drop(held);
+        waiter.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), ["queued"]);
+    }
+
+    #[tokio::test]
+    async fn callers_that_gave_up_do_not_hold_the_queue() {
+        let limiter = PriorityLimiter::with_queue_limit(1, 1);
+        let held = limiter.acquire(Priority::Normal).await.unwrap();
+        let gave_up = tokio::time::timeout(Duration::from_millis(10), limiter.acquire(Priority::Normal)).await;
+        assert!(gave_up.is_err());
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let waiter = queue(&limiter, Priority::Normal, "queued", &order);
+        tokio::task::yield_now().await;
+        drop(held);
+        waiter.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), ["queued"]);
+        assert_eq!(limiter.state.lock().unwrap().available, 1);
+    }
+}
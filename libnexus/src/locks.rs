@@ -0,0 +1,222 @@
+//! Command locks (`#[command(lock = "pool:{pool}")]`): commands naming the
+//! same resource run one at a time, so two `volume create` calls on one
+//! pool cannot race. The key is filled in from the command's arguments;
+//! with `try_lock` a command finding it taken fails as `Busy` (retry after
+//! `LOCK_RETRY`) instead of waiting. A command whose `#[arg(glob)]` pattern
+//! matches several resources takes the lock of each, in order of their keys.
+
+use crate::context::Context;
+use crate::error::Busy;
+use crate::registry::{bind_command, Args, CommandInfo, LOCK_RETRY};
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+
+/// The key `info`'s lock takes for `args`: its template with each `{arg}`
+/// replaced by the argument's value (its default if omitted, the values
+/// joined by commas if variadic).
+pub(crate) fn key(info: &CommandInfo, args: &Args) -> String {
+    let Ok((slots, rest)) = bind_command(info, args) else {
+        return info.lock.clone();
+    };
+    let rest = (!rest.is_empty()).then(|| rest.join(","));
+    let mut key = info.lock.clone();
+    for (arg, value) in info.args.iter().zip(slots.into_iter().chain([rest])) {
+        let value = value.unwrap_or_else(|| arg.default.clone());
+        key = key.replace(&format!("{{{}}}", arg.name), &value);
+    }
+    key
+}
+
+/// Whose lock it is: the tenant and the key.
+type Key = (Option<String>, String);
+
+/// The locks commands hold or wait for.
+#[derive(Default)]
+pub(crate) struct Locks {
+    locks: Mutex<HashMap<Key, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Locks {
+    /// Take the locks `info` names for each of `invocations` (a command's
+    /// arguments, with its globs expanded), waiting while another command
+    /// holds one (failing as `Busy` for `try_lock` commands). None for
+    /// commands without a lock and previews, and none of those the calling
+    /// command holds already.
+    pub(crate) async fn acquire(
+        &self,
+        ctx: &Context,
+        info: Option<&CommandInfo>,
+        invocations: &[Args],
+    ) -> Result<Vec<OwnedMutexGuard<()>>> {
+        let Some(info) = info.filter(|info| !info.lock.is_empty() && !ctx.dry_run) else {
+            return Ok(Vec::new());
+        };
+        // Taken in order, so two commands needing several of the same locks
+        // cannot each hold one the other waits for.
+        let keys: BTreeSet<String> = invocations
+            .iter()
+            .map(|args| key(info, args))
+            .filter(|key| !ctx.holds_lock(key))
+            .collect();
+        let mut held = Vec::with_capacity(keys.len());
+        for key in &keys {
+            held.push(self.lock(ctx, info, key).await?);
+        }
+        for key in keys {
+            ctx.hold_lock(key);
+        }
+        Ok(held)
+    }
+
+    /// Take the lock `key` of a command of `info`.
+    async fn lock(&self, ctx: &Context, info: &CommandInfo, key: &str) -> Result<OwnedMutexGuard<()>> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // Forget the locks nobody holds or waits for.
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry((ctx.tenant.clone(), key.to_string())).or_default().clone()
+        };
+        let guard = match lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) if info.try_lock => {
+                let reason = format!("'{}' is in use by another command", key);
+                return Err(Busy::new(reason).retry_after(LOCK_RETRY).into());
+            }
+            Err(_) => {
+                tracing::debug!(lock = key, "waiting for lock");
+                tokio::select! {
+                    guard = lock.lock_owned() => guard,
+                    _ = ctx.cancellation().cancelled() => {
+                        let reason = format!("gave up waiting for '{}'", key);
+                        return Err(Busy::new(reason).retry_after(LOCK_RETRY).into());
+                    }
+                }
+            }
+        };
+        Ok(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Busy;
+    use crate::registry::{Args, Registry, LOCK_RETRY};
+    use crate::{nexus_service, Context};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    /// Volumes whose deletion is recorded, and a command that holds a
+    /// volume's lock until told to let go.
+    #[derive(Default)]
+    struct Volumes {
+        deleted: Mutex<Vec<String>>,
+        release: Notify,
+    }
+
+    struct Volume(Arc<Volumes>);
+
+    /// Volumes.
+    #[nexus_service]
+    impl Volume {
+        /// List the volumes.
+        #[command(readonly)]
+        async fn list(&self) -> anyhow::Result<String> {
+            Ok("vol1, vol2, other".to_string())
+        }
+
+        /// Delete volumes.
+        #[command(lock = "volume:{name}")]
+        async fn delete(&self, #[arg(complete = "volume.list", glob)] name: String) -> anyhow::Result<String> {
+            self.0.deleted.lock().unwrap().push(name);
+            Ok(String::new())
+        }
+
+        /// Resize a volume, unless another command has it.
+        #[command(try_lock = "volume:{name}")]
+        async fn resize(&self, name: String) -> anyhow::Result<String> {
+            Ok(name)
+        }
+
+        /// Hold a volume's lock until released.
+        #[command(lock = "volume:{name}")]
+        async fn hold(&self, name: String) -> anyhow::Result<String> {
+            let _ = name;
+            self.0.release.notified().await;
+            Ok(String::new())
+        }
+    }
+
+    fn registry() -> (Arc<Registry>, Arc<Volumes>) {
+        let volumes = Arc::new(Volumes::default());
+        let registry = Arc::new(Registry::new());
+        registry.register(Volume(volumes.clone()));
+        (registry, volumes)
+    }
+
+    fn run(registry: &Arc<Registry>, action: &'static str, arg: &str) -> tokio::task::JoinHandle<anyhow::Result<()>> {
+        let (registry, args) = (registry.clone(), Args::new(vec![arg.to_string()]));
+        tokio::spawn(async move {
+            registry.execute(&Context::new(), "volume", action, args).await?;
+            Ok(())
+        })
+    }
+
+    /// Let the spawned commands run until they wait.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn commands_wait_for_the_lock() {
+        let (registry, volumes) = registry();
+        let hold = run(&registry, "hold", "vol1");
+        settle().await;
+
+        let delete = run(&registry, "delete", "vol1");
+        let other = run(&registry, "delete", "vol2");
+        other.await.unwrap().unwrap();
+        settle().await;
+        assert_eq!(*volumes.deleted.lock().unwrap(), ["vol2"]);
+
+        volumes.release.notify_one();
+        hold.await.unwrap().unwrap();
+        delete.await.unwrap().unwrap();
+        assert_eq!(*volumes.deleted.lock().unwrap(), ["vol2", "vol1"]);
+    }
+
+    #[tokio::test]
+    async fn try_lock_commands_are_busy_instead_of_waiting() {
+        let (registry, volumes) = registry();
+        let hold = run(&registry, "hold", "vol1");
+        settle().await;
+
+        let err = run(&registry, "resize", "vol1").await.unwrap().unwrap_err();
+        let busy = err.downcast_ref::<Busy>().expect("a Busy error");
+        assert_eq!(busy.reason, "'volume:vol1' is in use by another command");
+        assert_eq!(busy.retry_after, Some(LOCK_RETRY));
+        run(&registry, "resize", "vol2").await.unwrap().unwrap();
+
+        volumes.release.notify_one();
+        hold.await.unwrap().unwrap();
+        run(&registry, "resize", "vol1").await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn globs_lock_each_match() {
+        let (registry, volumes) = registry();
+        let hold = run(&registry, "hold", "vol2");
+        settle().await;
+
+        let delete = run(&registry, "delete", "vol*");
+        settle().await;
+        assert!(volumes.deleted.lock().unwrap().is_empty());
+
+        volumes.release.notify_one();
+        hold.await.unwrap().unwrap();
+        delete.await.unwrap().unwrap();
+        assert_eq!(*volumes.deleted.lock().unwrap(), ["vol1", "vol2"]);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::proto::{ArgDef, CommandDef, ServiceInfo};
+use crate::registry::format_duration;
+use std::io::{IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// Render the long, man-page style help of one command.
+pub(crate) fn render(service: &ServiceInfo, cmd: &CommandDef) -> String {
+    let mut out = String::new();
+    let heading = |out: &mut String, title: &str| {
+        out.push_str(title);
+        out.push('\n');
+    };
+
+    let path = crate::cli::command_path(&service.name);
+    heading(&mut out, "NAME");
+    if cmd.description.is_empty() {
+        out.push_str(&format!("    {} {}\n\n", path, cmd.name));
+    } else {
+        out.push_str(&format!("    {} {} - {}\n\n", path, cmd.name, cmd.description));
+    }
+
+    heading(&mut out, "SYNOPSIS");
+    let placeholders: Vec<String> = cmd.args.iter().map(crate::cli::arg_placeholder).collect();
+    out.push_str(&format!("    {} {} {}\n\n", path, cmd.name, placeholders.join(" ")));
+
+    let (description, examples) = help_sections(cmd);
+    let section = |out: &mut String, title: &str, lines: &[&str]| {
+        let lines: Vec<&str> = trim_blank(lines);
+        if !lines.is_empty() {
+            heading(out, title);
+            for line in lines {
+                out.push_str(&format!("    {}\n", line).replace("    \n", "\n"));
+            }
+            out.push('\n');
+        }
+    };
+    section(&mut out, "DESCRIPTION", &description);
+
+    if !cmd.args.is_empty() {
+        heading(&mut out, "ARGUMENTS");
+        for arg in &cmd.args {
+            out.push_str(&format!(
+                "    {}, --{} ({})\n",
+                crate::cli::arg_placeholder(arg),
+                arg.name,
+                arg_constraints(arg).join(", ")
+            ));
+            if !arg.description.is_empty() {
+                out.push_str(&format!("        {}\n", arg.description));
+            }
+            if !arg.completer.is_empty() {
+                out.push_str(&format!("        Completions from `{}`.\n", crate::cli::completer_spec(arg)));
+            }
+        }
+        out.push('\n');
+    }
+
+    section(&mut out, "EXAMPLES", &examples);
+
+    let notes = notes(cmd);
+    let notes: Vec<&str> = notes.iter().map(String::as_str).collect();
+    section(&mut out, "NOTES", &notes);
+    out
+}
+
+/// The lines of a command's doc comment after its summary: the description
+/// and, under a `# Examples` heading, the examples, each without blank
+/// lines around it. Code fences are dropped.
+pub(crate) fn help_sections(cmd: &CommandDef) -> (Vec<&str>, Vec<&str>) {
+    let mut description = Vec::new();
+    let mut examples = Vec::new();
+    let mut in_examples = false;
+    for line in cmd.help.lines().skip_while(|l| !l.trim().is_empty()) {
+        if line.trim_start().starts_with("```") {
+            continue;
+        }
+        if let Some(title) = line.strip_prefix("# ") {
+            in_examples = title.trim().eq_ignore_ascii_case("examples");
+            continue;
+        }
+        if in_examples {
+            examples.push(line);
+        } else {
+            description.push(line);
+        }
+    }
+    (trim_blank(&description), trim_blank(&examples))
+}
+
+/// What the CLI says about a deprecated command: "deprecated: use
+/// destroy", or "deprecated" if it names no replacement.
+pub(crate) fn deprecation(cmd: &CommandDef) -> Option<String> {
+    if !cmd.deprecated {
+        return None;
+    }
+    if cmd.deprecation.is_empty() {
+        return Some("deprecated".to_string());
+    }
+    Some(format!("deprecated: {}", cmd.deprecation))
+}
+
+/// One sentence per notable property of a command: deprecation, the
+/// version it appeared in, dry-run support, streaming, running as a job,
+/// the permission it needs and its time limit.
+pub(crate) fn notes(cmd: &CommandDef) -> Vec<String> {
+    let mut notes = Vec::new();
+    if cmd.deprecated {
+        if cmd.deprecation.is_empty() {
+            notes.push("Deprecated; it may be removed in a later version.".to_string());
+        } else {
+            notes.push(format!("Deprecated: {}.", cmd.deprecation.trim_end_matches('.')));
+        }
+    }
+    if !cmd.since.is_empty() {
+        notes.push(format!("Available since version {}.", cmd.since));
+    }
+    if cmd.dry_run {
+        notes.push("Supports `preview` (dry-run).".to_string());
+    }
+    if cmd.streaming {
+        notes.push("Streams its output as it runs.".to_string());
+    }
+    if cmd.background {
+        notes.push("Runs as a background job: prints the job's ID, to follow with `job status <id>`.".to_string());
+    }
+    if cmd.interactive {
+        notes.push("Asks questions as it runs, answered in the shell.".to_string());
+    }
+    if !cmd.permission.is_empty() {
+        notes.push(format!("Requires permission '{}'.", cmd.permission));
+    }
+    if cmd.timeout_ms > 0 {
+        let limit = format_duration(Duration::from_millis(cmd.timeout_ms));
+        notes.push(format!("Times out after {}.", limit));
+    }
+    notes
+}
+
+/// What an argument accepts: its type, whether it is required (or its
+/// default), its choices and glob support.
+pub(crate) fn arg_constraints(arg: &ArgDef) -> Vec<String> {
+    let mut constraints = Vec::new();
+    if !arg.type_name.is_empty() {
+        constraints.push(arg.type_name.clone());
+    }
+    if arg.variadic {
+        constraints.push("one or more".to_string());
+    } else if !arg.default_value.is_empty() {
+        constraints.push(format!("default {}", arg.default_value));
+    } else if arg.optional {
+        constraints.push("optional".to_string());
+    } else {
+        constraints.push("required".to_string());
+    }
+    if !arg.choices.is_empty() {
+        constraints.push(format!("one of {}", arg.choices.join("|")));
+    }
+    if arg.glob {
+        constraints.push("accepts glob patterns".to_string());
+    }
+    constraints
+}
+
+/// Drop leading and trailing blank lines.
+fn trim_blank<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    let start = lines.iter().position(|l| !l.trim().is_empty());
+    let end = lines.iter().rposition(|l| !l.trim().is_empty());
+    match (start, end) {
+        (Some(start), Some(end)) => lines[start..=end].to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// Show `text` in `$PAGER` (default `less`) when stdout is a terminal,
+/// otherwise (or if the pager cannot start) print it directly.
+pub(crate) fn page(text: &str) {
+    if std::io::stdout().is_terminal() {
+        let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+        let mut words = pager.split_whitespace();
+        if let Some(program) = words.next() {
+            let child = Command::new(program)
+                .args(words)
+                .stdin(Stdio::piped())
+                .spawn();
+            if let Ok(mut child) = child {
+                if let Some(mut stdin) = child.stdin.take() {
+                    let _ = stdin.write_all(text.as_bytes());
+                }
+                let _ = child.wait();
+                return;
+            }
+        }
+    }
+    print!("{}", text);
+}
+
+/// Print `text`, through the pager when it does not fit on the terminal.
+pub(crate) fn page_long(text: &str) {
+    match terminal_rows() {
+        Some(rows) if text.lines().count() >= rows => page(text),
+        _ => print!("{}", text),
+    }
+}
+
+/// Height of the terminal on stdout, if it is one.
+#[cfg(unix)]
+fn terminal_rows() -> Option<usize> {
+    use std::os::fd::AsRawFd;
+
+    nix::ioctl_read_bad!(window_size, nix::libc::TIOCGWINSZ, nix::pty::Winsize);
+    let stdout = std::io::stdout();
+    if !stdout.is_terminal() {
+        return None;
+    }
+    let mut size = nix::pty::Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer.
+    unsafe { window_size(stdout.as_raw_fd(), &mut size) }.ok()?;
+    (size.ws_row > 0).then_some(usize::from(size.ws_row))
+}
+
+#[cfg(not(unix))]
+fn terminal_rows() -> Option<usize> {
+    None
+}
@@ -0,0 +1,237 @@
+//! Model Context Protocol server (`NexusServer::with_mcp`): the commands as
+//! MCP tools, so AI assistants can list and run them. It speaks JSON-RPC
+//! over the protocol's Streamable HTTP transport at `POST /mcp`, answering
+//! each request with a JSON body.
+//!
+//! Each command is a tool named like its OpenAPI operation
+//! (`volume_snapshot_create`), described by its doc comment and taking its
+//! arguments as a JSON object with the gateway's schema. Calls go through
+//! the gateway's authentication and the same `Execute` handler as gRPC, so
+//! `authorization` and `nexus-*` headers apply, and each caller only sees
+//! the services visible to its tenant.
+
+use crate::cli::{command_path, response_output};
+use crate::gateway::{json_args, Gateway};
+use crate::manual;
+use crate::openapi::{arguments_schema, operation_id};
+use crate::proto::nexus_service_server::NexusService;
+use crate::proto::{CommandDef, CommandRequest, CommandResponse, ListServicesRequest, ServiceInfo};
+use crate::registry::CommandOutput;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{HeaderValue, ALLOW, CONTENT_TYPE};
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Map, Value};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Largest request body accepted, as for the HTTP gateway.
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Protocol revisions understood, newest first; a client asking for another
+/// one is offered the newest.
+const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// A JSON-RPC error: its code and message.
+type RpcError = (i64, String);
+
+/// Serve MCP on `listener` until the task is dropped.
+pub(crate) async fn serve(listener: TcpListener, gateway: Gateway) {
+    let gateway = Arc::new(gateway);
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let gateway = gateway.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |request| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(handle(&gateway, request).await) }
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn handle(gateway: &Gateway, request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let (parts, body) = request.into_parts();
+    if parts.uri.path() != "/mcp" {
+        return status(StatusCode::NOT_FOUND);
+    }
+    // No stream of server-initiated messages is offered, so only POST.
+    if parts.method != Method::POST {
+        let mut response = status(StatusCode::METHOD_NOT_ALLOWED);
+        response.headers_mut().insert(ALLOW, HeaderValue::from_static("POST"));
+        return response;
+    }
+    let body = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(_) => return status(StatusCode::PAYLOAD_TOO_LARGE),
+    };
+    let message: Value = match serde_json::from_slice(&body) {
+        Ok(message) => message,
+        Err(e) => return reply(error(Value::Null, (PARSE_ERROR, e.to_string()))),
+    };
+    let Some(method) = message["method"].as_str() else {
+        return reply(error(message["id"].clone(), (INVALID_REQUEST, "expected a request".to_string())));
+    };
+    // Notifications (and responses to requests the server never sends)
+    // need no answer.
+    let Some(id) = message.get("id").cloned() else {
+        return status(StatusCode::ACCEPTED);
+    };
+    tracing::debug!(method, "MCP request");
+    match gateway.rpc(parts.headers, method, &message["params"]).await {
+        Ok(result) => reply(json!({ "jsonrpc": "2.0", "id": id, "result": result })),
+        Err(e) => reply(error(id, e)),
+    }
+}
+
+impl Gateway {
+    /// The result of the JSON-RPC request `method` with `params`.
+    async fn rpc(&self, headers: HeaderMap, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str();
+                let version = PROTOCOL_VERSIONS
+                    .into_iter()
+                    .find(|version| Some(*version) == requested)
+                    .unwrap_or(PROTOCOL_VERSIONS[0]);
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": { "listChanged": false } },
+                    "serverInfo": { "name": "nexus", "version": env!("CARGO_PKG_VERSION") },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => {
+                let tools: Vec<Value> = self
+                    .services(headers)
+                    .await?
+                    .iter()
+                    .flat_map(|service| service.commands.iter().map(move |command| tool(service, command)))
+                    .collect();
+                Ok(json!({ "tools": tools }))
+            }
+            "tools/call" => {
+                let Some(name) = params["name"].as_str() else {
+                    return Err((INVALID_PARAMS, "missing tool name".to_string()));
+                };
+                let arguments = match &params["arguments"] {
+                    Value::Null => Map::new(),
+                    Value::Object(arguments) => arguments.clone(),
+                    _ => return Err((INVALID_PARAMS, "arguments must be an object".to_string())),
+                };
+                self.call(headers, name, arguments).await
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{}'", method))),
+        }
+    }
+
+    /// The services visible to the caller.
+    async fn services(&self, headers: HeaderMap) -> Result<Vec<ServiceInfo>, RpcError> {
+        let request = self.authorize(headers, ListServicesRequest::new()).map_err(|e| (INVALID_REQUEST, e))?;
+        match self.grpc.list_services(request).await {
+            Ok(response) => Ok(response.into_inner().services),
+            Err(status) => Err((INTERNAL_ERROR, status.message().to_string())),
+        }
+    }
+
+    /// Run the command of the tool `name`. A command that fails is a result
+    /// flagged `isError`, for the assistant to read, not a JSON-RPC error.
+    async fn call(&self, headers: HeaderMap, name: &str, arguments: Map<String, Value>) -> Result<Value, RpcError> {
+        let services = self.services(headers.clone()).await?;
+        let Some((service, command)) = services
+            .iter()
+            .flat_map(|service| service.commands.iter().map(move |command| (service, command)))
+            .find(|(service, command)| operation_id(service, command) == name)
+        else {
+            return Err((INVALID_PARAMS, format!("unknown tool '{}'", name)));
+        };
+        let (args, named_args) = json_args(arguments).map_err(|e| (INVALID_PARAMS, e.to_string()))?;
+        let command = CommandRequest {
+            service: service.name.clone(),
+            action: command.name.clone(),
+            args,
+            named_args,
+            ..Default::default()
+        };
+        let request = self.authorize(headers, command).map_err(|e| (INVALID_REQUEST, e))?;
+        match self.grpc.execute(request).await {
+            Ok(response) => Ok(tool_result(&response.into_inner())),
+            Err(status) => Err((INTERNAL_ERROR, status.message().to_string())),
+        }
+    }
+}
+
+/// The MCP tool for `command` of `service`.
+fn tool(service: &ServiceInfo, command: &CommandDef) -> Value {
+    let mut description = if command.help.is_empty() {
+        command.description.clone()
+    } else {
+        command.help.clone()
+    };
+    if !command.permission.is_empty() {
+        description.push_str(&format!("\n\nRequires the '{}' permission.", command.permission));
+    }
+    if command.background {
+        description.push_str("\n\nRuns as a background job; the result names its ID.");
+    }
+    if let Some(deprecation) = manual::deprecation(command) {
+        description.push_str(&format!("\n\nThis command is {}.", deprecation));
+    }
+    json!({
+        "name": operation_id(service, command),
+        "title": format!("{} {}", command_path(&service.name), command.name),
+        "description": description,
+        "inputSchema": arguments_schema(command),
+        "annotations": { "readOnlyHint": command.readonly },
+    })
+}
+
+/// A `tools/call` result: the output as text (with any warnings), and
+/// JSON and table output also as structured content.
+fn tool_result(response: &CommandResponse) -> Value {
+    if !response.success {
+        return json!({ "content": [{ "type": "text", "text": response.message }], "isError": true });
+    }
+    let output = response_output(response);
+    let mut content = vec![json!({ "type": "text", "text": output.to_plain() })];
+    for warning in &response.warnings {
+        content.push(json!({ "type": "text", "text": format!("Warning: {}", warning) }));
+    }
+    let mut result = json!({ "content": content, "isError": false });
+    if !matches!(output, CommandOutput::Text(_)) {
+        result["structuredContent"] = json!({ "output": output.to_json() });
+    }
+    result
+}
+
+fn error(id: Value, (code, message): RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn reply(body: Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn status(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
@@ -0,0 +1,141 @@
+//! Per-command execution metrics, served in the Prometheus text format over
+//! HTTP (`NexusServer::with_metrics`) and summed up by `admin stats`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default, Clone)]
+pub(crate) struct CommandStats {
+    pub(crate) count: u64,
+    pub(crate) errors: u64,
+    /// Observations per bucket (not cumulative); the last slot is `+Inf`.
+    buckets: [u64; BUCKETS.len() + 1],
+    /// Total execution time.
+    pub(crate) seconds: f64,
+}
+
+/// Invocation counts, errors and latencies keyed by (service, command).
+#[derive(Default)]
+pub(crate) struct Metrics {
+    commands: Mutex<BTreeMap<(String, String), CommandStats>>,
+}
+
+impl Metrics {
+    pub(crate) fn record(&self, service: &str, command: &str, elapsed: Duration, success: bool) {
+        let mut commands = self.commands.lock().unwrap();
+        let stats = commands
+            .entry((service.to_string(), command.to_string()))
+            .or_default();
+        let seconds = elapsed.as_secs_f64();
+        stats.count += 1;
+        if !success {
+            stats.errors += 1;
+        }
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        stats.buckets[bucket] += 1;
+        stats.seconds += seconds;
+    }
+
+    /// The statistics of every command run so far, by service and command.
+    pub(crate) fn totals(&self) -> Vec<((String, String), CommandStats)> {
+        let commands = self.commands.lock().unwrap();
+        commands.iter().map(|(key, stats)| (key.clone(), stats.clone())).collect()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub(crate) fn render(&self) -> String {
+        let commands = self.commands.lock().unwrap();
+        let mut out = String::new();
+        let labels = |(service, command): &(String, String)| {
+            format!("service=\"{}\",command=\"{}\"", escape(service), escape(command))
+        };
+
+        out.push_str("# HELP nexus_commands_total Commands executed.\n");
+        out.push_str("# TYPE nexus_commands_total counter\n");
+        for (key, stats) in commands.iter() {
+            let _ = writeln!(out, "nexus_commands_total{{{}}} {}", labels(key), stats.count);
+        }
+
+        out.push_str("# HELP nexus_command_errors_total Commands that returned an error.\n");
+        out.push_str("# TYPE nexus_command_errors_total counter\n");
+        for (key, stats) in commands.iter() {
+            let _ = writeln!(out, "nexus_command_errors_total{{{}}} {}", labels(key), stats.errors);
+        }
+
+        out.push_str("# HELP nexus_command_duration_seconds Command execution time.\n");
+        out.push_str("# TYPE nexus_command_duration_seconds histogram\n");
+        for (key, stats) in commands.iter() {
+            let labels = labels(key);
+            let mut cumulative = 0;
+            for (i, count) in stats.buckets.iter().enumerate() {
+                cumulative += count;
+                let le = BUCKETS
+                    .get(i)
+                    .map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "nexus_command_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                );
+            }
+            let _ = writeln!(out, "nexus_command_duration_seconds_sum{{{}}} {}", labels, stats.seconds);
+            let _ = writeln!(out, "nexus_command_duration_seconds_count{{{}}} {}", labels, stats.count);
+        }
+        out
+    }
+}
+
+/// Escape a Prometheus label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve `GET /metrics` on `listener` until the task is dropped.
+pub(crate) async fn serve(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // Only the request line matters; read until the end of the headers.
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 8192 {
+                match socket.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+            let request = String::from_utf8_lossy(&request);
+            let mut words = request.split_whitespace();
+            let response = match (words.next(), words.next()) {
+                (Some("GET"), Some("/metrics")) => {
+                    let body = metrics.render();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.shutdown().await;
+        });
+    }
+}
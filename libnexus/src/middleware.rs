@@ -0,0 +1,33 @@
+//! Request middleware: code the server runs before every command, to reject
+//! requests or attach values (`Context::with_extension`) that commands read
+//! back with `ctx.extension(key)`.
+
+use crate::context::Context;
+use tonic::metadata::MetadataMap;
+
+/// Runs before every command request, after authentication; installed with
+/// `NexusServer::with_middleware`, in order.
+///
+/// ```ignore
+/// server.with_middleware(|metadata: &MetadataMap, ctx: &mut Context| {
+///     if let Some(ticket) = metadata.get("x-change-ticket").and_then(|v| v.to_str().ok()) {
+///         ctx.extensions.insert("ticket".into(), ticket.into());
+///     }
+///     Ok(())
+/// })
+/// ```
+pub trait Middleware: Send + Sync + 'static {
+    /// Inspect the request's metadata and context. An error rejects the
+    /// request: the client receives it as the command's result, classified
+    /// like command errors (return a `NexusError` to pick the code).
+    fn on_request(&self, metadata: &MetadataMap, ctx: &mut Context) -> anyhow::Result<()>;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&MetadataMap, &mut Context) -> anyhow::Result<()> + Send + Sync + 'static,
+{
+    fn on_request(&self, metadata: &MetadataMap, ctx: &mut Context) -> anyhow::Result<()> {
+        self(metadata, ctx)
+    }
+}
@@ -0,0 +1,308 @@
+//! OpenAPI 3 description of the HTTP gateway, generated from the service
+//! metadata (`GET /api/openapi.json`, or `describe --openapi` in the CLI).
+
+use crate::proto::{ArgDef, CommandDef, ServiceInfo};
+use serde_json::{json, Map, Value};
+
+/// The OpenAPI document for `services` as exposed by the HTTP gateway.
+pub(crate) fn document(services: &[ServiceInfo]) -> Value {
+    let mut paths = Map::new();
+    for service in services {
+        for command in &service.commands {
+            paths.insert(
+                format!("/api/{}/{}", service.name.replace('.', "/"), command.name),
+                json!({ "post": operation(service, command) }),
+            );
+        }
+    }
+    add_job_paths(&mut paths);
+    let tags: Vec<Value> = services
+        .iter()
+        .map(|service| json!({ "name": service.name, "description": service.description }))
+        .collect();
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Nexus API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "tags": tags,
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer" },
+            },
+            "schemas": {
+                "CommandResult": {
+                    "type": "object",
+                    "required": ["success", "output", "warnings", "plan"],
+                    "properties": {
+                        "success": { "type": "boolean" },
+                        "output": {
+                            "description": "Text as a string, JSON as is, tables as arrays of objects keyed by header, bytes as base64.",
+                        },
+                        "warnings": { "type": "array", "items": { "type": "string" } },
+                        "plan": {
+                            "type": "array",
+                            "description": "Changes a dry run would make.",
+                            "items": { "$ref": "#/components/schemas/Change" },
+                        },
+                        "job_id": {
+                            "type": "integer",
+                            "description": "ID of the job a background command was started as.",
+                        },
+                    },
+                },
+                "Job": {
+                    "type": "object",
+                    "required": ["id", "service", "action", "args", "state", "started_at_ms", "elapsed_ms"],
+                    "properties": {
+                        "id": { "type": "integer" },
+                        "service": { "type": "string" },
+                        "action": { "type": "string" },
+                        "args": { "type": "string", "description": "Bound arguments as `name=value` pairs, secrets redacted." },
+                        "state": { "type": "string", "enum": ["running", "succeeded", "failed", "cancelled"] },
+                        "started_at_ms": { "type": "integer" },
+                        "elapsed_ms": { "type": "integer" },
+                        "result": {
+                            "description": "Once finished: the command's result or error.",
+                            "oneOf": [
+                                { "$ref": "#/components/schemas/CommandResult" },
+                                { "$ref": "#/components/schemas/Error" },
+                            ],
+                        },
+                    },
+                },
+                "Change": {
+                    "type": "object",
+                    "properties": {
+                        "resource": { "type": "string" },
+                        "before": { "type": "string" },
+                        "after": { "type": "string" },
+                    },
+                },
+                "Error": {
+                    "type": "object",
+                    "required": ["success", "error"],
+                    "properties": {
+                        "success": { "type": "boolean" },
+                        "error": { "type": "string" },
+                        "code": {
+                            "type": "string",
+                            "enum": [
+                                "not_found", "invalid_argument", "permission_denied", "internal", "busy", "timeout",
+                                "resource_exhausted", "unavailable",
+                            ],
+                        },
+                        "details": { "type": "object", "additionalProperties": { "type": "string" } },
+                        "warnings": { "type": "array", "items": { "type": "string" } },
+                        "busy": { "type": "boolean" },
+                        "retry_after_ms": { "type": "integer" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// JSON schema of the object of named arguments `command` takes, as sent
+/// to the HTTP gateway and to MCP tools.
+pub(crate) fn arguments_schema(command: &CommandDef) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for arg in &command.args {
+        properties.insert(arg.name.clone(), arg_schema(arg));
+        if !arg.optional {
+            required.push(arg.name.clone());
+        }
+    }
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+fn operation(service: &ServiceInfo, command: &CommandDef) -> Value {
+    let body = arguments_schema(command);
+    let required = command.args.iter().any(|arg| !arg.optional);
+
+    let mut parameters = vec![json!({
+        "name": "priority",
+        "in": "query",
+        "schema": { "type": "string", "enum": ["low", "normal", "high"] },
+    })];
+    if command.dry_run {
+        parameters.push(json!({
+            "name": "dry_run",
+            "in": "query",
+            "description": "Report the changes the command would make without applying them.",
+            "schema": { "type": "boolean" },
+        }));
+    }
+
+    let error = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+        })
+    };
+    let mut op = json!({
+        "operationId": operation_id(service, command),
+        "tags": [service.name],
+        "summary": command.description,
+        "parameters": parameters,
+        "requestBody": {
+            "required": required,
+            "content": { "application/json": { "schema": body } },
+        },
+        "responses": {
+            "200": {
+                "description": "The command succeeded.",
+                "content": { "application/json": { "schema": { "$ref": "#/components/schemas/CommandResult" } } },
+            },
+            "400": error("The command failed or the request was invalid."),
+            "401": error("The token is malformed or invalid."),
+            "404": error("The service, command or an object it refers to does not exist."),
+            "429": error("The client sent too many requests; retry after `Retry-After` seconds."),
+            "503": error("The server is busy; retry after `Retry-After` seconds."),
+            "504": error("The command did not finish within its time limit."),
+        },
+    });
+    if command.help != command.description && !command.help.is_empty() {
+        op["description"] = json!(command.help);
+    }
+    if !command.permission.is_empty() {
+        op["security"] = json!([{ "bearerAuth": [] }]);
+        op["x-nexus-permission"] = json!(command.permission);
+        op["responses"]["403"] = error("The caller's role lacks the command's permission.");
+    }
+    if command.streaming {
+        op["x-nexus-streaming"] = json!(true);
+    }
+    if command.deprecated {
+        op["deprecated"] = json!(true);
+    }
+    if !command.since.is_empty() {
+        op["x-nexus-since"] = json!(command.since);
+    }
+    if command.background {
+        op["x-nexus-background"] = json!(true);
+        let responses = op["responses"].as_object_mut().expect("responses is an object");
+        responses.remove("200");
+        responses.insert(
+            "202".into(),
+            json!({
+                "description": "The command was started as a background job; follow it at `/api/jobs/{job_id}`.",
+                "content": { "application/json": { "schema": { "$ref": "#/components/schemas/CommandResult" } } },
+            }),
+        );
+    }
+    op
+}
+
+/// A command's name in generated APIs: `volume_snapshot_create` for
+/// `volume snapshot create`.
+pub(crate) fn operation_id(service: &ServiceInfo, command: &CommandDef) -> String {
+    format!("{}_{}", service.name.replace('.', "_"), command.name)
+}
+
+/// `GET /api/jobs`, `GET /api/jobs/{id}` and `DELETE /api/jobs/{id}`.
+fn add_job_paths(paths: &mut Map<String, Value>) {
+    let job = json!({
+        "description": "The job.",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Job" } } },
+    });
+    let not_found = json!({
+        "description": "There is no such job.",
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+    });
+    let id = json!([{ "name": "id", "in": "path", "required": true, "schema": { "type": "integer" } }]);
+    paths.insert(
+        "/api/jobs".into(),
+        json!({
+            "get": {
+                "operationId": "jobs_list",
+                "tags": ["jobs"],
+                "summary": "List the background jobs started by the caller's tenant.",
+                "responses": {
+                    "200": {
+                        "description": "The jobs, oldest first.",
+                        "content": { "application/json": { "schema": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/Job" },
+                        } } },
+                    },
+                },
+            },
+        }),
+    );
+    paths.insert(
+        "/api/jobs/{id}".into(),
+        json!({
+            "parameters": id,
+            "get": {
+                "operationId": "jobs_status",
+                "tags": ["jobs"],
+                "summary": "Report a job's state and, once it has finished, its result.",
+                "responses": { "200": job, "404": not_found },
+            },
+            "delete": {
+                "operationId": "jobs_cancel",
+                "tags": ["jobs"],
+                "summary": "Cancel a running job; a finished job is returned unchanged.",
+                "responses": { "200": job, "404": not_found },
+            },
+        }),
+    );
+}
+
+/// JSON schema of an argument, from its declared Rust type.
+fn arg_schema(arg: &ArgDef) -> Value {
+    let mut schema = type_schema(&arg.type_name);
+    if !arg.description.is_empty() {
+        schema["description"] = json!(arg.description);
+    }
+    if !arg.default_value.is_empty() {
+        schema["default"] = scalar(&schema, &arg.default_value);
+    }
+    if !arg.choices.is_empty() {
+        let target = if arg.variadic { &mut schema["items"] } else { &mut schema };
+        target["enum"] = json!(arg.choices);
+    }
+    schema
+}
+
+fn type_schema(type_name: &str) -> Value {
+    if let Some(inner) = strip_generic(type_name, "Option") {
+        return type_schema(inner);
+    }
+    if let Some(inner) = strip_generic(type_name, "Vec") {
+        return json!({ "type": "array", "items": type_schema(inner), "minItems": 1 });
+    }
+    match type_name {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => json!({ "type": "integer", "minimum": 0 }),
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => json!({ "type": "integer" }),
+        "f32" | "f64" => json!({ "type": "number" }),
+        "bool" => json!({ "type": "boolean" }),
+        _ => json!({ "type": "string" }),
+    }
+}
+
+/// `T` from `wrapper<T>`, also for paths such as `std::option::Option<T>`.
+pub(crate) fn strip_generic<'a>(type_name: &'a str, wrapper: &str) -> Option<&'a str> {
+    let (path, rest) = type_name.split_once('<')?;
+    if path.rsplit("::").next() != Some(wrapper) {
+        return None;
+    }
+    rest.strip_suffix('>')
+}
+
+/// A default value as JSON of the schema's type, falling back to the string.
+fn scalar(schema: &Value, value: &str) -> Value {
+    let parsed = match schema["type"].as_str() {
+        Some("integer" | "number" | "boolean") => serde_json::from_str(value).ok(),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| json!(value))
+}
@@ -0,0 +1,41 @@
+/// A single change a command would make, reported while running in dry-run
+/// mode so the CLI can preview it before applying.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// What is being changed (e.g. "volume/vol0").
+    pub resource: String,
+    /// Current state; `None` when the resource would be created.
+    pub before: Option<String>,
+    /// Proposed state; `None` when the resource would be removed.
+    pub after: Option<String>,
+}
+
+impl Change {
+    pub fn create(resource: impl Into<String>, after: impl Into<String>) -> Self {
+        Self {
+            resource: resource.into(),
+            before: None,
+            after: Some(after.into()),
+        }
+    }
+
+    pub fn update(
+        resource: impl Into<String>,
+        before: impl Into<String>,
+        after: impl Into<String>,
+    ) -> Self {
+        Self {
+            resource: resource.into(),
+            before: Some(before.into()),
+            after: Some(after.into()),
+        }
+    }
+
+    pub fn delete(resource: impl Into<String>, before: impl Into<String>) -> Self {
+        Self {
+            resource: resource.into(),
+            before: Some(before.into()),
+            after: None,
+        }
+    }
+}
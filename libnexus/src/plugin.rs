@@ -0,0 +1,377 @@
+//! Plugins: services built as shared libraries and loaded into a running
+//! daemon (`NexusServer::with_plugin_dir`, `plugin load <path>`), so it can be
+//! extended without recompiling.
+//!
+//! A plugin is a `cdylib` crate declaring its services with `nexus_plugin!`:
+//!
+//! ```ignore
+//! // Cargo.toml: [lib] crate-type = ["cdylib"]
+//! libnexus::nexus_plugin!(Snapshot, Replication::new("/srv/replicas"));
+//! ```
+//!
+//! Rust has no stable ABI, so a plugin must be built by the same compiler
+//! against the same libnexus as the daemon; loading checks both. Build it in
+//! the daemon's workspace so shared dependencies resolve to the same versions
+//! as well, and keep the default global allocator on both sides.
+//!
+//! A plugin carries its own copy of tokio. Its commands run on a runtime of
+//! their own inside the plugin, so `tokio::spawn`, timers and I/O work there
+//! as usual.
+
+use crate::context::Context;
+use crate::health::Health;
+use crate::registry::{Args, CommandInfo, CommandOutput, RegistryHandle, Service, StartContext};
+use crate::stream::CommandStream;
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use nexus_derive::nexus_service;
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+/// Compiler that built this copy of libnexus.
+pub const RUSTC_VERSION: &str = env!("NEXUS_RUSTC_VERSION");
+/// Version of this copy of libnexus.
+pub const NEXUS_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Name of the symbol `nexus_plugin!` exports.
+const DECLARATION_SYMBOL: &CStr = c"NEXUS_PLUGIN";
+
+/// What `nexus_plugin!` exports: the versions the plugin was built with and
+/// its registration function.
+#[doc(hidden)]
+#[repr(C)]
+pub struct PluginDeclaration {
+    pub rustc_version: &'static str,
+    pub nexus_version: &'static str,
+    pub register: fn(&mut PluginRegistrar),
+}
+
+/// Collects the services of a plugin while it registers them.
+pub struct PluginRegistrar {
+    services: Vec<Arc<dyn Service>>,
+}
+
+impl PluginRegistrar {
+    /// Add `service` to the daemon. Its commands run on the plugin's runtime.
+    pub fn add_service<S: Service>(&mut self, service: S) {
+        self.services.push(Arc::new(PluginService(Arc::new(service))));
+    }
+}
+
+/// Declare the services a plugin library provides, e.g.
+/// `nexus_plugin!(Snapshot, Replication::default());`. Each expression is
+/// evaluated when the plugin is loaded.
+#[macro_export]
+macro_rules! nexus_plugin {
+    ($($service:expr),+ $(,)?) => {
+        #[no_mangle]
+        pub static NEXUS_PLUGIN: $crate::plugin::PluginDeclaration = $crate::plugin::PluginDeclaration {
+            rustc_version: $crate::plugin::RUSTC_VERSION,
+            nexus_version: $crate::plugin::NEXUS_VERSION,
+            register: {
+                fn register(registrar: &mut $crate::plugin::PluginRegistrar) {
+                    $(registrar.add_service($service);)+
+                }
+                register
+            },
+        };
+    };
+}
+
+/// The runtime plugin commands run on. Inside a plugin this is the plugin's
+/// own copy, started on first use.
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .thread_name("nexus-plugin")
+            .enable_all()
+            .build()
+            .expect("failed to start the plugin runtime")
+    })
+}
+
+/// Hand the changes and warnings a command recorded on `detached` back to
+/// the request's context.
+fn reattach(ctx: &Context, detached: Context) {
+    for change in detached.take_plan() {
+        ctx.propose(change);
+    }
+    for warning in detached.take_warnings() {
+        ctx.warn(warning);
+    }
+}
+
+/// A plugin's service, running every call on the plugin runtime.
+struct PluginService<S>(Arc<S>);
+
+#[async_trait]
+impl<S: Service> Service for PluginService<S> {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn aliases(&self) -> &[&str] {
+        self.0.aliases()
+    }
+
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+
+    fn commands(&self) -> Vec<CommandInfo> {
+        self.0.commands()
+    }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        self.0.max_concurrency()
+    }
+
+    fn max_queue(&self) -> Option<usize> {
+        self.0.max_queue()
+    }
+
+    async fn execute(&self, ctx: &Context, action: &str, args: Args) -> Result<CommandOutput> {
+        let (service, action, detached) = (self.0.clone(), action.to_string(), ctx.detached());
+        let (output, detached) = runtime()
+            .spawn(async move {
+                let output = service.execute(&detached, &action, args).await;
+                (output, detached)
+            })
+            .await
+            .context("plugin command panicked")?;
+        reattach(ctx, detached);
+        output
+    }
+
+    async fn execute_stream(
+        &self,
+        ctx: &Context,
+        action: &str,
+        args: Args,
+    ) -> Option<Result<CommandStream>> {
+        let (service, action, detached) = (self.0.clone(), action.to_string(), ctx.detached());
+        let (started_tx, started_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::channel(16);
+        runtime().spawn(async move {
+            let started = service.execute_stream(&detached, &action, args).await;
+            let mut stream = match started {
+                Some(Ok(stream)) => stream,
+                other => {
+                    let _ = started_tx.send((other.map(|r| r.map(|_| ())), detached));
+                    return;
+                }
+            };
+            let _ = started_tx.send((Some(Ok(())), detached));
+            // Forward lines until the stream ends or the caller drops it.
+            loop {
+                tokio::select! {
+                    line = stream.next() => match line {
+                        Some(line) => {
+                            if tx.send(line).await.is_err() {
+                                return;
+                            }
+                        }
+                        None => return,
+                    },
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+        let Ok((started, detached)) = started_rx.await else {
+            return Some(Err(anyhow::anyhow!("plugin command panicked")));
+        };
+        reattach(ctx, detached);
+        match started? {
+            Ok(()) => Some(Ok(Box::pin(ReceiverStream::new(rx)))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    async fn health(&self) -> Health {
+        let service = self.0.clone();
+        runtime()
+            .spawn(async move { service.health().await })
+            .await
+            .unwrap_or_else(|_| Health::not_serving("health check panicked"))
+    }
+
+    async fn rollback(&self, ctx: &Context, action: &str, args: Args) -> Result<()> {
+        let (service, action, detached) = (self.0.clone(), action.to_string(), ctx.detached());
+        runtime()
+            .spawn(async move { service.rollback(&detached, &action, args).await })
+            .await
+            .context("rollback hook panicked")?
+    }
+
+    async fn on_start(&self, ctx: &StartContext) -> Result<()> {
+        let (service, ctx) = (self.0.clone(), ctx.clone());
+        runtime()
+            .spawn(async move { service.on_start(&ctx).await })
+            .await
+            .context("start hook panicked")?
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        let service = self.0.clone();
+        runtime()
+            .spawn(async move { service.on_stop().await })
+            .await
+            .context("stop hook panicked")?
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        let service = self.0.clone();
+        runtime()
+            .spawn(async move { service.shutdown().await })
+            .await
+            .context("shutdown hook panicked")?
+    }
+}
+
+/// The message of the last `dlopen`/`dlsym` failure.
+fn dl_error() -> String {
+    // SAFETY: dlerror returns null or a NUL-terminated string that stays
+    // valid until the next dl* call on this thread.
+    let message = unsafe { nix::libc::dlerror() };
+    if message.is_null() {
+        return "unknown error".to_string();
+    }
+    unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned()
+}
+
+/// Load the plugin library at `path` and return the services it declares.
+/// The library stays loaded for the life of the process: its services'
+/// code lives in it.
+fn open(path: &Path) -> Result<Vec<Arc<dyn Service>>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("invalid plugin path {}", path.display()))?;
+    // SAFETY: loading a library runs its initializers; plugins are trusted
+    // code, as much as the daemon itself.
+    let library = unsafe { nix::libc::dlopen(c_path.as_ptr(), nix::libc::RTLD_NOW | nix::libc::RTLD_LOCAL) };
+    if library.is_null() {
+        // The message names the file.
+        anyhow::bail!("failed to load plugin: {}", dl_error());
+    }
+    // SAFETY: `library` is a live handle from dlopen.
+    let symbol = unsafe { nix::libc::dlsym(library, DECLARATION_SYMBOL.as_ptr()) };
+    if symbol.is_null() {
+        // SAFETY: nothing from the library is in use.
+        unsafe { nix::libc::dlclose(library) };
+        anyhow::bail!("{} is not a nexus plugin (no NEXUS_PLUGIN symbol)", path.display());
+    }
+    // SAFETY: `nexus_plugin!` defines NEXUS_PLUGIN as a PluginDeclaration,
+    // whose leading version fields are laid out alike across releases.
+    let declaration = unsafe { &*(symbol as *const PluginDeclaration) };
+    if declaration.rustc_version != RUSTC_VERSION || declaration.nexus_version != NEXUS_VERSION {
+        let built = format!("{}, libnexus {}", declaration.rustc_version, declaration.nexus_version);
+        // SAFETY: nothing from the library is in use.
+        unsafe { nix::libc::dlclose(library) };
+        anyhow::bail!(
+            "plugin {} was built with {}, but the daemon with {}, libnexus {}",
+            path.display(),
+            built,
+            RUSTC_VERSION,
+            NEXUS_VERSION
+        );
+    }
+    let mut registrar = PluginRegistrar { services: Vec::new() };
+    (declaration.register)(&mut registrar);
+    Ok(registrar.services)
+}
+
+/// A plugin that has been loaded, and the services it added.
+struct Loaded {
+    path: PathBuf,
+    services: Vec<String>,
+}
+
+/// The plugins of a server: those in its plugin directory, loaded at
+/// startup, and any loaded later with `plugin load`.
+pub(crate) struct Plugins {
+    handle: RegistryHandle,
+    dir: PathBuf,
+    loaded: Mutex<Vec<Loaded>>,
+}
+
+impl Plugins {
+    pub(crate) fn new(handle: RegistryHandle, dir: PathBuf) -> Self {
+        Self {
+            handle,
+            dir,
+            loaded: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Load the plugin at `path` (relative paths are taken from the plugin
+    /// directory) and register its services, replacing services of the same
+    /// name. Returns the names of the services added.
+    pub(crate) fn load(&self, path: &Path) -> Result<Vec<String>> {
+        let path = self.dir.join(path);
+        let services = open(&path)?;
+        let names: Vec<String> = services.iter().map(|s| s.name().to_string()).collect();
+        for service in services {
+            self.handle.registry.insert(service);
+        }
+        tracing::info!(plugin = %path.display(), services = ?names, "plugin loaded");
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.retain(|plugin| plugin.path != path);
+        loaded.push(Loaded {
+            path,
+            services: names.clone(),
+        });
+        Ok(names)
+    }
+
+    /// Load every `.so` file in the plugin directory, in name order. A
+    /// plugin that fails to load is reported and does not stop the others;
+    /// a missing directory means no plugins.
+    pub(crate) fn load_dir(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "so"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            match self.load(&path) {
+                Ok(services) => println!("Loaded plugin {} ({})", path.display(), services.join(", ")),
+                Err(e) => eprintln!("Error: {:#}", e),
+            }
+        }
+    }
+}
+
+/// Admin commands for the server's plugins.
+pub(crate) struct PluginAdmin(pub(crate) Arc<Plugins>);
+
+/// Load plugins into the running daemon.
+#[nexus_service(name = "plugin")]
+impl PluginAdmin {
+    /// Load a plugin library and register its services.
+    #[command(permission = "admin")]
+    async fn load(
+        &self,
+        #[arg(doc = "Plugin library (.so), relative to the plugin directory")] path: String,
+    ) -> Result<String> {
+        let services = self.0.load(Path::new(&path))?;
+        Ok(format!("Loaded {} ({})", path, services.join(", ")))
+    }
+
+    /// List the loaded plugins and their services.
+    #[command]
+    async fn list(&self) -> Result<CommandOutput> {
+        let loaded = self.0.loaded.lock().unwrap();
+        let rows = loaded
+            .iter()
+            .map(|plugin| [plugin.path.display().to_string(), plugin.services.join(", ")]);
+        Ok(CommandOutput::table(["plugin", "services"], rows))
+    }
+}
@@ -0,0 +1,65 @@
+//! Progress reporting: a command declaring a `progress: Progress` parameter
+//! reports how far it has got, and the server forwards the updates to
+//! clients that follow it (the interactive shell draws a progress bar).
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// How far a command has got.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgressUpdate {
+    /// 0 to 100.
+    pub percent: u32,
+    /// The step the command is on (from 1) and how many there are, for
+    /// commands that count steps; both 0 otherwise.
+    pub step: u32,
+    pub total_steps: u32,
+    /// What the command is doing, e.g. "Copying data".
+    pub message: String,
+}
+
+/// Handle through which a command reports progress. Cheap to clone, so it
+/// can be moved into spawned tasks. Updates nobody follows are discarded,
+/// and only the latest one is kept: a client that falls behind skips ahead.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    tx: Option<Arc<watch::Sender<ProgressUpdate>>>,
+}
+
+impl Progress {
+    /// A handle whose updates are read from the returned receiver.
+    pub fn channel() -> (Self, watch::Receiver<ProgressUpdate>) {
+        let (tx, rx) = watch::channel(ProgressUpdate::default());
+        (Self { tx: Some(Arc::new(tx)) }, rx)
+    }
+
+    /// Report the percentage done (capped at 100), keeping the message.
+    pub fn percent(&self, percent: u32) {
+        self.update(|update| update.percent = percent.min(100));
+    }
+
+    /// Report that the command is on `step` of `total` steps, doing
+    /// `message`. The percentage becomes that of the steps before it.
+    pub fn step(&self, step: u32, total: u32, message: impl Into<String>) {
+        let step = step.clamp(1, total.max(1));
+        let message = message.into();
+        self.update(|update| {
+            update.percent = (step - 1) * 100 / total.max(1);
+            update.step = step;
+            update.total_steps = total;
+            update.message = message;
+        });
+    }
+
+    /// Report what the command is doing, keeping the percentage.
+    pub fn message(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.update(|update| update.message = message);
+    }
+
+    fn update(&self, modify: impl FnOnce(&mut ProgressUpdate)) {
+        if let Some(tx) = &self.tx {
+            tx.send_modify(modify);
+        }
+    }
+}
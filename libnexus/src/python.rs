@@ -0,0 +1,532 @@
+//! Python client bindings generated from `ListServices` (`cli-shell
+//! bindings`): a module with a class per service and a method per command
+//! that builds the `Execute` request, so scripts call
+//! `client.volume.create("data0", "sdb", size=20)` instead of writing
+//! protobuf glue.
+//!
+//! The module only needs `grpcio`: it encodes `CommandRequest` and decodes
+//! `CommandResponse` and `StreamFrame` itself (`RUNTIME`), so no code has to
+//! be generated from `nexus.proto` as well.
+
+use crate::cli::{command_path, nested_services};
+use crate::manual;
+use crate::openapi::strip_generic;
+use crate::proto::{ArgDef, CommandDef, ServiceInfo};
+use std::fmt::Write as _;
+
+/// Python's reserved words, which names get a trailing `_` to avoid.
+const KEYWORDS: &[&str] = &[
+    "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+    "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal",
+    "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+/// The start of the module, after its docstring.
+const IMPORTS: &str = r#"import json
+import warnings
+from typing import Optional
+
+import grpc
+
+__all__ = ["Client", "NexusError", "NexusWarning"]
+"#;
+
+/// The part of the module that does not depend on the services: the
+/// client, the wire format and the errors.
+const RUNTIME: &str = r#"
+_ERROR_CODES = {
+    1: "not_found",
+    2: "invalid_argument",
+    3: "permission_denied",
+    4: "internal",
+    5: "busy",
+    6: "timeout",
+    7: "resource_exhausted",
+    8: "unavailable",
+}
+
+
+class NexusError(Exception):
+    """A command failed: ``code`` is the ``ErrorCode`` name (e.g. "not_found"),
+    ``details`` its structured details and ``suggestions`` the names the
+    caller may have meant."""
+
+    def __init__(self, message, code, details, suggestions, retry_after_ms):
+        super().__init__(message)
+        self.message = message
+        self.code = code
+        self.details = details
+        self.suggestions = suggestions
+        self.retry_after_ms = retry_after_ms
+
+
+class NexusWarning(UserWarning):
+    """A warning a command reported alongside its result."""
+
+
+def _varint(value):
+    out = bytearray()
+    while True:
+        bits = value & 0x7F
+        value >>= 7
+        if not value:
+            out.append(bits)
+            return bytes(out)
+        out.append(bits | 0x80)
+
+
+def _bytes(number, data):
+    return _varint(number << 3 | 2) + _varint(len(data)) + data
+
+
+def _string(number, text):
+    return _bytes(number, text.encode())
+
+
+def _read_varint(data, pos):
+    result = shift = 0
+    while True:
+        byte = data[pos]
+        pos += 1
+        result |= (byte & 0x7F) << shift
+        if not byte & 0x80:
+            return result, pos
+        shift += 7
+
+
+def _fields(data):
+    """The (number, value) pairs of an encoded message: ints for varints,
+    bytes for everything else."""
+    pos = 0
+    while pos < len(data):
+        key, pos = _read_varint(data, pos)
+        number, wire = key >> 3, key & 7
+        if wire == 0:
+            value, pos = _read_varint(data, pos)
+        elif wire == 2:
+            size, pos = _read_varint(data, pos)
+            value, pos = data[pos:pos + size], pos + size
+        elif wire in (1, 5):
+            size = 8 if wire == 1 else 4
+            value, pos = data[pos:pos + size], pos + size
+        else:
+            raise ValueError("unsupported protobuf wire type %d" % wire)
+        yield number, value
+
+
+def _text(fields, number):
+    return next((value.decode() for n, value in fields if n == number), "")
+
+
+def _encode_request(service, action, args, named_args, dry_run):
+    out = _string(1, service) + _string(2, action)
+    for arg in args:
+        out += _string(3, arg)
+    if dry_run:
+        out += _varint(4 << 3) + _varint(1)
+    for name, value in named_args.items():
+        out += _bytes(5, _string(1, name) + _string(2, value))
+    return out
+
+
+def _decode_table(data):
+    headers, rows = [], []
+    for number, value in _fields(data):
+        if number == 1:
+            headers.append(value.decode())
+        elif number == 2:
+            rows.append([cell.decode() for n, cell in _fields(value) if n == 1])
+    return [dict(zip(headers, row)) for row in rows]
+
+
+def _decode_response(data):
+    response = {
+        "success": False, "message": "", "retry_after_ms": 0, "plan": [], "output": None, "warnings": [],
+        "error_code": 0, "error_details": {}, "job_id": 0, "suggestions": [],
+    }
+    for number, value in _fields(data):
+        if number == 1:
+            response["success"] = bool(value)
+        elif number == 2:
+            response["message"] = value.decode()
+        elif number == 4:
+            response["retry_after_ms"] = value
+        elif number == 5:
+            change = list(_fields(value))
+            response["plan"].append(
+                {"resource": _text(change, 1), "before": _text(change, 2), "after": _text(change, 3)})
+        elif number == 6:
+            response["output"] = value.decode()
+        elif number == 7:
+            response["output"] = json.loads(value)
+        elif number == 8:
+            response["output"] = _decode_table(value)
+        elif number == 16:
+            response["output"] = value
+        elif number == 9:
+            response["warnings"].append(value.decode())
+        elif number == 11:
+            response["error_code"] = value
+        elif number == 12:
+            entry = list(_fields(value))
+            response["error_details"][_text(entry, 1)] = _text(entry, 2)
+        elif number == 13:
+            response["job_id"] = value
+        elif number == 14:
+            response["suggestions"].append(value.decode())
+    return response
+
+
+def _result(response, dry_run=False):
+    """What a method returns for ``response``; raises ``NexusError`` if the
+    command failed and reports its warnings as ``NexusWarning``s."""
+    for warning in response["warnings"]:
+        warnings.warn(warning, NexusWarning, stacklevel=4)
+    if not response["success"]:
+        raise NexusError(
+            response["message"],
+            _ERROR_CODES.get(response["error_code"], "internal"),
+            response["error_details"],
+            response["suggestions"],
+            response["retry_after_ms"],
+        )
+    if dry_run:
+        return response["plan"]
+    if response["job_id"]:
+        return response["job_id"]
+    if response["output"] is None:
+        return response["message"]
+    return response["output"]
+
+
+class Client:
+    """A connection to a nexus daemon.
+
+    ``target`` is an address as ``cli-shell`` takes it: a Unix socket
+    path, ``host:port`` or a ``unix://``, ``tcp://`` or ``http(s)://`` URL.
+    ``token`` and ``tenant`` are sent with every call, and ``credentials``
+    (from ``grpc.ssl_channel_credentials``) connect over TLS. ``timeout``
+    is the deadline of each call in seconds.
+    """
+
+    def __init__(self, target=DEFAULT_ENDPOINT, token=None, tenant=None, credentials=None, timeout=None):
+        target = _target(target)
+        if credentials is None:
+            self._channel = grpc.insecure_channel(target)
+        else:
+            self._channel = grpc.secure_channel(target, credentials)
+        self._execute = self._channel.unary_unary("/nexus.NexusService/Execute")
+        self._execute_stream = self._channel.unary_stream("/nexus.NexusService/ExecuteStream")
+        self._metadata = []
+        if token:
+            self._metadata.append(("authorization", "Bearer " + token))
+        if tenant:
+            self._metadata.append(("nexus-tenant", tenant))
+        self._timeout = timeout
+        _attach_services(self)
+
+    def close(self):
+        self._channel.close()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *exc):
+        self.close()
+
+    def _call(self, service, action, args, named_args, dry_run=False):
+        request = _encode_request(service, action, args, named_args, dry_run)
+        response = self._execute(request, metadata=self._metadata, timeout=self._timeout)
+        return _result(_decode_response(response), dry_run)
+
+    def _stream(self, service, action, args, named_args):
+        request = _encode_request(service, action, args, named_args, False)
+        frames = self._execute_stream(request, metadata=self._metadata, timeout=self._timeout)
+        for frame in frames:
+            for number, value in _fields(frame):
+                if number == 1:
+                    yield value.decode()
+                elif number == 2:
+                    _result(_decode_response(value))
+
+
+def _target(address):
+    """``address`` as grpcio names it."""
+    if address.startswith("/"):
+        return "unix:" + address
+    for scheme in ("unix://", "tcp://", "http://", "https://"):
+        if address.startswith(scheme):
+            rest = address[len(scheme):]
+            return "unix:" + rest if scheme == "unix://" else rest.rstrip("/")
+    return address
+
+
+def _arguments(**named):
+    """The named arguments that were given, as the strings sent."""
+    return {name: _value(value) for name, value in named.items() if value is not None}
+
+
+def _value(value):
+    if isinstance(value, bool):
+        return "true" if value else "false"
+    return str(value)
+"#;
+
+/// The Python module for `services`, connecting to `endpoint` unless told
+/// otherwise. Generated by `program`, as the docstring tells.
+pub(crate) fn module(program: &str, endpoint: &str, services: &[ServiceInfo]) -> String {
+    let mut sorted: Vec<&ServiceInfo> = services.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    let example = sorted
+        .iter()
+        .find_map(|s| Some((s, s.commands.iter().find(|c| !c.streaming && !c.background)?)));
+
+    let mut out = String::new();
+    let _ = writeln!(out, "\"\"\"Client for the services of a nexus daemon, generated by `{} bindings`.\n", program);
+    out.push_str("Requires ``grpcio``. Each service is an attribute of ``Client`` and each of its\n");
+    out.push_str("commands a method, which returns the command's output: text as a string, JSON as\n");
+    out.push_str("is, tables as a list of dicts and binary data as bytes. A failed command raises\n");
+    out.push_str("``NexusError``.\n");
+    if let Some((service, command)) = example {
+        let _ = writeln!(
+            out,
+            "\n    with Client() as client:\n        print(client.{}.{}({}))",
+            attribute_path(service),
+            identifier(&command.name),
+            if command.args.is_empty() { "" } else { "..." }
+        );
+    }
+    out.push_str("\"\"\"\n\n");
+    out.push_str(IMPORTS);
+    let _ = writeln!(out, "\n# The server the module was generated from.\nDEFAULT_ENDPOINT = {}\n", string(endpoint));
+    out.push_str(RUNTIME);
+
+    for service in &sorted {
+        out.push_str("\n\n");
+        class(&mut out, services, service);
+    }
+
+    // The services are attributes of the client; nested ones are reached
+    // through their parent.
+    out.push_str("\n\ndef _attach_services(client):\n");
+    let top: Vec<&&ServiceInfo> = sorted.iter().filter(|s| parent(services, s).is_none()).collect();
+    if top.is_empty() {
+        out.push_str("    pass\n");
+    }
+    for service in top {
+        let attribute = identifier(&service.name.replace('.', "_"));
+        let _ = writeln!(out, "    client.{} = {}(client)", attribute, class_name(service));
+    }
+    out
+}
+
+/// The registered parent of a nested service, if it is in `services`.
+fn parent<'a>(services: &'a [ServiceInfo], service: &ServiceInfo) -> Option<&'a ServiceInfo> {
+    let (path, _) = service.name.rsplit_once('.')?;
+    services.iter().find(|s| s.name == path)
+}
+
+/// How a script reaches `service` from a client: `volume.snapshot`.
+fn attribute_path(service: &ServiceInfo) -> String {
+    service.name.split('.').map(identifier).collect::<Vec<_>>().join(".")
+}
+
+/// `_VolumeSnapshot` for "volume.snapshot".
+fn class_name(service: &ServiceInfo) -> String {
+    let mut name = String::from("_");
+    for word in service.name.split(['.', '_', '-']) {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
+
+/// `name` as a Python identifier.
+fn identifier(name: &str) -> String {
+    let name = name.replace('-', "_");
+    if KEYWORDS.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
+
+/// `text` as a Python string literal.
+fn string(text: &str) -> String {
+    serde_json::Value::String(text.to_string()).to_string()
+}
+
+fn class(out: &mut String, services: &[ServiceInfo], service: &ServiceInfo) {
+    let _ = writeln!(out, "class {}:", class_name(service));
+    let summary = if service.description.is_empty() {
+        format!("The {} service.", command_path(&service.name))
+    } else {
+        service.description.clone()
+    };
+    let _ = writeln!(out, "    {}\n", docstring(&summary, 4));
+    out.push_str("    def __init__(self, client):\n        self._client = client\n");
+    for (name, nested) in nested_services(services, &service.name) {
+        // A command of the same name takes the attribute.
+        let mut attribute = identifier(name);
+        if service.commands.iter().any(|command| identifier(&command.name) == attribute) {
+            attribute.push('_');
+        }
+        let _ = writeln!(out, "        self.{} = {}(client)", attribute, class_name(nested));
+    }
+    for command in &service.commands {
+        out.push('\n');
+        method(out, service, command);
+    }
+}
+
+fn method(out: &mut String, service: &ServiceInfo, command: &CommandDef) {
+    // Once an optional argument has been declared, the ones after it can
+    // only be passed by name.
+    let mut params = vec!["self".to_string()];
+    let (mut optional, mut keyword_only) = (false, false);
+    for arg in &command.args {
+        let name = identifier(&arg.name);
+        let annotation = python_type(&arg.type_name);
+        if arg.variadic {
+            params.push(format!("*{}: {}", name, annotation));
+            keyword_only = true;
+        } else if arg.optional {
+            params.push(format!("{}: Optional[{}] = None", name, annotation));
+            optional = true;
+        } else {
+            if optional && !keyword_only {
+                params.push("*".to_string());
+                keyword_only = true;
+            }
+            params.push(format!("{}: {}", name, annotation));
+        }
+    }
+    if command.dry_run {
+        if !keyword_only {
+            params.push("*".to_string());
+        }
+        params.push("dry_run: bool = False".to_string());
+    }
+    let _ = writeln!(out, "    def {}({}):", identifier(&command.name), params.join(", "));
+    let _ = writeln!(out, "        {}", docstring(&method_doc(service, command), 8));
+
+    let named: Vec<String> = command
+        .args
+        .iter()
+        .filter(|arg| !arg.variadic)
+        .map(|arg| format!("{}={}", identifier(&arg.name), identifier(&arg.name)))
+        .collect();
+    let positional = match command.args.iter().find(|arg| arg.variadic) {
+        Some(arg) => format!("[_value(value) for value in {}]", identifier(&arg.name)),
+        None => "[]".to_string(),
+    };
+    let call = format!(
+        "{}, {}, {}, _arguments({})",
+        string(&service.name),
+        string(&command.name),
+        positional,
+        named.join(", ")
+    );
+    if command.streaming {
+        let _ = writeln!(out, "        return self._client._stream({})", call);
+    } else if command.dry_run {
+        let _ = writeln!(out, "        return self._client._call({}, dry_run)", call);
+    } else {
+        let _ = writeln!(out, "        return self._client._call({})", call);
+    }
+}
+
+/// The docstring of a command's method: its doc comment, arguments and
+/// what it returns.
+fn method_doc(service: &ServiceInfo, command: &CommandDef) -> String {
+    let mut doc = if command.help.is_empty() {
+        command.description.clone()
+    } else {
+        command.help.clone()
+    };
+    if doc.is_empty() {
+        doc = format!("Run `{} {}`.", command_path(&service.name), command.name);
+    }
+    if !command.args.is_empty() || command.dry_run {
+        doc.push_str("\n\nArgs:");
+        for arg in &command.args {
+            let _ = write!(doc, "\n    {}: {}", identifier(&arg.name), arg_doc(arg));
+        }
+        if command.dry_run {
+            doc.push_str("\n    dry_run: Return the planned changes instead of making them.");
+        }
+    }
+    let returns = if command.streaming {
+        "An iterator over the output lines."
+    } else if command.background {
+        "The ID of the background job the command runs as."
+    } else if command.dry_run {
+        "The output, or with ``dry_run`` the changes as dicts."
+    } else {
+        "The output."
+    };
+    let _ = write!(doc, "\n\nReturns:\n    {}", returns);
+    if !command.permission.is_empty() {
+        let _ = write!(doc, "\n\nRequires the '{}' permission.", command.permission);
+    }
+    if let Some(deprecation) = manual::deprecation(command) {
+        let _ = write!(doc, "\n\nThis command is {}.", deprecation);
+    }
+    doc
+}
+
+fn arg_doc(arg: &ArgDef) -> String {
+    let mut text = if arg.description.is_empty() {
+        arg.hint.clone()
+    } else {
+        arg.description.clone()
+    };
+    let mut sentence = |extra: String| {
+        if !text.is_empty() && !text.ends_with('.') {
+            text.push('.');
+        }
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&extra);
+    };
+    if !arg.choices.is_empty() {
+        sentence(format!("One of: {}.", arg.choices.join(", ")));
+    }
+    if !arg.default_value.is_empty() {
+        sentence(format!("Default: {}.", arg.default_value));
+    }
+    text
+}
+
+/// The Python annotation for an argument's Rust type.
+fn python_type(type_name: &str) -> &'static str {
+    if let Some(inner) = strip_generic(type_name, "Option").or_else(|| strip_generic(type_name, "Vec")) {
+        return python_type(inner);
+    }
+    match type_name {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => "int",
+        "f32" | "f64" => "float",
+        "bool" => "bool",
+        _ => "str",
+    }
+}
+
+/// `text` as a triple-quoted docstring whose continuation lines are
+/// indented by `indent` spaces.
+fn docstring(text: &str, indent: usize) -> String {
+    let text = text.replace('\\', "\\\\").replace("\"\"\"", "\\\"\\\"\\\"");
+    let pad = " ".repeat(indent);
+    let lines: Vec<String> = text
+        .lines()
+        .map(|line| if line.trim().is_empty() { String::new() } else { format!("{}{}", pad, line) })
+        .collect();
+    match lines.len() {
+        0 => "\"\"\"\"\"\"".to_string(),
+        1 => format!("\"\"\"{}\"\"\"", text.trim()),
+        _ => format!("\"\"\"{}\n{}\n{}\"\"\"", lines[0].trim_start(), lines[1..].join("\n"), pad),
+    }
+}
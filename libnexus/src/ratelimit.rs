@@ -0,0 +1,320 @@
+//! Request rate limiting: middleware that gives each client a budget of
+//! requests, so a runaway script cannot swamp the daemon. Requests over the
+//! budget are rejected with `ErrorCode::ResourceExhausted` and a hint of
+//! when to retry.
+//!
+//! ```ignore
+//! server.with_middleware(
+//!     RateLimit::new()
+//!         .per_peer(Rate::per_second(20).burst(40))
+//!         .per_command("pool destroy", Rate::per_minute(1)),
+//! )
+//! ```
+//!
+//! Limits are token buckets: a client may send `burst` requests at once,
+//! and regains one every `per / requests` after that. Clients are told apart
+//! by their user on a Unix socket and their IP address over TCP; requests
+//! with neither (e.g. through the HTTP gateway) share one budget.
+
+use crate::context::Context;
+use crate::error::RateLimited;
+use crate::middleware::Middleware;
+use crate::registry::format_duration;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+
+/// Clients tracked before buckets that have refilled are forgotten.
+const MAX_TRACKED: usize = 4096;
+
+/// How many requests a client may send: `requests` every `per`, and up to
+/// `burst` at once (by default `requests`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    requests: u32,
+    per: Duration,
+    burst: u32,
+}
+
+impl Rate {
+    /// `requests` every `per`. `requests` and `per` must not be zero.
+    pub fn new(requests: u32, per: Duration) -> Self {
+        assert!(requests > 0 && !per.is_zero(), "a rate needs requests and a period");
+        Self {
+            requests,
+            per,
+            burst: requests,
+        }
+    }
+
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Allow up to `burst` requests at once (at least 1).
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Seconds it takes to regain one request.
+    fn interval(&self) -> f64 {
+        self.per.as_secs_f64() / f64::from(self.requests)
+    }
+}
+
+/// Parses `<requests>/<period>`, where the period is `s`, `m` or `h` or a
+/// duration such as `10s`: "20/s", "1/m", "5/10s".
+impl std::str::FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("invalid rate '{}': expected e.g. \"20/s\", \"1/m\" or \"5/10s\"", s);
+        let (requests, per) = s.split_once('/').ok_or_else(invalid)?;
+        let requests: u32 = requests.trim().parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?;
+        let per = per.trim();
+        let per = match per {
+            "s" | "m" | "h" => crate::config::parse_duration(&format!("1{}", per)),
+            _ => crate::config::parse_duration(per),
+        };
+        Ok(Self::new(requests, per.ok_or_else(invalid)?))
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.per.as_secs() {
+            1 | 60 | 3600 if self.per.subsec_nanos() == 0 => {
+                write!(f, "{}/{}", self.requests, &format_duration(self.per)[1..])
+            }
+            _ => write!(f, "{}/{}", self.requests, format_duration(self.per)),
+        }
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Rate {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Who a request counts against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Client {
+    User(u32),
+    Ip(IpAddr),
+    Unknown,
+}
+
+impl Client {
+    pub(crate) fn of(ctx: &Context) -> Self {
+        match (ctx.peer, ctx.remote_addr) {
+            (Some(peer), _) => Self::User(peer.uid),
+            (None, Some(addr)) => Self::Ip(addr.ip()),
+            (None, None) => Self::Unknown,
+        }
+    }
+}
+
+/// "uid 1000", "192.0.2.7" or "unknown".
+impl fmt::Display for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(uid) => write!(f, "uid {}", uid),
+            Self::Ip(addr) => write!(f, "{}", addr),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Requests a client has left under one limit.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// The bucket refilled up to `now`.
+    fn refilled(bucket: Option<&Bucket>, rate: &Rate, now: Instant) -> Self {
+        let burst = f64::from(rate.burst);
+        let tokens = bucket.map_or(burst, |b| {
+            let regained = now.saturating_duration_since(b.updated).as_secs_f64() / rate.interval();
+            (b.tokens + regained).min(burst)
+        });
+        Self { tokens, updated: now }
+    }
+
+    /// How long until the bucket has a request to spare; zero if it has one.
+    fn wait(&self, rate: &Rate) -> Duration {
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) * rate.interval())
+    }
+
+    fn is_full(&self, rate: &Rate, now: Instant) -> bool {
+        Self::refilled(Some(self), rate, now).tokens >= f64::from(rate.burst)
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    peers: HashMap<Client, Bucket>,
+    commands: HashMap<(Client, String), Bucket>,
+}
+
+/// Middleware enforcing per-client request rates; install it with
+/// `NexusServer::with_middleware`.
+#[derive(Default)]
+pub struct RateLimit {
+    per_peer: Option<Rate>,
+    /// Command path ("pool destroy") -> the limit on each client's calls of it.
+    per_command: HashMap<String, Rate>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimit {
+    /// A limiter with no limits yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit every client's requests, across all commands, to `rate`.
+    pub fn per_peer(mut self, rate: Rate) -> Self {
+        self.per_peer = Some(rate);
+        self
+    }
+
+    /// Limit every client's calls of `command` (as typed on the CLI, e.g.
+    /// "pool destroy") to `rate`, on top of the per-peer limit.
+    pub fn per_command(mut self, command: &str, rate: Rate) -> Self {
+        self.per_command.insert(command.to_string(), rate);
+        self
+    }
+}
+
+impl Middleware for RateLimit {
+    fn on_request(&self, _metadata: &MetadataMap, ctx: &mut Context) -> anyhow::Result<()> {
+        let client = Client::of(ctx);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { peers, commands } = &mut *buckets;
+
+        let peer = self.per_peer.map(|rate| (rate, Bucket::refilled(peers.get(&client), &rate, now)));
+        let command_key = (client.clone(), ctx.command.clone());
+        let command = self
+            .per_command
+            .get(&ctx.command)
+            .map(|&rate| (rate, Bucket::refilled(commands.get(&command_key), &rate, now)));
+
+        // Both limits must have a request to spare before either is charged.
+        let exceeded = [
+            peer.map(|(rate, bucket)| (bucket.wait(&rate), rate.to_string())),
+            command.map(|(rate, bucket)| (bucket.wait(&rate), format!("{} for '{}'", rate, ctx.command))),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|(wait, _)| !wait.is_zero())
+        .max_by_key(|(wait, _)| *wait);
+        if let Some((wait, limit)) = exceeded {
+            // Round up, so a client retrying on time finds a request to spare.
+            let retry_after = Duration::from_millis(wait.as_millis() as u64 + 1);
+            return Err(RateLimited { limit, retry_after }.into());
+        }
+
+        if peers.len() + commands.len() >= MAX_TRACKED {
+            if let Some(rate) = &self.per_peer {
+                peers.retain(|_, bucket| !bucket.is_full(rate, now));
+            }
+            commands.retain(|(_, command), bucket| {
+                self.per_command.get(command).is_some_and(|rate| !bucket.is_full(rate, now))
+            });
+        }
+        if let Some((_, mut bucket)) = peer {
+            bucket.tokens -= 1.0;
+            peers.insert(client, bucket);
+        }
+        if let Some((_, mut bucket)) = command {
+            bucket.tokens -= 1.0;
+            commands.insert(command_key, bucket);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spend(bucket: &mut Bucket, requests: u32) {
+        bucket.tokens -= f64::from(requests);
+    }
+
+    #[test]
+    fn new_clients_start_with_a_full_burst() {
+        let rate = Rate::per_second(2).burst(5);
+        let bucket = Bucket::refilled(None, &rate, Instant::now());
+        assert_eq!(bucket.tokens, 5.0);
+        assert_eq!(bucket.wait(&rate), Duration::ZERO);
+    }
+
+    #[test]
+    fn buckets_regain_one_request_per_interval() {
+        let rate = Rate::per_second(2);
+        let start = Instant::now();
+        let mut bucket = Bucket::refilled(None, &rate, start);
+        spend(&mut bucket, 2);
+        assert_eq!(bucket.wait(&rate), Duration::from_millis(500));
+
+        let later = Bucket::refilled(Some(&bucket), &rate, start + Duration::from_millis(250));
+        assert_eq!(later.tokens, 0.5);
+        assert_eq!(later.wait(&rate), Duration::from_millis(250));
+
+        let later = Bucket::refilled(Some(&bucket), &rate, start + Duration::from_millis(500));
+        assert_eq!(later.tokens, 1.0);
+        assert_eq!(later.wait(&rate), Duration::ZERO);
+    }
+
+    #[test]
+    fn buckets_refill_no_further_than_the_burst() {
+        let rate = Rate::per_minute(6).burst(3);
+        let start = Instant::now();
+        let mut bucket = Bucket::refilled(None, &rate, start);
+        spend(&mut bucket, 3);
+        assert!(!bucket.is_full(&rate, start + Duration::from_secs(20)));
+        assert!(bucket.is_full(&rate, start + Duration::from_secs(30)));
+        let later = Bucket::refilled(Some(&bucket), &rate, start + Duration::from_secs(3_600));
+        assert_eq!(later.tokens, 3.0);
+    }
+
+    #[test]
+    fn a_clock_going_back_regains_nothing() {
+        let rate = Rate::per_second(1);
+        let start = Instant::now() + Duration::from_secs(10);
+        let mut bucket = Bucket::refilled(None, &rate, start);
+        spend(&mut bucket, 1);
+        let earlier = Bucket::refilled(Some(&bucket), &rate, start - Duration::from_secs(5));
+        assert_eq!(earlier.tokens, 0.0);
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!("20/s".parse::<Rate>().unwrap(), Rate::per_second(20));
+        assert_eq!("1/m".parse::<Rate>().unwrap(), Rate::per_minute(1));
+        assert_eq!("5/10s".parse::<Rate>().unwrap(), Rate::new(5, Duration::from_secs(10)));
+        for invalid in ["", "0/s", "5", "5/", "x/s", "5/fortnight"] {
+            assert!(invalid.parse::<Rate>().is_err(), "{:?}", invalid);
+        }
+        assert_eq!(Rate::per_minute(1).to_string(), "1/m");
+        assert_eq!(Rate::new(5, Duration::from_secs(10)).to_string(), "5/10s");
+    }
+}
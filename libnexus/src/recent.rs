@@ -0,0 +1,82 @@
+//! Results of the latest commands, kept so that clients can fetch output
+//! that scrolled away or was printed by a script through the
+//! `RecentResults` RPC (the CLI's `last` builtin). Each caller sees its own
+//! commands; callers are told apart like by `RateLimit`, by their user on a
+//! Unix socket or their IP address over TCP, and by their tenant.
+
+use crate::context::Context;
+use crate::proto::{CommandResponse, RecentResult};
+use crate::ratelimit::Client;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How many results are kept, across all callers; older ones are forgotten.
+pub(crate) const MAX_RECENT_RESULTS: usize = 100;
+
+/// How many lines of a streaming command's output are kept with its result.
+pub(crate) const MAX_RECENT_LINES: usize = 1000;
+
+struct Entry {
+    client: Client,
+    tenant: Option<String>,
+    result: RecentResult,
+}
+
+/// The latest results, oldest first.
+#[derive(Default)]
+pub(crate) struct Recent {
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+impl Recent {
+    /// Remember `response` as the result of the command `ctx` ran, with its
+    /// arguments described (secrets redacted) as `args`.
+    pub(crate) fn record(
+        &self,
+        ctx: &Context,
+        service: &str,
+        action: &str,
+        args: String,
+        elapsed: Duration,
+        response: CommandResponse,
+    ) {
+        let client = Client::of(ctx);
+        let finished_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let result = RecentResult {
+            service: service.to_string(),
+            action: action.to_string(),
+            args,
+            caller: client.to_string(),
+            tenant: ctx.tenant.clone().unwrap_or_default(),
+            finished_at_ms: finished_at.as_millis() as u64,
+            elapsed_ms: elapsed.as_millis() as u64,
+            result: Some(response),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_RECENT_RESULTS {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            client,
+            tenant: ctx.tenant.clone(),
+            result,
+        });
+    }
+
+    /// The latest `limit` results (all kept with 0), oldest first: those of
+    /// the caller `ctx` stands for, or of everyone if `all`.
+    pub(crate) fn list(&self, ctx: &Context, all: bool, limit: usize) -> Vec<RecentResult> {
+        let client = Client::of(ctx);
+        let entries = self.entries.lock().unwrap();
+        let mut results: Vec<RecentResult> = entries
+            .iter()
+            .rev()
+            .filter(|e| all || (e.client == client && e.tenant == ctx.tenant))
+            .take(if limit == 0 { usize::MAX } else { limit })
+            .map(|e| e.result.clone())
+            .collect();
+        results.reverse();
+        results
+    }
+}
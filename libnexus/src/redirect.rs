@@ -0,0 +1,170 @@
+//! Output redirection for the interactive shell: `volume list | grep vol1`
+//! feeds what a command line prints to a local shell pipeline, and
+//! `block info sda > sda.txt` writes it to a file.
+//!
+//! Standard output is pointed at a pipe while the line runs, so builtins
+//! and commands need no changes. A thread copies what arrives to the
+//! destination without the color codes meant for the terminal; warnings
+//! and errors on standard error still go to the terminal.
+//!
+//! `set NAME=$(<command>)` collects the output in memory the same way.
+
+use crate::tokenize::{self, Redirect};
+use anyhow::Context as _;
+use std::fs::OpenOptions;
+use std::io::{PipeReader, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// How many captures are in place (`$(...)` can run inside a redirected
+/// line).
+static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether standard output is redirected by a capture.
+pub(crate) fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed) > 0
+}
+
+/// Standard output redirected until this is dropped, which waits for the
+/// destination to receive everything.
+pub(crate) struct Capture {
+    /// The terminal, restored on drop.
+    stdout: OwnedFd,
+    copier: Option<JoinHandle<()>>,
+    pipeline: Option<Child>,
+}
+
+impl Capture {
+    /// Redirect standard output to `redirect`: start the pipeline, or
+    /// create (or for `>>` append to) the file.
+    pub(crate) fn start(redirect: Redirect<'_>) -> anyhow::Result<Self> {
+        let (sink, pipeline): (Box<dyn Write + Send>, _) = match redirect {
+            Redirect::Pipe("") => anyhow::bail!("expected a command after '|'"),
+            Redirect::Pipe(pipeline) => {
+                // Spawned before the redirection, so its own output goes to
+                // the terminal.
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(pipeline)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .context("failed to run sh")?;
+                let stdin = child.stdin.take().expect("stdin is piped");
+                (Box::new(stdin), Some(child))
+            }
+            Redirect::File { target, append } => {
+                let words = tokenize::split(target)?;
+                let [path] = words.as_slice() else {
+                    anyhow::bail!("expected one file name after '>'");
+                };
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .append(append)
+                    .truncate(!append)
+                    .open(path)
+                    .with_context(|| format!("failed to open {}", path))?;
+                (Box::new(file), None)
+            }
+        };
+        Self::redirect(sink, pipeline)
+    }
+
+    /// Redirect standard output to memory. What was printed is in the
+    /// buffer once the capture is dropped.
+    pub(crate) fn collect() -> anyhow::Result<(Self, Arc<Mutex<Vec<u8>>>)> {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let capture = Self::redirect(Box::new(Buffer(buffer.clone())), None)?;
+        Ok((capture, buffer))
+    }
+
+    fn redirect(sink: Box<dyn Write + Send>, pipeline: Option<Child>) -> anyhow::Result<Self> {
+        std::io::stdout().flush()?;
+        let (reader, writer) = std::io::pipe()?;
+        let stdout = std::io::stdout().as_fd().try_clone_to_owned()?;
+        nix::unistd::dup2(writer.as_raw_fd(), std::io::stdout().as_raw_fd())?;
+        // Standard output now holds the only write end.
+        drop(writer);
+        let copier = std::thread::spawn(move || copy_plain(reader, sink));
+        ACTIVE.fetch_add(1, Ordering::Relaxed);
+        Ok(Self {
+            stdout,
+            copier: Some(copier),
+            pipeline,
+        })
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+        // Closes the pipe, so the copier reaches its end.
+        let _ = nix::unistd::dup2(self.stdout.as_raw_fd(), std::io::stdout().as_raw_fd());
+        ACTIVE.fetch_sub(1, Ordering::Relaxed);
+        if let Some(copier) = self.copier.take() {
+            let _ = copier.join();
+        }
+        if let Some(pipeline) = &mut self.pipeline {
+            let _ = pipeline.wait();
+        }
+    }
+}
+
+/// A sink appending to a shared buffer.
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Copy `from` to `to`, leaving out ANSI escape sequences. Once `to` stops
+/// accepting data (e.g. `| head -1` exited), the rest is read and dropped,
+/// so the shell never blocks on a full pipe.
+fn copy_plain(mut from: PipeReader, mut to: Box<dyn Write + Send>) {
+    let mut buf = [0; 8192];
+    let mut state = Escape::Text;
+    let mut open = true;
+    while let Ok(n @ 1..) = from.read(&mut buf) {
+        if !open {
+            continue;
+        }
+        let plain: Vec<u8> = buf[..n].iter().copied().filter(|&b| state.keep(b)).collect();
+        open = to.write_all(&plain).is_ok();
+    }
+    let _ = to.flush();
+}
+
+/// Where the copier is relative to an escape sequence such as `\x1b[1m`.
+#[derive(Clone, Copy)]
+enum Escape {
+    Text,
+    /// After `ESC`.
+    Start,
+    /// Inside `ESC [ ...`, until its final byte.
+    Csi,
+}
+
+impl Escape {
+    /// Whether byte `b` is text, advancing past escape sequences.
+    fn keep(&mut self, b: u8) -> bool {
+        match (*self, b) {
+            (Escape::Text, 0x1b) => *self = Escape::Start,
+            (Escape::Text, _) => return true,
+            (Escape::Start, b'[') => *self = Escape::Csi,
+            (Escape::Start, _) => *self = Escape::Text,
+            (Escape::Csi, 0x40..=0x7e) => *self = Escape::Text,
+            (Escape::Csi, _) => {}
+        }
+        false
+    }
+}
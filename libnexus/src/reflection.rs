@@ -0,0 +1,233 @@
+//! The standard gRPC server reflection service (`grpc.reflection.v1`, and
+//! `grpc.reflection.v1alpha` for older clients), served with
+//! `NexusServer::with_reflection` so generic tools like `grpcurl` can list,
+//! describe and call the daemon's gRPC services without their .proto files.
+//!
+//! The descriptors are the ones `build.rs` compiles the protos from, so
+//! they always match the services served. They describe the gRPC API, not
+//! the registered commands: those are called through `NexusService/Execute`
+//! and listed by `ListServices`.
+
+use crate::proto::FILE_DESCRIPTOR_SET;
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::{Request, Response, Status, Streaming};
+
+/// `google.rpc.Code` values for `ErrorResponse`.
+const NOT_FOUND: i32 = 5;
+const UNIMPLEMENTED: i32 = 12;
+
+/// The files of `FILE_DESCRIPTOR_SET`, indexed for lookups.
+struct Descriptors {
+    /// Each file, encoded, with the names of the files it imports.
+    files: HashMap<String, (Vec<u8>, Vec<String>)>,
+    /// The file defining each fully qualified message, enum, service and
+    /// method name.
+    symbols: HashMap<String, String>,
+    /// Fully qualified service names, sorted.
+    services: Vec<String>,
+}
+
+impl Descriptors {
+    fn get() -> &'static Self {
+        static DESCRIPTORS: OnceLock<Descriptors> = OnceLock::new();
+        DESCRIPTORS.get_or_init(|| {
+            let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).expect("build.rs writes a valid descriptor set");
+            let mut descriptors = Descriptors {
+                files: HashMap::new(),
+                symbols: HashMap::new(),
+                services: Vec::new(),
+            };
+            for file in set.file {
+                descriptors.add(file);
+            }
+            descriptors.services.sort();
+            descriptors
+        })
+    }
+
+    fn add(&mut self, file: FileDescriptorProto) {
+        let name = file.name().to_string();
+        let prefix = match file.package() {
+            "" => String::new(),
+            package => format!("{}.", package),
+        };
+        for message in &file.message_type {
+            self.add_message(&name, &prefix, message);
+        }
+        for kind in &file.enum_type {
+            self.symbols.insert(format!("{}{}", prefix, kind.name()), name.clone());
+        }
+        for service in &file.service {
+            let service_name = format!("{}{}", prefix, service.name());
+            for method in &service.method {
+                self.symbols.insert(format!("{}.{}", service_name, method.name()), name.clone());
+            }
+            self.symbols.insert(service_name.clone(), name.clone());
+            self.services.push(service_name);
+        }
+        let encoded = file.encode_to_vec();
+        self.files.insert(name, (encoded, file.dependency));
+    }
+
+    /// Add `message` and the messages and enums nested in it.
+    fn add_message(&mut self, file: &str, prefix: &str, message: &DescriptorProto) {
+        let name = format!("{}{}", prefix, message.name());
+        let nested = format!("{}.", name);
+        for inner in &message.nested_type {
+            self.add_message(file, &nested, inner);
+        }
+        for kind in &message.enum_type {
+            self.symbols.insert(format!("{}{}", nested, kind.name()), file.to_string());
+        }
+        self.symbols.insert(name, file.to_string());
+    }
+
+    /// The encoded file `name` followed by every file it imports, directly
+    /// or not.
+    fn with_dependencies(&self, name: &str) -> Option<Vec<Vec<u8>>> {
+        self.files.get(name)?;
+        let mut order = vec![name];
+        let mut next = 0;
+        while next < order.len() {
+            let (_, dependencies) = &self.files[order[next]];
+            for dependency in dependencies {
+                if self.files.contains_key(dependency) && !order.contains(&dependency.as_str()) {
+                    order.push(dependency);
+                }
+            }
+            next += 1;
+        }
+        Some(order.into_iter().map(|file| self.files[file].0.clone()).collect())
+    }
+}
+
+/// A reflection request, whichever version of the protocol it came in.
+enum Query {
+    File(String),
+    Symbol(String),
+    Extension(String),
+    ExtensionNumbers(String),
+    Services,
+}
+
+/// What a `Query` is answered with.
+enum Answer {
+    Files(Vec<Vec<u8>>),
+    /// The message named has no extensions: the protos use none.
+    ExtensionNumbers(String),
+    Services(Vec<String>),
+}
+
+/// An `ErrorResponse`: a `google.rpc.Code` and a message.
+type Failure = (i32, String);
+
+fn answer(query: Option<Query>) -> Result<Answer, Failure> {
+    let descriptors = Descriptors::get();
+    let not_found = |what: String| (NOT_FOUND, format!("{} not found", what));
+    match query {
+        Some(Query::File(name)) => descriptors
+            .with_dependencies(&name)
+            .map(Answer::Files)
+            .ok_or_else(|| not_found(format!("file '{}'", name))),
+        Some(Query::Symbol(symbol)) => descriptors
+            .symbols
+            .get(&symbol)
+            .and_then(|file| descriptors.with_dependencies(file))
+            .map(Answer::Files)
+            .ok_or_else(|| not_found(format!("symbol '{}'", symbol))),
+        Some(Query::Extension(message)) => Err(not_found(format!("extension of '{}'", message))),
+        Some(Query::ExtensionNumbers(message)) if descriptors.symbols.contains_key(&message) => {
+            Ok(Answer::ExtensionNumbers(message))
+        }
+        Some(Query::ExtensionNumbers(message)) => Err(not_found(format!("message '{}'", message))),
+        Some(Query::Services) => Ok(Answer::Services(descriptors.services.clone())),
+        None => Err((UNIMPLEMENTED, "empty reflection request".to_string())),
+    }
+}
+
+/// The reflection service; one value serves both versions.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ReflectionService;
+
+/// Implement `ServerReflection` of the protocol version `$version` (a
+/// module of `proto::reflection`) for `ReflectionService`.
+macro_rules! server_reflection {
+    ($version:ident) => {
+        mod $version {
+            use super::*;
+            use crate::proto::reflection::$version::server_reflection_request::MessageRequest;
+            use crate::proto::reflection::$version::server_reflection_response::MessageResponse;
+            use crate::proto::reflection::$version::server_reflection_server::ServerReflection;
+            use crate::proto::reflection::$version::{
+                ErrorResponse, ExtensionNumberResponse, FileDescriptorResponse, ListServiceResponse,
+                ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+            };
+
+            fn respond(request: ServerReflectionRequest) -> ServerReflectionResponse {
+                let query = request.message_request.clone().map(|message| match message {
+                    MessageRequest::FileByFilename(name) => Query::File(name),
+                    MessageRequest::FileContainingSymbol(symbol) => Query::Symbol(symbol),
+                    MessageRequest::FileContainingExtension(extension) => {
+                        Query::Extension(extension.containing_type)
+                    }
+                    MessageRequest::AllExtensionNumbersOfType(message) => Query::ExtensionNumbers(message),
+                    MessageRequest::ListServices(_) => Query::Services,
+                });
+                let response = match answer(query) {
+                    Ok(Answer::Files(files)) => MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                        file_descriptor_proto: files,
+                    }),
+                    Ok(Answer::ExtensionNumbers(message)) => {
+                        MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                            base_type_name: message,
+                            extension_number: Vec::new(),
+                        })
+                    }
+                    Ok(Answer::Services(names)) => MessageResponse::ListServicesResponse(ListServiceResponse {
+                        service: names.into_iter().map(|name| ServiceResponse { name }).collect(),
+                    }),
+                    Err((error_code, error_message)) => MessageResponse::ErrorResponse(ErrorResponse {
+                        error_code,
+                        error_message,
+                    }),
+                };
+                ServerReflectionResponse {
+                    valid_host: request.host.clone(),
+                    original_request: Some(request),
+                    message_response: Some(response),
+                }
+            }
+
+            #[tonic::async_trait]
+            impl ServerReflection for ReflectionService {
+                type ServerReflectionInfoStream = ReceiverStream<Result<ServerReflectionResponse, Status>>;
+
+                async fn server_reflection_info(
+                    &self,
+                    request: Request<Streaming<ServerReflectionRequest>>,
+                ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+                    let mut requests = request.into_inner();
+                    let (tx, rx) = mpsc::channel(1);
+                    tokio::spawn(async move {
+                        while let Some(request) = requests.next().await {
+                            let response = request.map(respond);
+                            if tx.send(response).await.is_err() {
+                                return;
+                            }
+                        }
+                    });
+                    Ok(Response::new(ReceiverStream::new(rx)))
+                }
+            }
+        }
+    };
+}
+
+server_reflection!(v1);
+server_reflection!(v1alpha);
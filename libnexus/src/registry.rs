@@ -1,6 +1,372 @@
-use anyhow::Result;
+use crate::auth::Authenticator;
+use crate::cache::{self, ResultCache};
+use crate::config::OutputFormat;
+use crate::context::Context;
+use crate::error::{closest, Busy, ErrorCode, NexusError};
+use crate::events::EventBus;
+use crate::glob;
+use crate::health::Health;
+use crate::job_store::JobStore;
+use crate::jobs::{Job, JobRetention, Jobs};
+use crate::locks::Locks;
+use crate::limiter::{LimiterPermit, PriorityLimiter};
+use crate::metrics::Metrics;
+use crate::progress::Progress;
+use crate::state::States;
+use crate::stream::{CommandStream, Deadline, Guarded, Observed};
+use anyhow::{Context as _, Result};
 use async_trait::async_trait;
-use std::collections::HashMap;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+/// How long a caller turned away by a full service queue
+/// (`#[nexus_service(max_queue)]`) is asked to wait before retrying.
+pub const QUEUE_FULL_RETRY: Duration = Duration::from_secs(1);
+
+/// How long a caller turned away by a held command lock (`try_lock`, or a
+/// wait given up) is asked to wait before retrying.
+pub const LOCK_RETRY: Duration = Duration::from_secs(1);
+
+/// How many commands deep `Context::call` may nest unless the server sets
+/// another limit, counting the one the client asked for.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 8;
+
+/// The error of a command abandoned after running for `limit`.
+fn timed_out(service: &dyn Service, action: &str, limit: Duration) -> NexusError {
+    let command = format!("{} {}", crate::cli::command_path(service.name()), action);
+    NexusError::new(
+        ErrorCode::Timeout,
+        format!("'{}' timed out after {}", command, format_duration(limit)),
+    )
+    .detail("timeout_ms", limit.as_millis().to_string())
+}
+
+/// The error of a command still unfinished (or not yet started) when the
+/// request's deadline passed.
+fn missed_deadline(service: &dyn Service, action: &str) -> NexusError {
+    let command = format!("{} {}", crate::cli::command_path(service.name()), action);
+    NexusError::new(
+        ErrorCode::Timeout,
+        format!("'{}' did not finish before the request's deadline", command),
+    )
+}
+
+/// Run `future` for the request, giving up with `missed_deadline` (and
+/// cancelling the request) once its deadline passes. A request whose
+/// deadline passed already is turned away without running `future`.
+async fn by_deadline<T>(
+    ctx: &Context,
+    service: &dyn Service,
+    action: &str,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(deadline) = ctx.deadline() else {
+        return future.await;
+    };
+    if deadline <= Instant::now() {
+        return Err(missed_deadline(service, action).into());
+    }
+    tokio::time::timeout_at(deadline.into(), future).await.unwrap_or_else(|_| {
+        ctx.cancellation().cancel();
+        Err(missed_deadline(service, action).into())
+    })
+}
+
+/// A duration in the largest unit that shows it exactly, as accepted by
+/// `#[command(timeout)]`: "500ms", "30s", "5m", "1h".
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let ms = duration.as_millis();
+    [("h", 3_600_000), ("m", 60_000), ("s", 1000)]
+        .into_iter()
+        .find(|&(_, scale)| ms > 0 && ms.is_multiple_of(scale))
+        .map_or_else(|| format!("{}ms", ms), |(unit, scale)| format!("{}{}", ms / scale, unit))
+}
+
+/// Predicate over argument names selecting secrets to redact from traces.
+pub type Redact = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Log how a command invocation ended, inside its span.
+fn trace_outcome(elapsed: Duration, error: Option<&dyn std::fmt::Display>) {
+    let duration_ms = elapsed.as_millis() as u64;
+    match error {
+        None => tracing::info!(duration_ms, "command succeeded"),
+        Some(e) => tracing::warn!(duration_ms, error = %e, "command failed"),
+    }
+}
+
+/// Scheduling class of a command. When the concurrency limit is reached,
+/// waiting commands are admitted highest priority first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low = 0,
+    #[default]
+    Normal = 1,
+    High = 2,
+}
+
+impl FromStr for Priority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "high" => Ok(Priority::High),
+            _ => Err(anyhow::anyhow!("invalid priority '{}' (expected low, normal, or high)", s)),
+        }
+    }
+}
+
+/// Result of a command: plain text, a JSON document, a table the client
+/// renders itself, or binary data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandOutput {
+    Text(String),
+    Json(serde_json::Value),
+    Table {
+        headers: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+    /// Bytes that are not text (a disk header, an archive), sent as is; the
+    /// CLI saves them to a file rather than print them.
+    Bytes(Vec<u8>),
+}
+
+impl CommandOutput {
+    /// Build a table from any string-like headers and cells.
+    pub fn table<H, R, C>(headers: H, rows: R) -> Self
+    where
+        H: IntoIterator,
+        H::Item: Into<String>,
+        R: IntoIterator<Item = C>,
+        C: IntoIterator,
+        C::Item: Into<String>,
+    {
+        CommandOutput::Table {
+            headers: headers.into_iter().map(Into::into).collect(),
+            rows: rows
+                .into_iter()
+                .map(|row| row.into_iter().map(Into::into).collect())
+                .collect(),
+        }
+    }
+
+    /// Plain-text rendering: text as is, pretty-printed JSON, a table with
+    /// space-aligned columns, or the size of binary data.
+    pub fn to_plain(&self) -> String {
+        match self {
+            CommandOutput::Text(text) => text.clone(),
+            CommandOutput::Json(value) => {
+                serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+            }
+            CommandOutput::Table { headers, rows } => format_table(headers, rows),
+            CommandOutput::Bytes(bytes) => format!("({} bytes of binary data)", bytes.len()),
+        }
+    }
+
+    /// YAML rendering of `to_json`.
+    pub fn to_yaml(&self) -> String {
+        crate::yaml::to_string(&self.to_json())
+    }
+
+    /// The output as a table where it has that shape: a JSON array of
+    /// objects becomes one row per object and a column per key, and a JSON
+    /// object one row per key. Anything else is returned as is.
+    pub fn tabulated(self) -> CommandOutput {
+        fn cell(value: &serde_json::Value) -> String {
+            match value {
+                serde_json::Value::String(s) => s.clone(),
+                value => value.to_string(),
+            }
+        }
+        match self {
+            CommandOutput::Json(serde_json::Value::Array(items))
+                if !items.is_empty() && items.iter().all(serde_json::Value::is_object) =>
+            {
+                let mut headers: Vec<String> = Vec::new();
+                for key in items.iter().filter_map(serde_json::Value::as_object).flat_map(|item| item.keys()) {
+                    if !headers.contains(key) {
+                        headers.push(key.clone());
+                    }
+                }
+                let rows = items
+                    .iter()
+                    .map(|item| headers.iter().map(|key| item.get(key).map(cell).unwrap_or_default()).collect())
+                    .collect();
+                CommandOutput::Table { headers, rows }
+            }
+            CommandOutput::Json(serde_json::Value::Object(map)) => CommandOutput::Table {
+                headers: vec!["key".to_string(), "value".to_string()],
+                rows: map.iter().map(|(key, value)| vec![key.clone(), cell(value)]).collect(),
+            },
+            output => output,
+        }
+    }
+
+    /// The output rendered in `format`, as the server sends it in
+    /// `CommandResponse.message`.
+    pub fn render(&self, format: OutputFormat) -> String {
+        match format {
+            OutputFormat::Plain => self.to_plain(),
+            OutputFormat::Json => serde_json::to_string_pretty(&self.to_json()).unwrap_or_default(),
+            OutputFormat::Yaml => self.to_yaml(),
+            OutputFormat::Table => self.clone().tabulated().to_plain(),
+        }
+    }
+
+    /// JSON rendering: tables become an array of objects keyed by header,
+    /// and binary data a base64 string.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            CommandOutput::Text(text) => serde_json::Value::String(text.clone()),
+            CommandOutput::Bytes(bytes) => serde_json::Value::String(STANDARD.encode(bytes)),
+            CommandOutput::Json(value) => value.clone(),
+            CommandOutput::Table { headers, rows } => serde_json::Value::Array(
+                rows.iter()
+                    .map(|row| {
+                        serde_json::Value::Object(
+                            headers
+                                .iter()
+                                .cloned()
+                                .zip(row.iter().cloned().map(serde_json::Value::String))
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Values offered when this output is used as a completer: comma-separated
+    /// text items, the strings of a JSON array, or a table's first column.
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            CommandOutput::Text(text) => text
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            CommandOutput::Json(serde_json::Value::Array(items)) => items
+                .iter()
+                .filter_map(|item| item.as_str().map(str::to_string))
+                .collect(),
+            CommandOutput::Json(_) | CommandOutput::Bytes(_) => Vec::new(),
+            CommandOutput::Table { rows, .. } => {
+                rows.iter().filter_map(|row| row.first().cloned()).collect()
+            }
+        }
+    }
+
+    /// Combine the outputs of several invocations (e.g. a glob expansion).
+    /// Tables with identical headers merge their rows and binary data is
+    /// appended; anything else is joined as plain text, one output per line.
+    pub fn concat(outputs: Vec<CommandOutput>) -> CommandOutput {
+        if !outputs.is_empty() && outputs.iter().all(|o| matches!(o, CommandOutput::Bytes(_))) {
+            let mut merged = Vec::new();
+            for output in outputs {
+                if let CommandOutput::Bytes(bytes) = output {
+                    merged.extend(bytes);
+                }
+            }
+            return CommandOutput::Bytes(merged);
+        }
+        let same_table = match outputs.first() {
+            Some(CommandOutput::Table { headers, .. }) => outputs.iter().all(
+                |o| matches!(o, CommandOutput::Table { headers: h, .. } if h == headers),
+            ),
+            _ => false,
+        };
+        if same_table {
+            let mut merged_headers = Vec::new();
+            let mut merged_rows = Vec::new();
+            for output in outputs {
+                if let CommandOutput::Table { headers, rows } = output {
+                    merged_headers = headers;
+                    merged_rows.extend(rows);
+                }
+            }
+            return CommandOutput::Table {
+                headers: merged_headers,
+                rows: merged_rows,
+            };
+        }
+        CommandOutput::Text(
+            outputs
+                .iter()
+                .map(CommandOutput::to_plain)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+}
+
+impl From<String> for CommandOutput {
+    fn from(text: String) -> Self {
+        CommandOutput::Text(text)
+    }
+}
+
+impl From<&str> for CommandOutput {
+    fn from(text: &str) -> Self {
+        CommandOutput::Text(text.to_string())
+    }
+}
+
+/// No output, for commands that only report success.
+impl From<()> for CommandOutput {
+    fn from(_: ()) -> Self {
+        CommandOutput::Text(String::new())
+    }
+}
+
+impl From<serde_json::Value> for CommandOutput {
+    fn from(value: serde_json::Value) -> Self {
+        CommandOutput::Json(value)
+    }
+}
+
+impl From<Vec<u8>> for CommandOutput {
+    fn from(bytes: Vec<u8>) -> Self {
+        CommandOutput::Bytes(bytes)
+    }
+}
+
+/// Render rows under headers with columns padded to their widest cell.
+pub fn format_table(headers: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            let len = cell.chars().count();
+            match widths.get_mut(i) {
+                Some(width) => *width = (*width).max(len),
+                None => widths.push(len),
+            }
+        }
+    }
+    let render = |cells: &[String]| {
+        cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:<width$}", cell, width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let mut lines = vec![render(headers)];
+    lines.extend(rows.iter().map(|row| render(row)));
+    lines.join("\n")
+}
 
 /// Metadata about a single argument on a command.
 #[derive(Debug, Clone)]
@@ -10,8 +376,26 @@ pub struct ArgInfo {
     pub hint: String,
     /// Completer reference in "service.command" form (e.g. "block.list").
     pub completer: String,
+    /// Names of the command's other arguments whose values are passed to
+    /// the completer, in order (`complete = "volume.snapshot.list($volume)"`).
+    pub completer_args: Vec<String>,
     /// Human-readable description of this argument.
     pub description: String,
+    /// Whether glob patterns (e.g. `vol*`) are expanded server-side against
+    /// the completer's values, running the command once per match.
+    pub glob: bool,
+    /// Whether the argument may be omitted (`Option<T>` or has a default).
+    pub optional: bool,
+    /// Value used when the argument is omitted (`#[arg(default = "...")]`), or empty.
+    pub default: String,
+    /// Declared Rust type of the parameter (e.g. "u64", "Option<String>").
+    pub type_name: String,
+    /// Whether the value is sensitive (`#[arg(secret)]`) and must not be logged.
+    pub secret: bool,
+    /// The only accepted values (`#[arg(choices(...))]`), or empty.
+    pub choices: Vec<String>,
+    /// Whether this trailing `Vec<T>` parameter takes all remaining positional values.
+    pub variadic: bool,
 }
 
 /// Metadata about a single command on a service.
@@ -19,59 +403,1563 @@ pub struct ArgInfo {
 pub struct CommandInfo {
     pub name: String,
     pub args: Vec<ArgInfo>,
+    /// Summary line (first paragraph of the doc comment).
     pub description: String,
+    /// Full doc comment with line breaks, shown by `help <service> <command> --full`.
+    pub help: String,
+    /// Whether the command honors `Context::dry_run` (`#[command(dry_run)]`).
+    pub dry_run: bool,
+    /// Default scheduling class (`#[command(priority = "high")]`).
+    pub priority: Priority,
+    /// Whether the command streams its output (returns `impl Stream`) and
+    /// is run through `Service::execute_stream`.
+    pub streaming: bool,
+    /// Permission the caller's role must grant (`#[command(permission = "admin")]`),
+    /// or empty if anyone may run the command.
+    pub permission: String,
+    /// How long the command may run before it is abandoned with a timeout
+    /// error (`#[command(timeout = "30s")]`), overriding the server default.
+    pub timeout: Option<Duration>,
+    /// Whether the command runs as a background job (`#[command(background)]`):
+    /// the request returns a job ID and the command keeps running.
+    pub background: bool,
+    /// Whether the command reports progress (takes a `Progress` parameter),
+    /// which `ExecuteStream` forwards to the client.
+    pub progress: bool,
+    /// Version of the service the command first appeared in
+    /// (`#[command(since = "1.2")]`), or empty.
+    pub since: String,
+    /// Set if the command is deprecated (`#[command(deprecated)]`), to what
+    /// to use instead (`#[command(deprecated = "use destroy")]`) or empty.
+    pub deprecated: Option<String>,
+    /// Whether the command only reads state (`#[command(readonly)]`). Other
+    /// commands of the service drop its cached results when they succeed.
+    pub readonly: bool,
+    /// How long a result of the read-only command is reused for the same
+    /// tenant and arguments (`#[command(readonly, cache = "5s")]`).
+    pub cache: Option<Duration>,
+    /// Resource the command holds while it runs (`#[command(lock =
+    /// "pool:{pool}")]`), with `{arg}` standing for an argument's value, or
+    /// empty. Commands taking the same key run one at a time.
+    pub lock: String,
+    /// Whether a command finding its lock taken fails as `Busy` (retry after
+    /// `LOCK_RETRY`) instead of waiting (`#[command(try_lock = "...")]`).
+    pub try_lock: bool,
+    /// Whether the command takes a file (an `Upload` parameter), sent with
+    /// it through the `Upload` RPC.
+    pub upload: bool,
+    /// Whether the command sends a file back (takes a `Download`
+    /// parameter), received through the `Download` RPC.
+    pub download: bool,
+    /// Whether the command asks questions as it runs (takes a `Session`
+    /// parameter), answered through the `Session` RPC.
+    pub interactive: bool,
+}
+
+/// Arguments of a command invocation: positional values plus `--name value` flags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    pub positional: Vec<String>,
+    pub named: HashMap<String, String>,
+}
+
+impl Args {
+    pub fn new(positional: Vec<String>) -> Self {
+        Self {
+            positional,
+            named: HashMap::new(),
+        }
+    }
+
+    pub fn with_named(mut self, name: &str, value: &str) -> Self {
+        self.named.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Assign values to parameters in declaration order. A `--name` flag wins;
+    /// otherwise the parameter takes the next positional value. Unknown flags
+    /// and leftover positional values are errors.
+    pub fn bind(&self, names: &[&str]) -> Result<Vec<Option<String>>> {
+        if let Some(unknown) = self.named.keys().find(|k| !names.contains(&k.as_str())) {
+            return Err(NexusError::invalid_argument(format!("unknown option '--{}'", unknown)).into());
+        }
+        let mut positional = self.positional.iter();
+        let slots: Vec<Option<String>> = names
+            .iter()
+            .map(|name| match self.named.get(*name) {
+                Some(value) => Some(value.clone()),
+                None => positional.next().cloned(),
+            })
+            .collect();
+        if positional.next().is_some() {
+            return Err(NexusError::invalid_argument(format!(
+                "too many arguments (expected at most {})",
+                names.len()
+            ))
+            .into());
+        }
+        Ok(slots)
+    }
+
+    /// Like `bind`, but the last parameter is variadic: it takes its `--name`
+    /// value, if given, followed by every positional value left over.
+    pub fn bind_variadic(&self, names: &[&str]) -> Result<(Vec<Option<String>>, Vec<String>)> {
+        let Some((last, fixed)) = names.split_last() else {
+            return Ok((self.bind(names)?, Vec::new()));
+        };
+        if let Some(unknown) = self.named.keys().find(|k| !names.contains(&k.as_str())) {
+            return Err(NexusError::invalid_argument(format!("unknown option '--{}'", unknown)).into());
+        }
+        let mut positional = self.positional.iter();
+        let slots: Vec<Option<String>> = fixed
+            .iter()
+            .map(|name| match self.named.get(*name) {
+                Some(value) => Some(value.clone()),
+                None => positional.next().cloned(),
+            })
+            .collect();
+        let rest = self.named.get(*last).into_iter().chain(positional).cloned().collect();
+        Ok((slots, rest))
+    }
+}
+
+/// Bind `args` to `info`'s parameters: the single-valued slots, then the
+/// values of a trailing variadic parameter (empty if there is none).
+pub(crate) fn bind_command(info: &CommandInfo, args: &Args) -> Result<(Vec<Option<String>>, Vec<String>)> {
+    let names: Vec<&str> = info.args.iter().map(|arg| arg.name.as_str()).collect();
+    if info.args.last().is_some_and(|arg| arg.variadic) {
+        args.bind_variadic(&names)
+    } else {
+        Ok((args.bind(&names)?, Vec::new()))
+    }
+}
+
+/// Fail, as the command itself would, if a required argument is missing.
+/// Lets `start_job` reject such requests instead of starting a job that
+/// fails at once.
+fn check_required(info: &CommandInfo, args: &Args) -> Result<()> {
+    let (slots, rest) = bind_command(info, args)?;
+    let required = info.args.iter().filter(|arg| !arg.optional).count();
+    for (i, arg) in info.args.iter().enumerate().filter(|(_, arg)| !arg.optional) {
+        let bound = slots.get(i).is_some_and(Option::is_some);
+        if arg.variadic && !bound && rest.is_empty() {
+            return Err(NexusError::invalid_argument(format!(
+                "missing argument '{}' (expected at least one value)",
+                arg.name
+            ))
+            .into());
+        }
+        if !arg.variadic && !bound {
+            return Err(NexusError::invalid_argument(format!(
+                "missing argument '{}' (expected {} args)",
+                arg.name, required
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+impl From<Vec<String>> for Args {
+    fn from(positional: Vec<String>) -> Self {
+        Self::new(positional)
+    }
+}
+
+/// Run `f`, the body of a synchronous command method, without stalling the
+/// other tasks of this worker thread: on a multi-threaded runtime the
+/// thread hands them to another one while `f` runs. Called by the code
+/// `#[nexus_service]` generates.
+#[doc(hidden)]
+pub fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// Run `f`, the body of a `#[command(blocking)]` method, on tokio's pool of
+/// blocking threads. A panic in `f` fails the command. Called by the code
+/// `#[nexus_service]` generates.
+#[doc(hidden)]
+pub async fn offload<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| NexusError::internal(format!("blocking command failed: {}", e)).into())
+}
+
+/// What `Service::on_start` is given: the server's registry, and a token
+/// cancelled when the server begins to stop, for background tasks the
+/// service starts.
+#[derive(Clone)]
+pub struct StartContext {
+    pub handle: RegistryHandle,
+    pub stopping: CancellationToken,
+}
+
+impl StartContext {
+    /// The server's shared value of type `T`, as `Context::state`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.handle.registry.states.get()
+    }
+
+    /// The server's event bus, for tasks the hook starts to emit events
+    /// from (a device watcher), as commands do with `Context::emit`.
+    pub fn events(&self) -> EventBus {
+        self.handle.events()
+    }
 }
 
 /// Trait that every service must implement. Use `#[nexus_service]` to auto-generate.
 #[async_trait]
 pub trait Service: Send + Sync + 'static {
-    /// The service name used for dispatch (e.g., "volume").
+    /// The service name used for dispatch (e.g., "volume"). A service
+    /// nested under another is named by their path, as in "volume.snapshot",
+    /// and invoked as `volume snapshot <command>`.
     fn name(&self) -> &str;
 
+    /// Other names the service can be invoked by (e.g., "vol"). Those of a
+    /// nested service stand in for its last name segment only.
+    fn aliases(&self) -> &[&str] {
+        &[]
+    }
+
     /// Human-readable description of the service (from doc comments on the impl block).
     fn description(&self) -> &str;
 
     /// List of commands this service supports.
     fn commands(&self) -> Vec<CommandInfo>;
 
-    /// Execute a command by action name with positional string arguments.
-    async fn execute(&self, action: &str, args: Vec<String>) -> Result<String>;
+    /// Most commands of this service that may run at once
+    /// (`#[nexus_service(max_concurrency = 4)]`); further ones queue by
+    /// priority. `None` leaves only the server-wide limit.
+    fn max_concurrency(&self) -> Option<usize> {
+        None
+    }
+
+    /// How many commands may queue for a slot once `max_concurrency` is
+    /// reached (`#[nexus_service(max_queue = 16)]`); further ones fail as
+    /// busy. `None` lets the queue grow without bound.
+    fn max_queue(&self) -> Option<usize> {
+        None
+    }
+
+    /// Execute a command by action name with positional and named string arguments.
+    async fn execute(&self, ctx: &Context, action: &str, args: Args) -> Result<CommandOutput>;
+
+    /// Start a streaming command. Returns `None` if `action` is not one of
+    /// this service's streaming commands; services without any keep the default.
+    async fn execute_stream(
+        &self,
+        _ctx: &Context,
+        _action: &str,
+        _args: Args,
+    ) -> Option<Result<CommandStream>> {
+        None
+    }
+
+    /// Undo `action`, which succeeded with `args`, because a later command
+    /// of an atomic batch (the `ExecuteBatch` RPC) failed. With
+    /// `#[nexus_service]`, mark a method `#[rollback]` to implement it;
+    /// services without one cannot be rolled back.
+    async fn rollback(&self, _ctx: &Context, _action: &str, _args: Args) -> Result<()> {
+        anyhow::bail!("cannot be rolled back")
+    }
+
+    /// Report whether the service can currently serve requests. Polled by
+    /// health probes and the CLI `health` builtin. With `#[nexus_service]`,
+    /// mark a method `#[health]` to implement it.
+    async fn health(&self) -> Health {
+        Health::Serving
+    }
+
+    /// Called once when the server starts, before it accepts requests, e.g.
+    /// to open devices or start background tasks. An error stops the server
+    /// from starting. With `#[nexus_service]`, mark a method `#[on_start]`
+    /// to run it here.
+    async fn on_start(&self, _ctx: &StartContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the server begins to stop, while in-flight requests
+    /// drain, e.g. to stop the background tasks started in `on_start`;
+    /// `shutdown` follows once both are done. With `#[nexus_service]`, mark
+    /// a method `#[on_stop]` to run it here.
+    async fn on_stop(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the server stops, after in-flight requests finish.
+    /// With `#[nexus_service]`, mark a method `#[shutdown]` to run it here.
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A service registered with `Registry::register_nested`, renamed to its
+/// path under the parent.
+struct Nested<S> {
+    name: String,
+    service: S,
+}
+
+#[async_trait]
+impl<S: Service> Service for Nested<S> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn aliases(&self) -> &[&str] {
+        self.service.aliases()
+    }
+
+    fn description(&self) -> &str {
+        self.service.description()
+    }
+
+    fn commands(&self) -> Vec<CommandInfo> {
+        self.service.commands()
+    }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        self.service.max_concurrency()
+    }
+
+    fn max_queue(&self) -> Option<usize> {
+        self.service.max_queue()
+    }
+
+    async fn execute(&self, ctx: &Context, action: &str, args: Args) -> Result<CommandOutput> {
+        self.service.execute(ctx, action, args).await
+    }
+
+    async fn execute_stream(
+        &self,
+        ctx: &Context,
+        action: &str,
+        args: Args,
+    ) -> Option<Result<CommandStream>> {
+        self.service.execute_stream(ctx, action, args).await
+    }
+
+    async fn rollback(&self, ctx: &Context, action: &str, args: Args) -> Result<()> {
+        self.service.rollback(ctx, action, args).await
+    }
+
+    async fn health(&self) -> Health {
+        self.service.health().await
+    }
+
+    async fn on_start(&self, ctx: &StartContext) -> Result<()> {
+        self.service.on_start(ctx).await
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        self.service.on_stop().await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.service.shutdown().await
+    }
 }
 
 /// Holds registered services and dispatches commands to them.
+///
+/// Services registered with `register_for_tenant` are visible only to
+/// requests carrying that tenant ID and take precedence over a shared
+/// service of the same name. Services can be added and removed while the
+/// server runs (see `RegistryHandle`); every change bumps the revision.
 pub struct Registry {
-    services: HashMap<String, Box<dyn Service>>,
+    catalog: RwLock<Catalog>,
+    limiter: Option<Arc<PriorityLimiter>>,
+    auth: Option<Arc<dyn Authenticator>>,
+    metrics: Option<Arc<Metrics>>,
+    /// Decides by name which further arguments are secrets.
+    redact: Option<Redact>,
+    /// Revision of the catalog, bumped on every change.
+    revision: watch::Sender<u64>,
+    /// Time limit of non-streaming commands without their own.
+    default_timeout: Option<Duration>,
+    /// Background jobs, running and recently finished.
+    jobs: Jobs,
+    /// How many commands deep `Context::call` may nest.
+    max_call_depth: usize,
+    /// Values added with `NexusServer::with_state`, handed to every request.
+    states: Arc<States>,
+    /// Results of `#[command(cache)]` commands.
+    cache: ResultCache,
+    /// Locks of `#[command(lock)]` commands.
+    locks: Locks,
+    maintenance: Arc<Maintenance>,
+    /// Where `Context::emit` publishes, for `Subscribe` callers.
+    events: EventBus,
+}
+
+/// Maintenance mode (`admin maintenance on`), for firmware updates and
+/// migrations: while it is on, only read-only commands run and the others
+/// fail as unavailable, with the operator's message.
+#[derive(Default)]
+pub(crate) struct Maintenance {
+    message: RwLock<Option<String>>,
+}
+
+impl Maintenance {
+    /// The operator's message if maintenance mode is on; it may be empty.
+    pub(crate) fn get(&self) -> Option<String> {
+        self.message.read().unwrap().clone()
+    }
+
+    /// Turn maintenance mode on with `Some(message)`, or off with `None`.
+    pub(crate) fn set(&self, message: Option<String>) {
+        match &message {
+            Some(message) => tracing::warn!(message, "maintenance mode on"),
+            None => tracing::info!("maintenance mode off"),
+        }
+        *self.message.write().unwrap() = message;
+    }
+}
+
+/// The registered services. Lookups clone a service out so that the lock is
+/// never held while a command runs.
+#[derive(Default)]
+struct Catalog {
+    services: HashMap<String, Arc<dyn Service>>,
+    /// tenant ID -> service name -> tenant-scoped instance
+    tenant_services: HashMap<String, HashMap<String, Arc<dyn Service>>>,
+    /// alias -> service name; aliases of nested services are keyed by
+    /// their full path, e.g. "volume.snap" -> "volume.snapshot"
+    aliases: HashMap<String, String>,
+    /// service name -> slots of a service with `max_concurrency`, shared by
+    /// its tenant instances
+    limiters: HashMap<String, Arc<PriorityLimiter>>,
+    /// The built-in `admin` service, which still runs in maintenance mode
+    admin: Option<Arc<dyn Service>>,
+}
+
+impl Catalog {
+    /// Record the aliases and concurrency limit of a service being added.
+    fn index(&mut self, service: &dyn Service) {
+        if let Some(permits) = service.max_concurrency() {
+            let limiter = match service.max_queue() {
+                Some(max_queued) => PriorityLimiter::with_queue_limit(permits, max_queued),
+                None => PriorityLimiter::new(permits),
+            };
+            self.limiters.insert(service.name().to_string(), limiter);
+        }
+        let parent = service.name().rsplit_once('.').map(|(parent, _)| parent);
+        for alias in service.aliases() {
+            let alias = match parent {
+                Some(parent) => format!("{}.{}", parent, alias),
+                None => alias.to_string(),
+            };
+            self.aliases.insert(alias, service.name().to_string());
+        }
+    }
+
+    /// The full name of a service given by name or alias, resolving each
+    /// segment of a nested path in turn: "vol.snap" -> "volume.snapshot".
+    fn resolve(&self, service_name: &str) -> String {
+        let mut segments = service_name.split('.');
+        let first = segments.next().unwrap_or_default();
+        let mut name = self.aliases.get(first).cloned().unwrap_or_else(|| first.to_string());
+        for segment in segments {
+            let path = format!("{}.{}", name, segment);
+            name = self.aliases.get(&path).cloned().unwrap_or(path);
+        }
+        name
+    }
+
+    /// Names and aliases of the services visible to `tenant`.
+    fn names(&self, tenant: Option<&str>) -> Vec<&str> {
+        let own = tenant.and_then(|tenant| self.tenant_services.get(tenant));
+        let visible = |name: &str| self.services.contains_key(name) || own.is_some_and(|own| own.contains_key(name));
+        let mut names: Vec<&str> = self
+            .services
+            .keys()
+            .chain(own.into_iter().flat_map(|own| own.keys()))
+            .map(String::as_str)
+            .collect();
+        names.extend(self.aliases.iter().filter(|(_, name)| visible(name)).map(|(alias, _)| alias.as_str()));
+        names
+    }
+
+    /// Drop the aliases and limiter of `name` once no instance of it is left.
+    fn prune(&mut self, name: &str) {
+        let in_use = self.services.contains_key(name)
+            || self.tenant_services.values().any(|services| services.contains_key(name));
+        if !in_use {
+            self.aliases.retain(|_, target| target != name);
+            self.limiters.remove(name);
+        }
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Registry {
     pub fn new() -> Self {
         Self {
-            services: HashMap::new(),
+            catalog: RwLock::new(Catalog::default()),
+            limiter: None,
+            auth: None,
+            metrics: None,
+            redact: None,
+            revision: watch::Sender::new(0),
+            default_timeout: None,
+            jobs: Jobs::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            states: Arc::default(),
+            cache: ResultCache::default(),
+            locks: Locks::default(),
+            maintenance: Arc::default(),
+            events: EventBus::default(),
+        }
+    }
+
+    /// Cap the number of commands executing at once across all services.
+    pub fn set_max_concurrency(&mut self, permits: usize) {
+        self.limiter = Some(PriorityLimiter::new(permits));
+    }
+
+    /// Enforce `#[command(permission)]` annotations using `auth` to decide
+    /// which roles hold which permissions.
+    pub fn set_authenticator(&mut self, auth: Arc<dyn Authenticator>) {
+        self.auth = Some(auth);
+    }
+
+    /// Abandon non-streaming commands that run longer than `timeout`, unless
+    /// they set their own with `#[command(timeout)]`. Streaming commands and
+    /// background jobs are often meant to run for long, so they are only
+    /// limited by their own timeout.
+    pub fn set_default_timeout(&mut self, timeout: Duration) {
+        self.default_timeout = Some(timeout);
+    }
+
+    /// Save background jobs in `dir`, to be loaded again when the server
+    /// next starts.
+    pub fn set_job_store(&mut self, dir: PathBuf) {
+        self.jobs.store = Some(JobStore::new(dir));
+    }
+
+    /// Keep the finished jobs `retention` allows instead of the latest
+    /// `MAX_FINISHED_JOBS`.
+    pub fn set_job_retention(&mut self, retention: JobRetention) {
+        self.jobs.retention = retention;
+    }
+
+    /// Let `Context::call` nest up to `depth` commands, counting the one
+    /// the client asked for, instead of `DEFAULT_MAX_CALL_DEPTH`.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// The time limit a command runs under, if any.
+    pub(crate) fn timeout(&self, info: Option<&CommandInfo>) -> Option<Duration> {
+        match info {
+            Some(info) if info.streaming || info.background || info.interactive => info.timeout,
+            Some(info) => info.timeout.or(self.default_timeout),
+            None => self.default_timeout,
         }
     }
 
-    pub fn register<S: Service>(&mut self, service: S) {
-        self.services
-            .insert(service.name().to_string(), Box::new(service));
+    /// The invocation counts, errors and latencies of every command,
+    /// recorded from the first call on.
+    pub(crate) fn metrics(&mut self) -> Arc<Metrics> {
+        self.metrics.get_or_insert_with(Default::default).clone()
+    }
+
+    /// Share `value` with every service, as `State<T>` parameters and
+    /// `Context::state`, replacing an earlier value of the same type.
+    pub fn set_state<T: Send + Sync + 'static>(&mut self, value: Arc<T>) {
+        Arc::get_mut(&mut self.states)
+            .expect("shared state must be added before the server starts")
+            .insert(value);
+    }
+
+    /// The shared state, to attach to request contexts.
+    pub(crate) fn states(&self) -> Arc<States> {
+        self.states.clone()
+    }
+
+    /// Turn maintenance mode on with `Some(message)`, shown to the callers
+    /// it turns away, or off with `None`.
+    pub fn set_maintenance(&self, message: Option<String>) {
+        self.maintenance.set(message);
+    }
+
+    /// The operator's message if maintenance mode is on.
+    pub fn maintenance(&self) -> Option<String> {
+        self.maintenance.get()
+    }
+
+    /// The bus `Context::emit` publishes events to.
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// The maintenance switch, for the `admin` service.
+    pub(crate) fn maintenance_mode(&self) -> Arc<Maintenance> {
+        self.maintenance.clone()
+    }
+
+    /// Also redact, in traces, the arguments whose name `redact` returns
+    /// `true` for (on top of those marked `#[arg(secret)]`).
+    pub fn set_redaction(&mut self, redact: Redact) {
+        self.redact = Some(redact);
+    }
+
+    pub fn register<S: Service>(&self, service: S) {
+        self.insert(Arc::new(service));
+    }
+
+    /// Register `service` nested under the service called `parent`, so its
+    /// commands are invoked as `<parent> <service> <command>`. This is the
+    /// same as `#[nexus_service(parent = "...")]`, for services whose
+    /// placement is only known at runtime.
+    pub fn register_nested<S: Service>(&self, parent: &str, service: S) {
+        self.insert(Arc::new(Nested {
+            name: format!("{}.{}", parent, service.name()),
+            service,
+        }));
+    }
+
+    /// Register an already shared service, e.g. one loaded from a plugin.
+    pub(crate) fn insert(&self, service: Arc<dyn Service>) {
+        let mut catalog = self.catalog.write().unwrap();
+        catalog.index(service.as_ref());
+        catalog
+            .services
+            .insert(service.name().to_string(), service);
+        drop(catalog);
+        self.changed();
+    }
+
+    /// Register the built-in `admin` service, whose commands run in
+    /// maintenance mode too (so it can be turned off again).
+    pub(crate) fn register_admin<S: Service>(&self, service: S) {
+        let service: Arc<dyn Service> = Arc::new(service);
+        self.catalog.write().unwrap().admin = Some(service.clone());
+        self.insert(service);
+    }
+
+    /// Register a service instance serving only requests from `tenant`.
+    pub fn register_for_tenant<S: Service>(&self, tenant: &str, service: S) {
+        let mut catalog = self.catalog.write().unwrap();
+        catalog.index(&service);
+        catalog
+            .tenant_services
+            .entry(tenant.to_string())
+            .or_default()
+            .insert(service.name().to_string(), Arc::new(service));
+        drop(catalog);
+        self.changed();
+    }
+
+    /// Remove the shared service called `name`, returning it if there was one.
+    pub fn deregister(&self, name: &str) -> Option<Arc<dyn Service>> {
+        let mut catalog = self.catalog.write().unwrap();
+        let removed = catalog.services.remove(name)?;
+        if catalog.admin.as_ref().is_some_and(|admin| Arc::ptr_eq(admin, &removed)) {
+            catalog.admin = None;
+        }
+        catalog.prune(name);
+        drop(catalog);
+        self.changed();
+        Some(removed)
+    }
+
+    /// Remove `tenant`'s own instance of the service called `name`.
+    pub fn deregister_for_tenant(&self, tenant: &str, name: &str) -> Option<Arc<dyn Service>> {
+        let mut catalog = self.catalog.write().unwrap();
+        let services = catalog.tenant_services.get_mut(tenant)?;
+        let removed = services.remove(name)?;
+        if services.is_empty() {
+            catalog.tenant_services.remove(tenant);
+        }
+        catalog.prune(name);
+        drop(catalog);
+        self.changed();
+        Some(removed)
+    }
+
+    fn changed(&self) {
+        self.revision.send_modify(|revision| *revision += 1);
+    }
+
+    /// Revision of the set of services; it changes whenever a service is
+    /// registered or removed.
+    pub fn revision(&self) -> u64 {
+        *self.revision.borrow()
+    }
+
+    /// Follow changes to the set of services.
+    pub(crate) fn subscribe(&self) -> watch::Receiver<u64> {
+        self.revision.subscribe()
+    }
+
+    /// Resolve a service name or alias for the request's tenant: its own
+    /// instance first, then the shared one.
+    fn service(&self, ctx: &Context, service_name: &str) -> Result<Arc<dyn Service>> {
+        let catalog = self.catalog.read().unwrap();
+        let name = catalog.resolve(service_name);
+        ctx.tenant
+            .as_ref()
+            .and_then(|tenant| catalog.tenant_services.get(tenant))
+            .and_then(|services| services.get(&name))
+            .or_else(|| catalog.services.get(&name))
+            .cloned()
+            .ok_or_else(|| {
+                let mut suggestions = closest(service_name, catalog.names(ctx.tenant.as_deref()));
+                // One name per service, the closest of it and its aliases.
+                let mut seen = HashSet::new();
+                suggestions.retain(|name| seen.insert(catalog.resolve(name)));
+                NexusError::not_found(format!("unknown service '{}'", service_name)).suggest(suggestions).into()
+            })
+    }
+
+    /// `error`, from running the unknown command `action`, with the commands
+    /// and nested services of `service` named closest to it suggested.
+    fn unknown_command(
+        &self,
+        ctx: &Context,
+        service: &dyn Service,
+        action: &str,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        match error.downcast::<NexusError>() {
+            Ok(e) if e.code == ErrorCode::NotFound && e.suggestions.is_empty() => {
+                let nested = format!("{}.", service.name());
+                let catalog = self.catalog.read().unwrap();
+                let mut names: Vec<String> = service.commands().into_iter().map(|c| c.name).collect();
+                names.extend(
+                    catalog
+                        .names(ctx.tenant.as_deref())
+                        .into_iter()
+                        .filter_map(|name| name.strip_prefix(&nested))
+                        .filter(|name| !name.contains('.'))
+                        .map(str::to_string),
+                );
+                e.suggest(closest(action, names.iter().map(String::as_str))).into()
+            }
+            Ok(e) => e.into(),
+            Err(e) => e,
+        }
     }
 
     pub async fn execute(
         &self,
+        ctx: &Context,
         service_name: &str,
         action: &str,
-        args: Vec<String>,
-    ) -> Result<String> {
-        let service = self
-            .services
-            .get(service_name)
-            .ok_or_else(|| anyhow::anyhow!("unknown service '{}'", service_name))?;
-        service.execute(action, args).await
+        args: Args,
+    ) -> Result<CommandOutput> {
+        let (service, info) = self.lookup(ctx, service_name, action)?;
+        ctx.enter(service.name(), action);
+        let span = self.command_span(ctx, service.as_ref(), info.as_ref(), action, &args);
+        let started = Instant::now();
+        // Dropping this future before the command finishes (e.g. the client
+        // went away) cancels the request's token.
+        let abandoned = ctx.cancellation().clone().drop_guard();
+        let result = self
+            .dispatch(ctx, service.as_ref(), info.as_ref(), action, args)
+            .instrument(span.clone())
+            .await;
+        abandoned.disarm();
+        let result = match result {
+            Err(e) if info.is_none() => Err(self.unknown_command(ctx, service.as_ref(), action, e)),
+            result => result,
+        };
+        let elapsed = started.elapsed();
+        span.in_scope(|| trace_outcome(elapsed, result.as_ref().err().map(|e| e as _)));
+        // Unknown commands are not recorded, so callers cannot add series.
+        if let (Some(metrics), Some(_)) = (&self.metrics, &info) {
+            metrics.record(service.name(), action, elapsed, result.is_ok());
+        }
+        result
+    }
+
+    /// Run `action` of the service for the command running with `ctx`, as
+    /// `Context::call` does. The call runs with a context of its own that
+    /// records the chain of commands, so it can fail a cycle or too deep a
+    /// chain before running anything. It takes no concurrency slot, since
+    /// the outermost command holds one already and waiting for another
+    /// could deadlock. In a preview, a read-only command runs as usual and
+    /// any other as a dry run.
+    pub async fn call(
+        &self,
+        ctx: &Context,
+        service_name: &str,
+        action: &str,
+        args: Args,
+    ) -> Result<CommandOutput> {
+        let service = self.service(ctx, service_name)?;
+        let mut calls = ctx.calls();
+        let callee = (service.name().to_string(), action.to_string());
+        let cycle = calls.contains(&callee);
+        calls.push(callee);
+        let chain = || {
+            let commands: Vec<String> = calls
+                .iter()
+                .map(|(service, action)| format!("{} {}", crate::cli::command_path(service), action))
+                .collect();
+            commands.join(" -> ")
+        };
+        if cycle {
+            return Err(NexusError::internal(format!("call cycle: {}", chain())).into());
+        }
+        if calls.len() > self.max_call_depth {
+            let message = format!("calls nested deeper than {}: {}", self.max_call_depth, chain());
+            return Err(NexusError::internal(message).into());
+        }
+        let readonly = service.commands().iter().any(|c| c.name == action && c.readonly);
+        let callee = ctx
+            .detached()
+            .with_dry_run(ctx.dry_run && !readonly)
+            .with_progress(Progress::default())
+            .with_calls(calls);
+        let result = self.execute(&callee, service_name, action, args).await;
+        for change in callee.take_plan() {
+            ctx.propose(change);
+        }
+        for warning in callee.take_warnings() {
+            ctx.warn(warning);
+        }
+        result
+    }
+
+    /// Undo `action` of the service, which succeeded with `args` earlier in
+    /// the same batch, through the service's `Service::rollback`.
+    pub async fn rollback(&self, ctx: &Context, service_name: &str, action: &str, args: Args) -> Result<()> {
+        let service = self.service(ctx, service_name)?;
+        let result = service.rollback(ctx, action, args).await;
+        match &result {
+            Ok(()) => tracing::info!(service = service.name(), action, "command rolled back"),
+            Err(e) => tracing::warn!(service = service.name(), action, error = %e, "rollback failed"),
+        }
+        result
+    }
+
+    async fn dispatch(
+        &self,
+        ctx: &Context,
+        service: &dyn Service,
+        info: Option<&CommandInfo>,
+        action: &str,
+        args: Args,
+    ) -> Result<CommandOutput> {
+        if info.is_some_and(|info| info.streaming) {
+            // Unary callers of a streaming command get the whole output at once.
+            let mut stream = self.start_stream(ctx, service, info, action, args).await?;
+            let mut lines = Vec::new();
+            while let Some(line) = stream.next().await {
+                lines.push(line?);
+            }
+            return Ok(CommandOutput::Text(lines.join("\n")));
+        }
+        let ttl = info.and_then(|info| info.cache).filter(|_| !ctx.dry_run);
+        let Some(ttl) = ttl else {
+            let result = self.run(ctx, service, info, action, args).await;
+            if result.is_ok() && !info.is_some_and(|info| info.readonly) {
+                self.cache.invalidate(service.name());
+            }
+            return result;
+        };
+        let key = cache::key(ctx, service.name(), action, &args);
+        if let Some((output, warnings)) = self.cache.get(&key) {
+            tracing::debug!(service = service.name(), action, "cached result");
+            for warning in warnings {
+                ctx.warn(warning);
+            }
+            return Ok(output);
+        }
+        // Only the warnings of this run are kept with its result.
+        let earlier = ctx.take_warnings();
+        let result = self.run(ctx, service, info, action, args).await;
+        let warnings = ctx.take_warnings();
+        for warning in earlier.into_iter().chain(warnings.iter().cloned()) {
+            ctx.warn(warning);
+        }
+        if let Ok(output) = &result {
+            self.cache.insert(key, output.clone(), warnings, ttl);
+        }
+        result
+    }
+
+    /// Run a non-streaming command once it has a slot, under its time limit
+    /// and before the request's deadline.
+    async fn run(
+        &self,
+        ctx: &Context,
+        service: &dyn Service,
+        info: Option<&CommandInfo>,
+        action: &str,
+        args: Args,
+    ) -> Result<CommandOutput> {
+        // Unlike the time limit, the deadline covers waiting for the lock
+        // and a slot: the client stops waiting either way.
+        by_deadline(ctx, service, action, async {
+            // Globs are expanded first, so a lock is taken on each resource
+            // a pattern matches rather than on the pattern. The locks are
+            // taken before a slot, so commands waiting for them do not hold
+            // slots others could use.
+            let invocations = self.expand_globs(ctx, info, &args).await?;
+            let _locks = match &invocations {
+                Some(invocations) => self.locks.acquire(ctx, info, invocations).await?,
+                None => self.locks.acquire(ctx, info, std::slice::from_ref(&args)).await?,
+            };
+            let _permits = self.admit(ctx, service, info).await?;
+            // The time limit starts once the command has a slot, so waiting
+            // in the queue does not count against it.
+            let run = async {
+                let Some(invocations) = invocations else {
+                    return service.execute(ctx, action, args).await;
+                };
+                let mut outputs = Vec::with_capacity(invocations.len());
+                for args in invocations {
+                    outputs.push(service.execute(ctx, action, args).await?);
+                }
+                Ok(CommandOutput::concat(outputs))
+            };
+            let Some(limit) = self.timeout(info) else {
+                return run.await;
+            };
+            ctx.limit_deadline(Instant::now() + limit);
+            tokio::time::timeout(limit, run).await.unwrap_or_else(|_| {
+                ctx.cancellation().cancel();
+                Err(timed_out(service, action, limit).into())
+            })
+        })
+        .await
+    }
+
+    /// Whether `action` of the service is a `#[command(background)]` command
+    /// the request may start as a job (not when previewing it).
+    pub fn is_background(&self, ctx: &Context, service_name: &str, action: &str) -> bool {
+        !ctx.dry_run
+            && self.service(ctx, service_name).is_ok_and(|service| {
+                service.commands().iter().any(|c| c.name == action && c.background)
+            })
+    }
+
+    /// Whether `action` of the service reports progress, which the caller
+    /// can follow with `Context::with_progress`.
+    pub fn reports_progress(&self, ctx: &Context, service_name: &str, action: &str) -> bool {
+        self.service(ctx, service_name)
+            .is_ok_and(|service| service.commands().iter().any(|c| c.name == action && c.progress))
+    }
+
+    /// Whether `action` of the service takes a file, which the caller sends
+    /// through the `Upload` RPC.
+    pub fn takes_upload(&self, ctx: &Context, service_name: &str, action: &str) -> bool {
+        self.service(ctx, service_name)
+            .is_ok_and(|service| service.commands().iter().any(|c| c.name == action && c.upload))
+    }
+
+    /// Whether `action` of the service sends a file back, which the caller
+    /// receives through the `Download` RPC.
+    pub fn sends_download(&self, ctx: &Context, service_name: &str, action: &str) -> bool {
+        self.service(ctx, service_name)
+            .is_ok_and(|service| service.commands().iter().any(|c| c.name == action && c.download))
+    }
+
+    /// Whether `action` of the service asks questions as it runs, which the
+    /// caller answers through the `Session` RPC.
+    pub fn is_interactive(&self, ctx: &Context, service_name: &str, action: &str) -> bool {
+        self.service(ctx, service_name)
+            .is_ok_and(|service| service.commands().iter().any(|c| c.name == action && c.interactive))
+    }
+
+    /// Start a command as a background job and return the job's ID. The
+    /// request is checked (permission, arguments) before the job starts;
+    /// everything after that, including errors, is reported by the job.
+    /// `execute` still runs a background command to completion.
+    pub fn start_job(
+        self: &Arc<Self>,
+        ctx: &Context,
+        service_name: &str,
+        action: &str,
+        args: Args,
+    ) -> Result<u64> {
+        let (service, info) = self.lookup(ctx, service_name, action)?;
+        if let Some(info) = &info {
+            check_required(info, &args)?;
+        }
+        let described = self.describe_args(info.as_ref(), &args);
+        let (progress, updates) = match info.as_ref().is_some_and(|info| info.progress) {
+            true => {
+                let (progress, updates) = Progress::channel();
+                (progress, Some(updates))
+            }
+            false => (Progress::default(), None),
+        };
+        let id = self.jobs.add(service.name(), action, described, ctx.tenant.clone(), updates);
+        tracing::info!(job = id, service = service.name(), action, "job started");
+        // The job outlives the request, so it gets a token and deadline of
+        // its own, and does not count as holding the request's locks.
+        let ctx = ctx
+            .detached()
+            .with_cancellation(CancellationToken::new())
+            .with_deadline(None)
+            .with_progress(progress)
+            .with_locks(Vec::new());
+        let registry = self.clone();
+        let (service, action) = (service.name().to_string(), action.to_string());
+        let task = tokio::spawn(
+            async move {
+                let outcome = registry.execute(&ctx, &service, &action, args).await;
+                registry.jobs.complete(id, outcome, ctx.take_warnings());
+            }
+            .instrument(tracing::info_span!("job", id)),
+        );
+        self.jobs.attach(id, task.abort_handle());
+        Ok(id)
+    }
+
+    /// The background jobs started by `tenant`, oldest first. Finished jobs
+    /// are kept until `MAX_FINISHED_JOBS` newer ones have finished.
+    pub fn jobs(&self, tenant: Option<&str>) -> Vec<Job> {
+        self.jobs.list(tenant)
     }
 
-    pub fn list_services(&self) -> Vec<(&str, &str, Vec<CommandInfo>)> {
-        self.services
+    /// Job `id`, if it was started by `tenant` and is still known.
+    pub fn job(&self, tenant: Option<&str>, id: u64) -> Option<Job> {
+        self.jobs.get(tenant, id)
+    }
+
+    /// Cancel job `id` if it is running; a finished job is returned as is.
+    /// Cancelling drops the command's future and cancels its token, like a
+    /// timeout does.
+    pub fn cancel_job(&self, tenant: Option<&str>, id: u64) -> Option<Job> {
+        self.jobs.cancel(tenant, id)
+    }
+
+    /// Start a streaming command. The concurrency slot is held until the
+    /// returned stream is finished or dropped, and dropping it cancels the
+    /// request's token. Glob matches run one after another, their output
+    /// concatenated.
+    pub async fn execute_stream(
+        &self,
+        ctx: &Context,
+        service_name: &str,
+        action: &str,
+        args: Args,
+    ) -> Result<CommandStream> {
+        let (service, info) = self.lookup(ctx, service_name, action)?;
+        ctx.enter(service.name(), action);
+        let span = self.command_span(ctx, service.as_ref(), info.as_ref(), action, &args);
+        let started = Instant::now();
+        let abandoned = ctx.cancellation().clone().drop_guard();
+        let result = self
+            .start_stream(ctx, service.as_ref(), info.as_ref(), action, args)
+            .instrument(span.clone())
+            .await;
+        let metrics = self.metrics.clone().filter(|_| info.is_some());
+        let (service, action) = (service.name().to_string(), action.to_string());
+        let stream = match result {
+            Ok(stream) => stream,
+            Err(e) => {
+                let elapsed = started.elapsed();
+                span.in_scope(|| trace_outcome(elapsed, Some(&e)));
+                if let Some(metrics) = metrics {
+                    metrics.record(&service, &action, elapsed, false);
+                }
+                abandoned.disarm();
+                return Err(e);
+            }
+        };
+        let finished = span.clone();
+        let observed = Observed::new(stream, span, started, move |elapsed, failed| {
+            let error: Option<&dyn std::fmt::Display> = failed.then_some(&"an output line was an error");
+            finished.in_scope(|| trace_outcome(elapsed, error));
+            if let Some(metrics) = metrics {
+                metrics.record(&service, &action, elapsed, !failed);
+            }
+        });
+        Ok(Box::pin(Guarded::new(Box::pin(observed), abandoned)))
+    }
+
+    /// Span covering one command invocation, carrying the caller and the
+    /// bound arguments. Secret arguments are logged as `***`.
+    fn command_span(
+        &self,
+        ctx: &Context,
+        service: &dyn Service,
+        info: Option<&CommandInfo>,
+        action: &str,
+        args: &Args,
+    ) -> tracing::Span {
+        tracing::info_span!(
+            "command",
+            request_id = ctx.request_id,
+            service = service.name(),
+            action,
+            args = ?self.describe_args(info, args),
+            tenant = ctx.tenant.as_deref().unwrap_or("-"),
+            role = ctx.role.as_deref().unwrap_or("-"),
+            dry_run = ctx.dry_run,
+        )
+    }
+
+    /// The service's full name and the arguments of its command `action`
+    /// as `describe_args` gives them; the names as given for an unknown
+    /// service.
+    pub(crate) fn describe(&self, ctx: &Context, service_name: &str, action: &str, args: &Args) -> (String, String) {
+        let Ok(service) = self.service(ctx, service_name) else {
+            return (service_name.to_string(), String::new());
+        };
+        let info = service.commands().into_iter().find(|c| c.name == action);
+        (service.name().to_string(), self.describe_args(info.as_ref(), args))
+    }
+
+    /// Whether the caller's role grants `permission`. Every caller does when
+    /// no authenticator is set, as for `#[command(permission)]`.
+    pub(crate) fn allows(&self, ctx: &Context, permission: &str) -> bool {
+        self.auth
+            .as_ref()
+            .is_none_or(|auth| ctx.role.as_deref().is_some_and(|role| auth.allows(role, permission)))
+    }
+
+    /// `name=value` pairs of the arguments as they bind to the command's
+    /// parameters, with secrets redacted.
+    fn describe_args(&self, info: Option<&CommandInfo>, args: &Args) -> String {
+        let Some(info) = info else {
+            return String::new();
+        };
+        let Ok((slots, rest)) = bind_command(info, args) else {
+            return "<invalid>".to_string();
+        };
+        let rest = (!rest.is_empty()).then(|| rest.join(","));
+        info.args
             .iter()
-            .map(|(name, svc)| (name.as_str(), svc.description(), svc.commands()))
-            .collect()
+            .zip(slots.into_iter().chain([rest]))
+            .filter_map(|(arg, value)| {
+                let value = value?;
+                let secret = arg.secret || self.redact.as_ref().is_some_and(|redact| redact(&arg.name));
+                Some(format!("{}={}", arg.name, if secret { "***" } else { &value }))
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    async fn start_stream(
+        &self,
+        ctx: &Context,
+        service: &dyn Service,
+        info: Option<&CommandInfo>,
+        action: &str,
+        args: Args,
+    ) -> Result<CommandStream> {
+        let client_deadline = ctx.deadline();
+        let (invocations, permits) = by_deadline(ctx, service, action, async {
+            let invocations = self
+                .expand_globs(ctx, info, &args)
+                .await?
+                .unwrap_or_else(|| vec![args]);
+            let locks = self.locks.acquire(ctx, info, &invocations).await?;
+            Ok((invocations, (locks, self.admit(ctx, service, info).await?)))
+        })
+        .await?;
+        let start = async {
+            let mut streams = Vec::with_capacity(invocations.len());
+            for args in invocations {
+                let stream = service
+                    .execute_stream(ctx, action, args)
+                    .await
+                    .ok_or_else(|| {
+                        NexusError::invalid_argument(format!("command '{}' does not stream output", action))
+                    })??;
+                streams.push(stream);
+            }
+            let stream: CommandStream = streams
+                .into_iter()
+                .reduce(|a, b| Box::pin(a.chain(b)))
+                .unwrap_or_else(|| Box::pin(tokio_stream::empty()));
+            Ok::<_, anyhow::Error>(stream)
+        };
+        // One deadline, the sooner of the time limit's end and the request's
+        // own, covers starting the stream and producing every line.
+        let limited = self.timeout(info).map(|limit| (Instant::now() + limit, timed_out(service, action, limit)));
+        let requested = client_deadline.map(|deadline| (deadline, missed_deadline(service, action)));
+        let Some((deadline, error)) = limited.into_iter().chain(requested).min_by_key(|(deadline, _)| *deadline)
+        else {
+            return Ok(Box::pin(Guarded::new(start.await?, permits)));
+        };
+        ctx.limit_deadline(deadline);
+        let deadline = tokio::time::Instant::from_std(deadline);
+        let stream = tokio::time::timeout_at(deadline, start)
+            .await
+            .map_err(|_| {
+                ctx.cancellation().cancel();
+                error.clone()
+            })??;
+        Ok(Box::pin(Deadline::new(Box::pin(Guarded::new(stream, permits)), deadline, error)))
+    }
+
+    /// Find a service and the metadata of one of its commands, rejecting
+    /// dry-run requests for commands that do not support them and callers
+    /// lacking the command's permission.
+    fn lookup(
+        &self,
+        ctx: &Context,
+        service_name: &str,
+        action: &str,
+    ) -> Result<(Arc<dyn Service>, Option<CommandInfo>)> {
+        self.check(ctx, service_name, action).inspect_err(|e| {
+            tracing::warn!(
+                service = service_name,
+                action,
+                tenant = ctx.tenant.as_deref().unwrap_or("-"),
+                role = ctx.role.as_deref().unwrap_or("-"),
+                error = %e,
+                "command rejected"
+            )
+        })
+    }
+
+    fn check(
+        &self,
+        ctx: &Context,
+        service_name: &str,
+        action: &str,
+    ) -> Result<(Arc<dyn Service>, Option<CommandInfo>)> {
+        let service = self.service(ctx, service_name)?;
+        let info = service.commands().into_iter().find(|c| c.name == action);
+        if ctx.dry_run && !info.as_ref().is_some_and(|info| info.dry_run) {
+            return Err(NexusError::invalid_argument(format!(
+                "command '{}' does not support dry-run",
+                action
+            ))
+            .into());
+        }
+        if let (Some(auth), Some(info)) = (&self.auth, &info) {
+            let permitted = info.permission.is_empty()
+                || ctx
+                    .role
+                    .as_deref()
+                    .is_some_and(|role| auth.allows(role, &info.permission));
+            if !permitted {
+                let message = format!(
+                    "'{} {}' requires permission '{}'",
+                    service_name, action, info.permission
+                );
+                return Err(NexusError::permission_denied(message)
+                    .detail("permission", &info.permission)
+                    .into());
+            }
+        }
+        // Previews and read-only commands still run in maintenance mode, and
+        // so does the built-in admin service, to turn it off again (not
+        // another service registered under its name). Commands the service
+        // does not declare may change anything.
+        let admin = self.catalog.read().unwrap().admin.as_ref().is_some_and(|admin| Arc::ptr_eq(admin, &service));
+        let exempt = ctx.dry_run || admin || info.as_ref().is_some_and(|info| info.readonly);
+        if let Some(message) = self.maintenance.get().filter(|_| !exempt) {
+            return Err(in_maintenance(&message).into());
+        }
+        Ok((service, info))
+    }
+
+    /// Wait for a slot of the service's own limit, if it has one, then for
+    /// a server-wide slot if a limit is configured. Taking them in this order
+    /// keeps commands queued behind a busy service from holding server-wide
+    /// slots. Fails as busy if the service's queue is full.
+    async fn admit(
+        &self,
+        ctx: &Context,
+        service: &dyn Service,
+        info: Option<&CommandInfo>,
+    ) -> Result<(Option<LimiterPermit>, Option<LimiterPermit>)> {
+        if ctx.is_nested() {
+            return Ok((None, None));
+        }
+        let priority = ctx
+            .priority
+            .or(info.map(|info| info.priority))
+            .unwrap_or_default();
+        let limiter = self.catalog.read().unwrap().limiters.get(service.name()).cloned();
+        let service_permit = match limiter {
+            Some(limiter) => Some(limiter.acquire(priority).await.ok_or_else(|| {
+                let reason = format!(
+                    "too many '{}' commands queued",
+                    crate::cli::command_path(service.name())
+                );
+                Busy::new(reason).retry_after(QUEUE_FULL_RETRY)
+            })?),
+            None => None,
+        };
+        let permit = match &self.limiter {
+            Some(limiter) => limiter.acquire(priority).await,
+            None => None,
+        };
+        Ok((service_permit, permit))
+    }
+
+    /// Expand `#[arg(glob)]` patterns against each argument's completer.
+    /// Returns `None` when nothing needs expanding, otherwise one invocation
+    /// per combination of matched values, with every argument passed by name.
+    /// Patterns in a variadic argument are replaced by all their matches
+    /// within the same invocation.
+    async fn expand_globs(
+        &self,
+        ctx: &Context,
+        info: Option<&CommandInfo>,
+        args: &Args,
+    ) -> Result<Option<Vec<Args>>> {
+        let Some(info) = info else {
+            return Ok(None);
+        };
+        if !info.args.iter().any(|arg| arg.glob) {
+            return Ok(None);
+        }
+        let names: Vec<&str> = info.args.iter().map(|arg| arg.name.as_str()).collect();
+        let (slots, rest) = bind_command(info, args)?;
+        let mut invocations = vec![slots.clone()];
+        let mut expanded = false;
+        for (i, arg) in info.args.iter().enumerate().take(slots.len()) {
+            let Some(pattern) = &slots[i] else {
+                continue;
+            };
+            if !arg.glob || !glob::is_pattern(pattern) {
+                continue;
+            }
+            let matched = self.glob_matches(ctx, info, &slots, arg, pattern).await?;
+            invocations = invocations
+                .into_iter()
+                .flat_map(|inv| {
+                    matched.iter().map(move |value| {
+                        let mut inv = inv.clone();
+                        inv[i] = Some(value.clone());
+                        inv
+                    })
+                })
+                .collect();
+            expanded = true;
+        }
+        let mut values = Vec::new();
+        for value in rest {
+            match info.args.last() {
+                Some(arg) if arg.glob && glob::is_pattern(&value) => {
+                    values.extend(self.glob_matches(ctx, info, &slots, arg, &value).await?);
+                    expanded = true;
+                }
+                _ => values.push(value),
+            }
+        }
+        if !expanded {
+            return Ok(None);
+        }
+        let invocations = invocations
+            .into_iter()
+            .map(|slots| Args {
+                positional: values.clone(),
+                named: names
+                    .iter()
+                    .zip(slots)
+                    .filter_map(|(name, value)| Some((name.to_string(), value?)))
+                    .collect(),
+            })
+            .collect();
+        Ok(Some(invocations))
+    }
+
+    /// Values of `arg`'s completer that match the glob `pattern`. The
+    /// completer is passed the values `slots` binds to the arguments of
+    /// `info` it names, up to the first one not given.
+    async fn glob_matches(
+        &self,
+        ctx: &Context,
+        info: &CommandInfo,
+        slots: &[Option<String>],
+        arg: &ArgInfo,
+        pattern: &str,
+    ) -> Result<Vec<String>> {
+        if arg.completer.is_empty() {
+            anyhow::bail!("argument '{}' accepts globs but has no completer", arg.name);
+        }
+        let values = arg
+            .completer_args
+            .iter()
+            .map_while(|name| {
+                let i = info.args.iter().position(|a| &a.name == name)?;
+                slots.get(i)?.clone()
+            })
+            .collect();
+        let matched: Vec<String> = self
+            .completions(ctx, &arg.completer, Args::new(values))
+            .await
+            .with_context(|| format!("expanding '{}' for argument '{}'", pattern, arg.name))?
+            .into_iter()
+            .filter(|value| glob::matches(pattern, value))
+            .collect();
+        if matched.is_empty() {
+            let message = format!("no {} matches pattern '{}'", arg.name, pattern);
+            return Err(NexusError::not_found(message).into());
+        }
+        Ok(matched)
+    }
+
+    /// Run a completer in "service.command" form (the service possibly
+    /// nested, as in "volume.snapshot.list") with `args` and collect its values.
+    /// It is called like any command the running one calls (see `call`), so
+    /// the caller needs its permission, maintenance mode and its lock apply,
+    /// and it runs in the slot the running command holds.
+    async fn completions(&self, ctx: &Context, completer: &str, args: Args) -> Result<Vec<String>> {
+        let (service_name, action) = completer
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow::anyhow!("invalid completer '{}'", completer))?;
+        // Boxed: the call runs a command, which may expand globs again.
+        let output = Box::pin(self.call(ctx, service_name, action, args)).await?;
+        Ok(output.values())
+    }
+
+    /// Services visible to `tenant`: the shared ones, overridden or extended
+    /// by the tenant's own instances.
+    pub fn list_services(&self, tenant: Option<&str>) -> Vec<Arc<dyn Service>> {
+        let catalog = self.catalog.read().unwrap();
+        let mut visible: HashMap<&str, &Arc<dyn Service>> = catalog
+            .services
+            .iter()
+            .map(|(name, svc)| (name.as_str(), svc))
+            .collect();
+        if let Some(services) = tenant.and_then(|tenant| catalog.tenant_services.get(tenant)) {
+            visible.extend(services.iter().map(|(name, svc)| (name.as_str(), svc)));
+        }
+        visible.into_values().cloned().collect()
+    }
+
+    /// Health of every service visible to `tenant`, sorted by name.
+    pub async fn health(&self, tenant: Option<&str>) -> Vec<(String, Health)> {
+        let mut report = Vec::new();
+        for service in self.list_services(tenant) {
+            report.push((service.name().to_string(), service.health().await));
+        }
+        report.sort_by(|a, b| a.0.cmp(&b.0));
+        report
+    }
+
+    /// Health of the shared service called `name` (or an alias of it), or
+    /// `None` if there is no such service.
+    pub async fn service_health(&self, name: &str) -> Option<Health> {
+        let service = self.service(&Context::new(), name).ok()?;
+        Some(service.health().await)
+    }
+
+    /// Every registered service, shared and tenant-scoped.
+    fn all_services(&self) -> Vec<Arc<dyn Service>> {
+        let catalog = self.catalog.read().unwrap();
+        let tenant_services = catalog.tenant_services.values().flat_map(|services| services.values());
+        catalog.services.values().chain(tenant_services).cloned().collect()
+    }
+
+    /// Load the saved jobs, then run the `on_start` hook of every
+    /// registered service, stopping at the first that fails.
+    pub(crate) async fn start(&self, ctx: &StartContext) -> Result<()> {
+        self.jobs.load()?;
+        for service in self.all_services() {
+            service
+                .on_start(ctx)
+                .await
+                .with_context(|| format!("failed to start service '{}'", service.name()))?;
+        }
+        Ok(())
+    }
+
+    /// Run the `on_stop` hook of every registered service. A failing hook
+    /// is reported and does not stop the others.
+    pub(crate) async fn stop(&self) {
+        for service in self.all_services() {
+            if let Err(e) = service.on_stop().await {
+                tracing::warn!(service = service.name(), error = %e, "on_stop hook failed");
+            }
+        }
+    }
+
+    /// Run the `shutdown` hook of every registered service, shared and
+    /// tenant-scoped. A failing hook is reported and does not stop the others.
+    pub async fn shutdown(&self) {
+        self.jobs.cancel_all();
+        for service in self.all_services() {
+            if let Err(e) = service.shutdown().await {
+                tracing::warn!(service = service.name(), error = %e, "shutdown hook failed");
+            }
+        }
+    }
+}
+
+/// The error commands that change state fail with in maintenance mode.
+fn in_maintenance(message: &str) -> NexusError {
+    if message.is_empty() {
+        return NexusError::new(ErrorCode::Unavailable, "server is in maintenance; only read-only commands run");
+    }
+    NexusError::new(ErrorCode::Unavailable, format!("server is in maintenance: {}", message))
+        .detail("maintenance", message)
+}
+
+/// Adds and removes services of a running server (`NexusServer::handle`,
+/// or `Context::registry` in a command).
+///
+/// Cloning is cheap; every clone changes the same registry. Connected CLIs
+/// pick up the new service list after their next command.
+#[derive(Clone)]
+pub struct RegistryHandle {
+    pub(crate) registry: Arc<Registry>,
+}
+
+impl std::fmt::Debug for RegistryHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegistryHandle").finish_non_exhaustive()
+    }
+}
+
+impl RegistryHandle {
+    /// Register `service`, replacing a shared service of the same name.
+    pub fn add_service<S: Service>(&self, service: S) {
+        self.registry.register(service);
+    }
+
+    /// Register a service instance visible only to requests from `tenant`.
+    pub fn add_service_for_tenant<S: Service>(&self, tenant: &str, service: S) {
+        self.registry.register_for_tenant(tenant, service);
+    }
+
+    /// Remove the shared service called `name` and run its `shutdown` hook.
+    /// Commands already executing on it are not waited for. Returns whether
+    /// there was such a service.
+    pub async fn remove_service(&self, name: &str) -> bool {
+        let Some(service) = self.registry.deregister(name) else {
+            return false;
+        };
+        if let Err(e) = service.shutdown().await {
+            tracing::warn!(service = name, error = %e, "shutdown hook of a removed service failed");
+        }
+        true
+    }
+
+    /// The server's event bus, to emit events from outside a command.
+    pub fn events(&self) -> EventBus {
+        self.registry.events()
+    }
+
+    /// Turn maintenance mode on or off, as `Registry::set_maintenance` does.
+    pub fn set_maintenance(&self, message: Option<String>) {
+        self.registry.set_maintenance(message);
+    }
+
+    /// Remove `tenant`'s own instance of the service called `name`, as
+    /// `remove_service` does for shared ones.
+    pub async fn remove_service_for_tenant(&self, tenant: &str, name: &str) -> bool {
+        let Some(service) = self.registry.deregister_for_tenant(tenant, name) else {
+            return false;
+        };
+        if let Err(e) = service.shutdown().await {
+            tracing::warn!(service = name, tenant, error = %e, "shutdown hook of a removed service failed");
+        }
+        true
     }
 }
@@ -0,0 +1,80 @@
+//! Configuration reloads (`NexusServer::with_reload_handler`): code run on
+//! SIGHUP and on the `daemon reload` command, so services can pick up
+//! changed config files without a restart. The listeners, connections and
+//! in-flight requests are left alone.
+
+use anyhow::Result;
+use nexus_derive::nexus_service;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A handler added with `with_reload_handler`.
+pub(crate) type ReloadHandler = Box<dyn Fn() -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+/// The reload handlers of a server.
+pub(crate) struct Reloader {
+    handlers: Vec<ReloadHandler>,
+    /// Held while the handlers run, so a SIGHUP arriving during a `daemon
+    /// reload` waits for it instead of overlapping.
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Reloader {
+    pub(crate) fn new(handlers: Vec<ReloadHandler>) -> Self {
+        Self {
+            handlers,
+            running: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Run every handler, in the order they were added. One failing does
+    /// not stop the others; their errors are returned together.
+    pub(crate) async fn reload(&self) -> Result<()> {
+        let _running = self.running.lock().await;
+        let mut errors = Vec::new();
+        for handler in &self.handlers {
+            if let Err(e) = handler().await {
+                errors.push(format!("{:#}", e));
+            }
+        }
+        if !errors.is_empty() {
+            anyhow::bail!("reload failed: {}", errors.join("; "));
+        }
+        tracing::info!(handlers = self.handlers.len(), "configuration reloaded");
+        Ok(())
+    }
+
+    /// Reload on every SIGHUP until the task is dropped, reporting failures
+    /// on stderr. Elsewhere than on Unix only `daemon reload` reloads.
+    pub(crate) async fn on_hangup(self: Arc<Self>) {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+                eprintln!("Error: cannot listen for SIGHUP; reload with `daemon reload`");
+                return;
+            };
+            while hangup.recv().await.is_some() {
+                match self.reload().await {
+                    Ok(()) => println!("Reloaded configuration"),
+                    Err(e) => eprintln!("Error: {:#}", e),
+                }
+            }
+        }
+    }
+}
+
+/// Admin commands for the daemon itself.
+pub(crate) struct DaemonAdmin(pub(crate) Arc<Reloader>);
+
+/// Manage the running daemon.
+#[nexus_service(name = "daemon")]
+impl DaemonAdmin {
+    /// Reload the services' configuration, as on SIGHUP.
+    #[command(permission = "admin")]
+    async fn reload(&self) -> Result<String> {
+        self.0.reload().await?;
+        Ok("Reloaded configuration".to_string())
+    }
+}
@@ -0,0 +1,497 @@
+//! Scheduled commands (`NexusServer::with_scheduler`): `schedule add
+//! "0 3 * * *" pool scrub p1` runs `pool scrub p1` every day at 03:00 UTC,
+//! as a background job, so each run's outcome shows in `job list` like
+//! that of a job a client started. Schedules are saved to a file and
+//! survive restarts; runs missed while the daemon was down are skipped.
+
+use crate::context::Context;
+use crate::error::{ErrorCode, NexusError};
+use crate::registry::{Args, CommandOutput, RegistryHandle, StartContext};
+use anyhow::{Context as _, Result};
+use nexus_derive::nexus_service;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How far ahead the next run of a schedule is looked for; a schedule with
+/// none in that time (`0 0 30 2 *`) never runs.
+const SEARCH_DAYS: i64 = 366 * 5;
+
+/// When a schedule runs, in crontab syntax: minute, hour, day of month,
+/// month and day of week (0 or 7 for Sunday), each `*`, a value, a range
+/// `a-b`, a step `*/n` or `a-b/n`, or a comma-separated list of those.
+/// Times are UTC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month or of week is restricted (not `*`); if both
+    /// are, a day matching either runs, as in crontab.
+    restricted_day: bool,
+    restricted_weekday: bool,
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            anyhow::bail!(
+                "'{}' is not a schedule: expected 5 fields (minute hour day month weekday), got {}",
+                s,
+                fields.len()
+            );
+        };
+        let mut weekdays = field(weekday, 0, 7)?;
+        // Sunday is both 0 and 7.
+        if weekdays & 1 << 7 != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            restricted_day: day != "*",
+            restricted_weekday: weekday != "*",
+        })
+    }
+}
+
+/// The values one field allows, as bits.
+fn field(text: &str, min: u32, max: u32) -> Result<u64> {
+    let mut bits = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step: &u32| step > 0)),
+            None => (part, Some(1)),
+        };
+        let Some(step) = step else {
+            anyhow::bail!("invalid step in '{}'", part);
+        };
+        let value = |v: &str| v.parse::<u32>().ok().filter(|v| (min..=max).contains(v));
+        let bounds = match range.split_once('-') {
+            _ if range == "*" => Some((min, max)),
+            Some((from, to)) => value(from).zip(value(to)).filter(|(from, to)| from <= to),
+            // `5/15` means from 5 to the end, every 15.
+            None if part.contains('/') => value(range).map(|from| (from, max)),
+            None => value(range).map(|v| (v, v)),
+        };
+        let Some((from, to)) = bounds else {
+            anyhow::bail!("'{}' is not a value or range from {} to {}", range, min, max);
+        };
+        for v in (from..=to).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    /// Whether the schedule runs in the minute starting at `secs` (Unix time).
+    fn matches(&self, secs: u64) -> bool {
+        let minute = secs / 60 % 60;
+        let hour = secs / 3_600 % 24;
+        self.minutes & 1 << minute != 0 && self.hours & 1 << hour != 0 && self.runs_on((secs / 86_400) as i64)
+    }
+
+    /// Whether the schedule runs on the date `days` days after 1970-01-01.
+    fn runs_on(&self, days: i64) -> bool {
+        let (_, month, day) = civil(days);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4).rem_euclid(7);
+        let day_matches = self.days & 1 << day != 0;
+        let weekday_matches = self.weekdays & 1 << weekday != 0;
+        let date_matches = match (self.restricted_day, self.restricted_weekday) {
+            (true, true) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+        self.months & 1 << month != 0 && date_matches
+    }
+
+    /// The start of the first minute after `secs` (Unix time) the schedule
+    /// runs in, if there is one within `SEARCH_DAYS`.
+    pub(crate) fn next_after(&self, secs: u64) -> Option<u64> {
+        let start = secs / 60 * 60 + 60;
+        let first_day = (start / 86_400) as i64;
+        (first_day..first_day + SEARCH_DAYS).filter(|&day| self.runs_on(day)).find_map(|day| {
+            let midnight = day as u64 * 86_400;
+            (0..24 * 60)
+                .map(|minute| midnight + minute * 60)
+                .find(|&minute| minute >= start && self.matches(minute))
+        })
+    }
+}
+
+/// The year, month and day of the date `days` days after 1970-01-01.
+fn civil(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A Unix time to the minute, e.g. "2026-10-15 03:00Z".
+fn format_time(secs: u64) -> String {
+    let (year, month, day) = civil((secs / 86_400) as i64);
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600 % 24,
+        secs / 60 % 60
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// A command to run on a schedule, as saved to the schedules file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Schedule {
+    cron: String,
+    /// Full name of the service, e.g. "volume.snapshot".
+    service: String,
+    action: String,
+    args: Vec<String>,
+    /// The tenant and role of whoever added it, which its runs act as.
+    tenant: Option<String>,
+    role: Option<String>,
+    /// The job of the latest run since the daemon started.
+    #[serde(skip)]
+    last_job: Option<u64>,
+}
+
+impl Schedule {
+    fn command(&self) -> String {
+        let command = format!("{} {}", crate::cli::command_path(&self.service), self.action);
+        match self.args.is_empty() {
+            true => command,
+            false => format!("{} {}", command, crate::tokenize::join(&self.args)),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' {}", self.cron, self.command())
+    }
+}
+
+/// The schedules of a server and the file they are saved to.
+pub(crate) struct Schedules {
+    path: PathBuf,
+    /// Schedules by ID.
+    entries: Mutex<BTreeMap<u64, Schedule>>,
+    /// Set once the server has started.
+    registry: OnceLock<RegistryHandle>,
+}
+
+impl Schedules {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            entries: Mutex::new(BTreeMap::new()),
+            registry: OnceLock::new(),
+        }
+    }
+
+    /// Read the schedules saved in the file, if there is one.
+    fn load(&self) -> Result<()> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", self.path.display())),
+        };
+        let entries: BTreeMap<u64, Schedule> = serde_json::from_str(&text)
+            .with_context(|| format!("failed to read schedules from {}", self.path.display()))?;
+        for (id, schedule) in &entries {
+            schedule
+                .cron
+                .parse::<Cron>()
+                .with_context(|| format!("schedule {} in {}", id, self.path.display()))?;
+        }
+        *self.entries.lock().unwrap() = entries;
+        Ok(())
+    }
+
+    /// Write `entries` to the file, through a temporary file so a crash
+    /// midway leaves the previous version.
+    fn save(&self, entries: &BTreeMap<u64, Schedule>) -> Result<()> {
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir)?;
+            }
+            let temporary = self.path.with_extension("tmp");
+            std::fs::write(&temporary, serde_json::to_string_pretty(entries)?)?;
+            std::fs::rename(&temporary, &self.path)
+        };
+        write().with_context(|| format!("failed to save schedules to {}", self.path.display()))
+    }
+
+    fn registry(&self) -> Result<&RegistryHandle> {
+        self.registry
+            .get()
+            .ok_or_else(|| NexusError::new(ErrorCode::Unavailable, "the scheduler has not started yet").into())
+    }
+
+    /// Start the jobs of the schedules that run in the minute starting at
+    /// `minute`.
+    fn run_due(&self, minute: u64) {
+        let Ok(handle) = self.registry() else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        for (id, schedule) in entries.iter_mut() {
+            if !schedule.cron.parse::<Cron>().is_ok_and(|cron| cron.matches(minute)) {
+                continue;
+            }
+            let registry = &handle.registry;
+            let ctx = Context::new()
+                .with_tenant(schedule.tenant.clone())
+                .with_role(schedule.role.clone())
+                .with_request_id(format!("schedule-{}-{}", id, minute))
+                .with_command(format!("{} {}", crate::cli::command_path(&schedule.service), schedule.action))
+                .with_states(registry.states())
+                .with_events(registry.events())
+                .with_registry(handle.clone());
+            let args = Args::new(schedule.args.clone());
+            match registry.start_job(&ctx, &schedule.service, &schedule.action, args) {
+                Ok(job) => {
+                    tracing::info!(schedule = id, job, "scheduled command started");
+                    schedule.last_job = Some(job);
+                }
+                Err(e) => tracing::warn!(schedule = id, error = %e, "scheduled command not started"),
+            }
+        }
+    }
+}
+
+/// Run schedules until the server stops, checking at the start of every
+/// minute.
+async fn run(schedules: Arc<Schedules>, stopping: tokio_util::sync::CancellationToken) {
+    loop {
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let minute = elapsed.as_secs() / 60 * 60 + 60;
+        let wait = Duration::from_secs(minute) - elapsed.min(Duration::from_secs(minute));
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = stopping.cancelled() => return,
+        }
+        schedules.run_due(minute);
+    }
+}
+
+pub(crate) struct ScheduleAdmin(pub(crate) Arc<Schedules>);
+
+/// Run commands on a schedule, as background jobs.
+#[nexus_service(name = "schedule")]
+impl ScheduleAdmin {
+    /// Run a command on a schedule, e.g. `schedule add "0 3 * * *" pool scrub
+    /// p1` for every day at 03:00 UTC. Each run is a background job; see
+    /// `job list`.
+    #[command]
+    async fn add(
+        &self,
+        ctx: &Context,
+        #[arg(hint = "\"min hour day month weekday\"", doc = "When to run, as in crontab (UTC)")] cron: String,
+        #[arg(hint = "command", doc = "Command to run, with its arguments")] command: Vec<String>,
+    ) -> Result<String> {
+        let parsed: Cron = cron.parse().map_err(|e| NexusError::invalid_argument(format!("{:#}", e)))?;
+        let Some(next) = parsed.next_after(now()) else {
+            return Err(NexusError::invalid_argument(format!("schedule '{}' never runs", cron)).into());
+        };
+        let (service, action, args) = resolve(self.0.registry()?, ctx, &command)?;
+        let schedule = Schedule {
+            cron: cron.split_whitespace().collect::<Vec<_>>().join(" "),
+            service,
+            action,
+            args,
+            tenant: ctx.tenant.clone(),
+            role: ctx.role.clone(),
+            last_job: None,
+        };
+        let mut entries = self.0.entries.lock().unwrap();
+        let id = entries.keys().next_back().map_or(1, |id| id + 1);
+        let description = schedule.to_string();
+        entries.insert(id, schedule);
+        if let Err(e) = self.0.save(&entries) {
+            entries.remove(&id);
+            return Err(e);
+        }
+        tracing::info!(schedule = id, "schedule added: {}", description);
+        Ok(format!("Schedule {} added; next run at {}", id, format_time(next)))
+    }
+
+    /// List the scheduled commands, with when they next run.
+    #[command(readonly)]
+    async fn list(&self, ctx: &Context) -> Result<CommandOutput> {
+        let entries = self.0.entries.lock().unwrap();
+        let now = now();
+        let rows = entries
+            .iter()
+            .filter(|(_, schedule)| schedule.tenant == ctx.tenant)
+            .map(|(id, schedule)| {
+                let next = schedule.cron.parse::<Cron>().ok().and_then(|cron| cron.next_after(now));
+                [
+                    id.to_string(),
+                    schedule.cron.clone(),
+                    schedule.command(),
+                    next.map_or_else(|| "never".to_string(), format_time),
+                    schedule.last_job.map_or_else(|| "-".to_string(), |job| job.to_string()),
+                ]
+            });
+        Ok(CommandOutput::table(["id", "schedule", "command", "next run", "last job"], rows))
+    }
+
+    /// Stop running a scheduled command. Jobs it already started go on.
+    #[command]
+    async fn remove(
+        &self,
+        ctx: &Context,
+        #[arg(hint = "schedule id", doc = "Schedule to remove", complete = "schedule.list")] id: u64,
+    ) -> Result<String> {
+        let mut entries = self.0.entries.lock().unwrap();
+        let Some(schedule) = entries.get(&id).filter(|schedule| schedule.tenant == ctx.tenant).cloned() else {
+            return Err(NexusError::not_found(format!("no schedule {}", id)).into());
+        };
+        entries.remove(&id);
+        if let Err(e) = self.0.save(&entries) {
+            entries.insert(id, schedule);
+            return Err(e);
+        }
+        tracing::info!(schedule = id, "schedule removed: {}", schedule);
+        Ok(format!("Schedule {} removed", id))
+    }
+
+    /// Load the saved schedules and start running them.
+    #[on_start]
+    async fn start(&self, ctx: &StartContext) -> Result<()> {
+        self.0.load()?;
+        let _ = self.0.registry.set(ctx.handle.clone());
+        tokio::spawn(run(self.0.clone(), ctx.stopping.clone()));
+        Ok(())
+    }
+}
+
+/// Split the words of a command line into the service, the command and its
+/// arguments, as the CLI does: the service is the longest run of leading
+/// words that names one (`volume snapshot create ...`).
+fn resolve(handle: &RegistryHandle, ctx: &Context, words: &[String]) -> Result<(String, String, Vec<String>)> {
+    let services = handle.registry.list_services(ctx.tenant.as_deref());
+    let found = (1..words.len()).rev().find_map(|len| {
+        let name = words[..len].join(".");
+        let service = services.iter().find(|service| {
+            service.name() == name || (len == 1 && service.aliases().contains(&name.as_str()))
+        })?;
+        let info = service.commands().into_iter().find(|info| info.name == words[len])?;
+        Some((service.name().to_string(), info, len))
+    });
+    let Some((service, info, len)) = found else {
+        return Err(NexusError::invalid_argument(format!("'{}' is not a command", words.join(" "))).into());
+    };
+    if !info.permission.is_empty() && !handle.registry.allows(ctx, &info.permission) {
+        let message = format!("'{}' requires permission '{}'", words[..=len].join(" "), info.permission);
+        return Err(NexusError::permission_denied(message).into());
+    }
+    Ok((service, info.name, words[len + 1..].to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-14 00:00 UTC, a Wednesday.
+    const WEDNESDAY: u64 = 1_791_936_000;
+    const DAY: u64 = 86_400;
+
+    fn next(cron: &str, after: u64) -> Option<String> {
+        cron.parse::<Cron>().unwrap().next_after(after).map(format_time)
+    }
+
+    #[test]
+    fn steps_count_from_the_start_of_the_range() {
+        assert_eq!(field("*/15", 0, 59).unwrap(), 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(field("10-20/5", 0, 59).unwrap(), 1 << 10 | 1 << 15 | 1 << 20);
+        assert_eq!(field("5/20", 0, 59).unwrap(), 1 << 5 | 1 << 25 | 1 << 45);
+        assert_eq!(field("1,3-4", 0, 59).unwrap(), 1 << 1 | 1 << 3 | 1 << 4);
+        assert_eq!(field("*/5", 1, 12).unwrap(), 1 << 1 | 1 << 6 | 1 << 11);
+
+        assert_eq!(next("*/15 * * * *", WEDNESDAY).as_deref(), Some("2026-10-14 00:15Z"));
+        assert_eq!(next("*/15 * * * *", WEDNESDAY + 14 * 60 + 59).as_deref(), Some("2026-10-14 00:15Z"));
+        assert_eq!(next("0 9-17/4 * * *", WEDNESDAY + 14 * 3_600).as_deref(), Some("2026-10-14 17:00Z"));
+        assert_eq!(next("0 9-17/4 * * *", WEDNESDAY + 17 * 3_600).as_deref(), Some("2026-10-15 09:00Z"));
+    }
+
+    #[test]
+    fn runs_only_after_the_given_time() {
+        assert_eq!(next("0 0 * * *", WEDNESDAY).as_deref(), Some("2026-10-15 00:00Z"));
+        assert_eq!(next("0 0 * * *", WEDNESDAY - 1).as_deref(), Some("2026-10-14 00:00Z"));
+    }
+
+    #[test]
+    fn sunday_is_both_0_and_7() {
+        let zero: Cron = "0 0 * * 0".parse().unwrap();
+        let seven: Cron = "0 0 * * 7".parse().unwrap();
+        assert_eq!(zero.weekdays & 1, 1);
+        assert_eq!(seven.weekdays & 1, 1);
+        assert_eq!(next("0 0 * * 0", WEDNESDAY).as_deref(), Some("2026-10-18 00:00Z"));
+        assert_eq!(next("0 0 * * 7", WEDNESDAY).as_deref(), Some("2026-10-18 00:00Z"));
+        assert_eq!(next("0 0 * * 5-7", WEDNESDAY).as_deref(), Some("2026-10-16 00:00Z"));
+    }
+
+    #[test]
+    fn a_restricted_day_of_month_and_of_week_either_run() {
+        // The 20th or any Friday: the 16th is a Friday, the 20th a Tuesday.
+        assert_eq!(next("0 0 20 * 5", WEDNESDAY).as_deref(), Some("2026-10-16 00:00Z"));
+        assert_eq!(next("0 0 20 * 5", WEDNESDAY + 4 * DAY).as_deref(), Some("2026-10-20 00:00Z"));
+        // With only one of them restricted, the other does not widen it.
+        assert_eq!(next("0 0 20 * *", WEDNESDAY).as_deref(), Some("2026-10-20 00:00Z"));
+        assert_eq!(next("0 0 * * 5", WEDNESDAY + 3 * DAY).as_deref(), Some("2026-10-23 00:00Z"));
+    }
+
+    #[test]
+    fn dates_that_never_come_never_run() {
+        assert_eq!(next("0 0 30 2 *", WEDNESDAY), None);
+        assert_eq!(next("0 0 31 4,6,9,11 *", WEDNESDAY), None);
+        // February 29th comes in 2028.
+        assert_eq!(next("0 0 29 2 *", WEDNESDAY).as_deref(), Some("2028-02-29 00:00Z"));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for invalid in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(invalid.parse::<Cron>().is_err(), "{:?}", invalid);
+        }
+    }
+}
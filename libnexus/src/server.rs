@@ -1,70 +1,1414 @@
+use crate::admin::Admin;
+use crate::auth::{AuthInterceptor, Authenticator, CallerRole, PeerCredentials};
+use crate::chunked::{LargeResponses, COMPRESSION, DEFAULT_MAX_MESSAGE_SIZE};
+use crate::config::{Config, OutputFormat, ServerConfig};
+use crate::connections::{ConnectionRef, Connections};
+use crate::context::{Context, Preferences, REQUEST_ID_KEY};
+use crate::endpoint::{Endpoint, Keepalive};
+#[cfg(unix)]
+use crate::endpoint::UdsOptions;
+use crate::error::{Busy, ErrorCode, NexusError, RateLimited};
+use crate::gateway::Gateway;
+use crate::grpc_web::Cors;
+use crate::health::{Health, HealthService};
+use crate::jobs::{Job, JobRetention, JobState};
+use crate::metrics::Metrics;
+use crate::middleware::Middleware;
+use crate::ratelimit::RateLimit;
+use crate::idempotency::Idempotency;
+use crate::recent::{Recent, MAX_RECENT_LINES};
+use crate::reflection::ReflectionService;
+use crate::reload::{DaemonAdmin, ReloadHandler, Reloader};
+use crate::schedule::{ScheduleAdmin, Schedules};
+#[cfg(unix)]
+use crate::plugin::{PluginAdmin, Plugins};
+use crate::progress::{Progress, ProgressUpdate};
+use crate::proto::health::health_server::HealthServer;
+use crate::proto::reflection::v1::server_reflection_server::ServerReflectionServer as ReflectionV1Server;
+use crate::proto::reflection::v1alpha::server_reflection_server::ServerReflectionServer as ReflectionV1AlphaServer;
 use crate::proto::nexus_service_server::{NexusService, NexusServiceServer};
 use crate::proto::{
-    ArgDef, CommandDef, CommandRequest, CommandResponse, ListServicesRequest, ListServicesResponse,
-    ServiceInfo,
+    ArgDef, BatchRequest, BatchResponse, CommandDef, CommandRequest, CommandResponse, DownloadChunk,
+    EventMessage, HealthRequest, HealthResponse, Heartbeat, JobInfo, JobListRequest, JobListResponse, JobRequest,
+    ListServicesRequest, ListServicesResponse, PlanChange, ReadResponseRequest, RecentRequest, RecentResponse,
+    ResponseChunk, ServiceHealth, ServiceInfo, SessionInput, SessionOutput, StreamFrame, SubscribeRequest, Table,
+    TableRow, UploadChunk,
 };
-use crate::registry::{Registry, Service};
+use crate::proto;
+use crate::proto::stream_frame::Frame;
+use crate::registry::{Args, CommandOutput, Priority, Registry, RegistryHandle, Service, StartContext};
+use crate::session::{Relay, Session};
+use crate::transfer::{Download, Upload};
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::net::UnixListener;
-use tokio_stream::wrappers::UnixListenerStream;
-use tonic::{Request, Response, Status};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tokio_stream::{Stream, StreamExt};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Routes;
+use tonic::transport::server::Router;
+use tonic::{Request, Response, Status, Streaming};
 
 /// gRPC server wrapping a service registry.
 pub struct NexusServer {
     registry: Arc<Registry>,
+    keepalive: Keepalive,
+    auth: Option<Arc<dyn Authenticator>>,
+    /// Control socket used to hand the listener to a replacement daemon.
+    #[cfg(unix)]
+    takeover: Option<PathBuf>,
+    /// Plugins loaded from the plugin directory at startup and on demand.
+    #[cfg(unix)]
+    plugins: Option<Arc<Plugins>>,
+    /// Address of the Prometheus `/metrics` endpoint and the metrics it serves.
+    metrics: Option<(String, Arc<Metrics>)>,
+    /// Address of the HTTP/JSON gateway, if enabled.
+    http_gateway: Option<String>,
+    /// Address to serve MCP tools on.
+    mcp: Option<String>,
+    /// Address to serve gRPC-Web on, with the origins allowed to call it.
+    grpc_web: Option<(String, Cors)>,
+    /// CA that client certificates must chain to when serving TLS (mTLS).
+    #[cfg(feature = "tls")]
+    client_ca: Option<tonic::transport::Certificate>,
+    /// Endpoints added with `listen_*`, served alongside the one passed to
+    /// `serve`.
+    listeners: Vec<Listener>,
+    /// Run on every command request, in order.
+    middleware: Vec<Box<dyn Middleware>>,
+    /// Serve gRPC server reflection (`with_reflection`).
+    reflection: bool,
+    /// Largest message sent or accepted whole (`with_max_message_size`).
+    max_message_size: usize,
+    /// Compress responses for clients that accept it (`with_compression`).
+    compression: bool,
+    /// Run on SIGHUP and `daemon reload`, in order.
+    reload: Vec<ReloadHandler>,
+    /// The metrics `admin stats` reports, if the `admin` service is enabled.
+    admin: Option<Arc<Metrics>>,
+    /// Services to build when `serve` starts (`register_with`), in order.
+    pending: Vec<ServiceInit>,
+}
+
+/// Builds a service registered with `register_with`.
+type ServiceInit = Pin<Box<dyn Future<Output = anyhow::Result<Arc<dyn Service>>> + Send>>;
+
+/// An endpoint to serve on, with the identity to serve TLS with.
+pub(crate) struct Listener {
+    pub(crate) endpoint: Endpoint,
+    /// Applied to the socket file of a Unix endpoint once it is bound.
+    #[cfg(unix)]
+    pub(crate) uds: UdsOptions,
+    #[cfg(feature = "tls")]
+    identity: Option<tonic::transport::Identity>,
+}
+
+impl Listener {
+    fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            #[cfg(unix)]
+            uds: UdsOptions::default(),
+            #[cfg(feature = "tls")]
+            identity: None,
+        }
+    }
+}
+
+impl Default for NexusServer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NexusServer {
     pub fn new() -> Self {
         Self {
             registry: Arc::new(Registry::new()),
+            keepalive: Keepalive::default(),
+            auth: None,
+            #[cfg(unix)]
+            takeover: None,
+            #[cfg(unix)]
+            plugins: None,
+            metrics: None,
+            http_gateway: None,
+            grpc_web: None,
+            mcp: None,
+            #[cfg(feature = "tls")]
+            client_ca: None,
+            listeners: Vec::new(),
+            middleware: Vec::new(),
+            reflection: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            compression: true,
+            reload: Vec::new(),
+            admin: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// A server set up from the `[server]` table of the config file at
+    /// `path`, with environment overrides applied (see `crate::config`).
+    /// Serve it with `serve_configured`.
+    pub fn from_config(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::new().with_config(&Config::load(path)?.server)
+    }
+
+    /// Apply config file settings, each through the `with_*` method it
+    /// stands for. Call it after `with_auth` and `with_redaction`, since a
+    /// `plugin_dir` takes a `handle`. Fails if a TLS file cannot be read, or
+    /// if the config asks for something this build does not support.
+    pub fn with_config(mut self, config: &ServerConfig) -> anyhow::Result<Self> {
+        if let Some(permits) = config.max_concurrency {
+            self = self.with_max_concurrency(permits);
+        }
+        if let Some(timeout) = config.default_timeout {
+            self = self.with_default_timeout(timeout);
+        }
+        if let Some(addr) = &config.metrics {
+            self = self.with_metrics(addr);
+        }
+        if let Some(addr) = &config.http_gateway {
+            self = self.with_http_gateway(addr);
+        }
+        if let Some(addr) = &config.mcp {
+            self = self.with_mcp(addr);
+        }
+        if let Some(grpc_web) = &config.grpc_web {
+            let cors = grpc_web.allow_origins.iter().fold(Cors::new(), |cors, origin| cors.allow_origin(origin));
+            self = self.with_grpc_web(&grpc_web.listen, cors);
+        }
+        if config.reflection {
+            self = self.with_reflection(true);
+        }
+        if config.admin {
+            self = self.with_admin(true);
         }
+        if let Some(bytes) = config.max_message_size {
+            self = self.with_max_message_size(bytes);
+        }
+        if let Some(enabled) = config.compression {
+            self = self.with_compression(enabled);
+        }
+        if let Some(path) = &config.schedules {
+            self = self.with_scheduler(path);
+        }
+        #[cfg(unix)]
+        {
+            if let Some(control) = &config.takeover {
+                self = self.with_takeover(control);
+            }
+            if let Some(dir) = &config.plugin_dir {
+                self = self.with_plugin_dir(dir);
+            }
+        }
+        #[cfg(not(unix))]
+        if config.takeover.is_some() || config.plugin_dir.is_some() {
+            anyhow::bail!("takeover and plugin_dir are only supported on Unix");
+        }
+        if let Some(limits) = &config.rate_limit {
+            let mut rate_limit = RateLimit::new();
+            match (limits.per_peer, limits.burst) {
+                (Some(rate), burst) => rate_limit = rate_limit.per_peer(burst.map_or(rate, |burst| rate.burst(burst))),
+                (None, Some(_)) => anyhow::bail!("[server.rate_limit] burst applies to per_peer, which is not set"),
+                (None, None) => {}
+            }
+            for (command, rate) in &limits.commands {
+                rate_limit = rate_limit.per_command(command, *rate);
+            }
+            self = self.with_middleware(rate_limit);
+        }
+        let endpoints = config
+            .listen
+            .iter()
+            .map(|addr| addr.parse::<Endpoint>())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        #[cfg(feature = "tls")]
+        let mut identity = None;
+        if let Some(tls) = &config.tls {
+            if !endpoints.iter().any(|endpoint| matches!(endpoint, Endpoint::Tcp(_))) {
+                anyhow::bail!("[server.tls] applies to TCP `listen` addresses, and none is configured");
+            }
+            #[cfg(feature = "tls")]
+            {
+                use anyhow::Context as _;
+                let read = |path: &PathBuf| {
+                    std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+                };
+                identity = Some(tonic::transport::Identity::from_pem(read(&tls.cert)?, read(&tls.key)?));
+                if let Some(ca) = &tls.client_ca {
+                    self = self.with_client_ca(tonic::transport::Certificate::from_pem(read(ca)?));
+                }
+            }
+            #[cfg(not(feature = "tls"))]
+            anyhow::bail!("TLS for {} needs the `tls` feature", tls.cert.display());
+        }
+        for endpoint in endpoints {
+            #[cfg_attr(not(any(unix, feature = "tls")), allow(unused_mut))]
+            let mut listener = Listener::new(endpoint);
+            match listener.endpoint {
+                #[cfg(feature = "tls")]
+                Endpoint::Tcp(_) => listener.identity = identity.clone(),
+                #[cfg(unix)]
+                Endpoint::Unix(_) => listener.uds = config.socket.clone(),
+                _ => {}
+            }
+            self.listeners.push(listener);
+        }
+        Ok(self)
+    }
+
+    /// Cap the number of commands executing at once. Excess commands wait and
+    /// are admitted by priority, so short high-priority commands (health
+    /// checks, listings) are not stuck behind long maintenance operations.
+    pub fn with_max_concurrency(mut self, permits: usize) -> Self {
+        Arc::get_mut(&mut self.registry)
+            .expect("with_max_concurrency must be called before handle or serve")
+            .set_max_concurrency(permits);
+        self
+    }
+
+    /// Abandon commands that run longer than `timeout` and report a
+    /// `Timeout` error, so a hung service method does not tie up a worker.
+    /// `#[command(timeout = "...")]` overrides this per command; streaming
+    /// commands are only limited by their own timeout.
+    ///
+    /// The command's future is dropped when the time is up, which stops
+    /// async work at its next `.await`. Blocking code inside it, or tasks it
+    /// spawned, keep running.
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        Arc::get_mut(&mut self.registry)
+            .expect("with_default_timeout must be called before handle or serve")
+            .set_default_timeout(timeout);
+        self
+    }
+
+    /// Let commands nest calls to other commands (`Context::call`) up to
+    /// `depth` deep, counting the one the client asked for, instead of
+    /// `registry::DEFAULT_MAX_CALL_DEPTH`.
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        Arc::get_mut(&mut self.registry)
+            .expect("with_max_call_depth must be called before handle or serve")
+            .set_max_call_depth(depth);
+        self
+    }
+
+    /// Set how often idle connections are pinged and how long to wait for the
+    /// ack before treating the client as dead and cancelling its requests.
+    pub fn with_keepalive(mut self, keepalive: Keepalive) -> Self {
+        self.keepalive = keepalive;
+        self
+    }
+
+    /// Authenticate callers by the bearer token in their request metadata
+    /// and enforce `#[command(permission = "...")]` annotations. Requests
+    /// with an invalid token are rejected; requests without one run
+    /// anonymously and may only use commands that need no permission.
+    pub fn with_auth<A: Authenticator>(mut self, auth: A) -> Self {
+        let auth: Arc<dyn Authenticator> = Arc::new(auth);
+        Arc::get_mut(&mut self.registry)
+            .expect("with_auth must be called before handle or serve")
+            .set_authenticator(auth.clone());
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Run `middleware` on every command request before it reaches the
+    /// registry, after any installed earlier. See `crate::middleware`.
+    pub fn with_middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Record per-command invocation counts, error counts and latencies, and
+    /// serve them in the Prometheus text format at `http://<addr>/metrics`
+    /// (e.g. `0.0.0.0:9100`) while the server runs.
+    pub fn with_metrics(mut self, addr: &str) -> Self {
+        let metrics = Arc::get_mut(&mut self.registry)
+            .expect("with_metrics must be called before handle or serve")
+            .metrics();
+        self.metrics = Some((addr.to_string(), metrics));
+        self
+    }
+
+    /// Also serve every command over HTTP at `http://<addr>/api/{service}/{action}`
+    /// (e.g. `127.0.0.1:8080`): `POST` a JSON object of named arguments and
+    /// get the result back as JSON. Authentication, tenants and permissions
+    /// apply as for gRPC, using the same header names.
+    pub fn with_http_gateway(mut self, addr: &str) -> Self {
+        self.http_gateway = Some(addr.to_string());
+        self
+    }
+
+    /// Also serve every command as a Model Context Protocol tool at
+    /// `http://<addr>/mcp` (e.g. `127.0.0.1:8090`), so AI assistants can
+    /// list and run them (see `crate::mcp`). Authentication, tenants and
+    /// permissions apply as for the HTTP gateway.
+    pub fn with_mcp(mut self, addr: &str) -> Self {
+        self.mcp = Some(addr.to_string());
+        self
+    }
+
+    /// Also serve the gRPC services in the gRPC-Web format over HTTP/1.1 at
+    /// `addr` (e.g. `127.0.0.1:8081`), so a browser-based console can call
+    /// `ListServices` and `Execute` with a gRPC-Web client. Pages from the
+    /// origins `cors` allows may call it (see `crate::grpc_web`).
+    pub fn with_grpc_web(mut self, addr: &str, cors: Cors) -> Self {
+        self.grpc_web = Some((addr.to_string(), cors));
+        self
+    }
+
+    /// Also serve the gRPC server reflection protocol (`grpc.reflection.v1`
+    /// and `v1alpha`) on every endpoint, so tools like `grpcurl` can list,
+    /// describe and call the gRPC services without the .proto files. Like
+    /// health checks, it needs no token: it describes the API, not the
+    /// registered commands.
+    pub fn with_reflection(mut self, enabled: bool) -> Self {
+        self.reflection = enabled;
+        self
+    }
+
+    /// Send responses larger than `bytes` (4 MiB by default) in chunks, and
+    /// reject requests larger than it. Such a response is kept for a minute
+    /// and replaced by a stub that `NexusClient` and the CLI answer by
+    /// reading it with `ReadResponse`, so their callers still get it whole.
+    /// Other clients must do the same; the HTTP gateway and MCP tools, which
+    /// have no such limit, send responses whole.
+    pub fn with_max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    /// Compress responses for clients that accept it, as the CLI and
+    /// `NexusClient` do: with gzip, or zstd with the `zstd` feature. On by
+    /// default; turn it off where CPU is scarcer than bandwidth, e.g. for a
+    /// Unix socket. Compressed requests are accepted either way.
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    /// Register the built-in `admin` service: `uptime`, `stats` (calls,
+    /// errors and average time of each command), `connections`, and, with
+    /// the `admin` permission when authentication is enabled, `loglevel
+    /// [level]` (for `with_tracing_subscriber`) and `shutdown`, which stops
+    /// the server as SIGTERM does. Call it before `handle`.
+    pub fn with_admin(mut self, enabled: bool) -> Self {
+        self.admin = enabled.then(|| {
+            Arc::get_mut(&mut self.registry)
+                .expect("with_admin must be called before handle or serve")
+                .metrics()
+        });
+        self
+    }
+
+    /// Redact, in command traces, the values of arguments whose name
+    /// `redact` returns `true` for, in addition to `#[arg(secret)]` ones.
+    ///
+    /// ```ignore
+    /// server.with_redaction(|arg| arg.contains("password") || arg.ends_with("token"))
+    /// ```
+    pub fn with_redaction(mut self, redact: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Arc::get_mut(&mut self.registry)
+            .expect("with_redaction must be called before handle or serve")
+            .set_redaction(Arc::new(redact));
+        self
+    }
+
+    /// Install a basic `tracing` subscriber that writes to stderr, so every
+    /// command's span and outcome is logged. The level is read from
+    /// `NEXUS_LOG` (`error`, `warn`, `info`, `debug`, `trace`; default
+    /// `info`), and can be changed while the server runs with `admin
+    /// loglevel` (`with_admin`). Does nothing if a global subscriber is
+    /// already set; install your own (e.g. from `tracing-subscriber`)
+    /// instead for other formats.
+    pub fn with_tracing_subscriber(self) -> Self {
+        let level = std::env::var("NEXUS_LOG")
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(tracing::Level::INFO);
+        crate::trace::install(level);
+        self
+    }
+
+    /// Support zero-downtime upgrades through a control socket at `control`.
+    ///
+    /// On `serve`, if another daemon is running with the same control path,
+    /// its listening socket is handed over instead of binding a new one. The
+    /// old daemon then stops accepting connections, finishes in-flight
+    /// requests, and returns from `serve`. Clients reconnect to the new
+    /// daemon transparently on their next request.
+    #[cfg(unix)]
+    pub fn with_takeover(mut self, control: impl Into<PathBuf>) -> Self {
+        self.takeover = Some(control.into());
+        self
     }
 
-    /// Register a service with the server. Must be called before `serve`.
-    pub fn register<S: Service>(mut self, service: S) -> Self {
+    /// Load service plugins (`nexus_plugin!` libraries) from `dir`: every
+    /// `.so` file in it when `serve` starts, and further ones while running
+    /// through the `plugin load <path>` command (which needs the `admin`
+    /// permission when authentication is enabled). `plugin list` shows what
+    /// is loaded.
+    #[cfg(unix)]
+    pub fn with_plugin_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        let plugins = Arc::new(Plugins::new(self.handle(), dir.into()));
+        self.registry.register(PluginAdmin(plugins.clone()));
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Save background jobs in `dir`, one JSON file each, so `jobs` and `job
+    /// status` still show them, with their output, after the daemon
+    /// restarts. A job that was running when the daemon died is reported
+    /// as failed; on a graceful shutdown running jobs are cancelled. Call
+    /// it before `handle`.
+    pub fn with_job_store(mut self, dir: impl Into<PathBuf>) -> Self {
+        Arc::get_mut(&mut self.registry)
+            .expect("with_job_store must be called before handle or serve")
+            .set_job_store(dir.into());
+        self
+    }
+
+    /// Keep fewer or more finished jobs than the latest `MAX_FINISHED_JOBS`,
+    /// or forget those older than a given age, in memory and in the job
+    /// store alike:
+    ///
+    /// ```ignore
+    /// server.with_job_retention(JobRetention { max_jobs: 1000, max_age: Some(Duration::from_secs(7 * 86_400)) })
+    /// ```
+    pub fn with_job_retention(mut self, retention: JobRetention) -> Self {
         Arc::get_mut(&mut self.registry)
-            .expect("register must be called before serve")
-            .register(service);
+            .expect("with_job_retention must be called before handle or serve")
+            .set_job_retention(retention);
+        self
+    }
+
+    /// Register the `schedule` service, which runs commands on a crontab
+    /// schedule in UTC: `schedule add "0 3 * * *" pool scrub p1` starts
+    /// `pool scrub p1` as a background job every day at 03:00, acting as
+    /// the tenant and role that added it, so each run's outcome shows in
+    /// `job list`. `schedule list` and `schedule remove <id>` manage them.
+    /// Schedules are saved to `path` and loaded from it when `serve` starts.
+    pub fn with_scheduler(self, path: impl Into<PathBuf>) -> Self {
+        self.registry.register(ScheduleAdmin(Arc::new(Schedules::new(path.into()))));
+        self
+    }
+
+    /// Run `handler` to reload configuration on SIGHUP and on the `daemon
+    /// reload` command (which needs the `admin` permission when
+    /// authentication is enabled), e.g. to re-read a service's config file.
+    /// The listeners and connections stay up while it runs. Handlers run in
+    /// the order they were added; an error from one is reported without
+    /// stopping the others.
+    ///
+    /// ```ignore
+    /// let pools = Pools::load("/etc/storage/pools.toml")?;
+    /// let reloaded = pools.clone();
+    /// NexusServer::new()
+    ///     .register(pools)
+    ///     .with_reload_handler(move || {
+    ///         let pools = reloaded.clone();
+    ///         async move { pools.reload("/etc/storage/pools.toml").await }
+    ///     })
+    /// ```
+    pub fn with_reload_handler<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.reload.push(Box::new(move || Box::pin(handler())));
+        self
+    }
+
+    /// Share `state` with every service: command methods receive it by
+    /// declaring a `State<T>` parameter, and `Context::state` and
+    /// `StartContext::state` return it. One value is kept per type, so
+    /// services that need the same device manager or database handle get
+    /// the same one without a global static.
+    ///
+    /// ```ignore
+    /// NexusServer::new()
+    ///     .with_state(Arc::new(Devices::scan()?))
+    ///     .register(Volume)
+    ///     .register(Pool)
+    /// ```
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: Arc<T>) -> Self {
+        Arc::get_mut(&mut self.registry)
+            .expect("with_state must be called before handle or serve")
+            .set_state(state);
+        self
+    }
+
+    /// Transport builder with this server's liveness settings applied.
+    fn transport(&self) -> tonic::transport::Server {
+        tonic::transport::Server::builder()
+            .http2_keepalive_interval(Some(self.keepalive.interval))
+            .http2_keepalive_timeout(Some(self.keepalive.timeout))
+            .tcp_keepalive(Some(self.keepalive.interval))
+    }
+
+    /// Register a service with the server. Use `handle` to add services
+    /// once the server is running.
+    pub fn register<S: Service>(self, service: S) -> Self {
+        self.registry.register(service);
+        self
+    }
+
+    /// Register the service `init` builds, for services that need async
+    /// setup to be constructed, e.g. a connection to open or devices to
+    /// probe. `init` runs when `serve` starts, before the `on_start` hooks;
+    /// an error stops the server from starting.
+    ///
+    /// ```ignore
+    /// NexusServer::new().register_with(|| async { Volume::init("/etc/storage/volumes.toml").await })
+    /// ```
+    pub fn register_with<S, F, Fut>(mut self, init: F) -> Self
+    where
+        S: Service,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<S>> + Send + 'static,
+    {
+        self.pending.push(Box::pin(async move {
+            let service: Arc<dyn Service> = Arc::new(init().await?);
+            Ok(service)
+        }));
+        self
+    }
+
+    /// Register a service nested under `parent`, invoked as
+    /// `<parent> <service> <command>` (see `Registry::register_nested`).
+    pub fn register_nested<S: Service>(self, parent: &str, service: S) -> Self {
+        self.registry.register_nested(parent, service);
+        self
+    }
+
+    /// Register a service instance visible only to requests from `tenant`,
+    /// shadowing a shared service of the same name for that tenant.
+    pub fn register_for_tenant<S: Service>(self, tenant: &str, service: S) -> Self {
+        self.registry.register_for_tenant(tenant, service);
+        self
+    }
+
+    /// A handle for adding and removing services while the server runs.
+    /// Take it after the `with_*` settings that configure the registry
+    /// (concurrency, auth, metrics, redaction, shared state) and before
+    /// `serve`.
+    pub fn handle(&self) -> RegistryHandle {
+        RegistryHandle {
+            registry: self.registry.clone(),
+        }
+    }
+
+    /// Also serve on the TCP address `addr` (`host:port`). One daemon can
+    /// listen on several endpoints at once, e.g. a Unix socket for the local
+    /// CLI and a TLS port for remote management:
+    ///
+    /// ```ignore
+    /// NexusServer::new()
+    ///     .register(Volume)
+    ///     .listen_unix("/run/nexus.sock")
+    ///     .listen_tls("0.0.0.0:50051", identity)
+    ///     .serve_listeners()
+    ///     .await
+    /// ```
+    pub fn listen_tcp(mut self, addr: &str) -> Self {
+        self.listeners.push(Listener::new(Endpoint::Tcp(addr.to_string())));
+        self
+    }
+
+    /// Also serve on a Unix domain socket at `path` (see `listen_tcp`).
+    #[cfg(unix)]
+    pub fn listen_unix(self, path: impl Into<PathBuf>) -> Self {
+        self.listen_unix_with_opts(path, UdsOptions::default())
+    }
+
+    /// Also serve on a Unix domain socket at `path`, setting its mode and
+    /// ownership as in `serve_unix_with_opts`.
+    #[cfg(unix)]
+    pub fn listen_unix_with_opts(mut self, path: impl Into<PathBuf>, opts: UdsOptions) -> Self {
+        self.listeners.push(Listener {
+            uds: opts,
+            ..Listener::new(Endpoint::Unix(path.into()))
+        });
+        self
+    }
+
+    /// Also serve TLS on the TCP address `addr`, with the certificate and key
+    /// in `identity` (see `listen_tcp` and `serve_tls`).
+    #[cfg(feature = "tls")]
+    pub fn listen_tls(mut self, addr: &str, identity: tonic::transport::Identity) -> Self {
+        self.listeners.push(Listener {
+            identity: Some(identity),
+            ..Listener::new(Endpoint::Tcp(addr.to_string()))
+        });
         self
     }
 
-    /// Start the gRPC server on the given address.
+    /// Start the gRPC server on the given address, and on any endpoints
+    /// added with `listen_*`.
+    ///
+    /// The address is parsed as an [`Endpoint`]: `[::1]:50051` or
+    /// `tcp://[::1]:50051` for TCP, and `/tmp/nexus.sock` or
+    /// `unix:///tmp/nexus.sock` for a Unix domain socket.
     ///
-    /// If `addr` contains `:` it is treated as a TCP socket address (e.g.
-    /// `[::1]:50051`).  Otherwise it is treated as a Unix domain socket path
-    /// (e.g. `/tmp/nexus.sock`).
+    /// Runs until SIGINT or SIGTERM, then shuts down gracefully (see
+    /// `serve_with_shutdown`).
     pub async fn serve(self, addr: &str) -> anyhow::Result<()> {
+        self.serve_endpoint(addr.parse()?).await
+    }
+
+    /// Start the gRPC server on the endpoints added with `listen_*`, until
+    /// SIGINT or SIGTERM. Fails if there are none.
+    pub async fn serve_listeners(self) -> anyhow::Result<()> {
+        if self.listeners.is_empty() {
+            anyhow::bail!("no endpoints to serve on; add them with listen_tcp or listen_unix");
+        }
+        self.serve_with(None, shutdown_signal()).await
+    }
+
+    /// Start the gRPC server on the configured `listen` addresses
+    /// (`DEFAULT_ENDPOINT` if there are none), serving TLS on the TCP ones if
+    /// the config has a `tls` table.
+    pub async fn serve_configured(self) -> anyhow::Result<()> {
+        if self.listeners.is_empty() {
+            return self.serve(crate::DEFAULT_ENDPOINT).await;
+        }
+        self.serve_listeners().await
+    }
+
+    /// Start the gRPC server on an already parsed endpoint, and on any
+    /// endpoints added with `listen_*`.
+    pub async fn serve_endpoint(self, endpoint: Endpoint) -> anyhow::Result<()> {
+        self.serve_with(Some(Listener::new(endpoint)), shutdown_signal()).await
+    }
+
+    /// Start the gRPC server on a Unix domain socket at `path`, then set the
+    /// socket file's mode and ownership, e.g. to let only a management group
+    /// connect:
+    ///
+    /// ```ignore
+    /// let opts = UdsOptions { mode: Some(0o660), group: Some("storage-admin".into()), ..Default::default() };
+    /// NexusServer::new().register(Volume).serve_unix_with_opts("/run/nexus.sock", opts).await
+    /// ```
+    ///
+    /// Fails before serving if the options cannot be applied.
+    #[cfg(unix)]
+    pub async fn serve_unix_with_opts(self, path: impl Into<PathBuf>, opts: UdsOptions) -> anyhow::Result<()> {
+        let listener = Listener {
+            uds: opts,
+            ..Listener::new(Endpoint::Unix(path.into()))
+        };
+        self.serve_with(Some(listener), shutdown_signal()).await
+    }
+
+    /// Start the gRPC server on the given address and shut it down when
+    /// `signal` completes.
+    ///
+    /// On shutdown the server stops accepting connections, waits for
+    /// in-flight requests to finish, removes its Unix socket file, and runs
+    /// each service's `shutdown` hook before returning.
+    pub async fn serve_with_shutdown<F>(self, addr: &str, signal: F) -> anyhow::Result<()>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        self.serve_with(Some(Listener::new(addr.parse()?)), signal).await
+    }
+
+    /// Require TLS clients to present a certificate signed by `ca` (mutual
+    /// TLS). Only used by `serve_tls`.
+    #[cfg(feature = "tls")]
+    pub fn with_client_ca(mut self, ca: tonic::transport::Certificate) -> Self {
+        self.client_ca = Some(ca);
+        self
+    }
+
+    /// Start the gRPC server on a TCP address, encrypting connections with
+    /// the server certificate and key in `identity`.
+    ///
+    /// ```ignore
+    /// let identity = Identity::from_pem(std::fs::read("server.pem")?, std::fs::read("server.key")?);
+    /// NexusServer::new().register(Volume).serve_tls("0.0.0.0:50051", identity).await
+    /// ```
+    #[cfg(feature = "tls")]
+    pub async fn serve_tls(
+        self,
+        addr: &str,
+        identity: tonic::transport::Identity,
+    ) -> anyhow::Result<()> {
+        let endpoint = Endpoint::parse_tls(addr)?;
+        if !matches!(endpoint, Endpoint::Tcp(_)) {
+            anyhow::bail!("TLS is only supported for TCP endpoints, not {}", endpoint);
+        }
+        let listener = Listener {
+            identity: Some(identity),
+            ..Listener::new(endpoint)
+        };
+        self.serve_with(Some(listener), shutdown_signal()).await
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_config(&self, identity: tonic::transport::Identity) -> tonic::transport::ServerTlsConfig {
+        let tls = tonic::transport::ServerTlsConfig::new().identity(identity);
+        match &self.client_ca {
+            Some(ca) => tls.client_ca_root(ca.clone()),
+            None => tls,
+        }
+    }
+
+    /// Serve on `primary` (the address given to `serve*`) and the `listen_*`
+    /// endpoints until `signal` completes or one of them fails.
+    async fn serve_with<F>(
+        mut self,
+        primary: Option<Listener>,
+        signal: F,
+    ) -> anyhow::Result<()>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let listeners: Vec<_> = primary.into_iter().chain(std::mem::take(&mut self.listeners)).collect();
+        let mut endpoints = Vec::new();
+        #[cfg_attr(not(feature = "tls"), allow(unused_mut))]
+        for mut listener in listeners {
+            listener.endpoint.ensure_supported()?;
+            let transport = self.transport();
+            #[cfg(feature = "tls")]
+            let transport = match listener.identity.take() {
+                Some(identity) => transport.tls_config(self.tls_config(identity))?,
+                None => transport,
+            };
+            endpoints.push((transport, listener));
+        }
+        for init in std::mem::take(&mut self.pending) {
+            self.registry.insert(init.await?);
+        }
+        #[cfg(unix)]
+        if let Some(plugins) = &self.plugins {
+            plugins.load_dir();
+        }
+        // Cancelled to stop every endpoint, e.g. when one of them fails.
+        let stop = CancellationToken::new();
+        let connections = Arc::new(Connections::default());
+        if let Some(metrics) = self.admin.take() {
+            self.registry.register_admin(Admin {
+                started: Instant::now(),
+                metrics,
+                connections: connections.clone(),
+                maintenance: self.registry.maintenance_mode(),
+                stop: stop.clone(),
+            });
+        }
+        // Without handlers SIGHUP keeps its default action, and there is no
+        // `daemon` service.
+        let _hangup = match std::mem::take(&mut self.reload) {
+            handlers if handlers.is_empty() => None,
+            handlers => {
+                let reloader = Arc::new(Reloader::new(handlers));
+                self.registry.register(DaemonAdmin(reloader.clone()));
+                Some(AbortOnDrop(tokio::spawn(reloader.on_hangup())))
+            }
+        };
+        let start = StartContext {
+            handle: self.handle(),
+            stopping: stop.child_token(),
+        };
+        self.registry.start(&start).await?;
+        // The `on_stop` hooks run as soon as the server begins to stop, while
+        // requests drain, and finish before the `shutdown` hooks.
+        let stopped = {
+            let (registry, stop) = (self.registry.clone(), stop.clone());
+            tokio::spawn(async move {
+                stop.cancelled().await;
+                registry.stop().await;
+            })
+        };
+        let _metrics = match self.metrics {
+            Some((addr, metrics)) => {
+                let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+                println!("Metrics available at http://{}/metrics", listener.local_addr()?);
+                Some(AbortOnDrop(tokio::spawn(crate::metrics::serve(listener, metrics))))
+            }
+            None => None,
+        };
+        let (stopping, shutdown) = watch::channel(false);
+        let middleware: Arc<[Box<dyn Middleware>]> = std::mem::take(&mut self.middleware).into();
+        let recent = Arc::new(Recent::default());
+        let idempotency = Arc::new(Idempotency::default());
+        let _gateway = match self.http_gateway {
+            Some(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+                println!("HTTP gateway listening on http://{}/api", listener.local_addr()?);
+                let gateway = Gateway {
+                    grpc: NexusGrpcService {
+                        registry: self.registry.clone(),
+                        heartbeat: self.keepalive.interval,
+                        shutdown: shutdown.clone(),
+                        middleware: middleware.clone(),
+                        recent: recent.clone(),
+                        idempotency: idempotency.clone(),
+                        large: None,
+                    },
+                    auth: AuthInterceptor {
+                        auth: self.auth.clone(),
+                        connections: connections.clone(),
+                    },
+                };
+                Some(AbortOnDrop(tokio::spawn(crate::gateway::serve(listener, gateway))))
+            }
+            None => None,
+        };
+        let _mcp = match self.mcp {
+            Some(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+                println!("MCP tools available at http://{}/mcp", listener.local_addr()?);
+                let gateway = Gateway {
+                    grpc: NexusGrpcService {
+                        registry: self.registry.clone(),
+                        heartbeat: self.keepalive.interval,
+                        shutdown: shutdown.clone(),
+                        middleware: middleware.clone(),
+                        recent: recent.clone(),
+                        idempotency: idempotency.clone(),
+                        large: None,
+                    },
+                    auth: AuthInterceptor {
+                        auth: self.auth.clone(),
+                        connections: connections.clone(),
+                    },
+                };
+                Some(AbortOnDrop(tokio::spawn(crate::mcp::serve(listener, gateway))))
+            }
+            None => None,
+        };
+        let registry = self.registry.clone();
         let grpc_service = NexusGrpcService {
             registry: self.registry,
+            heartbeat: self.keepalive.interval,
+            shutdown,
+            middleware,
+            recent,
+            idempotency,
+            large: Some(Arc::new(LargeResponses::new(self.max_message_size))),
+        };
+        let mut server = NexusServiceServer::new(grpc_service).max_decoding_message_size(self.max_message_size);
+        for &encoding in COMPRESSION {
+            server = server.accept_compressed(encoding);
+            if self.compression {
+                server = server.send_compressed(encoding);
+            }
+        }
+        let auth = AuthInterceptor {
+            auth: self.auth,
+            connections: connections.clone(),
+        };
+        let svc = InterceptedService::new(server, auth);
+        let draining = Arc::new(AtomicBool::new(false));
+        let health = HealthServer::new(HealthService {
+            registry: registry.clone(),
+            draining: draining.clone(),
+        });
+        let _grpc_web = match self.grpc_web {
+            Some((addr, cors)) => {
+                let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+                println!("gRPC-Web listening on http://{}", listener.local_addr()?);
+                let routes = Routes::new(svc.clone()).add_service(health.clone());
+                Some(AbortOnDrop(tokio::spawn(crate::grpc_web::serve(listener, routes, cors))))
+            }
+            None => None,
+        };
+        let reflection = self.reflection.then_some(ReflectionService);
+        let routers: Vec<_> = endpoints
+            .into_iter()
+            .map(|(mut transport, listener)| {
+                let router = transport
+                    .add_service(svc.clone())
+                    .add_service(health.clone())
+                    .add_optional_service(reflection.map(ReflectionV1Server::new))
+                    .add_optional_service(reflection.map(ReflectionV1AlphaServer::new));
+                (router, listener)
+            })
+            .collect();
+        let signal = async {
+            tokio::select! {
+                _ = signal => {}
+                _ = stop.cancelled() => {}
+            }
+            // Health probes report NOT_SERVING while requests drain.
+            draining.store(true, Ordering::Relaxed);
+            stopping.send_replace(true);
+            println!("Shutting down; draining in-flight requests");
         };
-        let svc = NexusServiceServer::new(grpc_service);
 
-        if addr.contains(':') {
-            let sock_addr = addr.parse()?;
-            println!("Nexus server listening on {}", sock_addr);
-            tonic::transport::Server::builder()
-                .add_service(svc)
-                .serve(sock_addr)
-                .await?;
-        } else {
-            // Remove a stale socket file if it exists.
-            let _ = std::fs::remove_file(addr);
-            let uds = UnixListener::bind(addr)?;
-            let stream = UnixListenerStream::new(uds);
-            println!("Nexus server listening on {}", addr);
-            tonic::transport::Server::builder()
-                .add_service(svc)
-                .serve_with_incoming(stream)
-                .await?;
+        let tcp_keepalive = self.keepalive.interval;
+        #[cfg(unix)]
+        if let Some(control) = &self.takeover {
+            crate::takeover::serve(routers, control, tcp_keepalive, &connections, &stop, signal).await?;
+            let _ = stopped.await;
+            registry.shutdown().await;
+            return Ok(());
         }
 
+        let servers = routers
+            .into_iter()
+            .map(|(router, listener)| -> ServeFuture {
+                let stopped = stop.clone().cancelled_owned();
+                Box::pin(serve_on(router, listener, tcp_keepalive, connections.clone(), stopped))
+            })
+            .collect();
+        serve_all(servers, &stop, signal).await?;
+        let _ = stopped.await;
+        registry.shutdown().await;
         Ok(())
     }
 }
 
-struct NexusGrpcService {
-    registry: Arc<Registry>,
+/// One endpoint's server, completing once it has shut down.
+pub(crate) type ServeFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+/// Run `servers` until `signal` completes, then cancel `stop` (which they
+/// shut down on) and wait for them. One failing stops the others, and its
+/// error is returned once they have finished.
+pub(crate) async fn serve_all<F>(servers: Vec<ServeFuture>, stop: &CancellationToken, signal: F) -> anyhow::Result<()>
+where
+    F: Future<Output = ()>,
+{
+    let mut running = tokio::task::JoinSet::new();
+    for server in servers {
+        running.spawn(server);
+    }
+    let signal = async {
+        signal.await;
+        stop.cancel();
+    };
+    tokio::pin!(signal);
+    let mut signalled = false;
+    let mut result = Ok(());
+    while !running.is_empty() {
+        tokio::select! {
+            () = &mut signal, if !signalled => signalled = true,
+            Some(done) = running.join_next() => {
+                let done = done.map_err(anyhow::Error::from).and_then(|served| served);
+                if let (Err(e), Ok(())) = (done, &result) {
+                    stop.cancel();
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Serve `router` on the listener's endpoint until `signal` completes,
+/// tracking its connections in `connections`. Accepted TCP sockets get
+/// `tcp_keepalive`, as tonic's own listener would set.
+async fn serve_on<F>(
+    router: Router,
+    listener: Listener,
+    tcp_keepalive: Duration,
+    connections: Arc<Connections>,
+    signal: F,
+) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send,
+{
+    match &listener.endpoint {
+        Endpoint::Tcp(authority) => {
+            use tonic::transport::server::TcpIncoming;
+
+            let sock_addr = tokio::net::lookup_host(authority.as_str())
+                .await?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("could not resolve '{}'", authority))?;
+            let tcp = tokio::net::TcpListener::bind(sock_addr).await?;
+            println!("Nexus server listening on {}", tcp.local_addr()?);
+            let incoming =
+                TcpIncoming::from_listener(tcp, true, Some(tcp_keepalive)).map_err(anyhow::Error::from_boxed)?;
+            let incoming = connections.track(&listener.endpoint, incoming);
+            router.serve_with_incoming_shutdown(incoming, signal).await?;
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => {
+            use tokio::net::UnixListener;
+            use tokio_stream::wrappers::UnixListenerStream;
+
+            let uds = listener.uds.bind(path)?;
+            uds.set_nonblocking(true)?;
+            let uds = UnixListener::from_std(uds)?;
+            let stream = connections.track(&listener.endpoint, UnixListenerStream::new(uds));
+            println!("Nexus server listening on {}", path.display());
+            router.serve_with_incoming_shutdown(stream, signal).await?;
+            let _ = std::fs::remove_file(path);
+        }
+        _ => unreachable!("ensure_supported rejects unavailable transports"),
+    }
+    Ok(())
+}
+
+/// Aborts a background task when dropped, e.g. when the server stops.
+pub(crate) struct AbortOnDrop(pub(crate) tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Completes on SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    if tokio::signal::ctrl_c().await.is_err() {
+        // No way to be notified; run until the process is killed.
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Longest request ID accepted from a client; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// A request ID unique within this server process.
+fn next_request_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    format!("{:x}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// How much sooner than the client's deadline the request gives up, so that
+/// the command's timeout error reaches the client before tonic, on either
+/// end, abandons the call at the deadline itself (with a bare "Timeout
+/// expired").
+const DEADLINE_MARGIN: Duration = Duration::from_millis(5);
+
+/// The deadline a gRPC client set with the `grpc-timeout` header: a number
+/// followed by `H`, `M`, `S`, `m`, `u` or `n`, less `DEADLINE_MARGIN`.
+fn client_deadline(metadata: &tonic::metadata::MetadataMap) -> Option<std::time::Instant> {
+    let value = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    let timeout = match unit {
+        "H" => Duration::from_secs(amount.saturating_mul(3600)),
+        "M" => Duration::from_secs(amount.saturating_mul(60)),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+    std::time::Instant::now().checked_add(timeout.saturating_sub(DEADLINE_MARGIN))
+}
+
+/// Build the request context and arguments from an incoming command request.
+fn request_parts(request: Request<CommandRequest>) -> (Context, CommandRequest, Args) {
+    let mut preferences = Preferences::from_metadata(request.metadata());
+    if let Some(connection) = request.extensions().get::<ConnectionRef>() {
+        preferences = connection.preferences(preferences);
+    }
+    let tenant = Context::tenant_from_metadata(request.metadata());
+    let role = request.extensions().get::<CallerRole>().map(|r| r.0.clone());
+    let peer = request.extensions().get::<PeerCredentials>().copied();
+    let request_id = request
+        .metadata()
+        .get(REQUEST_ID_KEY)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map_or_else(next_request_id, str::to_string);
+    let deadline = client_deadline(request.metadata());
+    let mut req = request.into_inner();
+    if !req.format.is_empty() {
+        preferences.format = Some(std::mem::take(&mut req.format));
+    }
+    let command = format!("{} {}", crate::cli::command_path(&req.service), req.action);
+    let ctx = Context::new()
+        .with_preferences(preferences)
+        .with_tenant(tenant)
+        .with_role(role)
+        .with_peer(peer)
+        .with_request_id(request_id)
+        .with_command(command)
+        .with_deadline(deadline)
+        .with_dry_run(req.dry_run)
+        .with_priority(priority_from_proto(req.priority()));
+    let args = Args {
+        positional: std::mem::take(&mut req.args),
+        named: std::mem::take(&mut req.named_args),
+    };
+    (ctx, req, args)
+}
+
+/// The services visible to `tenant`, as sent by `ListServices`.
+fn service_list(registry: &Registry, tenant: Option<&str>) -> ListServicesResponse {
+    // Read before listing, so a concurrent change shows up as a newer revision.
+    let revision = registry.revision();
+    let mut services: Vec<ServiceInfo> = registry
+        .list_services(tenant)
+        .into_iter()
+        .map(|service| ServiceInfo {
+            name: service.name().to_string(),
+            description: service.description().to_string(),
+            aliases: service.aliases().iter().map(|a| a.to_string()).collect(),
+            commands: service
+                .commands()
+                .into_iter()
+                .map(|c| CommandDef {
+                    timeout_ms: registry.timeout(Some(&c)).map_or(0, |t| t.as_millis() as u64),
+                    name: c.name,
+                    args: c.args
+                        .into_iter()
+                        .map(|a| ArgDef {
+                            name: a.name,
+                            hint: a.hint,
+                            completer: a.completer,
+                            completer_args: a.completer_args,
+                            description: a.description,
+                            glob: a.glob,
+                            optional: a.optional,
+                            default_value: a.default,
+                            type_name: a.type_name,
+                            choices: a.choices,
+                            variadic: a.variadic,
+                        })
+                        .collect(),
+                    description: c.description,
+                    help: c.help,
+                    dry_run: c.dry_run,
+                    priority: priority_to_proto(c.priority) as i32,
+                    streaming: c.streaming,
+                    background: c.background,
+                    progress: c.progress,
+                    permission: c.permission,
+                    since: c.since,
+                    deprecated: c.deprecated.is_some(),
+                    deprecation: c.deprecated.unwrap_or_default(),
+                    readonly: c.readonly,
+                    upload: c.upload,
+                    download: c.download,
+                    interactive: c.interactive,
+                })
+                .collect(),
+        })
+        .collect();
+    services.sort_by(|a, b| a.name.cmp(&b.name));
+    ListServicesResponse {
+        services,
+        revision,
+        protocol_version: crate::PROTOCOL_VERSION,
+    }
+}
+
+/// Response reporting a failed command.
+fn error_response(e: &anyhow::Error) -> CommandResponse {
+    let busy = e.downcast_ref::<Busy>();
+    let retry_after = busy
+        .and_then(|b| b.retry_after)
+        .or_else(|| e.downcast_ref::<RateLimited>().map(|r| r.retry_after));
+    CommandResponse {
+        success: false,
+        message: e.to_string(),
+        busy: busy.is_some(),
+        retry_after_ms: retry_after.map(|d| d.as_millis() as u64).unwrap_or(0),
+        error_code: error_code_to_proto(ErrorCode::of(e)) as i32,
+        error_details: e
+            .downcast_ref::<NexusError>()
+            .map(|e| e.details.clone().into_iter().collect())
+            .unwrap_or_default(),
+        suggestions: e.downcast_ref::<NexusError>().map(|e| e.suggestions.clone()).unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+fn output_to_proto(output: CommandOutput) -> proto::command_response::Output {
+    use proto::command_response::Output;
+    match output {
+        CommandOutput::Text(text) => Output::Text(text),
+        CommandOutput::Json(value) => Output::Json(value.to_string()),
+        CommandOutput::Table { headers, rows } => Output::Table(Table {
+            headers,
+            rows: rows.into_iter().map(|cells| TableRow { cells }).collect(),
+        }),
+        CommandOutput::Bytes(bytes) => Output::Payload(bytes),
+    }
+}
+
+fn priority_from_proto(priority: proto::Priority) -> Option<Priority> {
+    match priority {
+        proto::Priority::Unspecified => None,
+        proto::Priority::Low => Some(Priority::Low),
+        proto::Priority::Normal => Some(Priority::Normal),
+        proto::Priority::High => Some(Priority::High),
+    }
+}
+
+pub(crate) fn priority_to_proto(priority: Priority) -> proto::Priority {
+    match priority {
+        Priority::Low => proto::Priority::Low,
+        Priority::Normal => proto::Priority::Normal,
+        Priority::High => proto::Priority::High,
+    }
+}
+
+pub(crate) fn error_code_to_proto(code: ErrorCode) -> proto::ErrorCode {
+    match code {
+        ErrorCode::NotFound => proto::ErrorCode::NotFound,
+        ErrorCode::InvalidArgument => proto::ErrorCode::InvalidArgument,
+        ErrorCode::PermissionDenied => proto::ErrorCode::PermissionDenied,
+        ErrorCode::Internal => proto::ErrorCode::Internal,
+        ErrorCode::Busy => proto::ErrorCode::Busy,
+        ErrorCode::Timeout => proto::ErrorCode::Timeout,
+        ErrorCode::ResourceExhausted => proto::ErrorCode::ResourceExhausted,
+        ErrorCode::Unavailable => proto::ErrorCode::Unavailable,
+    }
+}
+
+/// The error code of a failed response. Servers predating error codes leave
+/// it unset; their busy flag still counts.
+pub(crate) fn error_code_from_proto(response: &CommandResponse) -> ErrorCode {
+    match response.error_code() {
+        proto::ErrorCode::NotFound => ErrorCode::NotFound,
+        proto::ErrorCode::InvalidArgument => ErrorCode::InvalidArgument,
+        proto::ErrorCode::PermissionDenied => ErrorCode::PermissionDenied,
+        proto::ErrorCode::Busy => ErrorCode::Busy,
+        proto::ErrorCode::Timeout => ErrorCode::Timeout,
+        proto::ErrorCode::ResourceExhausted => ErrorCode::ResourceExhausted,
+        proto::ErrorCode::Unavailable => ErrorCode::Unavailable,
+        proto::ErrorCode::Internal | proto::ErrorCode::Unspecified if response.busy => ErrorCode::Busy,
+        proto::ErrorCode::Internal | proto::ErrorCode::Unspecified => ErrorCode::Internal,
+    }
+}
+
+pub(crate) struct NexusGrpcService {
+    pub(crate) registry: Arc<Registry>,
+    /// How long a streaming command may stay quiet before a heartbeat is sent.
+    pub(crate) heartbeat: Duration,
+    /// Becomes `true` when the server starts shutting down.
+    pub(crate) shutdown: watch::Receiver<bool>,
+    pub(crate) middleware: Arc<[Box<dyn Middleware>]>,
+    /// Results of the latest commands, for `RecentResults`.
+    pub(crate) recent: Arc<Recent>,
+    /// Responses to requests with an idempotency key, for their retries.
+    pub(crate) idempotency: Arc<Idempotency>,
+    /// Responses too large for a message, until `ReadResponse` fetches
+    /// them; `None` for the HTTP gateway and MCP, which send them whole.
+    pub(crate) large: Option<Arc<LargeResponses>>,
+}
+
+impl NexusGrpcService {
+    /// Build the request context and arguments from an incoming command
+    /// request and run the middleware on it. A middleware error is returned
+    /// alongside, for the caller to reject the request with.
+    fn request_parts(&self, request: Request<CommandRequest>) -> (Context, CommandRequest, Args, anyhow::Result<()>) {
+        let metadata = request.metadata().clone();
+        let remote_addr = request.remote_addr();
+        let (ctx, req, args) = request_parts(request);
+        let mut ctx = ctx
+            .with_remote_addr(remote_addr)
+            .with_states(self.registry.states())
+            .with_events(self.registry.events())
+            .with_registry(RegistryHandle {
+                registry: self.registry.clone(),
+            });
+        let admitted = self.middleware.iter().try_for_each(|middleware| middleware.on_request(&metadata, &mut ctx));
+        if let Err(e) = &admitted {
+            tracing::warn!(
+                request_id = ctx.request_id,
+                service = req.service,
+                action = req.action,
+                error = %e,
+                "request rejected"
+            );
+        }
+        (ctx, req, args, admitted)
+    }
+
+    /// Complete `response` with what the request gathered along the way.
+    fn reply(&self, ctx: &Context, response: CommandResponse) -> CommandResponse {
+        CommandResponse {
+            warnings: ctx.take_warnings(),
+            services_revision: self.registry.revision(),
+            ..response
+        }
+    }
+
+    /// `job` as sent to `tenant`, its result a stub if it is too large.
+    fn fit_job(&self, tenant: Option<&str>, mut job: JobInfo) -> JobInfo {
+        job.result = job.result.map(|result| fit(self.large.as_deref(), tenant, result));
+        job
+    }
+
+    /// Run an admitted command request, only once for retries that carry
+    /// the same idempotency key.
+    async fn run(&self, ctx: &Context, req: &CommandRequest, args: Args) -> CommandResponse {
+        if req.idempotency_key.is_empty() || ctx.dry_run {
+            return self.run_once(ctx, req, args).await;
+        }
+        let first = self.run_once(ctx, req, args.clone());
+        match self.idempotency.run(ctx, req, &args, first).await {
+            Ok(response) => response,
+            Err(e) => self.reply(ctx, error_response(&e.into())),
+        }
+    }
+
+    /// Run an admitted command request, or start it as a job, and record
+    /// its response among the recent results.
+    async fn run_once(&self, ctx: &Context, req: &CommandRequest, args: Args) -> CommandResponse {
+        let started = Instant::now();
+        let (service, described) = self.registry.describe(ctx, &req.service, &req.action, &args);
+        let response = if self.registry.is_background(ctx, &req.service, &req.action) {
+            match self.registry.start_job(ctx, &req.service, &req.action, args) {
+                Ok(id) => {
+                    let message = format!("Started job {}", id);
+                    CommandResponse {
+                        success: true,
+                        job_id: id,
+                        output: Some(proto::command_response::Output::Text(message.clone())),
+                        message,
+                        ..Default::default()
+                    }
+                }
+                Err(e) => error_response(&e),
+            }
+        } else {
+            match self.registry.execute(ctx, &req.service, &req.action, args).await {
+                Ok(output) => success_response(ctx, output),
+                Err(e) => error_response(&e),
+            }
+        };
+        let response = self.reply(ctx, response);
+        self.recent.record(ctx, &service, &req.action, described, started.elapsed(), response.clone());
+        response
+    }
+}
+
+/// `response` as sent to `tenant` over gRPC: a stub for `ReadResponse` if
+/// it is larger than `large` allows.
+fn fit(large: Option<&LargeResponses>, tenant: Option<&str>, response: CommandResponse) -> CommandResponse {
+    match large {
+        Some(large) => large.fit(tenant, response),
+        None => response,
+    }
+}
+
+/// Wrap `message`, returning the request's ID in the response metadata.
+fn with_request_id<T>(ctx: &Context, message: T) -> Response<T> {
+    let mut response = Response::new(message);
+    if let Ok(id) = MetadataValue::try_from(ctx.request_id.as_str()) {
+        response.metadata_mut().insert(REQUEST_ID_KEY, id);
+    }
+    response
 }
 
 #[tonic::async_trait]
@@ -73,49 +1417,638 @@ impl NexusService for NexusGrpcService {
         &self,
         request: Request<CommandRequest>,
     ) -> Result<Response<CommandResponse>, Status> {
-        let req = request.into_inner();
-        match self.registry.execute(&req.service, &req.action, req.args).await {
-            Ok(message) => Ok(Response::new(CommandResponse {
-                success: true,
-                message,
-            })),
-            Err(e) => Ok(Response::new(CommandResponse {
-                success: false,
-                message: e.to_string(),
-            })),
+        let (ctx, req, args, admitted) = self.request_parts(request);
+        let response = match admitted {
+            Ok(()) => self.run(&ctx, &req, args).await,
+            Err(e) => self.reply(&ctx, error_response(&e)),
+        };
+        let response = fit(self.large.as_deref(), ctx.tenant.as_deref(), response);
+        Ok(with_request_id(&ctx, response))
+    }
+
+    async fn execute_batch(&self, request: Request<BatchRequest>) -> Result<Response<BatchResponse>, Status> {
+        let (metadata, extensions, batch) = request.into_parts();
+        let mut results = Vec::with_capacity(batch.commands.len());
+        // The commands that changed something, to undo if a later one fails.
+        let mut applied = Vec::new();
+        for command in batch.commands {
+            // Each command is a request of its own from the batch's caller.
+            let (ctx, req, args, admitted) = self.request_parts(Request::from_parts(
+                metadata.clone(),
+                extensions.clone(),
+                command,
+            ));
+            let response = match admitted {
+                Ok(()) => self.run(&ctx, &req, args.clone()).await,
+                Err(e) => self.reply(&ctx, error_response(&e)),
+            };
+            let success = response.success;
+            results.push(fit(self.large.as_deref(), ctx.tenant.as_deref(), response));
+            if !success {
+                break;
+            }
+            if !ctx.dry_run {
+                applied.push((ctx, req, args));
+            }
+        }
+        let success = results.iter().all(|response| response.success);
+        let rolled_back = !success && batch.atomic && !applied.is_empty();
+        let mut rollback_errors = Vec::new();
+        if rolled_back {
+            for (ctx, req, args) in applied.into_iter().rev() {
+                if let Err(e) = self.registry.rollback(&ctx, &req.service, &req.action, args).await {
+                    let command = format!("{} {}", crate::cli::command_path(&req.service), req.action);
+                    rollback_errors.push(format!("{}: {:#}", command, e));
+                }
+            }
+        }
+        Ok(Response::new(BatchResponse {
+            success,
+            results,
+            rolled_back,
+            rollback_errors,
+        }))
+    }
+
+    type ExecuteStreamStream = Pin<Box<dyn Stream<Item = Result<StreamFrame, Status>> + Send>>;
+
+    async fn execute_stream(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<Self::ExecuteStreamStream>, Status> {
+        let (mut ctx, req, args, admitted) = self.request_parts(request);
+        if let Err(e) = admitted {
+            let result = frame(Frame::Result(self.reply(&ctx, error_response(&e))));
+            let rejected: Self::ExecuteStreamStream = Box::pin(tokio_stream::once(Ok(result)));
+            return Ok(with_request_id(&ctx, rejected));
         }
+        // A command reporting progress runs as for `Execute`, its updates
+        // sent ahead of the result.
+        let updates = self.registry.reports_progress(&ctx, &req.service, &req.action).then(|| {
+            let (progress, updates) = Progress::channel();
+            ctx = std::mem::take(&mut ctx).with_progress(progress);
+            updates
+        });
+        let started = Instant::now();
+        let (service, described) = self.registry.describe(&ctx, &req.service, &req.action, &args);
+        let (tx, rx) = mpsc::channel(16);
+        let registry = self.registry.clone();
+        let recent = self.recent.clone();
+        let large = self.large.clone();
+        let heartbeat = self.heartbeat;
+        let stream: Self::ExecuteStreamStream = Box::pin(ReceiverStream::new(rx));
+        let response = with_request_id(&ctx, stream);
+        tokio::spawn(async move {
+            let mut lines = VecDeque::new();
+            let forwarded = match updates {
+                Some(updates) => forward_progress(&registry, &ctx, &req, args, updates, &tx, heartbeat).await,
+                None => forward_lines(&registry, &ctx, &req, args, &tx, heartbeat, &mut lines).await,
+            };
+            let Some(mut result) = forwarded else {
+                // Client went away: dropping the command cancels it.
+                tracing::info!(
+                    service = req.service,
+                    action = req.action,
+                    "client disconnected; command cancelled"
+                );
+                return;
+            };
+            result.warnings = ctx.take_warnings();
+            result.services_revision = registry.revision();
+            let mut recorded = result.clone();
+            if !lines.is_empty() {
+                let text = Vec::from(lines).join("\n");
+                recorded.output = Some(proto::command_response::Output::Text(text));
+            }
+            recent.record(&ctx, &service, &req.action, described, started.elapsed(), recorded);
+            let result = fit(large.as_deref(), ctx.tenant.as_deref(), result);
+            let _ = tx.send(Ok(frame(Frame::Result(result)))).await;
+        });
+        Ok(response)
     }
 
     async fn list_services(
         &self,
-        _request: Request<ListServicesRequest>,
+        request: Request<ListServicesRequest>,
     ) -> Result<Response<ListServicesResponse>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        tracing::debug!(
+            tenant = tenant.as_deref().unwrap_or("-"),
+            protocol_version = request.get_ref().protocol_version,
+            "listing services"
+        );
+        Ok(Response::new(service_list(&self.registry, tenant.as_deref())))
+    }
+
+    type WatchServicesStream = ReceiverStream<Result<ListServicesResponse, Status>>;
+
+    async fn watch_services(
+        &self,
+        request: Request<ListServicesRequest>,
+    ) -> Result<Response<Self::WatchServicesStream>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        tracing::debug!(tenant = tenant.as_deref().unwrap_or("-"), "watching services");
+        let registry = self.registry.clone();
+        let mut changes = registry.subscribe();
+        let mut shutdown = self.shutdown.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                changes.borrow_and_update();
+                let listing = service_list(&registry, tenant.as_deref());
+                // Changes for other tenants leave this one's list untouched.
+                if last.as_ref() != Some(&listing.services) {
+                    last = Some(listing.services.clone());
+                    if tx.send(Ok(listing)).await.is_err() {
+                        return;
+                    }
+                }
+                tokio::select! {
+                    changed = changes.changed() => if changed.is_err() { return },
+                    // End the stream on shutdown so it does not hold up draining.
+                    _ = shutdown.wait_for(|stopping| *stopping) => return,
+                    _ = tx.closed() => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<EventMessage, Status>>;
+
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        let topics = request.into_inner().topics;
+        tracing::debug!(tenant = tenant.as_deref().unwrap_or("-"), ?topics, "subscribing to events");
+        let mut events = self.registry.events().subscribe();
+        let mut shutdown = self.shutdown.clone();
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let mut missed = 0;
+            loop {
+                let published = tokio::select! {
+                    received = events.recv() => match received {
+                        Ok(published) => published,
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            missed += n;
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    // End the stream on shutdown so it does not hold up draining.
+                    _ = shutdown.wait_for(|stopping| *stopping) => return,
+                    _ = tx.closed() => return,
+                };
+                if !published.is_for(tenant.as_deref(), &topics) {
+                    continue;
+                }
+                let message = EventMessage {
+                    emitted_at_ms: published.at.duration_since(UNIX_EPOCH).map_or(0, |at| at.as_millis() as u64),
+                    topic: published.event.topic,
+                    payload: published.event.payload,
+                    missed: std::mem::take(&mut missed),
+                };
+                if tx.send(Ok(message)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn upload(&self, request: Request<Streaming<UploadChunk>>) -> Result<Response<CommandResponse>, Status> {
+        let (metadata, extensions, mut chunks) = request.into_parts();
+        let first = chunks.message().await?.and_then(|chunk| chunk.chunk);
+        let Some(proto::upload_chunk::Chunk::Start(start)) = first else {
+            return Err(Status::invalid_argument("an upload starts with the command request"));
+        };
+        let request = Request::from_parts(metadata, extensions, start.request.unwrap_or_default());
+        let (ctx, req, args, admitted) = self.request_parts(request);
+        let data = chunks.map(|chunk| match chunk {
+            Ok(UploadChunk {
+                chunk: Some(proto::upload_chunk::Chunk::Data(data)),
+            }) => Ok(data),
+            Ok(_) => {
+                let message = "only the first message of an upload carries the request";
+                Err(NexusError::invalid_argument(message).into())
+            }
+            Err(status) => Err(anyhow::anyhow!("the upload broke off: {}", status.message())),
+        });
+        // Only the name is kept: where the file was is the client's business.
+        let name = Path::new(&start.file_name)
+            .file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+        let ctx = ctx.with_upload(Upload::new(name, (start.size > 0).then_some(start.size), Box::pin(data)));
+        let response = match admitted {
+            Ok(()) if !self.registry.takes_upload(&ctx, &req.service, &req.action) => {
+                let message = format!("'{}' does not take a file; run it without 'put'", ctx.command);
+                self.reply(&ctx, error_response(&NexusError::invalid_argument(message).into()))
+            }
+            Ok(()) => self.run(&ctx, &req, args).await,
+            Err(e) => self.reply(&ctx, error_response(&e)),
+        };
+        let response = fit(self.large.as_deref(), ctx.tenant.as_deref(), response);
+        Ok(with_request_id(&ctx, response))
+    }
+
+    type DownloadStream = Pin<Box<dyn Stream<Item = Result<DownloadChunk, Status>> + Send>>;
+
+    async fn download(&self, request: Request<CommandRequest>) -> Result<Response<Self::DownloadStream>, Status> {
+        let (ctx, req, args, admitted) = self.request_parts(request);
+        let rejected = match admitted {
+            Ok(()) if !self.registry.sends_download(&ctx, &req.service, &req.action) => {
+                let message = format!("'{}' does not send a file; run it without 'get'", ctx.command);
+                Some(NexusError::invalid_argument(message).into())
+            }
+            Ok(()) => None,
+            Err(e) => Some(e),
+        };
+        if let Some(e) = rejected {
+            let result = self.reply(&ctx, error_response(&e));
+            let result = download_chunk(proto::download_chunk::Chunk::Result(result));
+            let rejected: Self::DownloadStream = Box::pin(tokio_stream::once(Ok(result)));
+            return Ok(with_request_id(&ctx, rejected));
+        }
+        let (download, mut data) = Download::channel();
+        let ctx = ctx.with_download(download);
+        let started = Instant::now();
+        let (service, described) = self.registry.describe(&ctx, &req.service, &req.action, &args);
+        let (tx, rx) = mpsc::channel(4);
+        let registry = self.registry.clone();
+        let recent = self.recent.clone();
+        let large = self.large.clone();
+        let stream: Self::DownloadStream = Box::pin(ReceiverStream::new(rx));
+        let response = with_request_id(&ctx, stream);
+        tokio::spawn(async move {
+            let Some(mut result) = forward_download(&registry, &ctx, &req, args, &mut data, &tx).await else {
+                tracing::info!(
+                    service = req.service,
+                    action = req.action,
+                    "client disconnected; command cancelled"
+                );
+                return;
+            };
+            result.warnings = ctx.take_warnings();
+            result.services_revision = registry.revision();
+            recent.record(&ctx, &service, &req.action, described, started.elapsed(), result.clone());
+            let result = fit(large.as_deref(), ctx.tenant.as_deref(), result);
+            let _ = tx.send(Ok(download_chunk(proto::download_chunk::Chunk::Result(result)))).await;
+        });
+        Ok(response)
+    }
+
+    async fn check_health(
+        &self,
+        request: Request<HealthRequest>,
+    ) -> Result<Response<HealthResponse>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        tracing::debug!(tenant = tenant.as_deref().unwrap_or("-"), "checking health");
         let services = self
             .registry
-            .list_services()
+            .health(tenant.as_deref())
+            .await
             .into_iter()
-            .map(|(name, description, commands)| ServiceInfo {
-                name: name.to_string(),
-                description: description.to_string(),
-                commands: commands
-                    .into_iter()
-                    .map(|c| CommandDef {
-                        name: c.name,
-                        args: c.args
-                            .into_iter()
-                            .map(|a| ArgDef {
-                                name: a.name,
-                                hint: a.hint,
-                                completer: a.completer,
-                                description: a.description,
-                            })
-                            .collect(),
-                        description: c.description,
-                    })
-                    .collect(),
+            .map(|(name, health)| match health {
+                Health::Serving => ServiceHealth {
+                    name,
+                    serving: true,
+                    message: String::new(),
+                },
+                Health::NotServing(message) => ServiceHealth {
+                    name,
+                    serving: false,
+                    message,
+                },
             })
             .collect();
+        Ok(Response::new(HealthResponse { services }))
+    }
+
+    async fn job_status(&self, request: Request<JobRequest>) -> Result<Response<JobInfo>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        let id = request.into_inner().id;
+        let job = self.registry.job(tenant.as_deref(), id).ok_or_else(|| unknown_job(id))?;
+        Ok(Response::new(self.fit_job(tenant.as_deref(), job_to_proto(job))))
+    }
+
+    async fn job_list(
+        &self,
+        request: Request<JobListRequest>,
+    ) -> Result<Response<JobListResponse>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        let jobs = self.registry.jobs(tenant.as_deref()).into_iter();
+        let jobs = jobs.map(|job| self.fit_job(tenant.as_deref(), job_to_proto(job))).collect();
+        Ok(Response::new(JobListResponse { jobs }))
+    }
+
+    async fn job_cancel(&self, request: Request<JobRequest>) -> Result<Response<JobInfo>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        let id = request.into_inner().id;
+        let job = self.registry.cancel_job(tenant.as_deref(), id).ok_or_else(|| unknown_job(id))?;
+        Ok(Response::new(self.fit_job(tenant.as_deref(), job_to_proto(job))))
+    }
+
+    async fn recent_results(
+        &self,
+        request: Request<RecentRequest>,
+    ) -> Result<Response<RecentResponse>, Status> {
+        let ctx = Context::new()
+            .with_tenant(Context::tenant_from_metadata(request.metadata()))
+            .with_role(request.extensions().get::<CallerRole>().map(|r| r.0.clone()))
+            .with_peer(request.extensions().get::<PeerCredentials>().copied())
+            .with_remote_addr(request.remote_addr());
+        let RecentRequest { limit, all } = request.into_inner();
+        if all && !self.registry.allows(&ctx, "admin") {
+            return Err(Status::permission_denied("listing every caller's results requires permission 'admin'"));
+        }
+        let mut results = self.recent.list(&ctx, all, limit as usize);
+        let tenant = ctx.tenant.as_deref();
+        for recent in &mut results {
+            recent.result = recent.result.take().map(|result| fit(self.large.as_deref(), tenant, result));
+        }
+        Ok(Response::new(RecentResponse { results }))
+    }
+
+    type SessionStream = Pin<Box<dyn Stream<Item = Result<SessionOutput, Status>> + Send>>;
 
-        Ok(Response::new(ListServicesResponse { services }))
+    async fn session(
+        &self,
+        request: Request<Streaming<SessionInput>>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        let (metadata, extensions, mut inputs) = request.into_parts();
+        let first = inputs.message().await?.and_then(|input| input.input);
+        let Some(proto::session_input::Input::Start(start)) = first else {
+            return Err(Status::invalid_argument("a session starts with the command request"));
+        };
+        let request = Request::from_parts(metadata, extensions, start);
+        let (ctx, req, args, admitted) = self.request_parts(request);
+        let rejected = match admitted {
+            Ok(()) if !self.registry.is_interactive(&ctx, &req.service, &req.action) => {
+                let message = format!("'{}' does not ask questions; run it as usual", ctx.command);
+                Some(NexusError::invalid_argument(message).into())
+            }
+            Ok(()) => None,
+            Err(e) => Some(e),
+        };
+        if let Some(e) = rejected {
+            let result = self.reply(&ctx, error_response(&e));
+            let result = crate::session::output(proto::session_output::Output::Result(result));
+            let rejected: Self::SessionStream = Box::pin(tokio_stream::once(Ok(result)));
+            return Ok(with_request_id(&ctx, rejected));
+        }
+        let (tx, rx) = mpsc::channel(4);
+        let ctx = ctx.with_session(Session::new(Arc::new(Relay::new(tx.clone(), inputs))));
+        let started = Instant::now();
+        let (service, described) = self.registry.describe(&ctx, &req.service, &req.action, &args);
+        let registry = self.registry.clone();
+        let recent = self.recent.clone();
+        let large = self.large.clone();
+        let stream: Self::SessionStream = Box::pin(ReceiverStream::new(rx));
+        let response = with_request_id(&ctx, stream);
+        tokio::spawn(async move {
+            let outcome = tokio::select! {
+                outcome = registry.execute(&ctx, &req.service, &req.action, args) => outcome,
+                _ = tx.closed() => {
+                    tracing::info!(
+                        service = req.service,
+                        action = req.action,
+                        "client disconnected; command cancelled"
+                    );
+                    return;
+                }
+            };
+            let mut result = match outcome {
+                Ok(output) => success_response(&ctx, output),
+                Err(e) => error_response(&e),
+            };
+            result.warnings = ctx.take_warnings();
+            result.services_revision = registry.revision();
+            recent.record(&ctx, &service, &req.action, described, started.elapsed(), result.clone());
+            let result = fit(large.as_deref(), ctx.tenant.as_deref(), result);
+            let _ = tx.send(Ok(crate::session::output(proto::session_output::Output::Result(result)))).await;
+        });
+        Ok(response)
+    }
+
+    type ReadResponseStream = Pin<Box<dyn Stream<Item = Result<ResponseChunk, Status>> + Send>>;
+
+    async fn read_response(
+        &self,
+        request: Request<ReadResponseRequest>,
+    ) -> Result<Response<Self::ReadResponseStream>, Status> {
+        let tenant = Context::tenant_from_metadata(request.metadata());
+        let id = request.into_inner().id;
+        let chunks = self
+            .large
+            .as_deref()
+            .and_then(|large| large.take(tenant.as_deref(), id))
+            .ok_or_else(|| Status::not_found(format!("no response {} to read", id)))?;
+        let chunks = chunks.into_iter().map(|data| ResponseChunk { data }).map(Ok);
+        Ok(Response::new(Box::pin(tokio_stream::iter(chunks))))
+    }
+}
+
+fn unknown_job(id: u64) -> Status {
+    Status::not_found(format!("no job {}", id))
+}
+
+/// A job as sent by the job RPCs, with its outcome (if finished) as the
+/// response the command would have had.
+type FrameSender = mpsc::Sender<Result<StreamFrame, Status>>;
+
+fn frame(frame: Frame) -> StreamFrame {
+    StreamFrame { frame: Some(frame) }
+}
+
+/// Run a streaming command, sending its lines as frames and heartbeats while
+/// it is quiet, and keeping the last `MAX_RECENT_LINES` in `lines`. Returns
+/// the result to send last, or `None` if the client went away.
+async fn forward_lines(
+    registry: &Registry,
+    ctx: &Context,
+    req: &CommandRequest,
+    args: Args,
+    tx: &FrameSender,
+    heartbeat: Duration,
+    lines: &mut VecDeque<String>,
+) -> Option<CommandResponse> {
+    let mut stream = match registry.execute_stream(ctx, &req.service, &req.action, args).await {
+        Ok(stream) => stream,
+        Err(e) => return Some(error_response(&e)),
+    };
+    let mut ticker = tokio::time::interval(heartbeat);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            line = stream.next() => match line {
+                Some(Ok(line)) => {
+                    if lines.len() == MAX_RECENT_LINES {
+                        lines.pop_front();
+                    }
+                    lines.push_back(line.clone());
+                    tx.send(Ok(frame(Frame::Line(line)))).await.ok()?;
+                    ticker.reset();
+                }
+                Some(Err(e)) => return Some(error_response(&e)),
+                None => {
+                    return Some(CommandResponse {
+                        success: true,
+                        ..Default::default()
+                    })
+                }
+            },
+            _ = ticker.tick() => tx.send(Ok(frame(Frame::Heartbeat(Heartbeat {})))).await.ok()?,
+            _ = tx.closed() => return None,
+        }
+    }
+}
+
+/// Run a command reporting progress, sending each update as a frame and
+/// heartbeats while it is quiet. Returns the result to send last, or `None`
+/// if the client went away.
+async fn forward_progress(
+    registry: &Registry,
+    ctx: &Context,
+    req: &CommandRequest,
+    args: Args,
+    mut updates: watch::Receiver<ProgressUpdate>,
+    tx: &FrameSender,
+    heartbeat: Duration,
+) -> Option<CommandResponse> {
+    let run = registry.execute(ctx, &req.service, &req.action, args);
+    tokio::pin!(run);
+    let mut ticker = tokio::time::interval(heartbeat);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            outcome = &mut run => {
+                return Some(match outcome {
+                    Ok(output) => success_response(ctx, output),
+                    Err(e) => error_response(&e),
+                })
+            }
+            Ok(()) = updates.changed() => {
+                let update = progress_to_proto(&updates.borrow_and_update());
+                tx.send(Ok(frame(Frame::Progress(update)))).await.ok()?;
+                ticker.reset();
+            }
+            _ = ticker.tick() => tx.send(Ok(frame(Frame::Heartbeat(Heartbeat {})))).await.ok()?,
+            _ = tx.closed() => return None,
+        }
+    }
+}
+
+fn download_chunk(chunk: proto::download_chunk::Chunk) -> DownloadChunk {
+    DownloadChunk { chunk: Some(chunk) }
+}
+
+/// Run a command sending a file back, forwarding what it writes as chunks.
+/// Returns the result to send last, or `None` if the client went away.
+async fn forward_download(
+    registry: &Registry,
+    ctx: &Context,
+    req: &CommandRequest,
+    args: Args,
+    data: &mut mpsc::Receiver<Vec<u8>>,
+    tx: &mpsc::Sender<Result<DownloadChunk, Status>>,
+) -> Option<CommandResponse> {
+    let run = registry.execute(ctx, &req.service, &req.action, args);
+    tokio::pin!(run);
+    let outcome = loop {
+        tokio::select! {
+            outcome = &mut run => break outcome,
+            Some(chunk) = data.recv() => {
+                tx.send(Ok(download_chunk(proto::download_chunk::Chunk::Data(chunk)))).await.ok()?;
+            }
+            _ = tx.closed() => return None,
+        }
+    };
+    // What the command wrote just before it returned.
+    while let Ok(chunk) = data.try_recv() {
+        tx.send(Ok(download_chunk(proto::download_chunk::Chunk::Data(chunk)))).await.ok()?;
+    }
+    Some(match outcome {
+        Ok(output) => success_response(ctx, output),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// The format the client asked for through the request's `format` or its
+/// `nexus-format` preference; plain if neither names a known one.
+fn response_format(ctx: &Context) -> OutputFormat {
+    ctx.preferences
+        .format
+        .as_deref()
+        .and_then(|format| format.parse().ok())
+        .unwrap_or_default()
+}
+
+/// The response to a command that succeeded, with the plan it recorded.
+fn success_response(ctx: &Context, output: CommandOutput) -> CommandResponse {
+    CommandResponse {
+        success: true,
+        message: output.render(response_format(ctx)),
+        output: Some(output_to_proto(output)),
+        plan: ctx
+            .take_plan()
+            .into_iter()
+            .map(|c| PlanChange {
+                resource: c.resource,
+                before: c.before.unwrap_or_default(),
+                after: c.after.unwrap_or_default(),
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn progress_to_proto(update: &ProgressUpdate) -> proto::ProgressUpdate {
+    proto::ProgressUpdate {
+        percent: update.percent,
+        step: update.step,
+        total_steps: update.total_steps,
+        message: update.message.clone(),
+    }
+}
+
+fn job_to_proto(job: Job) -> JobInfo {
+    let state = match job.state {
+        JobState::Running => proto::JobState::Running,
+        JobState::Succeeded => proto::JobState::Succeeded,
+        JobState::Failed => proto::JobState::Failed,
+        JobState::Cancelled => proto::JobState::Cancelled,
+    };
+    let result = job.outcome.map(|outcome| {
+        let response = match outcome {
+            Ok(output) => CommandResponse {
+                success: true,
+                message: output.to_plain(),
+                output: Some(output_to_proto(output)),
+                ..Default::default()
+            },
+            Err(e) => error_response(&e),
+        };
+        CommandResponse {
+            warnings: job.warnings,
+            ..response
+        }
+    });
+    JobInfo {
+        id: job.id,
+        service: job.service,
+        action: job.action,
+        args: job.args,
+        state: state as i32,
+        started_at_ms: job
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64),
+        elapsed_ms: job.elapsed.as_millis() as u64,
+        result,
+        progress: job.progress.as_ref().map(progress_to_proto),
     }
 }
@@ -0,0 +1,297 @@
+//! Commands that ask questions as they run, e.g. a `pool repair` wizard
+//! confirming each step. Command methods receive a `Session` by declaring
+//! a parameter of that type, and put questions to the user with it. The
+//! user's end is a `SessionService`: for a call through the `Session` RPC,
+//! one relaying to the client, which answers with its own (the CLI prompts
+//! inline in the shell).
+
+use crate::chunked;
+use crate::cli::{with_deadline, Client};
+use crate::context::Context;
+use crate::error::NexusError;
+use crate::proto::session_input::Input;
+use crate::proto::session_output::Output;
+use crate::proto::{CommandRequest, CommandResponse, SessionAnswer, SessionInput, SessionOutput, SessionPrompt};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Streaming};
+
+/// A question put to the user.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prompt {
+    pub text: String,
+    /// The answers allowed; any if empty.
+    pub choices: Vec<String>,
+    /// The answer an empty one stands for.
+    pub default: Option<String>,
+    /// Whether the answer should not be echoed (a passphrase).
+    pub secret: bool,
+}
+
+impl Prompt {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Allow only these answers.
+    pub fn with_choices<S: Into<String>>(mut self, choices: impl IntoIterator<Item = S>) -> Self {
+        self.choices = choices.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Take an empty answer as `default`.
+    pub fn with_default(mut self, default: impl Into<String>) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    /// Hide the answer as it is typed.
+    pub fn with_secret(mut self, secret: bool) -> Self {
+        self.secret = secret;
+        self
+    }
+
+    fn to_proto(&self, id: u64) -> SessionPrompt {
+        SessionPrompt {
+            id,
+            text: self.text.clone(),
+            choices: self.choices.clone(),
+            default_answer: self.default.clone().unwrap_or_default(),
+            secret: self.secret,
+        }
+    }
+
+    fn from_proto(prompt: SessionPrompt) -> Self {
+        Self {
+            text: prompt.text,
+            choices: prompt.choices,
+            default: (!prompt.default_answer.is_empty()).then_some(prompt.default_answer),
+            secret: prompt.secret,
+        }
+    }
+}
+
+/// The user's end of an interactive command: shows its prompts and
+/// messages and answers the prompts. The server provides one for each call
+/// of the `Session` RPC, relaying to the client; clients pass theirs to
+/// `NexusClient::session`, and code running commands in-process can attach
+/// one with `Context::with_session`.
+#[async_trait]
+pub trait SessionService: Send + Sync + 'static {
+    /// Put `prompt` to the user and return their answer, as typed. An error
+    /// means there is none; the command's question fails.
+    async fn prompt(&self, prompt: Prompt) -> Result<String>;
+
+    /// Show `message` to the user. Dropped by default.
+    async fn say(&self, _message: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How a command talks to the user while it runs. Command methods receive
+/// it by declaring a `Session` parameter, which makes them interactive
+/// (`CommandInfo.interactive`): the CLI runs them through the `Session` RPC.
+#[derive(Clone)]
+pub struct Session {
+    user: Arc<dyn SessionService>,
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session").finish_non_exhaustive()
+    }
+}
+
+impl Session {
+    /// A session with `user` at the other end.
+    pub fn new(user: Arc<dyn SessionService>) -> Self {
+        Self { user }
+    }
+
+    /// The request's session. Fails with an invalid-argument error for a
+    /// request that has none (not made through the `Session` RPC). Called
+    /// by the code `#[nexus_service]` generates for `Session` parameters.
+    pub fn from_context(ctx: &Context) -> Result<Self> {
+        ctx.session().cloned().ok_or_else(|| {
+            let message = format!("'{}' asks questions as it runs; run it from the shell", ctx.command);
+            NexusError::invalid_argument(message).into()
+        })
+    }
+
+    /// Ask `question` and return the answer, trimmed.
+    pub async fn ask(&self, question: &str) -> Result<String> {
+        self.prompt(Prompt::new(question)).await
+    }
+
+    /// Ask a yes-or-no `question`; an empty answer is `default`.
+    pub async fn confirm(&self, question: &str, default: bool) -> Result<bool> {
+        let prompt = Prompt::new(question)
+            .with_choices(["y", "n"])
+            .with_default(if default { "y" } else { "n" });
+        let parse = |answer: &str| match answer.to_ascii_lowercase().as_str() {
+            "y" | "yes" => Some(true),
+            "n" | "no" => Some(false),
+            _ => None,
+        };
+        self.ask_until(prompt, parse, "Please answer y or n.").await
+    }
+
+    /// Ask `question` until the answer is one of `choices`, and return it.
+    pub async fn choose(&self, question: &str, choices: &[&str]) -> Result<String> {
+        self.prompt(Prompt::new(question).with_choices(choices.iter().copied())).await
+    }
+
+    /// Ask for a secret, such as a passphrase, without echoing it. The
+    /// answer is returned as typed.
+    pub async fn secret(&self, question: &str) -> Result<String> {
+        self.prompt(Prompt::new(question).with_secret(true)).await
+    }
+
+    /// Put `prompt` to the user until the answer is one of its choices (if
+    /// it has any), and return it; trimmed unless it is secret. An empty
+    /// answer stands for the default.
+    pub async fn prompt(&self, prompt: Prompt) -> Result<String> {
+        let hint = format!("Please answer one of: {}.", prompt.choices.join(", "));
+        let choices = prompt.choices.clone();
+        let parse = |answer: &str| {
+            (choices.is_empty() || choices.iter().any(|c| c == answer)).then(|| answer.to_string())
+        };
+        self.ask_until(prompt, parse, &hint).await
+    }
+
+    /// Show `message` to the user.
+    pub async fn say(&self, message: &str) -> Result<()> {
+        self.user.say(message).await
+    }
+
+    /// Put `prompt` to the user until `parse` accepts the answer, saying
+    /// `hint` after each it rejects.
+    async fn ask_until<T>(&self, prompt: Prompt, parse: impl Fn(&str) -> Option<T>, hint: &str) -> Result<T> {
+        loop {
+            let answer = self.user.prompt(prompt.clone()).await?;
+            let answer = if prompt.secret { answer.as_str() } else { answer.trim() };
+            let answer = match &prompt.default {
+                Some(default) if answer.is_empty() => default,
+                _ => answer,
+            };
+            if let Some(value) = parse(answer) {
+                return Ok(value);
+            }
+            self.say(hint).await?;
+        }
+    }
+}
+
+pub(crate) type OutputSender = mpsc::Sender<Result<SessionOutput, Status>>;
+
+pub(crate) fn output(output: Output) -> SessionOutput {
+    SessionOutput { output: Some(output) }
+}
+
+/// The `SessionService` of a call of the `Session` RPC: relays prompts and
+/// messages to the client and reads its answers from the request stream.
+pub(crate) struct Relay {
+    outputs: OutputSender,
+    inputs: Mutex<Streaming<SessionInput>>,
+    next_id: AtomicU64,
+}
+
+impl Relay {
+    pub(crate) fn new(outputs: OutputSender, inputs: Streaming<SessionInput>) -> Self {
+        Self {
+            outputs,
+            inputs: Mutex::new(inputs),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    async fn send(&self, message: Output) -> Result<()> {
+        self.outputs
+            .send(Ok(output(message)))
+            .await
+            .map_err(|_| NexusError::internal("the client ended the session").into())
+    }
+}
+
+#[async_trait]
+impl SessionService for Relay {
+    async fn prompt(&self, prompt: Prompt) -> Result<String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        // Held until the answer, so one question is out at a time.
+        let mut inputs = self.inputs.lock().await;
+        self.send(Output::Prompt(prompt.to_proto(id))).await?;
+        loop {
+            match inputs.message().await {
+                Ok(Some(SessionInput {
+                    input: Some(Input::Answer(answer)),
+                })) if answer.prompt_id == id => return Ok(answer.text),
+                // An answer to an earlier prompt, given up on.
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => {
+                    let message = format!("no answer to \"{}\": the client ended the session", prompt.text);
+                    return Err(NexusError::invalid_argument(message).into());
+                }
+            }
+        }
+    }
+
+    async fn say(&self, message: &str) -> Result<()> {
+        self.send(Output::Message(message.to_string())).await
+    }
+}
+
+/// Run `request`'s command through the `Session` RPC, which the server
+/// gives up on at `deadline` if there is one, answering its prompts with
+/// `user`. If `user` has no answer, the session ends and the command's
+/// question fails. Returns the result.
+pub(crate) async fn run(
+    client: &mut Client,
+    request: CommandRequest,
+    deadline: Option<Instant>,
+    user: &dyn SessionService,
+) -> Result<CommandResponse, Status> {
+    let (tx, rx) = mpsc::channel(4);
+    let input = |input| SessionInput { input: Some(input) };
+    // Buffered until the call starts.
+    let _ = tx.send(input(Input::Start(request))).await;
+    let mut answers = Some(tx);
+    let mut outputs = client.session(with_deadline(ReceiverStream::new(rx), deadline)).await?.into_inner();
+    while let Some(message) = outputs.message().await? {
+        match message.output {
+            Some(Output::Prompt(prompt)) => {
+                let prompt_id = prompt.id;
+                let answer = match user.prompt(Prompt::from_proto(prompt)).await {
+                    Ok(text) => Some(SessionAnswer { prompt_id, text }),
+                    Err(e) => {
+                        tracing::debug!(error = %e, "no answer; ending the session");
+                        None
+                    }
+                };
+                match (answer, &answers) {
+                    (Some(answer), Some(tx)) => {
+                        let _ = tx.send(input(Input::Answer(answer))).await;
+                    }
+                    // Ending the request stream fails the question.
+                    _ => answers = None,
+                }
+            }
+            Some(Output::Message(text)) => {
+                if let Err(e) = user.say(&text).await {
+                    tracing::debug!(error = %e, "message not shown");
+                }
+            }
+            Some(Output::Result(response)) => return chunked::complete(client, response).await,
+            None => {}
+        }
+    }
+    Err(Status::unknown("the session ended without a result"))
+}
@@ -0,0 +1,74 @@
+//! Shared application state: values added with `NexusServer::with_state`
+//! (a database handle, a device manager) that every service reaches by
+//! type, so services can share them without global statics. A command
+//! method receives one by declaring a `State<T>` parameter.
+
+use crate::context::Context;
+use crate::error::NexusError;
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// The values added with `with_state`, one per type.
+#[derive(Default)]
+pub(crate) struct States {
+    values: HashMap<TypeId, (&'static str, Arc<dyn Any + Send + Sync>)>,
+}
+
+impl States {
+    /// Add `value`, replacing an earlier one of the same type.
+    pub(crate) fn insert<T: Send + Sync + 'static>(&mut self, value: Arc<T>) {
+        self.values.insert(TypeId::of::<T>(), (type_name::<T>(), value));
+    }
+
+    pub(crate) fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let (_, value) = self.values.get(&TypeId::of::<T>())?;
+        value.clone().downcast().ok()
+    }
+}
+
+impl fmt::Debug for States {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.values.values().map(|(name, _)| name)).finish()
+    }
+}
+
+/// The server's shared value of type `T`, as a command parameter. Cheap to
+/// clone, and derefs to the value.
+pub struct State<T>(pub Arc<T>);
+
+impl<T: Send + Sync + 'static> State<T> {
+    /// The value the request's server was given with `with_state`. Not
+    /// having one is a mistake in the daemon, so it fails with an internal
+    /// error rather than something the client could fix.
+    pub fn from_context(ctx: &Context) -> Result<Self, NexusError> {
+        ctx.state().map(State).ok_or_else(|| {
+            NexusError::internal(format!(
+                "no shared state of type {}; add it with NexusServer::with_state",
+                type_name::<T>()
+            ))
+        })
+    }
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for State<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::error::NexusError;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
+use tokio_stream::Stream;
+
+/// Output lines of a streaming command, produced as the command runs.
+///
+/// The stream must be `'static`: it keeps running after `execute_stream`
+/// returns, so it cannot borrow the service or the request context. Move
+/// owned data (or `Arc`s) into it instead.
+pub type CommandStream = Pin<Box<dyn Stream<Item = anyhow::Result<String>> + Send>>;
+
+/// Keeps a value (e.g. a concurrency permit) alive for as long as the
+/// wrapped stream is being consumed.
+pub(crate) struct Guarded<G> {
+    stream: CommandStream,
+    _guard: G,
+}
+
+impl<G> Guarded<G> {
+    pub(crate) fn new(stream: CommandStream, guard: G) -> Self {
+        Self {
+            stream,
+            _guard: guard,
+        }
+    }
+}
+
+impl<G: Unpin> Stream for Guarded<G> {
+    type Item = anyhow::Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.stream.as_mut().poll_next(cx)
+    }
+}
+
+/// Runs each poll of the wrapped stream inside `span`, and when the stream is
+/// dropped (finished or abandoned) calls `on_finish` with the time since
+/// `started` and whether any line was an error.
+pub(crate) struct Observed {
+    stream: CommandStream,
+    span: tracing::Span,
+    started: Instant,
+    failed: bool,
+    on_finish: Option<Box<dyn FnOnce(Duration, bool) + Send>>,
+}
+
+impl Observed {
+    pub(crate) fn new(
+        stream: CommandStream,
+        span: tracing::Span,
+        started: Instant,
+        on_finish: impl FnOnce(Duration, bool) + Send + 'static,
+    ) -> Self {
+        Self {
+            stream,
+            span,
+            started,
+            failed: false,
+            on_finish: Some(Box::new(on_finish)),
+        }
+    }
+}
+
+impl Stream for Observed {
+    type Item = anyhow::Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let item = this.span.in_scope(|| this.stream.as_mut().poll_next(cx));
+        if let Poll::Ready(Some(Err(_))) = &item {
+            this.failed = true;
+        }
+        item
+    }
+}
+
+impl Drop for Observed {
+    fn drop(&mut self) {
+        if let Some(on_finish) = self.on_finish.take() {
+            on_finish(self.started.elapsed(), self.failed);
+        }
+    }
+}
+
+/// Ends the wrapped stream with `error` once `deadline` passes, dropping the
+/// stream (and with it the command's work) at that point.
+pub(crate) struct Deadline {
+    stream: CommandStream,
+    sleep: Pin<Box<tokio::time::Sleep>>,
+    error: Option<NexusError>,
+}
+
+impl Deadline {
+    pub(crate) fn new(stream: CommandStream, deadline: tokio::time::Instant, error: NexusError) -> Self {
+        Self {
+            stream,
+            sleep: Box::pin(tokio::time::sleep_until(deadline)),
+            error: Some(error),
+        }
+    }
+}
+
+impl Stream for Deadline {
+    type Item = anyhow::Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let Some(error) = &this.error else {
+            return Poll::Ready(None);
+        };
+        if let Poll::Ready(item) = this.stream.as_mut().poll_next(cx) {
+            return Poll::Ready(item);
+        }
+        if this.sleep.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let error = error.clone();
+        this.error = None;
+        this.stream = Box::pin(tokio_stream::empty());
+        Poll::Ready(Some(Err(error.into())))
+    }
+}
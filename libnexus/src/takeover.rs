@@ -0,0 +1,229 @@
+//! Hand the listening sockets from a running daemon to its replacement.
+//!
+//! Each daemon serves a control socket. A new daemon starting with the same
+//! control path connects to it and receives duplicates of the old daemon's
+//! listeners (`SCM_RIGHTS`). Both processes then share the listening sockets,
+//! so no connection attempt is refused; the old daemon stops accepting,
+//! finishes its in-flight requests, and returns from `serve`.
+//!
+//! The control socket is only open to the daemon's own user (mode 0600),
+//! and the listeners only go to a process running as that user.
+
+use crate::connections::Connections;
+use crate::endpoint::{Endpoint, UdsOptions};
+use crate::server::{serve_all, Listener, ServeFuture};
+use anyhow::Result;
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use std::future::Future;
+use std::io::{ErrorKind, IoSlice, IoSliceMut};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::server::{Router, TcpIncoming};
+
+/// Most listeners one daemon can hand over.
+const MAX_LISTENERS: usize = 16;
+
+/// Serve each router on its endpoint, inheriting the listeners from the
+/// daemon currently holding `control` if there is one (it must serve the
+/// same endpoints, in the same order), tracking their connections in
+/// `connections`. Accepted TCP sockets get `TCP_NODELAY` and
+/// `tcp_keepalive`, as in `serve_on`. Returns once the listeners have been
+/// handed over or `signal` completes, after in-flight requests finish.
+pub(crate) async fn serve<F>(
+    routers: Vec<(Router, Listener)>,
+    control: &Path,
+    tcp_keepalive: Duration,
+    connections: &Arc<Connections>,
+    stop: &CancellationToken,
+    signal: F,
+) -> Result<()>
+where
+    F: Future<Output = ()> + Send,
+{
+    if routers.len() > MAX_LISTENERS {
+        anyhow::bail!("takeover supports at most {} endpoints", MAX_LISTENERS);
+    }
+    let mut inherited = match request(control)? {
+        Some(fds) if fds.len() != routers.len() => anyhow::bail!(
+            "daemon on {} serves {} endpoints, but this one is configured with {}",
+            control.display(),
+            fds.len(),
+            routers.len()
+        ),
+        Some(fds) => Some(fds.into_iter()),
+        None => None,
+    };
+    let mut fds = Vec::new();
+    let mut servers: Vec<ServeFuture> = Vec::new();
+    let mut sockets = Vec::new();
+    for (router, Listener { endpoint, uds, .. }) in routers {
+        let tracked = endpoint.clone();
+        let inherited = inherited.as_mut().and_then(Iterator::next);
+        let stopped = stop.clone().cancelled_owned();
+        match endpoint {
+            Endpoint::Tcp(authority) => {
+                let listener = match inherited {
+                    Some(fd) => std::net::TcpListener::from(fd),
+                    None => std::net::TcpListener::bind(authority.as_str())?,
+                };
+                listener.set_nonblocking(true)?;
+                fds.push(listener.as_fd().try_clone_to_owned()?);
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                println!("Nexus server listening on {}", listener.local_addr()?);
+                let incoming = TcpIncoming::from_listener(listener, true, Some(tcp_keepalive))
+                    .map_err(anyhow::Error::from_boxed)?;
+                let incoming = connections.track(&tracked, incoming);
+                servers.push(Box::pin(async move {
+                    router.serve_with_incoming_shutdown(incoming, stopped).await?;
+                    Ok(())
+                }));
+            }
+            Endpoint::Unix(path) => {
+                let listener = match inherited {
+                    Some(fd) => UnixListener::from(fd),
+                    None => uds.bind(&path)?,
+                };
+                listener.set_nonblocking(true)?;
+                fds.push(listener.as_fd().try_clone_to_owned()?);
+                let listener = tokio::net::UnixListener::from_std(listener)?;
+                println!("Nexus server listening on {}", path.display());
+                let incoming = connections.track(&tracked, UnixListenerStream::new(listener));
+                servers.push(Box::pin(async move {
+                    router.serve_with_incoming_shutdown(incoming, stopped).await?;
+                    Ok(())
+                }));
+                sockets.push(path);
+            }
+            _ => unreachable!("ensure_supported rejects unavailable transports"),
+        }
+    }
+
+    let (handed_over, rx) = oneshot::channel();
+    listen(control, fds, handed_over)?;
+    let mut was_handed_over = false;
+    let shutdown = async {
+        let handover = async {
+            // A dropped sender (control thread gone) must not stop the server.
+            if rx.await.is_ok() {
+                println!("Listeners handed over to a new daemon; draining in-flight requests");
+            } else {
+                std::future::pending::<()>().await;
+            }
+        };
+        tokio::select! {
+            _ = handover => was_handed_over = true,
+            _ = signal => {}
+        }
+    };
+    serve_all(servers, stop, shutdown).await?;
+    // After a handover the socket files belong to the new daemon.
+    if !was_handed_over {
+        for path in sockets {
+            let _ = std::fs::remove_file(path);
+        }
+        let _ = std::fs::remove_file(control);
+    }
+    Ok(())
+}
+
+/// Ask the daemon on `control` for its listeners. Returns `None` if no
+/// daemon is running there.
+fn request(control: &Path) -> Result<Option<Vec<OwnedFd>>> {
+    let stream = match UnixStream::connect(control) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::ConnectionRefused) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut buf = [0u8; 1];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut space = nix::cmsg_space!([RawFd; MAX_LISTENERS]);
+    let msg = recvmsg::<()>(stream.as_raw_fd(), &mut iov, Some(&mut space), MsgFlags::empty())?;
+    for cmsg in msg.cmsgs()? {
+        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+            if !fds.is_empty() {
+                // SAFETY: the kernel just installed these descriptors for us
+                // and nothing else owns them.
+                return Ok(Some(fds.into_iter().map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }).collect()));
+            }
+        }
+    }
+    anyhow::bail!("daemon on {} did not hand over its listeners", control.display())
+}
+
+/// Serve takeover requests on `control` from a background thread. The first
+/// process of this user to connect receives `listeners`, then `handed_over`
+/// fires.
+fn listen(control: &Path, listeners: Vec<OwnedFd>, handed_over: oneshot::Sender<()>) -> Result<()> {
+    // The previous daemon's control socket (if any) has served its purpose.
+    let _ = std::fs::remove_file(control);
+    let options = UdsOptions {
+        mode: Some(0o600),
+        ..UdsOptions::default()
+    };
+    let control = options.bind(control)?;
+    std::thread::spawn(move || {
+        for stream in control.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if !same_user(&stream) {
+                tracing::warn!("refused a takeover request from another user");
+                continue;
+            }
+            let fds: Vec<RawFd> = listeners.iter().map(AsRawFd::as_raw_fd).collect();
+            let sent = sendmsg::<()>(
+                stream.as_raw_fd(),
+                &[IoSlice::new(b"L")],
+                &[ControlMessage::ScmRights(&fds)],
+                MsgFlags::empty(),
+                None,
+            );
+            if sent.is_ok() {
+                let _ = handed_over.send(());
+                return;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Whether the process on the other end of `stream` runs as this process's
+/// effective user.
+fn same_user(stream: &UnixStream) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let uid = nix::sys::socket::getsockopt(stream, nix::sys::socket::sockopt::PeerCredentials).map(|cred| cred.uid());
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let uid = nix::unistd::getpeereid(stream).map(|(uid, _)| uid.as_raw());
+    uid.is_ok_and(|uid| uid == nix::unistd::geteuid().as_raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    #[test]
+    fn hands_listeners_to_the_same_user_only() {
+        let (ours, _theirs) = UnixStream::pair().unwrap();
+        assert!(same_user(&ours));
+    }
+
+    #[tokio::test]
+    async fn control_sockets_are_private() {
+        let control = std::env::temp_dir().join(format!("nexus-takeover-{}.ctl", std::process::id()));
+        let (handed_over, _rx) = oneshot::channel();
+        listen(&control, Vec::new(), handed_over).unwrap();
+        let metadata = std::fs::symlink_metadata(&control).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        let _ = std::fs::remove_file(&control);
+    }
+}
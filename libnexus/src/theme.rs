@@ -0,0 +1,197 @@
+//! Colors of the interactive shell: how typed input is highlighted and how
+//! results, warnings and errors are printed. Each role of a `Theme` has a
+//! `Style`, written in the `[cli.theme]` config table as words such as
+//! `"bold red"` or `"bright-blue underline"`:
+//!
+//! ```toml
+//! [cli.theme]
+//! service = "bold cyan"
+//! unknown = "bright-red"
+//! hint = "dim"
+//! ```
+//!
+//! Roles left out keep their default. Colors can be switched off altogether
+//! with `NO_COLOR`, `NexusCli::with_color(false)` or `set color off`.
+
+use std::fmt;
+
+/// The colors `Style` accepts, in SGR order: `red` is 31, `bright-red` 91.
+const COLORS: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+/// A foreground color and text attributes. The default style leaves text
+/// as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    /// SGR code of the foreground color (30-37, 90-97).
+    color: Option<u8>,
+    bold: bool,
+    dim: bool,
+    italic: bool,
+    underline: bool,
+    reverse: bool,
+}
+
+impl Style {
+    /// No color or attributes.
+    pub const PLAIN: Style = Style {
+        color: None,
+        bold: false,
+        dim: false,
+        italic: false,
+        underline: false,
+        reverse: false,
+    };
+
+    /// Whether text in this style is printed as it is.
+    pub fn is_plain(&self) -> bool {
+        *self == Self::PLAIN
+    }
+
+    /// `text` wrapped in the escape codes for this style; unchanged if the
+    /// style is plain.
+    pub fn paint(&self, text: impl fmt::Display) -> String {
+        if self.is_plain() {
+            return text.to_string();
+        }
+        let codes: Vec<String> = [
+            self.bold.then_some(1),
+            self.dim.then_some(2),
+            self.italic.then_some(3),
+            self.underline.then_some(4),
+            self.reverse.then_some(7),
+            self.color,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|code| code.to_string())
+        .collect();
+        format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text)
+    }
+
+    fn color(code: u8) -> Self {
+        Self {
+            color: Some(code),
+            ..Self::PLAIN
+        }
+    }
+}
+
+/// Parses space-separated words: at most one color (`red`, `bright-red`,
+/// ...) and any of `bold`, `dim`, `italic`, `underline` and `reverse`.
+/// `none` (or nothing) is the plain style.
+impl std::str::FromStr for Style {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut style = Self::PLAIN;
+        for word in s.split_whitespace() {
+            let (bright, name) = match word.strip_prefix("bright-") {
+                Some(name) => (true, name),
+                None => (false, word),
+            };
+            if let Some(i) = COLORS.iter().position(|&c| c == name) {
+                if style.color.is_some() {
+                    anyhow::bail!("style '{}' has more than one color", s);
+                }
+                style.color = Some(if bright { 90 } else { 30 } + i as u8);
+                continue;
+            }
+            match word {
+                "bold" => style.bold = true,
+                "dim" => style.dim = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "reverse" => style.reverse = true,
+                "none" => {}
+                _ => anyhow::bail!(
+                    "unknown style '{}' (expected a color such as red or bright-red, bold, dim, italic, underline, reverse or none)",
+                    word
+                ),
+            }
+        }
+        Ok(style)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Style {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The style of each thing the shell colors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Service names as typed, including nested ones (`volume snapshot`).
+    pub service: Style,
+    /// Command names, builtins and aliases as typed.
+    pub command: Style,
+    /// A service or command the server does not offer.
+    pub unknown: Style,
+    /// `--name` flags.
+    pub flag: Style,
+    /// Argument placeholders shown after the cursor, and asides such as how
+    /// to follow a job.
+    pub hint: Style,
+    /// Table headers.
+    pub header: Style,
+    /// Good news: serving services, finished jobs, resources a plan creates.
+    pub success: Style,
+    /// Warnings, a busy server, cancelled jobs, resources a plan updates.
+    pub warning: Style,
+    /// Errors, services not serving, failed jobs, resources a plan removes.
+    pub error: Style,
+    /// Lines of `watch` output that differ from the previous run.
+    pub changed: Style,
+    /// Names of the servers added with `connect`, as typed.
+    pub connection: Style,
+}
+
+impl Theme {
+    /// Every role plain: no colors at all.
+    pub const PLAIN: Theme = Theme {
+        service: Style::PLAIN,
+        command: Style::PLAIN,
+        unknown: Style::PLAIN,
+        flag: Style::PLAIN,
+        hint: Style::PLAIN,
+        header: Style::PLAIN,
+        success: Style::PLAIN,
+        warning: Style::PLAIN,
+        error: Style::PLAIN,
+        changed: Style::PLAIN,
+        connection: Style::PLAIN,
+    };
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            service: Style::color(36),
+            command: Style {
+                bold: true,
+                ..Style::PLAIN
+            },
+            unknown: Style::color(31),
+            flag: Style::color(34),
+            hint: Style {
+                dim: true,
+                ..Style::PLAIN
+            },
+            header: Style {
+                bold: true,
+                ..Style::PLAIN
+            },
+            success: Style::color(32),
+            warning: Style::color(33),
+            error: Style::color(31),
+            changed: Style {
+                reverse: true,
+                ..Style::PLAIN
+            },
+            connection: Style::color(35),
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Shell-like splitting of CLI input lines, shared by command execution,
+//! completion and hints.
+//!
+//! Words are separated by whitespace. Single quotes keep everything literal;
+//! double quotes keep whitespace and allow `\"` and `\\`; outside quotes a
+//! backslash escapes the next character. `volume create "my volume" sda`
+//! yields three arguments. In the interactive shell an unquoted `|` or `>`
+//! ends the command and sends its output elsewhere (`split_redirect`).
+//! Before that, `&&` and `;` separate the commands of a chain
+//! (`split_chain`).
+//!
+//! Shell variables (`$VOL`, `${VOL}`) are replaced by `expand` before a line
+//! is split: outside quotes a value splits into words at whitespace, inside
+//! double quotes it stays part of the quoted word, and single quotes keep
+//! `$` literal.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// A word and the byte range of the line it was read from.
+pub(crate) type Word = (Range<usize>, String);
+
+/// A line split as far as it has been typed, for completion and hints.
+pub(crate) struct Partial {
+    pub(crate) words: Vec<String>,
+    /// Byte offset where the word under the cursor starts; the end of the
+    /// line when the cursor starts a new word.
+    pub(crate) start: usize,
+    /// Whether the cursor follows whitespace outside quotes, so the next
+    /// character starts a new word.
+    pub(crate) new_word: bool,
+}
+
+/// Split a complete line into words. Fails on an unterminated quote.
+pub(crate) fn split(line: &str) -> anyhow::Result<Vec<String>> {
+    let (words, _, quote) = scan(line);
+    if let Some(quote) = quote {
+        anyhow::bail!("unterminated {} quote", if quote == '"' { "double" } else { "single" });
+    }
+    Ok(words.into_iter().map(|(_, word)| word).collect())
+}
+
+/// The words of a line being typed, with the byte range each covers, quotes
+/// included.
+pub(crate) fn spans(line: &str) -> Vec<Word> {
+    scan(line).0
+}
+
+/// Split a line being typed. An open quote is part of the last word.
+pub(crate) fn split_partial(line: &str) -> Partial {
+    let (words, in_word, _) = scan(line);
+    let new_word = !in_word;
+    let start = match words.last() {
+        Some((span, _)) if in_word => span.start,
+        _ => line.len(),
+    };
+    Partial {
+        words: words.into_iter().map(|(_, word)| word).collect(),
+        start,
+        new_word,
+    }
+}
+
+/// How a command of a chain follows the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Link {
+    /// The first command, or one after `;`: runs whatever came before.
+    Always,
+    /// After `&&`: runs only if the command before succeeded.
+    IfSucceeded,
+}
+
+/// Split a line at each `&&` and `;` outside quotes and `$(...)` into the
+/// commands of a chain, trimmed. A trailing `;` is allowed; a command
+/// missing before `;` or around `&&` is an error.
+pub(crate) fn split_chain(line: &str) -> anyhow::Result<Vec<(Link, &str)>> {
+    let mut commands = Vec::new();
+    let mut link = Link::Always;
+    let mut start = 0;
+    let mut quote = None;
+    let mut depth = 0usize;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let (next, width) = match (quote, c) {
+            (Some(open), c) if c == open => {
+                quote = None;
+                continue;
+            }
+            (Some('"') | None, '\\') => {
+                chars.next();
+                continue;
+            }
+            (Some(_), _) => continue,
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                continue;
+            }
+            (None, '(') if line[..i].ends_with('$') || depth > 0 => {
+                depth += 1;
+                continue;
+            }
+            (None, ')') if depth > 0 => {
+                depth -= 1;
+                continue;
+            }
+            (None, ';') if depth == 0 => (Link::Always, 1),
+            (None, '&') if depth == 0 && matches!(chars.peek(), Some((_, '&'))) => (Link::IfSucceeded, 2),
+            _ => continue,
+        };
+        let command = line[start..i].trim();
+        if command.is_empty() {
+            anyhow::bail!("expected a command before '{}'", &line[i..i + width]);
+        }
+        commands.push((link, command));
+        (link, start) = (next, i + width);
+        if width == 2 {
+            chars.next();
+        }
+    }
+    match line[start..].trim() {
+        "" if link == Link::IfSucceeded => anyhow::bail!("expected a command after '&&'"),
+        "" => {}
+        command => commands.push((link, command)),
+    }
+    Ok(commands)
+}
+
+/// Where the interactive shell sends a line's output, as written after the
+/// command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Redirect<'a> {
+    /// `| <pipeline>`: a local shell command line to feed the output to.
+    Pipe(&'a str),
+    /// `> <file>`, or `>> <file>` to append; the file name is not split yet.
+    File { target: &'a str, append: bool },
+}
+
+/// Split a line at its first `|` or `>` outside quotes: the command before
+/// it, and where its output goes.
+pub(crate) fn split_redirect(line: &str) -> (&str, Option<Redirect<'_>>) {
+    let mut quote = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                chars.next();
+            }
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '|') => return (&line[..i], Some(Redirect::Pipe(line[i + 1..].trim()))),
+            (None, '>') => {
+                let rest = &line[i + 1..];
+                let (target, append) = match rest.strip_prefix('>') {
+                    Some(target) => (target, true),
+                    None => (rest, false),
+                };
+                let target = target.trim();
+                return (&line[..i], Some(Redirect::File { target, append }));
+            }
+            (None, _) => {}
+        }
+    }
+    (line, None)
+}
+
+/// Quote `word` so that `split` reads it back unchanged.
+pub(crate) fn quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && !word.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\'));
+    if plain {
+        return word.to_string();
+    }
+    format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Join words into a line that `split` turns back into the same words.
+pub(crate) fn join<S: AsRef<str>>(words: &[S]) -> String {
+    words
+        .iter()
+        .map(|word| quote(word.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether `name` can name a shell variable: a letter or `_`, then
+/// letters, digits and `_`.
+pub(crate) fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `line` with each `$NAME` and `${NAME}` replaced by the value of the
+/// variable, quoted so that `split` reads the value back as it is. Fails on
+/// an unknown variable and on `$(...)`, which only `set NAME=$(...)` runs.
+pub(crate) fn expand(line: &str, variables: &BTreeMap<String, String>) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(line.len());
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some('"') | None, '\\') => {
+                out.push(c);
+                if let Some((_, escaped)) = chars.next() {
+                    out.push(escaped);
+                }
+                continue;
+            }
+            (Some('"') | None, '$') => {
+                let rest = &line[i + 1..];
+                if rest.starts_with('(') {
+                    anyhow::bail!("'$(...)' is only supported as the value of 'set NAME=$(<command>)'");
+                }
+                let (name, len) = match rest.strip_prefix('{') {
+                    Some(braced) => {
+                        let Some(end) = braced.find('}') else {
+                            anyhow::bail!("missing '}}' after '${{'");
+                        };
+                        if !is_variable_name(&braced[..end]) {
+                            anyhow::bail!("invalid variable name '{}'", &braced[..end]);
+                        }
+                        (&braced[..end], end + 2)
+                    }
+                    None => {
+                        let end = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                        (&rest[..end], end)
+                    }
+                };
+                // A `$` not starting a name (`$5`, a lone `$`) is literal.
+                if !is_variable_name(name) {
+                    out.push(c);
+                    continue;
+                }
+                let Some(value) = variables.get(name) else {
+                    anyhow::bail!("unknown variable '${}' (set it with 'set {}=<value>')", name, name);
+                };
+                if quote.is_some() {
+                    out.push_str(&value.replace('\\', "\\\\").replace('"', "\\\""));
+                } else {
+                    let words: Vec<&str> = value.split_whitespace().collect();
+                    out.push_str(&join(&words));
+                }
+                for _ in 0..len {
+                    chars.next();
+                }
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    Ok(out)
+}
+
+/// Words with their byte ranges, whether the line ends inside a word, and
+/// the quote left open at the end.
+fn scan(line: &str) -> (Vec<Word>, bool, Option<char>) {
+    let mut words = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match quote {
+            Some('\'') if c == '\'' => quote = None,
+            Some('"') if c == '"' => quote = None,
+            Some('"') if c == '\\' && matches!(chars.peek(), Some((_, '"' | '\\'))) => {
+                let (_, escaped) = chars.next().unwrap();
+                word(&mut current, i).push(escaped);
+            }
+            Some(_) => word(&mut current, i).push(c),
+            None if c.is_whitespace() => words.extend(current.take().map(|(start, word)| (start..i, word))),
+            None if c == '\'' || c == '"' => {
+                word(&mut current, i);
+                quote = Some(c);
+            }
+            None if c == '\\' => {
+                let word = word(&mut current, i);
+                match chars.next() {
+                    Some((_, escaped)) => word.push(escaped),
+                    None => word.push('\\'),
+                }
+            }
+            None => word(&mut current, i).push(c),
+        }
+    }
+    let in_word = current.is_some();
+    words.extend(current.map(|(start, word)| (start..line.len(), word)));
+    (words, in_word, quote)
+}
+
+/// The word being built, starting one at `start` if there is none.
+fn word(current: &mut Option<(usize, String)>, start: usize) -> &mut String {
+    &mut current.get_or_insert_with(|| (start, String::new())).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_whitespace() {
+        assert_eq!(split("  volume   create\tsda ").unwrap(), ["volume", "create", "sda"]);
+        assert!(split("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quotes_keep_whitespace() {
+        assert_eq!(split(r#"volume create "my volume" sda"#).unwrap(), ["volume", "create", "my volume", "sda"]);
+        assert_eq!(split("echo 'a  b'").unwrap(), ["echo", "a  b"]);
+        assert_eq!(split(r#"say pre"fix"'ed'"#).unwrap(), ["say", "prefixed"]);
+        assert_eq!(split(r#"set name="""#).unwrap(), ["set", "name="]);
+        assert_eq!(split("'' \"\"").unwrap(), ["", ""]);
+    }
+
+    #[test]
+    fn single_quotes_keep_everything_literal() {
+        assert_eq!(split(r#"'a\"b' '$x'"#).unwrap(), [r#"a\"b"#, "$x"]);
+        assert_eq!(split(r#"'say "hi"'"#).unwrap(), [r#"say "hi""#]);
+    }
+
+    #[test]
+    fn backslashes_escape() {
+        assert_eq!(split(r"my\ volume").unwrap(), ["my volume"]);
+        assert_eq!(split(r#"\"quoted\""#).unwrap(), [r#""quoted""#]);
+        // In double quotes only `\"` and `\\` are escapes.
+        assert_eq!(split(r#""a\"b\\c\d""#).unwrap(), [r#"a"b\c\d"#]);
+        assert_eq!(split(r"trailing\").unwrap(), [r"trailing\"]);
+    }
+
+    #[test]
+    fn unterminated_quotes_fail() {
+        assert_eq!(split(r#"say "hi"#).unwrap_err().to_string(), "unterminated double quote");
+        assert_eq!(split("say 'hi").unwrap_err().to_string(), "unterminated single quote");
+        assert_eq!(split(r#"say "hi\""#).unwrap_err().to_string(), "unterminated double quote");
+    }
+
+    #[test]
+    fn partial_lines_keep_the_open_word() {
+        let partial = split_partial(r#"volume create "my vol"#);
+        assert_eq!(partial.words, ["volume", "create", "my vol"]);
+        assert_eq!(partial.start, 14);
+        assert!(!partial.new_word);
+
+        let partial = split_partial("volume create ");
+        assert_eq!(partial.words, ["volume", "create"]);
+        assert_eq!(partial.start, 14);
+        assert!(partial.new_word);
+
+        let spans = spans(r#"a "b c" d"#);
+        assert_eq!(spans, [(0..1, "a".to_string()), (2..7, "b c".to_string()), (8..9, "d".to_string())]);
+    }
+
+    #[test]
+    fn quoted_words_split_back_unchanged() {
+        let words = ["plain", "", "two words", r#"say "hi""#, r"back\slash", "it's", "tab\there"];
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote(""), r#""""#);
+        assert_eq!(quote("two words"), r#""two words""#);
+        assert_eq!(split(&join(&words)).unwrap(), words);
+    }
+
+    #[test]
+    fn chains_and_redirects_ignore_quoted_operators() {
+        let chain = split_chain(r#"echo "a && b"; pool list && echo 'x;y'"#).unwrap();
+        assert_eq!(
+            chain,
+            [
+                (Link::Always, r#"echo "a && b""#),
+                (Link::Always, "pool list"),
+                (Link::IfSucceeded, "echo 'x;y'"),
+            ]
+        );
+        assert_eq!(split_redirect(r#"echo "a | b" | wc"#), (r#"echo "a | b" "#, Some(Redirect::Pipe("wc"))));
+        assert_eq!(split_redirect(r"echo a\>b"), (r"echo a\>b", None));
+    }
+}
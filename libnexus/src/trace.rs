@@ -0,0 +1,256 @@
+//! A minimal `tracing` subscriber that prints events to stderr, for daemons
+//! that do not install their own (`NexusServer::with_tracing_subscriber`).
+//!
+//! Each line carries a UTC timestamp, the level, the fields of the enclosing
+//! spans and the event itself:
+//!
+//! ```text
+//! 2026-01-05T10:21:07Z  INFO command{service=volume action=create args="name=v0 disk=sda" tenant=- role=- dry_run=false}: command succeeded duration_ms=0
+//! ```
+//!
+//! Its level can be changed while the daemon runs (`admin loglevel`).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static CURRENT: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Levels from the least verbose to the most.
+const LEVELS: [Level; 5] = [Level::ERROR, Level::WARN, Level::INFO, Level::DEBUG, Level::TRACE];
+
+/// How many of `LEVELS` the installed `StderrSubscriber` prints; 0 while
+/// none is installed.
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Install a `StderrSubscriber` printing events up to `level` as the global
+/// default, unless another subscriber is installed already.
+pub(crate) fn install(level: Level) {
+    store_max_level(level);
+    if tracing::subscriber::set_global_default(StderrSubscriber::new()).is_err() {
+        MAX_LEVEL.store(0, Ordering::Relaxed);
+    }
+}
+
+/// The level of the installed `StderrSubscriber`, if there is one.
+pub(crate) fn max_level() -> Option<Level> {
+    MAX_LEVEL.load(Ordering::Relaxed).checked_sub(1).map(|index| LEVELS[index])
+}
+
+/// Print events up to `level` from now on. Fails if the daemon logs
+/// through a subscriber of its own.
+pub(crate) fn set_max_level(level: Level) -> anyhow::Result<()> {
+    if max_level().is_none() {
+        anyhow::bail!("the log level is set by the daemon's own tracing subscriber");
+    }
+    store_max_level(level);
+    // Callsites cache whether they are enabled.
+    tracing::callsite::rebuild_interest_cache();
+    Ok(())
+}
+
+fn store_max_level(level: Level) {
+    let index = LEVELS.iter().position(|l| *l == level).expect("LEVELS lists every level");
+    MAX_LEVEL.store(index + 1, Ordering::Relaxed);
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    parent: Option<u64>,
+    refs: usize,
+}
+
+struct StderrSubscriber {
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl StderrSubscriber {
+    fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn current() -> Option<u64> {
+        CURRENT.with(|stack| stack.borrow().last().copied())
+    }
+
+    /// `outer{fields}:inner{fields}: ` for the span `id` and its parents.
+    fn context(&self, mut id: Option<u64>) -> String {
+        let spans = self.spans.lock().unwrap();
+        let mut chain = Vec::new();
+        while let Some(span) = id.and_then(|id| spans.get(&id)) {
+            chain.push(format!("{}{{{}}}", span.name, span.fields.trim_start()));
+            id = span.parent;
+        }
+        if chain.is_empty() {
+            return String::new();
+        }
+        chain.reverse();
+        format!("{}: ", chain.join(":"))
+    }
+}
+
+/// Writes fields as ` name=value`, keeping the `message` field separate.
+struct FieldWriter<'a> {
+    fields: &'a mut String,
+    message: Option<String>,
+}
+
+impl Visit for FieldWriter<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl Subscriber for StderrSubscriber {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        max_level().is_some_and(|level| *metadata.level() <= level)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(max_level().map_or(LevelFilter::OFF, LevelFilter::from_level))
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut fields = String::new();
+        attrs.record(&mut FieldWriter {
+            fields: &mut fields,
+            message: None,
+        });
+        let parent = match attrs.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if attrs.is_contextual() => Self::current(),
+            None => None,
+        };
+        let span = SpanData {
+            name: attrs.metadata().name(),
+            fields,
+            parent,
+            refs: 1,
+        };
+        self.spans.lock().unwrap().insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut FieldWriter {
+                fields: &mut span.fields,
+                message: None,
+            });
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = String::new();
+        let mut writer = FieldWriter {
+            fields: &mut fields,
+            message: None,
+        };
+        event.record(&mut writer);
+        let message = writer.message.unwrap_or_default();
+        let parent = match event.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if event.is_contextual() => Self::current(),
+            None => None,
+        };
+        eprintln!(
+            "{} {:>5} {}{}{}",
+            timestamp(),
+            event.metadata().level(),
+            self.context(parent),
+            message,
+            fields
+        );
+    }
+
+    fn enter(&self, span: &Id) {
+        CURRENT.with(|stack| stack.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        CURRENT.with(|stack| {
+            let mut stack = stack.borrow_mut();
+            if let Some(pos) = stack.iter().rposition(|id| *id == span.into_u64()) {
+                stack.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let id = span.into_u64();
+        let Some(data) = spans.get_mut(&id) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        spans.remove(&id);
+        true
+    }
+}
+
+/// The current time as `YYYY-MM-DDTHH:MM:SSZ` (UTC).
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
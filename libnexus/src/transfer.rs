@@ -0,0 +1,228 @@
+//! Files sent along with a command or received from one. A command taking
+//! an `Upload` parameter (e.g. `volume import`) is run through the `Upload`
+//! RPC, which sends the request and then the file in chunks; the CLI's
+//! `put <file> <service> <command>` does so. One taking a `Download`
+//! parameter (e.g. `volume export`) is run through `Download`, which
+//! streams the chunks the command writes ahead of its result; the CLI's
+//! `get <file> <service> <command>` saves them to the file. Neither side
+//! holds the whole file in memory unless it reads it that way.
+
+use crate::chunked;
+use crate::cli::Client;
+use crate::context::Context;
+use crate::error::NexusError;
+use crate::proto::{download_chunk, upload_chunk, CommandRequest, CommandResponse, UploadChunk, UploadStart};
+use anyhow::{Context as _, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// The most bytes one message of a transfer carries; larger writes are
+/// split.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+pub(crate) type Chunks = Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send>>;
+
+/// The file the client sent with the request, read chunk by chunk. Command
+/// methods receive it by declaring an `Upload` parameter.
+pub struct Upload {
+    name: String,
+    size: Option<u64>,
+    received: u64,
+    chunks: Chunks,
+}
+
+impl fmt::Debug for Upload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upload")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("received", &self.received)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Upload {
+    pub(crate) fn new(name: String, size: Option<u64>, chunks: Chunks) -> Self {
+        Self {
+            name,
+            size,
+            received: 0,
+            chunks,
+        }
+    }
+
+    /// Take the request's file. Fails with an invalid-argument error for a
+    /// request that came without one (not through the `Upload` RPC), or
+    /// whose file was taken already. Called by the code `#[nexus_service]`
+    /// generates for `Upload` parameters.
+    pub fn from_context(ctx: &Context) -> Result<Self> {
+        ctx.take_upload().ok_or_else(|| {
+            let message = format!("'{}' takes a file; send one with 'put <file> {}'", ctx.command, ctx.command);
+            NexusError::invalid_argument(message).into()
+        })
+    }
+
+    /// The file's name on the client, without its directory.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The file's size in bytes, if the client said.
+    pub fn size(&self) -> Option<u64> {
+        self.size
+    }
+
+    /// The next part of the file, or `None` once all of it has arrived.
+    /// Fails if the upload broke off, or the file turned out shorter than
+    /// the size the client gave.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.chunks.next().await.transpose()? {
+            Some(chunk) => {
+                self.received += chunk.len() as u64;
+                if let Some(size) = self.size.filter(|size| self.received > *size) {
+                    let message = format!("the file is longer than the {} bytes announced", size);
+                    return Err(NexusError::invalid_argument(message).into());
+                }
+                Ok(Some(chunk))
+            }
+            None => match self.size.filter(|size| self.received < *size) {
+                Some(size) => {
+                    let message = format!("the file ended after {} of {} bytes", self.received, size);
+                    Err(NexusError::invalid_argument(message).into())
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// The rest of the file, read into memory.
+    pub async fn bytes(mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+}
+
+/// Where a command writes the file the client downloads. Command methods
+/// receive it by declaring a `Download` parameter; the file is what they
+/// wrote once they return.
+#[derive(Debug, Clone)]
+pub struct Download {
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl Download {
+    /// A download and the receiving end of what is written to it.
+    pub(crate) fn channel() -> (Self, mpsc::Receiver<Vec<u8>>) {
+        let (sender, receiver) = mpsc::channel(4);
+        (Self { sender }, receiver)
+    }
+
+    /// Where the request's file goes. Fails with an invalid-argument error
+    /// for a request that does not receive one (not through the `Download`
+    /// RPC). Called by the code `#[nexus_service]` generates for `Download`
+    /// parameters.
+    pub fn from_context(ctx: &Context) -> Result<Self> {
+        ctx.download().cloned().ok_or_else(|| {
+            let message = format!("'{}' sends a file; save it with 'get <file> {}'", ctx.command, ctx.command);
+            NexusError::invalid_argument(message).into()
+        })
+    }
+
+    /// Append `data` to the file, waiting while the client catches up.
+    /// Fails once the client has stopped receiving it.
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            if self.sender.send(chunk.to_vec()).await.is_err() {
+                return Err(NexusError::internal("the client stopped receiving the file").into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `request`'s command through the `Upload` RPC, sending the file at
+/// `path` with it.
+pub(crate) async fn send_file(
+    client: &mut Client,
+    request: CommandRequest,
+    path: &Path,
+) -> Result<CommandResponse> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    let start = UploadStart {
+        request: Some(request),
+        file_name: path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+        size: file.metadata().await?.len(),
+    };
+    let (tx, rx) = mpsc::channel(4);
+    let chunk = |chunk| UploadChunk { chunk: Some(chunk) };
+    tx.send(chunk(upload_chunk::Chunk::Start(start))).await?;
+    // A read error ends the upload early, which the server rejects as
+    // shorter than announced; it is reported here once the call returns.
+    let reader = tokio::spawn(async move {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 || tx.send(chunk(upload_chunk::Chunk::Data(buffer[..read].to_vec()))).await.is_err() {
+                return Ok::<_, std::io::Error>(());
+            }
+        }
+    });
+    let response = client.upload(ReceiverStream::new(rx)).await?.into_inner();
+    reader
+        .await?
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    Ok(chunked::complete(client, response).await?)
+}
+
+/// Run `request`'s command through the `Download` RPC and save the file it
+/// sends to `path`. The file is written under a temporary name and only
+/// takes `path`'s once the command has succeeded, so a failed download
+/// leaves nothing behind. Returns the response and the file's size.
+pub(crate) async fn receive_file(
+    client: &mut Client,
+    request: CommandRequest,
+    path: &Path,
+) -> Result<(CommandResponse, u64)> {
+    let temporary = PathBuf::from(format!("{}.part", path.display()));
+    let received = async {
+        let mut chunks = client.download(request).await?.into_inner();
+        let mut file = tokio::fs::File::create(&temporary)
+            .await
+            .with_context(|| format!("cannot write {}", temporary.display()))?;
+        let mut size = 0;
+        while let Some(chunk) = chunks.message().await? {
+            match chunk.chunk {
+                Some(download_chunk::Chunk::Data(data)) => {
+                    file.write_all(&data).await?;
+                    size += data.len() as u64;
+                }
+                Some(download_chunk::Chunk::Result(response)) => {
+                    file.flush().await?;
+                    return Ok((chunked::complete(client, response).await?, size));
+                }
+                None => {}
+            }
+        }
+        anyhow::bail!("download ended without a result")
+    };
+    let received = received.await;
+    match &received {
+        Ok((response, _)) if response.success => tokio::fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("cannot write {}", path.display()))?,
+        _ => {
+            let _ = tokio::fs::remove_file(&temporary).await;
+        }
+    }
+    received
+}
@@ -0,0 +1,57 @@
+//! Checks run by the code that `#[nexus_service]` generates for
+//! `#[arg(regex = "...", range = "...", choices(...))]`, before the command
+//! method is called.
+
+use crate::error::NexusError;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::RangeBounds;
+use std::sync::{Mutex, OnceLock};
+
+/// Reject `value` unless it matches `pattern`.
+pub fn pattern(arg: &str, value: &str, pattern: &'static str) -> anyhow::Result<()> {
+    // Patterns are string literals from the macro, so compile each only once.
+    static COMPILED: OnceLock<Mutex<HashMap<&'static str, Regex>>> = OnceLock::new();
+    let mut compiled = COMPILED.get_or_init(Default::default).lock().unwrap();
+    let re = match compiled.get(pattern) {
+        Some(re) => re,
+        None => {
+            let re = Regex::new(pattern)
+                .map_err(|e| anyhow::anyhow!("invalid pattern for <{}>: {}", arg, e))?;
+            compiled.entry(pattern).or_insert(re)
+        }
+    };
+    if !re.is_match(value) {
+        let message = format!("invalid value for <{}>: '{}' does not match {}", arg, value, pattern);
+        return Err(NexusError::invalid_argument(message).into());
+    }
+    Ok(())
+}
+
+/// Reject `value` unless it is one of `choices`.
+pub fn choice(arg: &str, value: &str, choices: &[&str]) -> anyhow::Result<()> {
+    if !choices.contains(&value) {
+        let message = format!(
+            "invalid value for <{}>: '{}' is not one of: {}",
+            arg,
+            value,
+            choices.join(", ")
+        );
+        return Err(NexusError::invalid_argument(message).into());
+    }
+    Ok(())
+}
+
+/// Reject `value` unless it lies in `range`; `text` is the range as written.
+pub fn range<T, R>(arg: &str, value: &T, range: R, text: &str) -> anyhow::Result<()>
+where
+    T: PartialOrd + Display,
+    R: RangeBounds<T>,
+{
+    if !range.contains(value) {
+        let message = format!("invalid value for <{}>: {} is not in {}", arg, value, text);
+        return Err(NexusError::invalid_argument(message).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! YAML rendering of JSON values, for the `yaml` output format. Only
+//! emits block-style YAML; strings are double-quoted whenever they would
+//! otherwise be read back as something else.
+
+use serde_json::Value;
+
+/// `value` as a YAML document, ending in a newline.
+pub(crate) fn to_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_block(&mut out, value, 0);
+    out
+}
+
+/// Write `value` at `indent`: collections one entry per line, scalars on
+/// their own line.
+fn write_block(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                out.push_str(&" ".repeat(indent));
+                out.push_str(&scalar(&Value::String(key.clone())));
+                out.push(':');
+                write_nested(out, value, indent + 2);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for item in items {
+                out.push_str(&" ".repeat(indent));
+                out.push('-');
+                if item.is_object() && is_collection(item) {
+                    // The item's first entry goes on the dash's line.
+                    let mut nested = String::new();
+                    write_block(&mut nested, item, indent + 2);
+                    out.push(' ');
+                    out.push_str(&nested[indent + 2..]);
+                } else {
+                    write_nested(out, item, indent + 2);
+                }
+            }
+        }
+        _ => {
+            out.push_str(&" ".repeat(indent));
+            out.push_str(&scalar(value));
+            out.push('\n');
+        }
+    }
+}
+
+/// Write the value after a `key:` or `-`: on the same line if it is a
+/// scalar or empty, else on the following lines at `indent`.
+fn write_nested(out: &mut String, value: &Value, indent: usize) {
+    if is_collection(value) {
+        out.push('\n');
+        write_block(out, value, indent);
+    } else {
+        out.push(' ');
+        out.push_str(&scalar(value));
+        out.push('\n');
+    }
+}
+
+/// A non-empty object or array, written over several lines.
+fn is_collection(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => !map.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        _ => false,
+    }
+}
+
+/// A scalar or empty collection on one line.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) if needs_quotes(s) => Value::String(s.clone()).to_string(),
+        Value::String(s) => s.clone(),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// Whether a plain (unquoted) `s` would not read back as the same string.
+fn needs_quotes(s: &str) -> bool {
+    const RESERVED: &[&str] = &[
+        "null", "Null", "NULL", "~", "true", "True", "TRUE", "false", "False", "FALSE", "yes", "no", "on", "off",
+    ];
+    let Some(first) = s.chars().next() else {
+        return true;
+    };
+    RESERVED.contains(&s)
+        || s.parse::<f64>().is_ok()
+        || "-?:,[]{}#&*!|>'\"%@`".contains(first)
+        || s.starts_with(char::is_whitespace)
+        || s.ends_with(char::is_whitespace)
+        || s.ends_with(':')
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.chars().any(char::is_control)
+}
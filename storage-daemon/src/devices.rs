@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Which pool or volume holds each block device. Shared by the `pool` and
+/// `volume` services through `NexusServer::with_state`, so a disk cannot be
+/// given to both.
+#[derive(Default)]
+pub struct Devices {
+    owners: Mutex<HashMap<String, String>>,
+}
+
+impl Devices {
+    /// Give `disks` to `owner` (e.g. "pool 'p0'"), unless another owner
+    /// holds one of them. Disks `owner` already holds are left as they are.
+    pub fn claim(&self, owner: &str, disks: &[String]) -> anyhow::Result<()> {
+        let mut owners = self.owners.lock().unwrap();
+        if let Some((disk, other)) = disks
+            .iter()
+            .find_map(|disk| owners.get(disk).filter(|other| *other != owner).map(|other| (disk, other)))
+        {
+            anyhow::bail!("disk '{}' is in use by {}", disk, other);
+        }
+        for disk in disks {
+            owners.insert(disk.clone(), owner.to_string());
+        }
+        Ok(())
+    }
+
+    /// Free the disks `owner` holds.
+    pub fn release(&self, owner: &str) {
+        self.owners.lock().unwrap().retain(|_, holder| holder != owner);
+    }
+}
@@ -1,24 +1,70 @@
+mod devices;
 mod services {
     pub mod block;
     pub mod network;
     pub mod pool;
+    pub mod snapshot;
     pub mod volume;
+    pub mod volume_crypto;
 }
 
-use libnexus::NexusServer;
-use services::{block::Block, network::Network, pool::Pool, volume::Volume};
+use devices::Devices;
+use libnexus::{Config, JobRetention, NexusServer, Rate, RateLimit, TokenAuth};
+use std::sync::Arc;
+use std::time::Duration;
+use services::{block::Block, network::Network, pool::Pool, snapshot::Snapshot, volume::Volume};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let addr = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| libnexus::DEFAULT_ENDPOINT.to_string());
+    // Settings from the TOML file named by NEXUS_CONFIG, overridden by
+    // NEXUS_* variables (e.g. NEXUS_HTTP_ADDR=127.0.0.1:8080 to serve HTTP,
+    // NEXUS_METRICS_ADDR=127.0.0.1:9100 for Prometheus metrics).
+    let config = match std::env::var("NEXUS_CONFIG") {
+        Ok(path) => Config::load(path)?,
+        Err(_) => Config::from_env()?,
+    };
 
-    NexusServer::new()
+    let server = NexusServer::new()
+        // Volumes and pools both take disks, so they share one record of
+        // which disk is whose.
+        .with_state(Arc::new(Devices::default()))
         .register(Volume)
+        // Invoked as `volume snapshot <command>`.
+        .register(Snapshot)
         .register(Block)
         .register(Network)
         .register(Pool)
-        .serve(&addr)
-        .await
+        .with_max_concurrency(8)
+        // Fail commands stuck on an unresponsive device instead of hanging.
+        .with_default_timeout(Duration::from_secs(60))
+        // Job results survive restarts, for a week.
+        .with_job_store("/tmp/storage-daemon.jobs")
+        .with_job_retention(JobRetention {
+            max_jobs: 500,
+            max_age: Some(Duration::from_secs(7 * 86_400)),
+        })
+        // A runaway script is throttled instead of starving other clients.
+        .with_middleware(RateLimit::new().per_peer(Rate::per_second(50).burst(100)))
+        // Log every command to stderr (level from NEXUS_LOG).
+        .with_tracing_subscriber()
+        // Lets grpcurl and other gRPC tools describe and call the API.
+        .with_reflection(true)
+        // `admin uptime`, `admin stats`, `admin connections`, ...
+        .with_admin(true)
+        // `schedule add "0 3 * * *" pool scrub p0` for nightly maintenance.
+        .with_scheduler("/tmp/storage-daemon.schedules.json")
+        // Starting a new version hands the socket over without dropping clients.
+        .with_takeover("/tmp/storage-daemon.ctl");
+    // Admin-only commands (e.g. `pool destroy`) are open to root on the Unix
+    // socket, and to anyone presenting NEXUS_ADMIN_TOKEN if it is set.
+    let mut auth = TokenAuth::new().peer_uid(0, "admin");
+    if let Ok(token) = std::env::var("NEXUS_ADMIN_TOKEN") {
+        auth = auth.token(&token, "admin");
+    }
+    let server = server.with_auth(auth).with_config(&config.server)?;
+    // An address on the command line wins over the configured one.
+    match std::env::args().nth(1) {
+        Some(addr) => server.serve(&addr).await,
+        None => server.serve_configured().await,
+    }
 }
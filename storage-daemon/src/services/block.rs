@@ -1,19 +1,115 @@
-use libnexus::nexus_service;
+use libnexus::{nexus_service, Event, Health, NexusError, StartContext};
+use std::collections::BTreeSet;
+use std::time::Duration;
 
+/// The devices the daemon manages.
+const DEVICES: [&str; 4] = ["sda", "sdb", "sdc", "nvme0n1"];
+
+#[derive(Clone)]
 pub struct Block;
 
 /// Query and inspect block devices.
 #[nexus_service]
 impl Block {
     /// List all block devices.
-    #[command]
-    async fn list(&self) -> anyhow::Result<String> {
-        Ok("sda, sdb, sdc, nvme0n1".to_string())
+    #[command(priority = "high", readonly, cache = "5s")]
+    fn list(&self) -> String {
+        DEVICES.join(", ")
     }
 
     /// Show info for a block device.
-    #[command]
+    #[command(timeout = "5s", readonly)]
     async fn info(&self, #[arg(doc = "Device path to inspect", complete = "block.list")] device: String) -> anyhow::Result<String> {
+        if !DEVICES.contains(&device.as_str()) {
+            let error = NexusError::not_found(format!("no block device '{}'", device)).detail("device", device);
+            return Err(error.into());
+        }
         Ok(format!("Block device '{}': size=500G, type=SSD", device))
     }
+
+    /// Dump the first sector of a block device (its partition table).
+    #[command(readonly, since = "1.2")]
+    fn header(&self, #[arg(doc = "Device to read", complete = "block.list")] device: String) -> anyhow::Result<Vec<u8>> {
+        if !DEVICES.contains(&device.as_str()) {
+            let error = NexusError::not_found(format!("no block device '{}'", device)).detail("device", device);
+            return Err(error.into());
+        }
+        // Stand-in for reading the device: an MBR with one Linux partition
+        // spanning the disk.
+        let mut sector = vec![0; 512];
+        sector[446..462].copy_from_slice(&[0, 0, 2, 0, 0x83, 0xfe, 0xff, 0xff, 0, 8, 0, 0, 0, 0xf8, 0xff, 0xff]);
+        sector[510..].copy_from_slice(&[0x55, 0xaa]);
+        Ok(sector)
+    }
+
+    /// Create a filesystem on a block device.
+    #[command(blocking)]
+    fn mkfs(
+        &self,
+        #[arg(doc = "Device to format", complete = "block.list")] device: String,
+        #[arg(doc = "Filesystem to create", default = "xfs", choices("xfs", "ext4"))] fs: String,
+    ) -> String {
+        // Stand-in for running mkfs, which blocks until the device is written.
+        std::thread::sleep(Duration::from_secs(2));
+        format!("Created {} filesystem on '{}'", fs, device)
+    }
+
+    /// Report disks appearing and disappearing as "block.hotplug" events,
+    /// checking /dev every two seconds until the daemon stops.
+    #[on_start]
+    async fn watch_hotplug(&self, ctx: &StartContext) -> anyhow::Result<()> {
+        let events = ctx.events();
+        let stopping = ctx.stopping.clone();
+        tokio::spawn(async move {
+            let mut known = disks();
+            let mut ticks = tokio::time::interval(Duration::from_secs(2));
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stopping.cancelled() => return,
+                }
+                let now = disks();
+                for added in now.difference(&known) {
+                    events.emit(Event::new("block.hotplug", format!("{} added", added)));
+                }
+                for removed in known.difference(&now) {
+                    events.emit(Event::new("block.hotplug", format!("{} removed", removed)));
+                }
+                known = now;
+            }
+        });
+        Ok(())
+    }
+
+    /// The service can only inspect devices if /dev is available.
+    #[health]
+    async fn devices_visible(&self) -> Health {
+        if std::path::Path::new("/dev").is_dir() {
+            Health::Serving
+        } else {
+            Health::not_serving("/dev is not mounted")
+        }
+    }
+}
+
+/// The whole-disk devices in /dev: sda, vdb, nvme0n1, but not their partitions.
+fn disks() -> BTreeSet<String> {
+    let Ok(entries) = std::fs::read_dir("/dev") else {
+        return BTreeSet::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| is_disk(name))
+        .collect()
+}
+
+fn is_disk(name: &str) -> bool {
+    if let Some(rest) = name.strip_prefix("nvme") {
+        // nvme0n1, but not the controller nvme0 or the partition nvme0n1p1.
+        return rest.contains('n') && !rest.contains('p');
+    }
+    ["sd", "vd"].iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_lowercase()))
+    })
 }
@@ -1,4 +1,4 @@
-use libnexus::nexus_service;
+use libnexus::{nexus_service, CommandOutput};
 
 pub struct Network;
 
@@ -7,8 +7,15 @@ pub struct Network;
 impl Network {
     /// List all network interfaces.
     #[command]
-    async fn list(&self) -> anyhow::Result<String> {
-        Ok("eth0, eth1, lo".to_string())
+    fn list(&self) -> CommandOutput {
+        CommandOutput::table(
+            ["iface", "ip", "state"],
+            [
+                ["eth0", "10.0.0.1", "UP"],
+                ["eth1", "10.0.1.1", "DOWN"],
+                ["lo", "127.0.0.1", "UP"],
+            ],
+        )
     }
 
     /// Show info for a network interface.
@@ -1,19 +1,125 @@
-use libnexus::nexus_service;
+use libnexus::tokio_stream::wrappers::ReceiverStream;
+use libnexus::tokio_stream::Stream;
+use crate::devices::Devices;
+use libnexus::{nexus_service, Args, CancellationToken, Change, Context, Session, State};
+use std::time::Duration;
 
 pub struct Pool;
 
 /// Manage storage pools.
-#[nexus_service]
+// Pool operations hit every disk in the pool: run two at a time and turn
+// callers away once eight are waiting.
+#[nexus_service(max_concurrency = 2, max_queue = 8)]
 impl Pool {
     /// Create a new storage pool.
     #[command]
-    async fn create(&self, #[arg(doc = "Name for the new pool")] name: String) -> anyhow::Result<String> {
-        Ok(format!("Pool '{}' created", name))
+    async fn create(
+        &self,
+        #[arg(doc = "Name for the new pool", regex = "^[a-z0-9_-]+$")] name: String,
+        #[arg(hint = "disk", doc = "Block devices to build the pool from", complete = "block.list")] disks: Vec<String>,
+        devices: State<Devices>,
+    ) -> anyhow::Result<String> {
+        devices.claim(&format!("pool '{}'", name), &disks)?;
+        Ok(format!("Pool '{}' created on {}", name, disks.join(", ")))
     }
 
     /// Destroy a storage pool.
-    #[command]
-    async fn destroy(&self, #[arg(doc = "Pool to destroy", complete = "pool.create")] name: String) -> anyhow::Result<String> {
+    #[command(dry_run, permission = "admin", lock = "pool:{name}")]
+    async fn destroy(
+        &self,
+        ctx: &Context,
+        #[arg(doc = "Pool to destroy", complete = "pool.create")] name: String,
+        devices: State<Devices>,
+    ) -> anyhow::Result<String> {
+        if ctx.dry_run {
+            ctx.propose(Change::delete(format!("pool/{}", name), "state=ONLINE, volumes=2"));
+            return Ok(format!("Would destroy pool '{}'", name));
+        }
+        devices.release(&format!("pool '{}'", name));
         Ok(format!("Pool '{}' destroyed", name))
     }
+
+    /// Scrub a storage pool, reporting progress as it runs. A pool is
+    /// scrubbed by one command at a time, and is not destroyed while it is.
+    #[command(timeout = "10m", try_lock = "pool:{name}")]
+    fn scrub(
+        &self,
+        #[arg(doc = "Pool to scrub", complete = "pool.create")] name: String,
+        cancel: CancellationToken,
+    ) -> impl Stream<Item = anyhow::Result<String>> {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        // The scrub runs on its own task, so it watches the token to stop
+        // when the client goes away or the command times out.
+        tokio::spawn(async move {
+            for percent in (0..=100).step_by(25) {
+                if tx.send(Ok(format!("Scrubbing '{}': {}%", name, percent))).await.is_err() {
+                    return;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_millis(500)) => {}
+                    _ = cancel.cancelled() => {
+                        println!("Scrub of '{}' stopped", name);
+                        return;
+                    }
+                }
+            }
+            let _ = tx.send(Ok(format!("Scrub of '{}' complete, 0 errors", name))).await;
+        });
+        ReceiverStream::new(rx)
+    }
+
+    /// Repair a degraded pool, asking before each step.
+    ///
+    /// The failed disk is replaced with a spare of the user's choosing and
+    /// the pool resilvered onto it; run it from the shell, which asks the
+    /// questions inline.
+    #[command(lock = "pool:{name}", since = "1.2")]
+    async fn repair(
+        &self,
+        #[arg(doc = "Pool to repair", complete = "pool.create")] name: String,
+        session: Session,
+    ) -> anyhow::Result<String> {
+        // Stand-in for finding the pool's failed disk and the spares.
+        session.say(&format!("Pool '{}' is degraded: disk sdb has failed", name)).await?;
+        if !session.confirm("Take sdb offline?", true).await? {
+            return Ok(format!("Pool '{}' left as it was", name));
+        }
+        let spare = session.choose("Replace it with which spare?", &["sdc", "sdd"]).await?;
+        if !session.confirm(&format!("Resilver '{}' onto {}?", name, spare), false).await? {
+            return Ok(format!("Disk sdb offline; pool '{}' still degraded", name));
+        }
+        session.say(&format!("Resilvering onto {}", spare)).await?;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(format!("Pool '{}' repaired: sdb replaced with {}", name, spare))
+    }
+
+    /// Destroy a pool created earlier in an atomic batch whose later
+    /// command failed.
+    #[rollback]
+    async fn undo(&self, ctx: &Context, action: &str, args: Args) -> anyhow::Result<()> {
+        if action != "create" {
+            anyhow::bail!("only `pool create` can be rolled back");
+        }
+        let (slots, _) = args.bind_variadic(&["name", "disks"])?;
+        let name = slots.into_iter().next().flatten().unwrap_or_default();
+        if let Some(devices) = ctx.state::<Devices>() {
+            devices.release(&format!("pool '{}'", name));
+        }
+        println!("Pool '{}' destroyed (rolled back)", name);
+        Ok(())
+    }
+
+    /// Import the pools on the attached disks before serving requests.
+    #[on_start]
+    async fn import_pools(&self) -> anyhow::Result<()> {
+        println!("Pools imported");
+        Ok(())
+    }
+
+    /// Export pools cleanly when the daemon stops.
+    #[shutdown]
+    async fn export_pools(&self) -> anyhow::Result<()> {
+        println!("Pools exported");
+        Ok(())
+    }
 }
@@ -0,0 +1,66 @@
+use libnexus::{nexus_service, CommandOutput, NexusError};
+
+pub struct Snapshot;
+
+/// Stand-in for the snapshots on disk: name and creation time.
+const SNAPSHOTS: [(&str, &str); 3] = [
+    ("vol0@daily", "2026-10-13 00:00"),
+    ("vol0@weekly", "2026-10-11 00:00"),
+    ("vol1@hourly", "2026-10-14 09:00"),
+];
+
+/// Manage point-in-time snapshots of volumes.
+#[nexus_service(parent = "volume", aliases("snap"))]
+impl Snapshot {
+    /// Take a snapshot of a volume.
+    #[command]
+    async fn create(
+        &self,
+        #[arg(hint = "volume name", doc = "Volume to snapshot", complete = "volume.list")] volume: String,
+        #[arg(doc = "Name for the new snapshot", regex = "^[a-z0-9_-]+$")] name: String,
+    ) -> anyhow::Result<String> {
+        Ok(format!("Snapshot '{}@{}' created", volume, name))
+    }
+
+    /// Delete a snapshot.
+    #[command]
+    async fn delete(
+        &self,
+        #[arg(hint = "volume@snapshot", doc = "Snapshot to delete", complete = "volume.snapshot.list")] snapshot: String,
+    ) -> anyhow::Result<String> {
+        if !SNAPSHOTS.iter().any(|(name, _)| *name == snapshot) {
+            return Err(NexusError::not_found(format!("snapshot '{}' does not exist", snapshot))
+                .detail("snapshot", &snapshot)
+                .into());
+        }
+        Ok(format!("Snapshot '{}' deleted", snapshot))
+    }
+
+    /// Roll a volume back to one of its snapshots.
+    #[command]
+    async fn rollback(
+        &self,
+        #[arg(hint = "volume name", doc = "Volume to roll back", complete = "volume.list")] volume: String,
+        #[arg(hint = "volume@snapshot", doc = "Snapshot to roll back to", complete = "volume.snapshot.list($volume)")]
+        snapshot: String,
+    ) -> anyhow::Result<String> {
+        if snapshot.split_once('@').map(|(of, _)| of) != Some(volume.as_str()) {
+            let message = format!("'{}' is not a snapshot of volume '{}'", snapshot, volume);
+            return Err(NexusError::invalid_argument(message).into());
+        }
+        Ok(format!("Volume '{}' rolled back to '{}'", volume, snapshot))
+    }
+
+    /// List snapshots.
+    #[command]
+    async fn list(
+        &self,
+        #[arg(hint = "volume name", doc = "Only list snapshots of this volume", complete = "volume.list")] volume: Option<String>,
+    ) -> CommandOutput {
+        let rows = SNAPSHOTS.iter().filter(|(name, _)| match &volume {
+            Some(volume) => name.split_once('@').is_some_and(|(of, _)| of == volume),
+            None => true,
+        });
+        CommandOutput::table(["snapshot", "created"], rows.map(|(name, created)| [*name, *created]))
+    }
+}
@@ -1,32 +1,146 @@
-use libnexus::nexus_service;
+use crate::devices::Devices;
+use libnexus::{nexus_service, Args, Context, Download, Event, Progress, State, Upload};
+use std::time::Duration;
 
 pub struct Volume;
 
 /// Manage storage volumes on block devices.
-#[nexus_service]
+#[nexus_service(aliases("vol", "volumes"), client)]
 impl Volume {
     /// Create a new volume on the specified disk.
-    #[command]
+    ///
+    /// The volume is allocated on `disk` and sized in GiB. Creating a volume
+    /// on a degraded disk succeeds but returns a warning.
+    ///
+    /// # Examples
+    ///
+    /// ```text
+    /// volume create data0 sda
+    /// volume create logs sdc --size 50
+    /// ```
+    #[command(lock = "disk:{disk}")]
     async fn create(
         &self,
-        #[arg(hint = "volume name", doc = "Name for the new volume")] name: String,
+        ctx: &Context,
+        #[arg(hint = "volume name", doc = "Name for the new volume", regex = "^[a-z0-9][a-z0-9 _-]*$")] name: String,
         #[arg(hint = "device", doc = "Block device to create the volume on", complete = "block.list")] disk: String,
+        #[arg(hint = "size GiB", doc = "Volume size in GiB", default = "10", range = "1..=1024")] size: u64,
+        #[arg(doc = "Filesystem to format the volume with", default = "xfs", choices("xfs", "ext4"))] fs: String,
+        devices: State<Devices>,
     ) -> anyhow::Result<String> {
-        Ok(format!("Volume '{}' created on disk '{}'", name, disk))
+        // Fails for a disk the block service does not know.
+        ctx.call("block", "info", Args::new(vec![disk.clone()])).await?;
+        // A disk can hold many volumes, but not while it belongs to a pool.
+        devices.claim("volumes", std::slice::from_ref(&disk))?;
+        if disk == "sdb" {
+            ctx.warn("disk sdb is degraded; consider migrating the volume");
+        }
+        ctx.emit(Event::new("volume.created", format!("{} on {}", name, disk)));
+        Ok(format!("Volume '{}' ({}G, {}) created on disk '{}'", name, size, fs, disk))
     }
 
     /// Delete an existing volume.
     #[command]
     async fn delete(
         &self,
-        #[arg(hint = "volume name", doc = "Volume to delete", complete = "volume.list")] name: String,
+        #[arg(hint = "volume name", doc = "Volume to delete", complete = "volume.list", glob)] name: String,
     ) -> anyhow::Result<String> {
         Ok(format!("Volume '{}' deleted", name))
     }
 
-    /// List all volumes.
+    /// Resize an existing volume.
     #[command]
-    async fn list(&self) -> anyhow::Result<String> {
-        Ok("vol0, vol1, vol2".to_string())
+    async fn resize(
+        &self,
+        #[arg(hint = "volume name", doc = "Volume to resize", complete = "volume.list")] name: String,
+        #[arg(hint = "size GiB", doc = "New size in GiB", range = "1..=1024")] size: u64,
+    ) -> anyhow::Result<String> {
+        Ok(format!("Volume '{}' resized to {}G", name, size))
+    }
+
+    /// Move a volume to another disk, copying its data while it stays online.
+    ///
+    /// Migrations take a while, so the command runs as a background job:
+    /// follow it with `job status <id>` and stop it with `job cancel <id>`.
+    #[command(background, since = "1.1")]
+    async fn migrate(
+        &self,
+        #[arg(hint = "volume name", doc = "Volume to migrate", complete = "volume.list")] name: String,
+        #[arg(hint = "device", doc = "Block device to move the volume to", complete = "block.list")] disk: String,
+        progress: Progress,
+    ) -> anyhow::Result<String> {
+        progress.message("Copying extents");
+        // Stand-in for copying the volume's extents.
+        for percent in (0..100).step_by(20) {
+            progress.percent(percent);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Ok(format!("Volume '{}' migrated to disk '{}'", name, disk))
+    }
+
+    /// Create a volume on a disk from an image file.
+    ///
+    /// ```text
+    /// put data0.img volume import data0 sda
+    /// ```
+    #[command(lock = "disk:{disk}", since = "1.2")]
+    async fn import(
+        &self,
+        #[arg(hint = "volume name", doc = "Name for the new volume", regex = "^[a-z0-9][a-z0-9 _-]*$")] name: String,
+        #[arg(hint = "device", doc = "Block device to create the volume on", complete = "block.list")] disk: String,
+        mut image: Upload,
+    ) -> anyhow::Result<String> {
+        let mut size = 0;
+        // Stand-in for writing the image to the disk.
+        while let Some(chunk) = image.chunk().await? {
+            size += chunk.len();
+        }
+        Ok(format!("Volume '{}' imported from '{}' ({} bytes) on disk '{}'", name, image.name(), size, disk))
+    }
+
+    /// Save an image of a volume.
+    ///
+    /// ```text
+    /// get data0.img volume export data0
+    /// ```
+    #[command(readonly, since = "1.2")]
+    async fn export(
+        &self,
+        #[arg(hint = "volume name", doc = "Volume to export", complete = "volume.list")] name: String,
+        image: Download,
+    ) -> anyhow::Result<String> {
+        // Stand-in for reading the volume's blocks.
+        for block in 0..16u8 {
+            image.write(&[block; 16 * 1024]).await?;
+        }
+        Ok(format!("Volume '{}' exported", name))
+    }
+
+    /// Delete a volume created earlier in an atomic batch whose later
+    /// command failed.
+    #[rollback]
+    async fn undo(&self, action: &str, args: Args) -> anyhow::Result<()> {
+        match action {
+            "create" => {}
+            // Listing changed nothing.
+            "list" => return Ok(()),
+            _ => anyhow::bail!("only `volume create` can be rolled back"),
+        }
+        let slots = args.bind(&["name", "disk", "size", "fs"])?;
+        let name = slots.into_iter().next().flatten().unwrap_or_default();
+        println!("Volume '{}' deleted (rolled back)", name);
+        Ok(())
+    }
+
+    /// List all volumes.
+    #[command(readonly)]
+    async fn list(
+        &self,
+        #[arg(doc = "Only list volumes in this pool")] pool: Option<String>,
+    ) -> anyhow::Result<String> {
+        match pool {
+            Some(pool) if pool != "p0" => Ok(format!("No volumes in pool '{}'", pool)),
+            _ => Ok("vol0, vol1, vol2".to_string()),
+        }
     }
 }
@@ -0,0 +1,28 @@
+use super::volume::{Volume, VolumeClient};
+use libnexus::{nexus_service, NexusError, Progress};
+use std::time::Duration;
+
+// Further `volume` commands, registered along with those in volume.rs.
+#[nexus_service(extend, client)]
+impl Volume {
+    /// Encrypt an existing volume with a passphrase.
+    #[command]
+    async fn encrypt(
+        &self,
+        #[arg(hint = "volume name", doc = "Volume to encrypt", complete = "volume.list")] name: String,
+        #[arg(doc = "Encryption passphrase", secret)] passphrase: String,
+        progress: Progress,
+    ) -> anyhow::Result<String> {
+        if passphrase.len() < 8 {
+            return Err(NexusError::invalid_argument("passphrase must be at least 8 characters")
+                .detail("min_length", "8")
+                .into());
+        }
+        let steps = ["Deriving key", "Encrypting data", "Writing header"];
+        for (i, step) in steps.iter().enumerate() {
+            progress.step(i as u32 + 1, steps.len() as u32, *step);
+            tokio::time::sleep(Duration::from_millis(400)).await;
+        }
+        Ok(format!("Volume '{}' encrypted", name))
+    }
+}